@@ -2,3 +2,4 @@ mod auth;
 mod http;
 mod public;
 mod rate_limiting;
+mod virtual_homeservers;
@@ -1,6 +1,10 @@
 use bytes::Bytes;
+use futures_util::io::Cursor;
 use pkarr::Keypair;
-use pubky_testnet::{pubky_homeserver::MockDataDir, EphemeralTestnet, Testnet};
+use pubky_testnet::{
+    pubky_homeserver::{MockDataDir, SignupMode},
+    EphemeralTestnet, Testnet,
+};
 use reqwest::{Method, StatusCode};
 
 #[tokio::test]
@@ -133,6 +137,139 @@ async fn put_quota_applied() {
     assert_eq!(resp.status(), StatusCode::CREATED);
 }
 
+#[tokio::test]
+async fn resumable_upload() {
+    let testnet = EphemeralTestnet::start().await.unwrap();
+    let server = testnet.homeserver_suite();
+
+    let client = testnet.pubky_client().unwrap();
+
+    let keypair = Keypair::random();
+
+    client
+        .signup(&keypair, &server.public_key(), None)
+        .await
+        .unwrap();
+
+    let url = format!("pubky://{}/pub/big.bin", keypair.public_key());
+    let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+
+    // Send the first chunk only.
+    let mut upload = client
+        .upload(&url, Cursor::new(data.clone()))
+        .chunk_size(30_000)
+        .start()
+        .await
+        .unwrap();
+    assert_eq!(upload.send_chunk().await.unwrap(), 30_000);
+    assert!(!upload.is_complete());
+    let id = upload.id().to_string();
+
+    // Not visible before the upload completes.
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Resume with a fresh upload handle.
+    let upload = client
+        .upload(&url, Cursor::new(data.clone()))
+        .chunk_size(30_000)
+        .upload_id(id)
+        .start()
+        .await
+        .unwrap();
+    assert_eq!(upload.offset(), 30_000);
+    let id = upload.id().to_string();
+    upload.finish().await.unwrap();
+
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.bytes().await.unwrap(), Bytes::from(data.clone()));
+
+    // The homeserver deleted the upload after creating the file, like when the response
+    // to the last chunk got lost. Resuming confirms the file instead of failing.
+    let upload = client
+        .upload(&url, Cursor::new(data.clone()))
+        .upload_id(id.clone())
+        .start()
+        .await
+        .unwrap();
+    assert!(upload.is_complete());
+    assert_eq!(upload.offset(), 100_000);
+
+    // Other content is not confirmed.
+    let other: Vec<u8> = vec![1; 100_000];
+    assert!(client
+        .upload(&url, Cursor::new(other))
+        .upload_id(id)
+        .start()
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn export_import_between_homeservers() {
+    let mut testnet = Testnet::new().await.unwrap();
+    let client = testnet.pubky_client().unwrap();
+
+    let mut servers = vec![];
+    for _ in 0..2 {
+        let mut mock_dir = MockDataDir::test();
+        mock_dir.config_toml.general.signup_mode = SignupMode::Open;
+        mock_dir.keypair = Keypair::random();
+        let server = testnet
+            .create_homeserver_suite_with_mock(mock_dir)
+            .await
+            .unwrap();
+        servers.push(server.public_key());
+    }
+    let (old_server, new_server) = (&servers[0], &servers[1]);
+
+    let keypair = Keypair::random();
+    let pubky = keypair.public_key();
+    client.signup(&keypair, old_server, None).await.unwrap();
+
+    let url = format!("pubky://{pubky}/pub/foo.txt");
+    client
+        .put(&url)
+        .body(vec![0, 1, 2, 3, 4])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = client
+        .get(format!("pubky://{pubky}/export"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let archive = response.bytes().await.unwrap();
+
+    // Move to the new homeserver.
+    client.signup(&keypair, new_server, None).await.unwrap();
+    // Fresh client without the cached homeserver of the user.
+    let client = testnet.pubky_client().unwrap();
+    client.signin(&keypair).await.unwrap();
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .post(format!("pubky://{pubky}/import"))
+        .body(archive)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.bytes().await.unwrap(),
+        Bytes::from(vec![0, 1, 2, 3, 4])
+    );
+}
+
 #[tokio::test]
 async fn unauthorized_put_delete() {
     let testnet = EphemeralTestnet::start().await.unwrap();
@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use pkarr::{Keypair, PublicKey};
 use pubky_testnet::{
-    pubky::Client,
+    pubky::{internal::rate_limit::RateLimitedError, Client},
     pubky_homeserver::{
         quota_config::{GlobPattern, LimitKey, LimitKeyType, PathLimit},
         ConfigToml, MockDataDir,
@@ -50,10 +50,15 @@ async fn test_limit_signin_get_session() {
     client.signin(&keypair).await.unwrap(); // First signin should be ok
 
     client.session(&keypair.public_key()).await.unwrap(); // First session should be ok
-    client
+    let error = client
         .session(&keypair.public_key())
         .await
         .expect_err("Should be rate limited"); // Second session should be rate limited
+    let rate_limited = error
+        .downcast_ref::<RateLimitedError>()
+        .expect("Should be a rate limit error");
+    let retry_after = rate_limited.retry_after.expect("Should have Retry-After");
+    assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(60));
 
     client
         .signin(&keypair)
@@ -61,6 +66,94 @@ async fn test_limit_signin_get_session() {
         .expect_err("Should be rate limited"); // Second signin should be rate limited
 }
 
+#[tokio::test]
+async fn test_rate_limit_backoff() {
+    let mut testnet = Testnet::new().await.unwrap();
+    let client = testnet
+        .pubky_client_builder()
+        .rate_limit_backoff(3, Duration::from_secs(5))
+        .build()
+        .unwrap();
+
+    let mut config = ConfigToml::test();
+    config.drive.rate_limits = vec![PathLimit::new(
+        GlobPattern::new("/session"),
+        Method::GET,
+        "1r/s".parse().unwrap(),
+        LimitKeyType::User,
+        None,
+    )];
+    let mock_dir = MockDataDir::new(config, None).unwrap();
+    let server = testnet
+        .create_homeserver_suite_with_mock(mock_dir)
+        .await
+        .unwrap();
+
+    let keypair = Keypair::random();
+    client
+        .signup(&keypair, &server.public_key(), None)
+        .await
+        .unwrap();
+
+    let start = Instant::now();
+    client.session(&keypair.public_key()).await.unwrap();
+    // Waits for the Retry-After delay instead of failing.
+    client.session(&keypair.public_key()).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(900));
+}
+
+/// Requests built with the public HTTP methods are retried when sent with `Client::send`.
+#[tokio::test]
+async fn test_rate_limit_backoff_public_requests() {
+    let mut testnet = Testnet::new().await.unwrap();
+    let client = testnet
+        .pubky_client_builder()
+        .rate_limit_backoff(3, Duration::from_secs(5))
+        .build()
+        .unwrap();
+
+    let mut config = ConfigToml::test();
+    config.drive.rate_limits = vec![PathLimit::new(
+        GlobPattern::new("/pub/**"),
+        Method::GET,
+        "1r/s".parse().unwrap(),
+        LimitKeyType::Ip,
+        None,
+    )];
+    let mock_dir = MockDataDir::new(config, None).unwrap();
+    let server = testnet
+        .create_homeserver_suite_with_mock(mock_dir)
+        .await
+        .unwrap();
+
+    let keypair = Keypair::random();
+    client
+        .signup(&keypair, &server.public_key(), None)
+        .await
+        .unwrap();
+    let url = format!("pubky://{}/pub/foo.txt", keypair.public_key());
+    client
+        .put(&url)
+        .body(vec![0, 1, 2])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let start = Instant::now();
+    let response = client.send(client.get(&url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // Waits for the Retry-After delay instead of failing.
+    let response = client.send(client.get(&url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(start.elapsed() >= Duration::from_millis(900));
+
+    // Sending the request builder directly doesn't retry.
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
 #[tokio::test]
 async fn test_limit_signin_get_session_whitelist() {
     let keypair = Keypair::random();
@@ -0,0 +1,72 @@
+use pkarr::Keypair;
+use pubky_testnet::{pubky_homeserver::MockDataDir, Testnet};
+use reqwest::StatusCode;
+
+#[tokio::test]
+async fn virtual_homeserver_signup_and_storage() {
+    let mut testnet = Testnet::new().await.unwrap();
+    let client = testnet.pubky_client().unwrap();
+
+    let mut mock_dir = MockDataDir::test();
+    mock_dir.config_toml.general.virtual_homeservers = vec!["alice".to_string()];
+    let server = testnet
+        .create_homeserver_suite_with_mock(mock_dir)
+        .await
+        .unwrap();
+    let virtual_homeservers = server.virtual_homeservers();
+    assert_eq!(virtual_homeservers.len(), 1);
+    let alice = &virtual_homeservers[0];
+    assert_ne!(alice, &server.public_key());
+
+    // The virtual homeserver has its own signup mode and is managed by the shared admin server.
+    let admin = format!("http://{}", server.admin().listen_socket().tcp().unwrap());
+    let http = reqwest::Client::new();
+    let token = http
+        .get(format!("{admin}/virtual/{alice}/generate_signup_token"))
+        .header("X-Admin-Password", "admin")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Pubky TLS presents the key of the virtual homeserver.
+    let keypair = Keypair::random();
+    let session = client.signup(&keypair, alice, Some(&token)).await.unwrap();
+    assert_eq!(session.pubky(), &keypair.public_key());
+
+    // Requests to the user are routed to the virtual homeserver.
+    let url = format!("pubky://{}/pub/foo.txt", keypair.public_key());
+    client
+        .put(&url)
+        .body(vec![0, 1, 2, 3, 4])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.bytes().await.unwrap(),
+        bytes::Bytes::from(vec![0, 1, 2, 3, 4])
+    );
+
+    // The user only exists in the storage of the virtual homeserver.
+    let user = |prefix: String| {
+        http.get(format!("{admin}{prefix}/users/{}", keypair.public_key()))
+            .header("X-Admin-Password", "admin")
+            .send()
+    };
+    assert_eq!(
+        user(format!("/virtual/{alice}")).await.unwrap().status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        user(String::new()).await.unwrap().status(),
+        StatusCode::NOT_FOUND
+    );
+}
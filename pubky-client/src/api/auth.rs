@@ -43,12 +43,11 @@ impl Client {
         let request_body = auth_token.serialize();
 
         // 4) Send POST request with the AuthToken in the body
-        let response = self
+        let request = self
             .cross_request(Method::POST, url)
             .await
-            .body(request_body)
-            .send()
-            .await?;
+            .body(request_body);
+        let response = self.send(request).await?;
 
         // 5) Check for non-2xx status codes
         handle_http_error!(response);
@@ -76,11 +75,10 @@ impl Client {
     /// Returns None  if not signed in, or [reqwest::Error]
     /// if the response has any other `>=404` status code.
     pub async fn session(&self, pubky: &PublicKey) -> Result<Option<Session>> {
-        let response = self
+        let request = self
             .cross_request(Method::GET, format!("pubky://{}/session", pubky))
-            .await
-            .send()
-            .await?;
+            .await;
+        let response = self.send(request).await?;
 
         if response.status() == StatusCode::NOT_FOUND {
             return Ok(None);
@@ -95,11 +93,10 @@ impl Client {
 
     /// Signout from a homeserver.
     pub async fn signout(&self, pubky: &PublicKey) -> Result<()> {
-        let response = self
+        let request = self
             .cross_request(Method::DELETE, format!("pubky://{}/session", pubky))
-            .await
-            .send()
-            .await?;
+            .await;
+        let response = self.send(request).await?;
 
         handle_http_error!(response);
 
@@ -209,12 +206,11 @@ impl Client {
         path_segments.push(&channel_id);
         drop(path_segments);
 
-        let response = self
+        let request = self
             .cross_request(Method::POST, callback_url)
             .await
-            .body(encrypted_token)
-            .send()
-            .await?;
+            .body(encrypted_token);
+        let response = self.send(request).await?;
 
         handle_http_error!(response);
 
@@ -222,12 +218,11 @@ impl Client {
     }
 
     pub(crate) async fn signin_with_authtoken(&self, token: &AuthToken) -> Result<Session> {
-        let response = self
+        let request = self
             .cross_request(Method::POST, format!("pubky://{}/session", token.pubky()))
             .await
-            .body(token.serialize())
-            .send()
-            .await?;
+            .body(token.serialize());
+        let response = self.send(request).await?;
 
         handle_http_error!(response);
 
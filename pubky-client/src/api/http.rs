@@ -19,10 +19,33 @@ impl Client {
     /// 2. Pubky URLs like `pubky://o4dksfbqk85ogzdb5osziw6befigbuxmuxkuxq8434q89uj56uyy`
     ///    by converting the url into `https://_pubky.o4dksfbqk85ogzdb5osziw6befigbuxmuxkuxq8434q89uj56uyy`
     ///
+    /// With the `http3` feature, homeservers that advertise HTTP/3 are requested over QUIC
+    /// once their `SVCB(HTTPS)` record is resolved.
+    ///
+    /// Send the request with [Client::send] to retry rate limited requests as configured by
+    /// [crate::ClientBuilder::rate_limit_backoff]. `RequestBuilder::send` sends it only once.
+    /// The same applies to [Client::get], [Client::put] and the other convenience methods.
+    ///
     /// # Errors
     ///
     /// This method fails whenever the supplied `Url` cannot be parsed.
     pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        #[cfg(feature = "http3")]
+        {
+            let pkarr_url = url
+                .as_str()
+                .strip_prefix("pubky://")
+                .map(|rest| format!("https://_pubky.{rest}"));
+            let pkarr_url = pkarr_url.as_deref().unwrap_or(url.as_str());
+            if let Some(request) = self.http3_request(&method, pkarr_url) {
+                return request;
+            }
+        }
+        self.tcp_request(method, url)
+    }
+
+    /// Start building a `Request` over TCP. [Client::send] decides whether to use HTTP/3.
+    fn tcp_request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
         let url = url.as_str();
 
         if url.starts_with("pubky://") {
@@ -131,7 +154,7 @@ impl Client {
     // === Private Methods ===
 
     pub(crate) async fn cross_request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
-        self.request(method, url)
+        self.tcp_request(method, url)
     }
 
     pub async fn prepare_request(&self, _url: &mut Url) -> Option<String> {
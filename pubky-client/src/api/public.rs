@@ -7,7 +7,7 @@ impl Client {
     /// Returns a [ListBuilder] to help pass options before calling [ListBuilder::send].
     ///
     /// `url` sets the path you want to lest within.
-    pub fn list<T: IntoUrl>(&self, url: T) -> Result<ListBuilder> {
+    pub fn list<T: IntoUrl>(&self, url: T) -> Result<ListBuilder<'_>> {
         Ok(ListBuilder::new(self, url))
     }
 }
@@ -100,12 +100,8 @@ impl<'a> ListBuilder<'a> {
 
         drop(query);
 
-        let response = self
-            .client
-            .cross_request(Method::GET, url)
-            .await
-            .send()
-            .await?;
+        let request = self.client.cross_request(Method::GET, url).await;
+        let response = self.client.send(request).await?;
 
         handle_http_error!(response);
 
@@ -0,0 +1,294 @@
+//! Resumable uploads of large files.
+
+use std::io::SeekFrom;
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use futures_util::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+use pubky_common::{
+    constants::headers::{CONTENT_HASH, UPLOAD_ID, UPLOAD_LENGTH, UPLOAD_OFFSET},
+    crypto::{Hash, Hasher},
+};
+use reqwest::{IntoUrl, Method, Response, StatusCode, header::ETAG};
+
+use crate::{Client, cross_debug, handle_http_error};
+
+/// Default size of the chunks of a resumable upload.
+pub const DEFAULT_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Default number of consecutive failed chunks before the upload gives up.
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+impl Client {
+    /// Returns an [UploadBuilder] to upload `content` to `url` in chunks.
+    ///
+    /// Unlike a single `PUT`, a failed chunk doesn't restart the whole upload.
+    /// Use it for large files or unreliable connections.
+    ///
+    /// `content` is read one chunk at a time, so only a chunk is kept in memory.
+    /// Pass a file, e.g. a `tokio::fs::File` wrapped with `tokio_util::compat`,
+    /// or a [futures_util::io::Cursor] for content that is already in memory.
+    pub fn upload<T, R>(&self, url: T, content: R) -> UploadBuilder<'_, R>
+    where
+        T: IntoUrl,
+        R: AsyncRead + AsyncSeek + Unpin,
+    {
+        UploadBuilder::new(self, url, content)
+    }
+}
+
+/// Helper struct to edit the options of a resumable upload before starting it.
+#[derive(Debug)]
+pub struct UploadBuilder<'a, R> {
+    client: &'a Client,
+    url: String,
+    content: R,
+    chunk_size: usize,
+    max_retries: u32,
+    upload_id: Option<String>,
+}
+
+impl<'a, R: AsyncRead + AsyncSeek + Unpin> UploadBuilder<'a, R> {
+    pub(crate) fn new<T: IntoUrl>(client: &'a Client, url: T, content: R) -> Self {
+        Self {
+            client,
+            url: url.as_str().to_string(),
+            content,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+            upload_id: None,
+        }
+    }
+
+    /// Set the size of the chunks in bytes. Defaults to [DEFAULT_CHUNK_SIZE].
+    ///
+    /// Must be smaller than the body limit of the homeserver.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Set how many consecutive chunks may fail before the upload gives up.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Resume a previous upload, for example after a restart, instead of creating a new one.
+    ///
+    /// The content must be the same as in the previous upload.
+    pub fn upload_id(mut self, upload_id: impl Into<String>) -> Self {
+        self.upload_id = Some(upload_id.into());
+        self
+    }
+
+    /// Create the upload on the homeserver, or look up where to continue
+    /// if [UploadBuilder::upload_id] is set.
+    ///
+    /// Store [Upload::id] to be able to resume the upload later.
+    ///
+    /// Reads the content once to hash it.
+    pub async fn start(mut self) -> Result<Upload<'a, R>> {
+        let length = self.content.seek(SeekFrom::End(0)).await?;
+        let content_hash = hash_content(&mut self.content, self.chunk_size).await?;
+        let mut upload = Upload {
+            client: self.client,
+            url: self.url,
+            content: self.content,
+            length,
+            content_hash,
+            chunk_size: self.chunk_size,
+            max_retries: self.max_retries,
+            id: String::new(),
+            offset: 0,
+            completed: false,
+        };
+
+        match self.upload_id {
+            Some(id) => {
+                upload.id = id;
+                // An empty chunk returns the current offset.
+                upload.patch(Bytes::new()).await?;
+            }
+            None => {
+                let request = self
+                    .client
+                    .cross_request(Method::POST, upload.url.as_str())
+                    .await
+                    .header(UPLOAD_LENGTH, upload.length)
+                    .header(CONTENT_HASH, upload.content_hash.to_hex().as_str());
+                let response = self.client.send(request).await?;
+
+                handle_http_error!(response);
+
+                upload.id = response
+                    .headers()
+                    .get(UPLOAD_ID)
+                    .and_then(|value| value.to_str().ok())
+                    .ok_or(anyhow!("Missing {UPLOAD_ID} header in response"))?
+                    .to_string();
+            }
+        }
+
+        Ok(upload)
+    }
+
+    /// Upload the whole content.
+    ///
+    /// Shorthand for [UploadBuilder::start] followed by [Upload::finish].
+    pub async fn send(self) -> Result<()> {
+        self.start().await?.finish().await
+    }
+}
+
+/// A resumable upload in progress.
+#[derive(Debug)]
+pub struct Upload<'a, R> {
+    client: &'a Client,
+    url: String,
+    content: R,
+    length: u64,
+    content_hash: Hash,
+    chunk_size: usize,
+    max_retries: u32,
+    id: String,
+    offset: u64,
+    completed: bool,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> Upload<'_, R> {
+    /// The id of the upload. Pass it to [UploadBuilder::upload_id] to resume the upload.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The number of bytes the homeserver received so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Whether the homeserver received all bytes and created the file.
+    pub fn is_complete(&self) -> bool {
+        self.completed
+    }
+
+    /// Send the next chunk.
+    ///
+    /// Returns the number of bytes the homeserver received so far.
+    pub async fn send_chunk(&mut self) -> Result<u64> {
+        let chunk = self.read_chunk().await?;
+        self.patch(chunk).await?;
+        Ok(self.offset)
+    }
+
+    /// Read the chunk at the current offset.
+    async fn read_chunk(&mut self) -> Result<Bytes> {
+        let offset = self.offset.min(self.length);
+        let size = (self.length - offset).min(self.chunk_size as u64) as usize;
+        self.content.seek(SeekFrom::Start(offset)).await?;
+        let mut chunk = vec![0; size];
+        self.content.read_exact(&mut chunk).await?;
+        Ok(chunk.into())
+    }
+
+    /// Send the remaining chunks until the file is created.
+    ///
+    /// After a failed chunk, the upload continues from where the homeserver left off.
+    pub async fn finish(mut self) -> Result<()> {
+        let mut failures = 0;
+        while !self.completed {
+            match self.send_chunk().await {
+                Ok(_) => failures = 0,
+                Err(error) => {
+                    failures += 1;
+                    if failures > self.max_retries {
+                        return Err(error);
+                    }
+                    cross_debug!(
+                        "Upload {} failed at offset {}: {}. Retrying.",
+                        self.id,
+                        self.offset,
+                        error
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send `chunk` at the current offset and update the offset from the response.
+    async fn patch(&mut self, chunk: Bytes) -> Result<()> {
+        let request = self
+            .client
+            .cross_request(Method::PATCH, self.url.as_str())
+            .await
+            .header(UPLOAD_ID, self.id.as_str())
+            .header(UPLOAD_OFFSET, self.offset)
+            .body(chunk);
+        let response = self.client.send(request).await?;
+
+        // The homeserver has a different offset, continue from there.
+        if response.status() == StatusCode::CONFLICT {
+            self.offset = response_offset(&response)?;
+            return Ok(());
+        }
+
+        // The homeserver deletes the upload once the file is created. If the response
+        // to the last chunk got lost, the retry or resume doesn't find the upload anymore.
+        if response.status() == StatusCode::NOT_FOUND && self.is_created().await? {
+            self.offset = self.length;
+            self.completed = true;
+            return Ok(());
+        }
+
+        handle_http_error!(response);
+
+        self.offset = response_offset(&response)?;
+        self.completed = response.status() == StatusCode::CREATED;
+        Ok(())
+    }
+
+    /// Whether the file exists with the content of this upload.
+    async fn is_created(&self) -> Result<bool> {
+        let request = self
+            .client
+            .cross_request(Method::HEAD, self.url.as_str())
+            .await;
+        let response = self.client.send(request).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        handle_http_error!(response);
+        let etag = format!("\"{}\"", self.content_hash.to_hex());
+        Ok(response
+            .headers()
+            .get(ETAG)
+            .is_some_and(|value| value.as_bytes() == etag.as_bytes()))
+    }
+}
+
+/// Hash the whole content, one chunk at a time.
+async fn hash_content<R: AsyncRead + AsyncSeek + Unpin>(
+    content: &mut R,
+    chunk_size: usize,
+) -> Result<Hash> {
+    content.seek(SeekFrom::Start(0)).await?;
+    let mut hasher = Hasher::new();
+    let mut buffer = vec![0; chunk_size.min(DEFAULT_CHUNK_SIZE)];
+    loop {
+        let read = content.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+fn response_offset(response: &Response) -> Result<u64> {
+    response
+        .headers()
+        .get(UPLOAD_OFFSET)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or(anyhow!("Missing {UPLOAD_OFFSET} header in response"))
+}
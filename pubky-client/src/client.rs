@@ -6,8 +6,14 @@ use super::internal::cookies::CookieJar;
 use std::sync::Arc;
 use std::time::Duration;
 
+use super::internal::rate_limit::RateLimitBackoff;
+
 static DEFAULT_USER_AGENT: &str = concat!("pubky.org", "@", env!("CARGO_PKG_VERSION"),);
 static DEFAULT_RELAYS: &[&str] = &["https://pkarr.pubky.org/", "https://pkarr.pubky.app/"];
+/// ALPN protocols offered to homeservers over TCP, most preferred first.
+/// `h3` is only offered on QUIC connections, see [crate::internal::http3].
+#[cfg(not(target_arch = "wasm32"))]
+static ALPN_PROTOCOLS: &[&[u8]] = &[b"h2", b"http/1.1"];
 
 #[derive(Debug, Default, Clone)]
 pub struct ClientBuilder {
@@ -19,6 +25,8 @@ pub struct ClientBuilder {
     /// The hostname to use for testnet URL transformations (WASM only).
     #[cfg(target_arch = "wasm32")]
     testnet_host: Option<String>,
+    /// Retry rate limited requests.
+    rate_limit_backoff: Option<RateLimitBackoff>,
 }
 
 impl ClientBuilder {
@@ -91,6 +99,23 @@ impl ClientBuilder {
         self
     }
 
+    /// Retry requests that were rejected with `429 Too Many Requests`
+    /// up to `max_retries` times, after the `Retry-After` delay of the homeserver.
+    ///
+    /// Gives up if the homeserver asks to wait longer than `max_wait`.
+    /// Disabled by default. Not supported in WASM.
+    ///
+    /// Applies to the Pubky API methods and to requests sent with [Client::send], like
+    /// `client.send(client.get(url)).await`. Calling `send` on the request builder directly,
+    /// like `client.get(url).send().await`, doesn't retry.
+    pub fn rate_limit_backoff(&mut self, max_retries: u32, max_wait: Duration) -> &mut Self {
+        self.rate_limit_backoff = Some(RateLimitBackoff {
+            max_retries,
+            max_wait,
+        });
+        self
+    }
+
     /// Build [Client]
     pub fn build(&self) -> Result<Client, BuildError> {
         let pkarr = self.pkarr.build()?;
@@ -101,8 +126,30 @@ impl ClientBuilder {
         // TODO: allow custom user agent, but force a Pubky user agent information
         let user_agent = DEFAULT_USER_AGENT;
 
+        // Same as `reqwest::ClientBuilder::from(pkarr)`, but offering HTTP/2 to homeservers.
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut tls = rustls::ClientConfig::from(pkarr.clone());
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            tls.alpn_protocols = ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect();
+        }
+
+        // reqwest uses the same TLS config for QUIC, so HTTP/3 needs its own client.
+        #[cfg(all(feature = "http3", not(target_arch = "wasm32")))]
+        let mut http3_builder = {
+            let mut tls = tls.clone();
+            tls.alpn_protocols = vec![super::internal::http3::ALPN_H3.to_vec()];
+            reqwest::Client::builder()
+                .dns_resolver(Arc::new(pkarr.clone()))
+                .use_preconfigured_tls(tls)
+                .cookie_provider(cookie_store.clone())
+                .user_agent(user_agent)
+        };
+
         #[cfg(not(target_arch = "wasm32"))]
-        let mut http_builder = reqwest::ClientBuilder::from(pkarr.clone())
+        let mut http_builder = reqwest::Client::builder()
+            .dns_resolver(Arc::new(pkarr.clone()))
+            .use_preconfigured_tls(tls)
             // TODO: use persistent cookie jar
             .cookie_provider(cookie_store.clone())
             .user_agent(user_agent);
@@ -120,6 +167,10 @@ impl ClientBuilder {
         #[cfg(not(target_arch = "wasm32"))]
         if let Some(timeout) = self.http_request_timeout {
             http_builder = http_builder.timeout(timeout);
+            #[cfg(feature = "http3")]
+            {
+                http3_builder = http3_builder.timeout(timeout);
+            }
 
             icann_http_builder = icann_http_builder.timeout(timeout);
         }
@@ -144,6 +195,13 @@ impl ClientBuilder {
 
             #[cfg(target_arch = "wasm32")]
             testnet_host: self.testnet_host.clone(),
+
+            rate_limit_backoff: self.rate_limit_backoff,
+
+            #[cfg(all(feature = "http3", not(target_arch = "wasm32")))]
+            http3: http3_builder.build().expect("config expected to not error"),
+            #[cfg(all(feature = "http3", not(target_arch = "wasm32")))]
+            http3_hosts: Default::default(),
         })
     }
 }
@@ -173,6 +231,17 @@ pub struct Client {
     /// The hostname to use for testnet URL transformations (WASM only).
     #[cfg(target_arch = "wasm32")]
     pub(crate) testnet_host: Option<String>,
+
+    /// Retry rate limited requests.
+    pub(crate) rate_limit_backoff: Option<RateLimitBackoff>,
+
+    /// Sends the requests over HTTP/3.
+    #[cfg(all(feature = "http3", not(target_arch = "wasm32")))]
+    pub(crate) http3: reqwest::Client,
+
+    /// The HTTP/3 support of the homeservers.
+    #[cfg(all(feature = "http3", not(target_arch = "wasm32")))]
+    pub(crate) http3_hosts: super::internal::http3::SharedHttp3Hosts,
 }
 
 impl Client {
@@ -0,0 +1,197 @@
+//! Preferring HTTP/3 for homeservers that advertise it in their `SVCB(HTTPS)` record.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use pkarr::{PublicKey, dns::rdata::SVCB};
+use reqwest::{Method, RequestBuilder, Response, Version};
+use url::Url;
+
+use crate::Client;
+
+/// What a [Client] learned about the HTTP/3 support of homeservers.
+#[derive(Debug, Default)]
+pub(crate) struct Http3Hosts {
+    /// The HTTP/3 port of each resolved host, `None` if it doesn't advertise HTTP/3
+    /// or its QUIC connection failed, for example because UDP is blocked.
+    ///
+    /// Failed hosts are not tried over HTTP/3 again by the same [Client].
+    ports: HashMap<String, Option<u16>>,
+}
+
+/// Shared between the clones of a [Client].
+pub(crate) type SharedHttp3Hosts = Arc<RwLock<Http3Hosts>>;
+
+/// ALPN id of HTTP/3.
+pub(crate) const ALPN_H3: &[u8] = b"h3";
+
+/// Returns true if the wire format `alpn` param contains `h3`.
+///
+/// The param is a list of ids, each prefixed with its length.
+fn contains_h3(mut alpn: &[u8]) -> bool {
+    while let Some((&len, rest)) = alpn.split_first() {
+        let Some((id, rest)) = rest.split_at_checked(len as usize) else {
+            return false;
+        };
+        if id == ALPN_H3 {
+            return true;
+        }
+        alpn = rest;
+    }
+    false
+}
+
+/// Returns true if `host` ends with a Pkarr public key.
+fn is_pkarr(host: &str) -> bool {
+    host.rsplit('.')
+        .next()
+        .is_some_and(|tld| PublicKey::try_from(tld).is_ok())
+}
+
+/// Switch `request` to HTTP/3 on `port`.
+fn set_http3(request: &mut reqwest::Request, port: u16) {
+    *request.version_mut() = Version::HTTP_3;
+    // Unlike over TCP, reqwest connects to the port of the url instead of the resolved one.
+    if request.url().port().is_none() {
+        let _ = request.url_mut().set_port(Some(port));
+    }
+}
+
+impl Client {
+    /// The known HTTP/3 port of `host`. `None` if it is unknown yet.
+    fn known_http3_port(&self, host: &str) -> Option<Option<u16>> {
+        self.http3_hosts
+            .read()
+            .expect("http3 hosts lock poisoned")
+            .ports
+            .get(host)
+            .copied()
+    }
+
+    fn set_http3_port(&self, host: &str, port: Option<u16>) {
+        self.http3_hosts
+            .write()
+            .expect("http3 hosts lock poisoned")
+            .ports
+            .insert(host.to_string(), port);
+    }
+
+    /// Returns the port of the endpoint of `host` if it is a Pkarr domain that advertises HTTP/3.
+    async fn http3_port(&self, host: &str) -> Option<u16> {
+        if !is_pkarr(host) {
+            return None;
+        }
+        if let Some(port) = self.known_http3_port(host) {
+            return port;
+        }
+        let port = resolve_http3_port(&self.pkarr, host).await;
+        self.set_http3_port(host, port);
+        port
+    }
+
+    /// Start a request over HTTP/3 if the host of `url` is known to advertise it.
+    ///
+    /// Unknown Pkarr hosts are resolved in the background, so the following requests use HTTP/3.
+    /// Unlike [Client::send], the request is not retried over TCP if the QUIC connection fails.
+    pub(crate) fn http3_request(&self, method: &Method, url: &str) -> Option<RequestBuilder> {
+        let url = Url::parse(url).ok().filter(|url| url.scheme() == "https")?;
+        let host = url.host_str().filter(|host| is_pkarr(host))?.to_string();
+        match self.known_http3_port(&host) {
+            Some(Some(port)) => {
+                let mut request = reqwest::Request::new(method.clone(), url);
+                set_http3(&mut request, port);
+                Some(RequestBuilder::from_parts(self.http3.clone(), request))
+            }
+            Some(None) => None,
+            None => {
+                let runtime = tokio::runtime::Handle::try_current().ok()?;
+                let client = self.clone();
+                runtime.spawn(async move {
+                    client.http3_port(&host).await;
+                });
+                None
+            }
+        }
+    }
+
+    /// Send a request over HTTP/3 if the homeserver advertises it.
+    ///
+    /// Falls back to TCP if the request fails before a response, for example because
+    /// UDP is blocked, and the request can be cloned.
+    pub(crate) async fn send_preferring_http3(
+        &self,
+        request: RequestBuilder,
+    ) -> reqwest::Result<Response> {
+        let (client, request) = request.build_split();
+        let mut request = request?;
+        let host = match request.url().host_str() {
+            Some(host) if request.version() == Version::HTTP_11 => host.to_string(),
+            // Explicitly chosen versions are respected.
+            _ => return client.execute(request).await,
+        };
+        let Some(port) = self.http3_port(&host).await else {
+            return client.execute(request).await;
+        };
+
+        let fallback = request.try_clone();
+        set_http3(&mut request, port);
+        match self.http3.execute(request).await {
+            Err(e) => {
+                let Some(fallback) = fallback else {
+                    return Err(e);
+                };
+                crate::cross_debug!("HTTP/3 request to {host} failed, falling back to TCP: {e}");
+                self.set_http3_port(&host, None);
+                client.execute(fallback).await
+            }
+            response => response,
+        }
+    }
+}
+
+/// Resolve the HTTP/3 port of `host` from its `SVCB(HTTPS)` record.
+async fn resolve_http3_port(pkarr: &pkarr::Client, host: &str) -> Option<u16> {
+    let endpoint = pkarr.resolve_https_endpoint(host).await.ok()?;
+    if !endpoint.get_param(SVCB::ALPN).is_some_and(contains_h3) {
+        return None;
+    }
+    endpoint.port()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains_h3() {
+        assert!(contains_h3(b"\x02h3\x02h2\x08http/1.1"));
+        assert!(contains_h3(b"\x02h2\x02h3"));
+        assert!(!contains_h3(b"\x02h2\x08http/1.1"));
+        assert!(!contains_h3(b"\x03h3"));
+        assert!(!contains_h3(b""));
+    }
+
+    #[tokio::test]
+    async fn test_http3_request_to_known_hosts() {
+        let client = Client::builder().build().unwrap();
+        let host = format!("_pubky.{}", pkarr::Keypair::random().public_key());
+        let url = format!("https://{host}/pub/file");
+
+        client.set_http3_port(&host, Some(6287));
+        let request = client.request(Method::GET, &url).build().unwrap();
+        assert_eq!(request.version(), Version::HTTP_3);
+        assert_eq!(request.url().port(), Some(6287));
+        // Requests sent by the client itself decide in `send`, so they can fall back to TCP.
+        let request = client
+            .cross_request(Method::GET, &url)
+            .await
+            .build()
+            .unwrap();
+        assert_eq!(request.version(), Version::HTTP_11);
+
+        client.set_http3_port(&host, None);
+        let request = client.request(Method::GET, &url).build().unwrap();
+        assert_eq!(request.version(), Version::HTTP_11);
+        assert_eq!(request.url().port(), None);
+    }
+}
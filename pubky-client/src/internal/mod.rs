@@ -1,3 +1,6 @@
 #[cfg(not(target_arch = "wasm32"))]
 pub mod cookies;
+#[cfg(all(feature = "http3", not(target_arch = "wasm32")))]
+pub mod http3;
 pub mod pkarr;
+pub mod rate_limit;
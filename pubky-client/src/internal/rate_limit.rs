@@ -0,0 +1,97 @@
+//! Handling of `429 Too Many Requests` responses of rate limited homeservers.
+
+use std::time::Duration;
+
+use reqwest::{RequestBuilder, Response, StatusCode, header::RETRY_AFTER};
+
+use crate::Client;
+
+/// The homeserver rejected a request with `429 Too Many Requests`.
+///
+/// Returned inside the [anyhow::Error] of the Pubky API methods. Use
+/// `error.downcast_ref::<RateLimitedError>()` to find out when to retry.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("429 Too Many Requests. Retry after {retry_after:?}. Error message: {message}")]
+pub struct RateLimitedError {
+    /// When the request is allowed again, if the homeserver said so.
+    pub retry_after: Option<Duration>,
+    /// The body of the response.
+    pub message: String,
+}
+
+/// Automatic retries of rate limited requests. See [crate::ClientBuilder::rate_limit_backoff].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RateLimitBackoff {
+    pub max_retries: u32,
+    pub max_wait: Duration,
+}
+
+/// The `Retry-After` header of a response.
+///
+/// Only the delay in seconds is supported, not HTTP dates.
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+impl Client {
+    /// Send a request.
+    ///
+    /// If [crate::ClientBuilder::rate_limit_backoff] is set, rate limited requests are retried
+    /// after the `Retry-After` delay, or with an exponential backoff starting at 1 second
+    /// if the homeserver didn't send one. Requests with a streaming body are not retried.
+    ///
+    /// With the `http3` feature, homeservers that advertise HTTP/3 are requested over QUIC.
+    pub async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(backoff) = self.rate_limit_backoff {
+            return self.send_with_backoff(request, backoff).await;
+        }
+        self.send_once(request).await
+    }
+
+    #[cfg(all(feature = "http3", not(target_arch = "wasm32")))]
+    async fn send_once(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        self.send_preferring_http3(request).await
+    }
+
+    #[cfg(not(all(feature = "http3", not(target_arch = "wasm32"))))]
+    async fn send_once(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        request.send().await
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn send_with_backoff(
+        &self,
+        mut request: RequestBuilder,
+        backoff: RateLimitBackoff,
+    ) -> reqwest::Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let retry = request.try_clone();
+            let response = self.send_once(request).await?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS || attempt >= backoff.max_retries
+            {
+                return Ok(response);
+            }
+            let Some(retry) = retry else {
+                return Ok(response);
+            };
+            let wait = retry_after(&response).unwrap_or(Duration::from_secs(1 << attempt.min(16)));
+            if wait > backoff.max_wait {
+                return Ok(response);
+            }
+            crate::cross_debug!("Rate limited. Retrying in {wait:?}.");
+            tokio::time::sleep(wait).await;
+            request = retry;
+            attempt += 1;
+        }
+    }
+}
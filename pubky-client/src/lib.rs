@@ -13,7 +13,11 @@ pub mod internal;
 mod macros;
 pub use client::*;
 
-pub use api::{auth::AuthRequest, public::ListBuilder};
+pub use api::{
+    auth::AuthRequest,
+    public::ListBuilder,
+    upload::{Upload, UploadBuilder},
+};
 pub use client::Client;
 pub use client::ClientBuilder;
 
@@ -13,6 +13,14 @@ macro_rules! cross_debug {
 #[macro_export]
 macro_rules! handle_http_error {
     ($res:expr) => {
+        if $res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = $crate::internal::rate_limit::retry_after(&$res);
+            return Err($crate::internal::rate_limit::RateLimitedError {
+                retry_after,
+                message: $res.text().await.unwrap_or_default(),
+            }
+            .into());
+        }
         if let Err(status) = $res.error_for_status_ref() {
             return match $res.text().await {
                 Ok(text) => Err(anyhow::anyhow!("{status}. Error message: {text}")),
@@ -16,3 +16,16 @@ pub mod testnet_ports {
     /// The local test network's hardcorded HTTP Relay port number.
     pub const HTTP_RELAY: u16 = 15412;
 }
+
+/// HTTP headers used between the homeserver and its clients.
+pub mod headers {
+    /// Optional header on PUT requests with the hex encoded Blake3 hash of the body.
+    /// The homeserver rejects the upload if the received content doesn't match.
+    pub const CONTENT_HASH: &str = "x-content-hash";
+    /// Total size in bytes of a resumable upload. Sent when the upload is created.
+    pub const UPLOAD_LENGTH: &str = "upload-length";
+    /// The id of a resumable upload.
+    pub const UPLOAD_ID: &str = "upload-id";
+    /// The number of bytes of a resumable upload the homeserver already received.
+    pub const UPLOAD_OFFSET: &str = "upload-offset";
+}
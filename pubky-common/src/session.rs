@@ -49,6 +49,11 @@ impl Session {
         &self.capabilities
     }
 
+    /// Returns the time this session was created at, in microseconds since the Unix epoch.
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
     // === Setters ===
 
     /// Set this session user agent.
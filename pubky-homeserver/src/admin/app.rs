@@ -1,56 +1,121 @@
-use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::Duration;
 
 use super::routes::{
-    delete_entry,
+    admin_keys, audit_log, blocklist, delete_entry, delete_user,
     disable_users::{disable_user, enable_user},
-    generate_signup_token, info, root,
+    generate_signup_token, health, info, metrics, rate_limits, reconcile_storage, reload_config,
+    root, scrub_storage, storage_migration, user_archive, user_tier, users,
 };
 use super::trace::with_trace_layer;
 use super::{app_state::AppState, auth_middleware::AdminAuthLayer};
+use crate::core::layers::global_limits::track_writes;
+use crate::core::unix_socket::{ServerHandle, UnixSocketServer, GRACEFUL_SHUTDOWN};
+use crate::core::VirtualHosts;
+use crate::shared::metrics::track_http_metrics;
 #[cfg(any(test, feature = "testing"))]
 use crate::MockDataDir;
 use crate::{admin::routes::dav_handler, app_context::AppContext};
-use crate::{AppContextConversionError, PersistentDataDir};
-use axum::routing::{any, delete, post};
+use crate::{AppContextConversionError, ListenAddr, PersistentDataDir};
+use axum::extract::DefaultBodyLimit;
+use axum::middleware;
+use axum::routing::{any, delete, post, put};
 use axum::{routing::get, Router};
 use axum_server::Handle;
 use tokio::task::JoinHandle;
 use tower_http::cors::CorsLayer;
 
-/// Admin password protected router.
-fn create_protected_router(password: &str) -> Router<AppState> {
+/// Router protected by the admin password or scoped admin keys.
+fn create_protected_router(state: &AppState) -> Router<AppState> {
     Router::new()
         .route(
             "/generate_signup_token",
             get(generate_signup_token::generate_signup_token),
         )
         .route("/info", get(info::info))
+        .route("/metrics", get(metrics::metrics))
         .route("/webdav/{*entry_path}", delete(delete_entry::delete_entry))
+        .route("/users", get(users::list_users))
+        .route(
+            "/users/{pubkey}",
+            get(users::get_user).delete(delete_user::delete_user),
+        )
+        .route("/users/{pubkey}/export", get(user_archive::export_user))
+        .route(
+            "/users/{pubkey}/import",
+            // Archives are only limited by the user quota.
+            post(user_archive::import_user).layer(DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/blocklist",
+            get(blocklist::list_blocked_content).post(blocklist::block_content),
+        )
+        .route("/blocklist/{hash}", delete(blocklist::unblock_content))
+        .route(
+            "/rate_limits",
+            get(rate_limits::get_rate_limits).put(rate_limits::set_rate_limits),
+        )
         .route("/users/{pubkey}/disable", post(disable_user))
         .route("/users/{pubkey}/enable", post(enable_user))
-        .layer(AdminAuthLayer::new(password.to_string()))
+        .route(
+            "/users/{pubkey}/tier",
+            put(user_tier::set_user_tier).delete(user_tier::delete_user_tier),
+        )
+        .route(
+            "/storage/reconcile",
+            post(reconcile_storage::reconcile_storage),
+        )
+        .route("/storage/scrub", post(scrub_storage::scrub_storage))
+        .route(
+            "/storage/migration",
+            get(storage_migration::get_storage_migration)
+                .post(storage_migration::start_storage_migration),
+        )
+        // Only the admin password can manage admin keys, read the audit log and reload the config.
+        .route("/config/reload", post(reload_config::reload_config))
+        .route("/audit_log", get(audit_log::list_audit_log))
+        .route("/audit_log/export", get(audit_log::export_audit_log))
+        .route(
+            "/admin_keys",
+            get(admin_keys::list_admin_keys).post(admin_keys::create_admin_key),
+        )
+        .route("/admin_keys/{id}", delete(admin_keys::delete_admin_key))
+        .layer(AdminAuthLayer::new(
+            state.admin_password.clone(),
+            state.db.clone(),
+        ))
 }
 
 /// Public router without any authentication.
 /// NO PASSWORD PROTECTION!
 fn create_public_router() -> Router<AppState> {
-    Router::new().route("/", get(root::root))
+    Router::new()
+        .route("/", get(root::root))
+        .route("/health/live", get(health::live))
+        .route("/health/ready", get(health::ready))
 }
 
 /// Create the app
-fn create_app(state: AppState, password: &str) -> axum::routing::IntoMakeService<Router> {
-    let admin_router = create_protected_router(password);
+fn create_app(state: AppState) -> Router {
+    let admin_router = create_protected_router(&state);
     let public_router = create_public_router();
     let app = Router::new()
         .merge(admin_router)
         .merge(public_router)
         .route("/dav{*path}", any(dav_handler::dav_handler))
+        // Drain the WebDAV writes and imports on shutdown like the uploads of the homeserver.
+        .layer(middleware::from_fn_with_state(
+            state.global_limits.clone(),
+            track_writes,
+        ))
+        .layer(middleware::from_fn_with_state(
+            (state.metrics.clone(), "admin"),
+            track_http_metrics,
+        ))
         .with_state(state)
         .layer(CorsLayer::very_permissive());
 
-    with_trace_layer(app).into_make_service()
+    with_trace_layer(app)
 }
 
 /// Errors that can occur when building a `AdminServer`.
@@ -71,9 +136,10 @@ pub enum AdminServerBuildError {
 ///
 /// When dropped, the server will stop.
 pub struct AdminServer {
-    http_handle: Handle,
-    join_handle: JoinHandle<()>,
-    socket: SocketAddr,
+    http_handle: ServerHandle,
+    /// Only set on a TCP socket. The Unix socket server stops itself when dropped.
+    join_handle: Option<JoinHandle<()>>,
+    socket: ListenAddr,
     password: String,
 }
 
@@ -99,10 +165,44 @@ impl AdminServer {
 
     /// Run the admin server.
     pub async fn start(context: &AppContext) -> Result<Self, AdminServerBuildError> {
+        Self::start_with_virtual_homeservers(context, &[], &VirtualHosts::default()).await
+    }
+
+    /// Run the admin server. The admin API of each virtual homeserver is served under
+    /// `/virtual/{public_key}`, protected by the admin password of the main homeserver.
+    /// Admin keys and the audit log are the ones in the LMDB of the virtual homeserver.
+    /// Users deleted there are removed from the `virtual_hosts` routing.
+    pub(crate) async fn start_with_virtual_homeservers(
+        context: &AppContext,
+        virtual_homeservers: &[AppContext],
+        virtual_hosts: &VirtualHosts,
+    ) -> Result<Self, AdminServerBuildError> {
         let password = context.config_toml.admin.admin_password.clone();
-        let state = AppState::new(context.db.clone(), context.file_service.clone(), &password);
-        let socket = context.config_toml.admin.listen_socket;
-        let app = create_app(state, password.as_str());
+        let state = AppState::new(context, &password);
+        let mut app = create_app(state);
+        for virtual_homeserver in virtual_homeservers {
+            let public_key = virtual_homeserver.keypair.public_key();
+            app = app.nest(
+                &format!("/virtual/{public_key}"),
+                virtual_hosts.track_deleted_users(
+                    &public_key,
+                    create_app(AppState::new(virtual_homeserver, &password)),
+                ),
+            );
+        }
+        let socket = match &context.config_toml.admin.listen_socket {
+            ListenAddr::Tcp(socket) => *socket,
+            ListenAddr::Unix(path) => {
+                let server =
+                    UnixSocketServer::start(path, app).map_err(AdminServerBuildError::Server)?;
+                return Ok(Self {
+                    http_handle: ServerHandle::Unix(server),
+                    join_handle: None,
+                    socket: ListenAddr::Unix(path.clone()),
+                    password,
+                });
+            }
+        };
         let listener = std::net::TcpListener::bind(socket)
             .map_err(|e| AdminServerBuildError::Server(e.into()))?;
         let socket = listener
@@ -113,26 +213,33 @@ impl AdminServer {
         let join_handle = tokio::spawn(async move {
             axum_server::from_tcp(listener)
                 .handle(inner_http_handle)
-                .serve(app)
+                .serve(app.into_make_service())
                 .await
                 .unwrap_or_else(|e| tracing::error!("Admin server error: {}", e));
         });
         Ok(Self {
-            http_handle,
-            socket,
-            join_handle,
+            http_handle: ServerHandle::Tcp(http_handle),
+            join_handle: Some(join_handle),
+            socket: ListenAddr::Tcp(socket),
             password,
         })
     }
 
-    /// Get the socket address of the admin server.
-    pub fn listen_socket(&self) -> SocketAddr {
-        self.socket
+    /// Stop accepting connections and give open ones `grace` to finish.
+    pub(crate) fn stop_accepting(&self, grace: Duration) {
+        self.http_handle.graceful_shutdown(grace);
+    }
+
+    /// Get the listen address of the admin server: a TCP socket or `unix:/path`.
+    pub fn listen_socket(&self) -> ListenAddr {
+        self.socket.clone()
     }
 
     /// Create a signup token for the given homeserver.
     pub async fn create_signup_token(&self) -> anyhow::Result<String> {
-        let admin_socket = self.listen_socket();
+        let Some(admin_socket) = self.socket.tcp() else {
+            anyhow::bail!("Creating signup tokens is not supported over a Unix domain socket");
+        };
         let url = format!("http://{}/generate_signup_token", admin_socket);
         let response = reqwest::Client::new()
             .get(url)
@@ -147,9 +254,10 @@ impl AdminServer {
 
 impl Drop for AdminServer {
     fn drop(&mut self) {
-        self.http_handle
-            .graceful_shutdown(Some(Duration::from_secs(5)));
-        self.join_handle.abort();
+        self.stop_accepting(GRACEFUL_SHUTDOWN);
+        if let Some(join_handle) = &self.join_handle {
+            join_handle.abort();
+        }
     }
 }
 
@@ -157,20 +265,12 @@ impl Drop for AdminServer {
 mod tests {
     use axum_test::TestServer;
 
-    use crate::persistence::files::FileService;
+    use crate::persistence::lmdb::tables::admin_keys::AdminScope;
 
     use super::*;
 
     fn create_test_server(context: &AppContext) -> TestServer {
-        TestServer::new(create_app(
-            AppState::new(
-                context.db.clone(),
-                FileService::new_from_context(context).unwrap(),
-                "",
-            ),
-            "test",
-        ))
-        .unwrap()
+        TestServer::new(create_app(AppState::new(context, "test"))).unwrap()
     }
 
     #[tokio::test]
@@ -179,6 +279,21 @@ mod tests {
         let server = create_test_server(&context);
         let response = server.get("/").expect_success().await;
         response.assert_status_ok();
+        assert!(response.text().contains("<title>Homeserver Admin</title>"));
+    }
+
+    #[tokio::test]
+    async fn test_health_without_password() {
+        let context = AppContext::test();
+        let server = create_test_server(&context);
+        server.get("/health/live").await.assert_status_ok();
+
+        let response = server.get("/health/ready").await;
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["checks"]["lmdb"]["status"], "ok");
+        assert_eq!(body["checks"]["storage"]["status"], "ok");
+        // Nothing was published in the test.
+        assert_eq!(body["checks"]["pkarr"]["status"], "degraded");
     }
 
     #[tokio::test]
@@ -209,4 +324,76 @@ mod tests {
             .await;
         response.assert_status_ok();
     }
+
+    #[tokio::test]
+    async fn test_admin_key_scopes() {
+        let context = AppContext::test();
+        let server = create_test_server(&context);
+        let (_, token) = context
+            .db
+            .create_admin_key("monitoring", vec![AdminScope::Stats], None)
+            .unwrap();
+        let bearer = format!("Bearer {token}");
+
+        server
+            .get("/info")
+            .add_header("Authorization", &bearer)
+            .expect_success()
+            .await;
+
+        // Missing scope
+        server
+            .get("/generate_signup_token")
+            .add_header("Authorization", &bearer)
+            .expect_failure()
+            .await
+            .assert_status_forbidden();
+
+        // Keys can't manage keys
+        server
+            .get("/admin_keys")
+            .add_header("Authorization", &bearer)
+            .expect_failure()
+            .await
+            .assert_status_forbidden();
+
+        // Invalid key
+        server
+            .get("/info")
+            .add_header("Authorization", "Bearer abc.def")
+            .expect_failure()
+            .await
+            .assert_status_unauthorized();
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_records_privileged_operations() {
+        let context = AppContext::test();
+        let server = create_test_server(&context);
+        server
+            .get("/generate_signup_token")
+            .add_header("X-Admin-Password", "test")
+            .expect_success()
+            .await;
+        // Plain reads are not persisted.
+        server
+            .get("/info")
+            .add_header("X-Admin-Password", "test")
+            .expect_success()
+            .await;
+        server
+            .post("/users/not_a_pubkey/disable")
+            .add_header("X-Admin-Password", "test")
+            .expect_failure()
+            .await;
+
+        let entries = context.db.list_audit_entries(None, None, false).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].1.actor, "password");
+        assert_eq!(entries[0].1.action, "GET /generate_signup_token");
+        assert_eq!(entries[0].1.outcome, 200);
+        assert_eq!(entries[1].1.action, "POST /users/{pubkey}/disable");
+        assert_eq!(entries[1].1.target, "/users/not_a_pubkey/disable");
+        assert_eq!(entries[1].1.outcome, 400);
+    }
 }
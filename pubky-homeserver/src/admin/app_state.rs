@@ -1,29 +1,58 @@
+use std::sync::Arc;
+
 use dav_server::DavHandler;
 use dav_server_opendalfs::OpendalFs;
 
-use crate::persistence::{files::FileService, lmdb::LmDB};
+use crate::{
+    core::layers::global_limits::GlobalLimits,
+    persistence::{
+        files::{FileService, StorageMigrator, StorageScrubber, UserDeleter},
+        lmdb::LmDB,
+    },
+    shared::{health::PublishStatus, metrics::Metrics},
+    AppContext, ConfigReloader, DataDir,
+};
 
 #[derive(Clone)]
 pub(crate) struct AppState {
     pub(crate) db: LmDB,
     pub(crate) file_service: FileService,
+    pub(crate) storage_migrator: StorageMigrator,
+    pub(crate) storage_scrubber: StorageScrubber,
+    pub(crate) user_deleter: UserDeleter,
+    pub(crate) metrics: Metrics,
+    pub(crate) publish_status: PublishStatus,
+    pub(crate) data_dir: Arc<dyn DataDir>,
+    pub(crate) config_reloader: ConfigReloader,
     pub(crate) admin_password: String,
-    pub(crate) inner_dav_handler: DavHandler,
+    pub(crate) global_limits: GlobalLimits,
 }
 
 impl AppState {
-    pub fn new(db: LmDB, file_service: FileService, admin_password: &str) -> Self {
-        let webdavfs = OpendalFs::new(file_service.opendal.operator.clone());
-        let inner_dav_handler = DavHandler::builder()
-            .filesystem(webdavfs)
-            .strip_prefix("/dav")
-            .autoindex(true)
-            .build_handler();
+    pub fn new(context: &AppContext, admin_password: &str) -> Self {
         Self {
-            db,
-            file_service,
+            db: context.db.clone(),
+            file_service: context.file_service.clone(),
+            storage_migrator: context.storage_migrator.clone(),
+            storage_scrubber: StorageScrubber::from_context(context),
+            user_deleter: UserDeleter::from_context(context),
+            metrics: context.metrics.clone(),
+            publish_status: context.publish_status.clone(),
+            data_dir: context.data_dir.clone(),
+            config_reloader: context.config_reloader.clone(),
             admin_password: admin_password.to_string(),
-            inner_dav_handler,
+            global_limits: context.global_limits.clone(),
         }
     }
+
+    /// Build the webdav handler on top of the current storage operator.
+    /// Built per request because the operator is swapped when the storage is migrated.
+    pub fn dav_handler(&self) -> DavHandler {
+        let webdavfs = OpendalFs::new(self.file_service.opendal.operator());
+        DavHandler::builder()
+            .filesystem(webdavfs)
+            .strip_prefix("/dav")
+            .autoindex(true)
+            .build_handler()
+    }
 }
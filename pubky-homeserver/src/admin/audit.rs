@@ -0,0 +1,59 @@
+//! Recording of privileged admin operations in the audit log.
+
+use axum::http::{Method, StatusCode};
+
+use super::auth_middleware::AdminActor;
+use crate::persistence::lmdb::LmDB;
+
+/// Whether a request changes state or hands out secrets or user data,
+/// and must be persisted in the audit log.
+///
+/// Plain reads like stats are only traced.
+pub(crate) fn is_audited(method: &Method, path: &str) -> bool {
+    let is_read = matches!(method.as_str(), "GET" | "HEAD" | "OPTIONS" | "PROPFIND");
+    !is_read || path == "/generate_signup_token" || path.ends_with("/export")
+}
+
+/// Trace the request and persist it in the audit log if it [is_audited].
+///
+/// `action` is the method with the matched route, `target` the concrete path.
+pub(crate) fn record(
+    db: &LmDB,
+    actor: &AdminActor,
+    method: &Method,
+    route: &str,
+    target: &str,
+    status: StatusCode,
+) {
+    let action = format!("{method} {route}");
+    tracing::info!(target: "admin_audit", "{actor} {action} {target} -> {status}");
+    if !is_audited(method, target) {
+        return;
+    }
+    if let Err(e) = db.append_audit_entry(&actor.to_string(), &action, target, status.as_u16()) {
+        tracing::error!("Failed to append to the audit log: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_audited() {
+        assert!(!is_audited(&Method::GET, "/info"));
+        assert!(!is_audited(&Method::GET, "/users/abc"));
+        assert!(is_audited(&Method::GET, "/generate_signup_token"));
+        assert!(is_audited(&Method::GET, "/users/abc/export"));
+        assert!(is_audited(&Method::POST, "/users/abc/disable"));
+        assert!(is_audited(&Method::DELETE, "/webdav/abc/pub/file.txt"));
+        assert!(is_audited(
+            &Method::from_bytes(b"MKCOL").unwrap(),
+            "/dav/abc/pub/dir"
+        ));
+        assert!(!is_audited(
+            &Method::from_bytes(b"PROPFIND").unwrap(),
+            "/dav/abc/pub/dir"
+        ));
+    }
+}
@@ -1,23 +1,70 @@
 // src/core/layers/admin_auth.rs
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    extract::MatchedPath,
+    http::{Method, Request, StatusCode},
     response::Response,
 };
 use futures_util::future::BoxFuture;
-use std::{convert::Infallible, task::Poll};
+use std::{convert::Infallible, fmt::Display, task::Poll};
 use tower::{Layer, Service};
 
-/// A Tower Layer that checks the “X-Admin-Password” header against a configured password.
+use super::audit;
+use crate::persistence::lmdb::{tables::admin_keys::AdminScope, LmDB};
+
+/// Who performed an admin request.
+///
+/// Added to the request extensions by the [AdminAuthMiddleware].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum AdminActor {
+    /// Authenticated with the admin password from the config.
+    Password,
+    /// Authenticated with a named admin API key.
+    Key { id: String, name: String },
+}
+
+impl Display for AdminActor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminActor::Password => write!(f, "password"),
+            AdminActor::Key { id, name } => write!(f, "key:{id} ({name})"),
+        }
+    }
+}
+
+/// The scope an admin key needs to access a route.
+/// `None` means the route is reserved for the admin password.
+pub(crate) fn required_scope(method: &Method, path: &str) -> Option<AdminScope> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let is_read = method == Method::GET || method == Method::HEAD;
+    match segments.as_slice() {
+        ["info"] | ["metrics"] => Some(AdminScope::Stats),
+        ["generate_signup_token"] => Some(AdminScope::SignupTokens),
+        ["users"] | ["users", _] if is_read => Some(AdminScope::Stats),
+        ["users", ..] => Some(AdminScope::Users),
+        ["webdav", ..] => Some(AdminScope::Webdav),
+        ["blocklist", ..] => Some(AdminScope::Moderation),
+        ["rate_limits"] if is_read => Some(AdminScope::Stats),
+        ["storage", "migration"] if is_read => Some(AdminScope::Stats),
+        ["storage", ..] => Some(AdminScope::Storage),
+        _ => None,
+    }
+}
+
+/// A Tower Layer that authenticates admin requests.
+///
+/// Accepts either the “X-Admin-Password” header with the configured password
+/// or an admin API key with the scope of the route as `Authorization: Bearer <key>`.
 #[derive(Clone)]
 pub struct AdminAuthLayer {
     password: String,
+    db: LmDB,
 }
 
 impl AdminAuthLayer {
     /// Create a new AdminAuthLayer with the given admin password.
-    pub fn new(password: String) -> Self {
-        Self { password }
+    pub fn new(password: String, db: LmDB) -> Self {
+        Self { password, db }
     }
 }
 
@@ -28,15 +75,63 @@ impl<S> Layer<S> for AdminAuthLayer {
         AdminAuthMiddleware {
             inner,
             password: self.password.clone(),
+            db: self.db.clone(),
         }
     }
 }
 
-/// Middleware that performs the admin password check.
+/// Middleware that performs the admin authentication.
 #[derive(Clone)]
 pub struct AdminAuthMiddleware<S> {
     inner: S,
     password: String,
+    db: LmDB,
+}
+
+impl<S> AdminAuthMiddleware<S> {
+    /// Authenticate the request and check that the actor may access the route.
+    fn authorize<ReqBody>(
+        &self,
+        req: &Request<ReqBody>,
+    ) -> Result<AdminActor, (StatusCode, &'static str)> {
+        if let Some(header_value) = req.headers().get("X-Admin-Password") {
+            return if header_value.to_str().unwrap_or("") == self.password {
+                Ok(AdminActor::Password)
+            } else {
+                Err((StatusCode::UNAUTHORIZED, "Invalid admin password"))
+            };
+        }
+
+        let token = match req
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            Some(token) => token,
+            None => return Err((StatusCode::UNAUTHORIZED, "Missing admin password")),
+        };
+
+        let key = match self.db.verify_admin_key(token) {
+            Ok(Some(key)) => key,
+            Ok(None) => return Err((StatusCode::UNAUTHORIZED, "Invalid or expired admin key")),
+            Err(e) => {
+                tracing::error!("Failed to verify admin key: {e}");
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"));
+            }
+        };
+
+        match required_scope(req.method(), req.uri().path()) {
+            Some(scope) if key.has_scope(scope) => Ok(AdminActor::Key {
+                id: key.id,
+                name: key.name,
+            }),
+            _ => Err((
+                StatusCode::FORBIDDEN,
+                "Admin key does not have the scope for this route",
+            )),
+        }
+    }
 }
 
 impl<S, ReqBody> Service<Request<ReqBody>> for AdminAuthMiddleware<S>
@@ -53,35 +148,80 @@ where
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
-        let password = self.password.clone();
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let actor = self.authorize(&req);
+        let db = self.db.clone();
         let mut inner = self.inner.clone();
 
         Box::pin(async move {
-            match req.headers().get("X-Admin-Password") {
-                Some(header_value) if header_value.to_str().unwrap_or("") == password => {
-                    // If the header is valid, proceed.
-                    inner.call(req).await
-                }
-                Some(_) => {
-                    // If header exists but password is incorrect,
-                    let msg = "Invalid admin password";
-                    let response = Response::builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .body(Body::from(msg))
-                        .unwrap_or_else(|_| Response::new(Body::from(msg)));
-                    Ok(response)
-                }
-                None => {
-                    // If header is missing, do the same.
-                    let msg = "Missing admin password";
-                    let response = Response::builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .body(Body::from(msg))
-                        .unwrap_or_else(|_| Response::new(Body::from(msg)));
-                    Ok(response)
-                }
-            }
+            let actor = match actor {
+                Ok(actor) => actor,
+                Err((status, msg)) => return Ok(error_response(status, msg)),
+            };
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let route = req
+                .extensions()
+                .get::<MatchedPath>()
+                .map(|route| route.as_str().to_string())
+                .unwrap_or_else(|| path.clone());
+            req.extensions_mut().insert(actor.clone());
+            let response = inner.call(req).await?;
+            audit::record(&db, &actor, &method, &route, &path, response.status());
+            Ok(response)
         })
     }
 }
+
+fn error_response(status: StatusCode, msg: &'static str) -> Response {
+    Response::builder()
+        .status(status)
+        .body(Body::from(msg))
+        .unwrap_or_else(|_| Response::new(Body::from(msg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(
+            required_scope(&Method::GET, "/info"),
+            Some(AdminScope::Stats)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/generate_signup_token"),
+            Some(AdminScope::SignupTokens)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/users/abc"),
+            Some(AdminScope::Stats)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/users/abc/export"),
+            Some(AdminScope::Users)
+        );
+        assert_eq!(
+            required_scope(&Method::DELETE, "/users/abc"),
+            Some(AdminScope::Users)
+        );
+        assert_eq!(
+            required_scope(&Method::DELETE, "/webdav/abc/pub/file.txt"),
+            Some(AdminScope::Webdav)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/storage/migration"),
+            Some(AdminScope::Stats)
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "/storage/migration"),
+            Some(AdminScope::Storage)
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "/blocklist"),
+            Some(AdminScope::Moderation)
+        );
+        assert_eq!(required_scope(&Method::GET, "/admin_keys"), None);
+    }
+}
@@ -1,8 +1,10 @@
 mod app;
 mod app_state;
+mod audit;
 mod auth_middleware;
 mod routes;
 mod trace;
 
 pub use app::AdminServer;
 pub use app::AdminServerBuildError;
+pub(crate) use routes::delete_user::DeletedUser;
@@ -0,0 +1,174 @@
+use super::super::app_state::AppState;
+use crate::{
+    persistence::lmdb::tables::admin_keys::{AdminKey, AdminScope},
+    shared::{HttpError, HttpResult},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use pkarr::Timestamp;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAdminKeyRequest {
+    name: String,
+    scopes: Vec<AdminScope>,
+    /// Seconds until the key expires. Never expires if not set.
+    expires_in_s: Option<u64>,
+}
+
+/// An admin key without its secret.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminKeyInfo {
+    id: String,
+    name: String,
+    scopes: Vec<AdminScope>,
+    created_at: u64,
+    expires_at: Option<u64>,
+    last_used_at: Option<u64>,
+}
+
+impl From<AdminKey> for AdminKeyInfo {
+    fn from(key: AdminKey) -> Self {
+        Self {
+            id: key.id,
+            name: key.name,
+            scopes: key.scopes,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateAdminKeyResponse {
+    #[serde(flatten)]
+    key: AdminKeyInfo,
+    /// Only returned once. Use it as `Authorization: Bearer <token>`.
+    token: String,
+}
+
+/// Create a named admin API key with the given scopes.
+///
+/// # Errors
+///
+/// - `400` if the name or the scopes are empty.
+///
+pub async fn create_admin_key(
+    State(state): State<AppState>,
+    Json(request): Json<CreateAdminKeyRequest>,
+) -> HttpResult<(StatusCode, Json<CreateAdminKeyResponse>)> {
+    if request.name.trim().is_empty() {
+        return Err(HttpError::bad_request("Name must not be empty"));
+    }
+    if request.scopes.is_empty() {
+        return Err(HttpError::bad_request("At least one scope is required"));
+    }
+    let expires_at = request.expires_in_s.map(|seconds| {
+        Timestamp::now()
+            .as_u64()
+            .saturating_add(seconds.saturating_mul(1_000_000))
+    });
+    let (key, token) = state
+        .db
+        .create_admin_key(&request.name, request.scopes, expires_at)?;
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateAdminKeyResponse {
+            key: key.into(),
+            token,
+        }),
+    ))
+}
+
+/// List all admin keys.
+pub async fn list_admin_keys(
+    State(state): State<AppState>,
+) -> HttpResult<(StatusCode, Json<Vec<AdminKeyInfo>>)> {
+    let keys = state
+        .db
+        .list_admin_keys()?
+        .into_iter()
+        .map(AdminKeyInfo::from)
+        .collect();
+    Ok((StatusCode::OK, Json(keys)))
+}
+
+/// Revoke an admin key.
+///
+/// # Errors
+///
+/// - `404` if the key does not exist.
+///
+pub async fn delete_admin_key(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> HttpResult<StatusCode> {
+    if !state.db.delete_admin_key(&id)? {
+        return Err(HttpError::new_with_message(
+            StatusCode::NOT_FOUND,
+            "Admin key not found",
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppContext;
+    use axum::response::IntoResponse;
+
+    #[tokio::test]
+    async fn test_admin_key_lifecycle() {
+        let context = AppContext::test();
+        let state = AppState::new(&context, "");
+
+        let (status, Json(created)) = create_admin_key(
+            State(state.clone()),
+            Json(CreateAdminKeyRequest {
+                name: "monitoring".to_string(),
+                scopes: vec![AdminScope::Stats],
+                expires_in_s: Some(3600),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(created.key.expires_at.is_some());
+        assert!(context
+            .db
+            .verify_admin_key(&created.token)
+            .unwrap()
+            .is_some());
+
+        let (_, Json(keys)) = list_admin_keys(State(state.clone())).await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].name, "monitoring");
+
+        let status = delete_admin_key(State(state.clone()), Path(created.key.id.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let error = delete_admin_key(State(state.clone()), Path(created.key.id))
+            .await
+            .unwrap_err();
+        assert_eq!(error.into_response().status(), StatusCode::NOT_FOUND);
+
+        // No scopes
+        let error = create_admin_key(
+            State(state),
+            Json(CreateAdminKeyRequest {
+                name: "nothing".to_string(),
+                scopes: vec![],
+                expires_in_s: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,158 @@
+use super::super::app_state::AppState;
+use crate::{
+    constants::DEFAULT_MAX_LIST_LIMIT,
+    persistence::lmdb::{tables::audit_log::AuditEntry, LmDB},
+    shared::HttpResult,
+};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ListAuditLogQuery {
+    cursor: Option<String>,
+    limit: Option<u16>,
+    /// Newest entries first.
+    #[serde(default)]
+    reverse: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditLogItem {
+    cursor: String,
+    #[serde(flatten)]
+    entry: AuditEntry,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListAuditLogResponse {
+    entries: Vec<AuditLogItem>,
+    /// Pass as `cursor` to get the next page. `None` if there are no entries left.
+    next_cursor: Option<String>,
+}
+
+/// List the audit log of privileged admin operations, oldest first.
+pub async fn list_audit_log(
+    State(state): State<AppState>,
+    Query(query): Query<ListAuditLogQuery>,
+) -> HttpResult<(StatusCode, Json<ListAuditLogResponse>)> {
+    let entries =
+        state
+            .db
+            .list_audit_entries(query.limit, query.cursor.as_deref(), query.reverse)?;
+    let next_cursor = entries.last().map(|(cursor, _)| cursor.clone());
+    let entries = entries
+        .into_iter()
+        .map(|(cursor, entry)| AuditLogItem { cursor, entry })
+        .collect();
+    Ok((
+        StatusCode::OK,
+        Json(ListAuditLogResponse {
+            entries,
+            next_cursor,
+        }),
+    ))
+}
+
+/// Download the whole audit log as JSON lines, oldest first.
+pub async fn export_audit_log(State(state): State<AppState>) -> HttpResult<impl IntoResponse> {
+    let pages = stream::try_unfold(
+        (state.db.clone(), None::<String>, false),
+        |(db, cursor, done)| async move {
+            if done {
+                return Ok(None);
+            }
+            let page = read_page(&db, cursor.as_deref())?;
+            let cursor = page.last().map(|(cursor, _)| cursor.clone());
+            let done = page.len() < DEFAULT_MAX_LIST_LIMIT as usize;
+            let mut lines = Vec::new();
+            for (cursor, entry) in page {
+                serde_json::to_writer(&mut lines, &AuditLogItem { cursor, entry })
+                    .map_err(std::io::Error::other)?;
+                lines.push(b'\n');
+            }
+            Ok::<_, std::io::Error>(Some((Bytes::from(lines), (db, cursor, done))))
+        },
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"audit_log.jsonl\"",
+            ),
+        ],
+        Body::from_stream(pages),
+    ))
+}
+
+fn read_page(db: &LmDB, cursor: Option<&str>) -> std::io::Result<Vec<(String, AuditEntry)>> {
+    db.list_audit_entries(Some(DEFAULT_MAX_LIST_LIMIT), cursor, false)
+        .map_err(std::io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppContext;
+    use axum::{routing::get, Router};
+
+    #[tokio::test]
+    async fn test_list_and_export_audit_log() {
+        let context = AppContext::test();
+        for i in 0..3 {
+            context
+                .db
+                .append_audit_entry(
+                    "password",
+                    "GET /generate_signup_token",
+                    &i.to_string(),
+                    200,
+                )
+                .unwrap();
+        }
+        let state = AppState::new(&context, "");
+
+        let (_, Json(page)) = list_audit_log(
+            State(state.clone()),
+            Query(ListAuditLogQuery {
+                limit: Some(2),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.entries.len(), 2);
+        let (_, Json(page)) = list_audit_log(
+            State(state.clone()),
+            Query(ListAuditLogQuery {
+                cursor: page.next_cursor,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.entries[0].entry.target, "2");
+
+        let router = Router::new()
+            .route("/audit_log/export", get(export_audit_log))
+            .with_state(state);
+        let server = axum_test::TestServer::new(router).unwrap();
+        let response = server.get("/audit_log/export").await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let lines: Vec<AuditLogItem> = response
+            .text()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].entry.target, "0");
+    }
+}
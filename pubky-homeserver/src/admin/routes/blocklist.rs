@@ -0,0 +1,217 @@
+use super::super::app_state::AppState;
+use crate::{
+    persistence::{
+        files::{EntryService, FileIoError},
+        lmdb::tables::blocked_content::BlockedContent,
+    },
+    shared::{webdav::EntryPath, HttpError, HttpResult},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use pubky_common::crypto::Hash;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BlockContentRequest {
+    /// Hex encoded blake3 hash of the content.
+    hash: Option<String>,
+    /// Block the content of this entry instead, for example `{pubkey}/pub/file.txt`.
+    path: Option<String>,
+    #[serde(default)]
+    reason: String,
+    /// Delete all existing entries with this content across all users.
+    #[serde(default)]
+    purge: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockedContentInfo {
+    hash: String,
+    #[serde(flatten)]
+    blocked: BlockedContent,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockContentResponse {
+    #[serde(flatten)]
+    info: BlockedContentInfo,
+    /// Paths of the entries deleted by `purge`.
+    purged: Vec<String>,
+}
+
+/// List all blocked content hashes.
+pub async fn list_blocked_content(
+    State(state): State<AppState>,
+) -> HttpResult<(StatusCode, Json<Vec<BlockedContentInfo>>)> {
+    let list = state
+        .db
+        .list_blocked_content()?
+        .into_iter()
+        .map(|(hash, blocked)| BlockedContentInfo {
+            hash: hash.to_hex().to_string(),
+            blocked,
+        })
+        .collect();
+    Ok((StatusCode::OK, Json(list)))
+}
+
+/// Block content by its hash so it can't be written anymore.
+/// Optionally delete all existing entries with this content.
+///
+/// # Errors
+///
+/// - `400` if neither or both of `hash` and `path` are given, or they are invalid.
+/// - `404` if the `path` does not exist.
+///
+pub async fn block_content(
+    State(state): State<AppState>,
+    Json(request): Json<BlockContentRequest>,
+) -> HttpResult<(StatusCode, Json<BlockContentResponse>)> {
+    let hash = match (&request.hash, &request.path) {
+        (Some(hash), None) => parse_hash(hash)?,
+        (None, Some(path)) => {
+            let path: EntryPath = path
+                .parse()
+                .map_err(|e| HttpError::bad_request(format!("Invalid path: {e}")))?;
+            *state.db.get_entry(&path)?.content_hash()
+        }
+        _ => return Err(HttpError::bad_request("Either hash or path must be given")),
+    };
+
+    let blocked = state.db.block_content(&hash, &request.reason)?;
+    tracing::info!("Blocked content {}: {}", hash, request.reason);
+
+    let mut purged = vec![];
+    if request.purge {
+        let entry_service = EntryService::new(state.db.clone());
+        for path in state.db.find_entries_by_content_hash(&hash)? {
+            match state.file_service.delete(&path).await {
+                Ok(()) => {}
+                // The file is missing in the storage. Delete the dangling entry.
+                Err(FileIoError::NotFound) => match entry_service.delete_entry(&path) {
+                    Ok(()) | Err(FileIoError::NotFound) => {}
+                    Err(e) => return Err(e.into()),
+                },
+                Err(e) => return Err(e.into()),
+            }
+            purged.push(path.to_string());
+        }
+        tracing::info!("Purged {} entries with content {}", purged.len(), hash);
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(BlockContentResponse {
+            info: BlockedContentInfo {
+                hash: hash.to_hex().to_string(),
+                blocked,
+            },
+            purged,
+        }),
+    ))
+}
+
+/// Remove a hash from the blocklist.
+///
+/// # Errors
+///
+/// - `400` if the hash is invalid.
+/// - `404` if the hash is not blocked.
+///
+pub async fn unblock_content(
+    State(state): State<AppState>,
+    Path(hash): Path<String>,
+) -> HttpResult<StatusCode> {
+    let hash = parse_hash(&hash)?;
+    if !state.db.unblock_content(&hash)? {
+        return Err(HttpError::new_with_message(
+            StatusCode::NOT_FOUND,
+            "Content is not blocked",
+        ));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn parse_hash(hex: &str) -> HttpResult<Hash> {
+    Hash::from_hex(hex).map_err(|e| HttpError::bad_request(format!("Invalid hash: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::webdav::WebDavPath;
+    use crate::AppContext;
+    use axum::response::IntoResponse;
+    use opendal::Buffer;
+    use pkarr::Keypair;
+
+    #[tokio::test]
+    async fn test_block_and_purge_content() {
+        let context = AppContext::test();
+        let state = AppState::new(&context, "");
+        let content = Buffer::from(b"banned".to_vec());
+
+        let mut paths = vec![];
+        for _ in 0..2 {
+            let pubkey = Keypair::random().public_key();
+            context.db.create_user(&pubkey).unwrap();
+            let path = EntryPath::new(pubkey, WebDavPath::new("/pub/file.txt").unwrap());
+            context
+                .file_service
+                .write(&path, content.clone())
+                .await
+                .unwrap();
+            paths.push(path);
+        }
+
+        let (status, Json(response)) = block_content(
+            State(state.clone()),
+            Json(BlockContentRequest {
+                path: Some(paths[0].to_string()),
+                reason: "takedown #1".to_string(),
+                purge: true,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(response.purged.len(), 2);
+        for path in &paths {
+            assert!(matches!(
+                context.file_service.get_info(path).await,
+                Err(FileIoError::NotFound)
+            ));
+        }
+
+        // Re-uploading is rejected.
+        let error = context
+            .file_service
+            .write(&paths[0], content.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, FileIoError::ContentBlocked));
+        assert!(matches!(
+            context.file_service.get_info(&paths[0]).await,
+            Err(FileIoError::NotFound)
+        ));
+
+        // Unblock
+        let status = unblock_content(State(state.clone()), Path(response.info.hash.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        context
+            .file_service
+            .write(&paths[0], content)
+            .await
+            .unwrap();
+        let error = unblock_content(State(state), Path(response.info.hash))
+            .await
+            .unwrap_err();
+        assert_eq!(error.into_response().status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,11 +1,15 @@
 //! This module provides a webdav endpoint that gives full access to all files.
 //! It is protected by a basic auth header with the username "admin" and the password set in the config.toml file.
-//! The password is set in the config.toml file.
-use crate::{admin::app_state::AppState, shared::HttpResult};
+//! Alternatively, an admin API key with the `webdav` scope can be used as the password with any username.
+use crate::{
+    admin::{app_state::AppState, audit, auth_middleware::AdminActor},
+    persistence::lmdb::tables::admin_keys::AdminScope,
+    shared::HttpResult,
+};
 use axum::{
     body::Body,
     extract::{Request, State},
-    http::Response,
+    http::{Method, Response},
     response::IntoResponse,
 };
 use base64::Engine;
@@ -14,18 +18,60 @@ pub async fn dav_handler(
     State(state): State<AppState>,
     req: Request<Body>,
 ) -> HttpResult<impl IntoResponse> {
-    if !is_valid_authorization_header(req.headers(), &state.admin_password) {
+    let Some(actor) = authorize(req.headers(), &state) else {
         return Ok(Response::builder()
             .status(401)
             .header("WWW-Authenticate", "Basic") // This header will trigger the browser to show the login dialog
             .body(Body::from("Unauthorized"))
             .expect("This response should always be valid"));
-    }
+    };
 
-    let dav_response = state.inner_dav_handler.handle(req).await;
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    // Writes must not reach the old storage while the storage is migrated.
+    let _write_guard = match is_read_only(&method) {
+        true => None,
+        false => Some(state.file_service.opendal.write_guard().await),
+    };
+    let dav_response = state.dav_handler().handle(req).await;
+    audit::record(
+        &state.db,
+        &actor,
+        &method,
+        "/dav{*path}",
+        &path,
+        dav_response.status(),
+    );
     Ok(dav_response.into_response())
 }
 
+/// Whether the webdav method never modifies the storage.
+fn is_read_only(method: &Method) -> bool {
+    matches!(method.as_str(), "GET" | "HEAD" | "OPTIONS" | "PROPFIND")
+}
+
+/// Authenticate the request with the admin password or an admin key with the webdav scope.
+fn authorize(headers: &axum::http::HeaderMap, state: &AppState) -> Option<AdminActor> {
+    if is_valid_authorization_header(headers, &state.admin_password) {
+        return Some(AdminActor::Password);
+    }
+    let (_, password) = headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_basic_auth)?;
+    match state.db.verify_admin_key(&password) {
+        Ok(Some(key)) if key.has_scope(AdminScope::Webdav) => Some(AdminActor::Key {
+            id: key.id,
+            name: key.name,
+        }),
+        Ok(_) => None,
+        Err(e) => {
+            tracing::error!("Failed to verify admin key: {e}");
+            None
+        }
+    }
+}
+
 /// Validate if the authorization header is correct.
 /// It must be a basic auth header with the username "admin" and the given password
 fn is_valid_authorization_header(headers: &axum::http::HeaderMap, should_password: &str) -> bool {
@@ -46,42 +92,88 @@ fn is_valid_authorization_header(headers: &axum::http::HeaderMap, should_passwor
 /// Validate that the authorization header is valid.
 /// It must be a basic auth header with the username "admin" and the given password
 fn is_valid_authorization_header_str(auth_header: &str, should_password: &str) -> bool {
-    // Check if the header starts with "Basic "
-    if !auth_header.starts_with("Basic ") {
-        return false;
+    match parse_basic_auth(auth_header) {
+        Some((username, password)) => username == "admin" && password == should_password,
+        None => false,
     }
+}
 
+/// Parse a basic auth header into username and password.
+fn parse_basic_auth(auth_header: &str) -> Option<(String, String)> {
     // Get the base64 encoded part after "Basic "
-    let base64_encoded = match auth_header.strip_prefix("Basic ") {
-        Some(encoded) => encoded,
-        None => return false,
-    };
+    let base64_encoded = auth_header.strip_prefix("Basic ")?;
 
     // Decode the base64 string
-    let decoded = match base64::engine::general_purpose::STANDARD.decode(base64_encoded) {
-        Ok(decoded) => decoded,
-        Err(_) => return false,
-    };
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(base64_encoded)
+        .ok()?;
 
     // Convert the decoded bytes to a string
-    let decoded_str = match String::from_utf8(decoded) {
-        Ok(str) => str,
-        Err(_) => return false,
-    };
+    let decoded_str = String::from_utf8(decoded).ok()?;
 
     // Split the decoded string into username and password
-    let parts: Vec<&str> = decoded_str.splitn(2, ':').collect();
-    if parts.len() != 2 {
-        return false;
-    }
-
-    // Check if username is "admin" and password matches
-    parts[0] == "admin" && parts[1] == should_password
+    let (username, password) = decoded_str.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use axum::{routing::any, Router};
+    use bytes::Bytes;
+    use tower::ServiceExt;
+
     use super::*;
+    use crate::{
+        persistence::files::StorageMigrator,
+        shared::webdav::{EntryPath, WebDavPath},
+        storage_config::StorageConfigToml,
+        AppContext,
+    };
+
+    #[tokio::test]
+    async fn test_write_during_storage_migration() {
+        let context = AppContext::test();
+        let pubkey = pkarr::Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+        let router = Router::new()
+            .route("/dav{*path}", any(dav_handler))
+            .with_state(AppState::new(&context, "test"));
+
+        // A webdav upload that is still receiving its body when the migration starts.
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Result<Bytes, std::io::Error>>();
+        let body = Body::from_stream(futures_util::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|chunk| (chunk, rx))
+        }));
+        let request = Request::put(format!("/dav/{pubkey}/pub/dav.txt"))
+            .header("Authorization", "Basic YWRtaW46dGVzdA==") // base64("admin:test")
+            .body(body)
+            .unwrap();
+        let put = tokio::spawn(router.oneshot(request));
+        tx.send(Ok(Bytes::from_static(b"written during "))).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let migrator = StorageMigrator::from_context(&context);
+        let migration =
+            tokio::spawn(async move { migrator.run(StorageConfigToml::FileSystem).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(
+            !migration.is_finished(),
+            "The switch must wait for the webdav write"
+        );
+
+        tx.send(Ok(Bytes::from_static(b"the migration"))).unwrap();
+        drop(tx);
+        let response = put.await.unwrap().unwrap();
+        assert!(response.status().is_success(), "{}", response.status());
+        migration.await.unwrap().unwrap();
+
+        // The file is served from the new storage.
+        let path = EntryPath::new(pubkey, WebDavPath::new("/pub/dav.txt").unwrap());
+        let content = context.file_service.opendal.get(&path).await.unwrap();
+        assert_eq!(content.to_vec(), b"written during the migration");
+    }
 
     #[test]
     fn test_is_valid_authorization_header() {
@@ -39,8 +39,8 @@ mod tests {
         let pubkey = keypair.public_key();
         let file_path = "my_file.txt";
         let db = context.db.clone();
-        let file_service = FileService::new_from_context(&context).unwrap();
-        let app_state = AppState::new(db.clone(), file_service.clone(), "");
+        let file_service = context.file_service.clone();
+        let app_state = AppState::new(&context, "");
         let router = Router::new()
             .route("/webdav/{*entry_path}", delete(delete_entry))
             .with_state(app_state);
@@ -89,12 +89,7 @@ mod tests {
         let keypair = Keypair::from_secret_key(&[0; 32]);
         let pubkey = keypair.public_key();
         let file_path = "my_file.txt";
-        let db = context.db.clone();
-        let app_state = AppState::new(
-            db.clone(),
-            FileService::new_from_context(&context).unwrap(),
-            "",
-        );
+        let app_state = AppState::new(&context, "");
         let router = Router::new()
             .route("/webdav/{*entry_path}", delete(delete_entry))
             .with_state(app_state);
@@ -110,12 +105,7 @@ mod tests {
     async fn test_invalid_pubkey() {
         // Set everything up
         let context = AppContext::test();
-        let db = context.db.clone();
-        let app_state = AppState::new(
-            db.clone(),
-            FileService::new_from_context(&context).unwrap(),
-            "",
-        );
+        let app_state = AppState::new(&context, "");
         let router = Router::new()
             .route("/webdav/{*entry_path}", delete(delete_entry))
             .with_state(app_state);
@@ -0,0 +1,96 @@
+use super::super::app_state::AppState;
+use crate::persistence::files::UserDeletionReport;
+use crate::shared::{HttpError, HttpResult, Z32Pubkey};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use pkarr::PublicKey;
+
+/// The user deleted by the admin. Added to the response extensions
+/// so the virtual hosts stop routing the requests of the user.
+#[derive(Debug, Clone)]
+pub(crate) struct DeletedUser(pub PublicKey);
+
+/// Delete a user with all their files, sessions and events.
+///
+/// Safe to retry. An interrupted deletion continues where it left off.
+///
+/// # Errors
+///
+/// - `400` if the pubkey is invalid.
+/// - `404` if neither the user nor any of their data exists.
+/// - `409` if one of the user's resumable uploads is receiving a chunk right now.
+///
+pub async fn delete_user(
+    State(state): State<AppState>,
+    Path(pubkey): Path<Z32Pubkey>,
+) -> HttpResult<(StatusCode, Extension<DeletedUser>, Json<UserDeletionReport>)> {
+    let report = state.user_deleter.delete(&pubkey.0).await?;
+    if report.is_empty() {
+        return Err(HttpError::new_with_message(
+            StatusCode::NOT_FOUND,
+            "User not found",
+        ));
+    }
+    Ok((
+        StatusCode::OK,
+        Extension(DeletedUser(pubkey.0)),
+        Json(report),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::app_state::AppState;
+    use super::*;
+    use crate::shared::webdav::{EntryPath, WebDavPath};
+    use crate::AppContext;
+    use axum::{routing::delete, Router};
+    use opendal::Buffer;
+    use pkarr::Keypair;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_delete_user() {
+        let context = AppContext::test();
+        let pubkey = Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+        let path = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/test.txt").unwrap());
+        context
+            .file_service
+            .write(&path, Buffer::from(vec![0; 10]))
+            .await
+            .unwrap();
+
+        let app_state = AppState::new(&context, "");
+        let router = Router::new()
+            .route("/users/{pubkey}", delete(delete_user))
+            .with_state(app_state);
+        let server = axum_test::TestServer::new(router.clone()).unwrap();
+
+        let response = server.delete(format!("/users/{pubkey}").as_str()).await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        assert!(context
+            .db
+            .get_user(&pubkey, &context.db.env.read_txn().unwrap())
+            .unwrap()
+            .is_none());
+        assert!(context.file_service.get_info(&path).await.is_err());
+
+        // Nothing left to delete
+        let response = server.delete(format!("/users/{pubkey}").as_str()).await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+
+        // The deleted user is added to the response extensions.
+        context.db.create_user(&pubkey).unwrap();
+        let request = axum::http::Request::delete(format!("/users/{pubkey}"))
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let DeletedUser(deleted) = response.extensions().get::<DeletedUser>().unwrap();
+        assert_eq!(deleted, &pubkey);
+    }
+}
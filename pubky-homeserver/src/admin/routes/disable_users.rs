@@ -77,7 +77,7 @@ pub async fn enable_user(
 mod tests {
     use super::super::super::app_state::AppState;
     use super::*;
-    use crate::{persistence::files::FileService, AppContext};
+    use crate::AppContext;
     use axum::routing::post;
     use axum::Router;
     use pkarr::Keypair;
@@ -99,11 +99,7 @@ mod tests {
         assert!(!user.disabled);
 
         // Setup server
-        let app_state = AppState::new(
-            db.clone(),
-            FileService::new_from_context(&context).unwrap(),
-            "",
-        );
+        let app_state = AppState::new(&context, "");
         let router = Router::new()
             .route("/users/{pubkey}/disable", post(disable_user))
             .route("/users/{pubkey}/enable", post(enable_user))
@@ -0,0 +1,29 @@
+use super::super::app_state::AppState;
+use crate::shared::health::{check_readiness, HealthStatus, ReadinessReport};
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Value};
+
+/// Liveness probe. Responds as long as the admin server is running.
+pub async fn live() -> (StatusCode, Json<Value>) {
+    (StatusCode::OK, Json(json!({ "status": "ok" })))
+}
+
+/// Readiness probe. Checks LMDB, the storage backend, the last pkarr publish
+/// and the free disk space.
+///
+/// Responds with `503` if any check failed. Degraded checks still respond with `200`.
+pub async fn ready(State(state): State<AppState>) -> (StatusCode, Json<ReadinessReport>) {
+    let report = check_readiness(
+        &state.db,
+        state.file_service.opendal.operator(),
+        &state.publish_status,
+        state.data_dir.path(),
+    )
+    .await;
+    let status = if report.status == HealthStatus::Failed {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(report))
+}
@@ -59,7 +59,6 @@ pub async fn info(State(state): State<AppState>) -> HttpResult<(StatusCode, Json
 mod tests {
     use super::*;
     use crate::admin::app_state::AppState;
-    use crate::persistence::files::FileService;
     use crate::AppContext;
     use axum::extract::State;
     use axum::http::StatusCode;
@@ -108,11 +107,7 @@ mod tests {
         db.validate_and_consume_signup_token(&code1, &key1).unwrap();
 
         // 4) Invoke handler
-        let state = AppState::new(
-            db.clone(),
-            FileService::new_from_context(&context).unwrap(),
-            "",
-        );
+        let state = AppState::new(&context, "");
         let (status, Json(info)) = info(State(state)).await.unwrap();
         assert_eq!(status, StatusCode::OK);
         assert_eq!(info.num_users, 2);
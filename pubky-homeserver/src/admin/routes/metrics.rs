@@ -0,0 +1,15 @@
+use super::super::app_state::AppState;
+use crate::shared::HttpResult;
+use axum::{extract::State, http::header, response::IntoResponse};
+
+/// Metrics of the homeserver and the admin server in the Prometheus text format.
+pub async fn metrics(State(state): State<AppState>) -> HttpResult<impl IntoResponse> {
+    let body = state.metrics.render(&state.db)?;
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )],
+        body,
+    ))
+}
@@ -1,6 +1,20 @@
+pub(crate) mod admin_keys;
+pub(crate) mod audit_log;
+pub(crate) mod blocklist;
 pub(crate) mod dav_handler;
 pub(crate) mod delete_entry;
+pub(crate) mod delete_user;
 pub(crate) mod disable_users;
 pub(crate) mod generate_signup_token;
+pub(crate) mod health;
 pub(crate) mod info;
+pub(crate) mod metrics;
+pub(crate) mod rate_limits;
+pub(crate) mod reconcile_storage;
+pub(crate) mod reload_config;
 pub(crate) mod root;
+pub(crate) mod scrub_storage;
+pub(crate) mod storage_migration;
+pub(crate) mod user_archive;
+pub(crate) mod user_tier;
+pub(crate) mod users;
@@ -0,0 +1,65 @@
+use super::super::app_state::AppState;
+use crate::{
+    quota_config::PathLimit,
+    shared::{HttpError, HttpResult},
+};
+use axum::{extract::State, http::StatusCode, Json};
+
+/// List the rate limits currently in effect.
+pub async fn get_rate_limits(
+    State(state): State<AppState>,
+) -> HttpResult<(StatusCode, Json<Vec<PathLimit>>)> {
+    Ok((
+        StatusCode::OK,
+        Json(state.config_reloader.rate_limits().limits()),
+    ))
+}
+
+/// Replace the rate limits at runtime.
+///
+/// The limits are not written to the config.toml. The next config reload reverts them.
+///
+/// # Errors
+///
+/// - `400` if a limit is invalid.
+///
+pub async fn set_rate_limits(
+    State(state): State<AppState>,
+    Json(limits): Json<Vec<PathLimit>>,
+) -> HttpResult<(StatusCode, Json<Vec<PathLimit>>)> {
+    for limit in &limits {
+        limit
+            .validate()
+            .map_err(|e| HttpError::bad_request(e.to_string()))?;
+    }
+    state.config_reloader.set_rate_limits(limits.clone());
+    Ok((StatusCode::OK, Json(limits)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppContext;
+
+    #[tokio::test]
+    async fn test_set_rate_limits() {
+        let context = AppContext::test();
+        let state = AppState::new(&context, "");
+        let limits: Vec<PathLimit> = serde_json::from_str(
+            r#"[{"path": "/pub/**", "method": "GET", "quota": "10r/m", "key": "ip", "burst": null}]"#,
+        )
+        .unwrap();
+
+        let (status, _) = set_rate_limits(State(state.clone()), Json(limits.clone()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let (_, Json(current)) = get_rate_limits(State(state)).await.unwrap();
+        assert_eq!(current, limits);
+
+        // A reload reverts to the config file.
+        let report = context.config_reloader.reload().unwrap();
+        assert_eq!(report.applied, vec!["drive.rate_limits"]);
+        assert!(context.config_reloader.rate_limits().limits().is_empty());
+    }
+}
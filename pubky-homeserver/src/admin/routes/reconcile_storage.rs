@@ -0,0 +1,68 @@
+use super::super::app_state::AppState;
+use crate::persistence::files::{ReconciliationReport, StorageReconciler};
+use crate::shared::HttpResult;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+
+#[derive(Deserialize, Default)]
+pub(crate) struct ReconcileStorageQuery {
+    /// Repair the found issues instead of only reporting them.
+    #[serde(default)]
+    repair: bool,
+}
+
+/// Reconcile the recorded user storage usage with the entries and the stored files.
+/// Only reports the drift unless `?repair=true` is set.
+pub async fn reconcile_storage(
+    State(state): State<AppState>,
+    Query(query): Query<ReconcileStorageQuery>,
+) -> HttpResult<(StatusCode, Json<ReconciliationReport>)> {
+    let reconciler = StorageReconciler::new(state.file_service.clone());
+    let report = reconciler.run(query.repair).await?;
+    Ok((StatusCode::OK, Json(report)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppContext;
+
+    #[tokio::test]
+    async fn test_reconcile_storage_repair() {
+        let context = AppContext::test();
+        let db = context.db.clone();
+        let pubkey = pkarr::Keypair::random().public_key();
+        db.create_user(&pubkey).unwrap();
+
+        // Drift the recorded usage.
+        {
+            let mut wtxn = db.env.write_txn().unwrap();
+            let mut user = db.get_user(&pubkey, &wtxn).unwrap().unwrap();
+            user.used_bytes = 1000;
+            db.tables.users.put(&mut wtxn, &pubkey, &user).unwrap();
+            wtxn.commit().unwrap();
+        }
+
+        let state = AppState::new(&context, "");
+        let (status, Json(report)) = reconcile_storage(
+            State(state.clone()),
+            Query(ReconcileStorageQuery { repair: false }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report.usage_mismatches.len(), 1);
+        assert_eq!(db.get_user_data_usage(&pubkey).unwrap(), Some(1000));
+
+        let (_, Json(report)) =
+            reconcile_storage(State(state), Query(ReconcileStorageQuery { repair: true }))
+                .await
+                .unwrap();
+        assert!(report.repaired);
+        assert_eq!(db.get_user_data_usage(&pubkey).unwrap(), Some(0));
+    }
+}
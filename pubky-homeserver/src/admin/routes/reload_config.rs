@@ -0,0 +1,41 @@
+use super::super::app_state::AppState;
+use crate::{
+    shared::{HttpError, HttpResult},
+    ConfigReloadReport,
+};
+use axum::{extract::State, http::StatusCode, Json};
+
+/// Reload the config.toml from the data directory.
+///
+/// Responds with the settings that were applied and the ones that need a restart.
+///
+/// # Errors
+///
+/// - `400` if the config file can't be read or is invalid. Nothing is applied in this case.
+///
+pub async fn reload_config(
+    State(state): State<AppState>,
+) -> HttpResult<(StatusCode, Json<ConfigReloadReport>)> {
+    let reloader = state.config_reloader.clone();
+    let report = tokio::task::spawn_blocking(move || reloader.reload())
+        .await
+        .map_err(|e| HttpError::internal_server_and_log(format!("Config reload panicked: {e}")))?
+        .map_err(|e| HttpError::bad_request(format!("Failed to reload config: {e}")))?;
+    Ok((StatusCode::OK, Json(report)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppContext;
+
+    #[tokio::test]
+    async fn test_reload_unchanged_config() {
+        let context = AppContext::test();
+        let (status, Json(report)) = reload_config(State(AppState::new(&context, "")))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report, ConfigReloadReport::default());
+    }
+}
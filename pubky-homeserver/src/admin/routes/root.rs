@@ -1,7 +1,13 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use axum::{http::StatusCode, response::Html, response::IntoResponse};
 
 use crate::shared::HttpResult;
 
+/// Single page admin UI. Embedded so the admin server has no asset directory to deploy.
+///
+/// The page itself contains no data. It calls the admin endpoints with the credentials
+/// the operator signs in with, so all data stays behind the admin auth.
+const ADMIN_UI: &str = include_str!("../ui/index.html");
+
 pub async fn root() -> HttpResult<impl IntoResponse> {
-    Ok((StatusCode::OK, "Homeserver - Admin Endpoint"))
+    Ok((StatusCode::OK, Html(ADMIN_UI)))
 }
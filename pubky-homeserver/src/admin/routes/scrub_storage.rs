@@ -0,0 +1,26 @@
+use super::super::app_state::AppState;
+use crate::persistence::files::ScrubReport;
+use crate::shared::HttpResult;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+
+#[derive(Deserialize, Default)]
+pub(crate) struct ScrubStorageQuery {
+    /// Quarantine corrupted files instead of only reporting them.
+    #[serde(default)]
+    quarantine: bool,
+}
+
+/// Verify all stored files against their content hash.
+/// Only reports corrupted files unless `?quarantine=true` is set.
+pub async fn scrub_storage(
+    State(state): State<AppState>,
+    Query(query): Query<ScrubStorageQuery>,
+) -> HttpResult<(StatusCode, Json<ScrubReport>)> {
+    let report = state.storage_scrubber.run(query.quarantine).await?;
+    Ok((StatusCode::OK, Json(report)))
+}
@@ -0,0 +1,84 @@
+use super::super::app_state::AppState;
+use crate::{
+    persistence::files::{StorageMigrationError, StorageMigrationProgress},
+    shared::{HttpError, HttpResult},
+    storage_config::StorageConfigToml,
+};
+use axum::{extract::State, http::StatusCode, Json};
+
+/// Start migrating all files to the storage given in the body.
+/// The body has the same format as the `[storage]` section of the config.toml.
+///
+/// # Errors
+///
+/// - `400` if the target storage is already in use.
+/// - `409` if a migration is already running.
+///
+pub async fn start_storage_migration(
+    State(state): State<AppState>,
+    Json(target): Json<StorageConfigToml>,
+) -> HttpResult<(StatusCode, Json<StorageMigrationProgress>)> {
+    match state.storage_migrator.start(target) {
+        Ok(()) => Ok((
+            StatusCode::ACCEPTED,
+            Json(state.storage_migrator.progress()),
+        )),
+        Err(StorageMigrationError::AlreadyRunning) => Err(HttpError::new_with_message(
+            StatusCode::CONFLICT,
+            "A storage migration is already running",
+        )),
+        Err(e) => Err(HttpError::internal_server_and_log(e)),
+    }
+}
+
+/// Get the progress of the current or last storage migration.
+pub async fn get_storage_migration(
+    State(state): State<AppState>,
+) -> HttpResult<(StatusCode, Json<StorageMigrationProgress>)> {
+    Ok((StatusCode::OK, Json(state.storage_migrator.progress())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppContext;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_storage_migration() {
+        let context = AppContext::test();
+        let state = AppState::new(&context, "");
+
+        let (status, Json(progress)) =
+            start_storage_migration(State(state.clone()), Json(StorageConfigToml::FileSystem))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert!(progress.running);
+
+        let mut progress = progress;
+        for _ in 0..100 {
+            let (_, Json(current)) = get_storage_migration(State(state.clone())).await.unwrap();
+            progress = current;
+            if !progress.running {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(progress.completed, "{:?}", progress);
+
+        // The file system is in use now.
+        let (_, Json(progress)) =
+            start_storage_migration(State(state.clone()), Json(StorageConfigToml::FileSystem))
+                .await
+                .unwrap();
+        assert!(progress.running);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (_, Json(progress)) = get_storage_migration(State(state)).await.unwrap();
+        assert!(!progress.completed);
+        assert_eq!(
+            progress.error,
+            Some(StorageMigrationError::SameStorage.to_string())
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use super::super::app_state::AppState;
+use crate::{
+    persistence::files::{ImportReport, UserArchiver},
+    shared::{HttpError, HttpResult, Z32Pubkey},
+};
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use futures_util::TryStreamExt;
+use pkarr::PublicKey;
+use tokio_util::io::StreamReader;
+
+/// Download a tar archive of all files of a user.
+///
+/// # Errors
+///
+/// - `400` if the pubkey is invalid.
+/// - `404` if the user does not exist.
+///
+pub async fn export_user(
+    State(state): State<AppState>,
+    Path(pubkey): Path<Z32Pubkey>,
+) -> HttpResult<impl IntoResponse> {
+    err_if_user_not_found(&state, &pubkey.0)?;
+    let stream = UserArchiver::new(state.file_service.clone()).export(&pubkey.0)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.tar\"", pubkey.0),
+            ),
+        ],
+        Body::from_stream(stream),
+    ))
+}
+
+/// Import an archive into the files of a user.
+///
+/// # Errors
+///
+/// - `400` if the pubkey or the archive is invalid.
+/// - `404` if the user does not exist.
+/// - `507` if the user quota is exceeded.
+///
+pub async fn import_user(
+    State(state): State<AppState>,
+    Path(pubkey): Path<Z32Pubkey>,
+    body: Body,
+) -> HttpResult<(StatusCode, Json<ImportReport>)> {
+    err_if_user_not_found(&state, &pubkey.0)?;
+    let reader = StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+    let report = UserArchiver::new(state.file_service.clone())
+        .import(&pubkey.0, reader)
+        .await?;
+    Ok((StatusCode::OK, Json(report)))
+}
+
+fn err_if_user_not_found(state: &AppState, pubkey: &PublicKey) -> HttpResult<()> {
+    match state.db.get_user(pubkey, &state.db.env.read_txn()?)? {
+        Some(_) => Ok(()),
+        None => Err(HttpError::new_with_message(
+            StatusCode::NOT_FOUND,
+            "User not found",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::app_state::AppState;
+    use super::*;
+    use crate::shared::webdav::{EntryPath, WebDavPath};
+    use crate::AppContext;
+    use axum::{
+        routing::{get, post},
+        Router,
+    };
+    use opendal::Buffer;
+    use pkarr::Keypair;
+
+    fn server(context: &AppContext) -> axum_test::TestServer {
+        let router = Router::new()
+            .route("/users/{pubkey}/export", get(export_user))
+            .route("/users/{pubkey}/import", post(import_user))
+            .with_state(AppState::new(context, ""));
+        axum_test::TestServer::new(router).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_import_user() {
+        let source = AppContext::test();
+        let pubkey = Keypair::random().public_key();
+        source.db.create_user(&pubkey).unwrap();
+        let path = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/test.txt").unwrap());
+        source
+            .file_service
+            .write(&path, Buffer::from(vec![7; 10]))
+            .await
+            .unwrap();
+
+        let response = server(&source)
+            .get(format!("/users/{pubkey}/export").as_str())
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let archive = response.into_bytes();
+
+        let target = AppContext::test();
+        let server = server(&target);
+
+        // The user must exist on the target homeserver.
+        let response = server
+            .post(format!("/users/{pubkey}/import").as_str())
+            .bytes(archive.clone())
+            .await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+
+        target.db.create_user(&pubkey).unwrap();
+        let response = server
+            .post(format!("/users/{pubkey}/import").as_str())
+            .bytes(archive)
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        assert_eq!(
+            target.file_service.get(&path).await.unwrap().as_ref(),
+            &[7; 10]
+        );
+
+        // Not a tar archive
+        let response = server
+            .post(format!("/users/{pubkey}/import").as_str())
+            .bytes(b"garbage".to_vec().into())
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+}
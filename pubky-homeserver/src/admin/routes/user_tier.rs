@@ -0,0 +1,112 @@
+use super::super::app_state::AppState;
+use crate::shared::{HttpError, HttpResult, Z32Pubkey};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use pkarr::PublicKey;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserTier {
+    /// Name of the tier in the `tiers` of the rate limits.
+    tier: String,
+}
+
+/// Assign a rate limit tier to a user.
+///
+/// Tiers that no rate limit defines are accepted. The user gets the default quotas for them.
+///
+/// # Errors
+///
+/// - `400` if the pubkey or tier is invalid.
+/// - `404` if the user does not exist.
+///
+pub async fn set_user_tier(
+    State(state): State<AppState>,
+    Path(pubkey): Path<Z32Pubkey>,
+    Json(body): Json<UserTier>,
+) -> HttpResult<(StatusCode, Json<UserTier>)> {
+    if body.tier.is_empty() {
+        return Err(HttpError::bad_request("Tier must not be empty"));
+    }
+    ensure_user_exists(&state, &pubkey.0)?;
+    state.db.set_user_tier(&pubkey.0, Some(&body.tier))?;
+    tracing::info!("Assigned tier {} to user {}", body.tier, pubkey.0);
+    Ok((StatusCode::OK, Json(body)))
+}
+
+/// Remove the rate limit tier of a user.
+///
+/// # Errors
+///
+/// - `400` if the pubkey is invalid.
+/// - `404` if the user does not exist.
+///
+pub async fn delete_user_tier(
+    State(state): State<AppState>,
+    Path(pubkey): Path<Z32Pubkey>,
+) -> HttpResult<StatusCode> {
+    ensure_user_exists(&state, &pubkey.0)?;
+    state.db.set_user_tier(&pubkey.0, None)?;
+    tracing::info!("Removed the tier of user {}", pubkey.0);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn ensure_user_exists(state: &AppState, pubkey: &PublicKey) -> HttpResult<()> {
+    let rtxn = state.db.env.read_txn()?;
+    match state.db.get_user(pubkey, &rtxn)? {
+        Some(_) => Ok(()),
+        None => Err(HttpError::new_with_message(
+            StatusCode::NOT_FOUND,
+            "User not found",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::app_state::AppState;
+    use super::*;
+    use crate::AppContext;
+    use axum::routing::put;
+    use axum::Router;
+    use pkarr::Keypair;
+
+    #[tokio::test]
+    async fn test_set_delete_user_tier() {
+        let context = AppContext::test();
+        let pubkey = Keypair::random().public_key();
+        let db = context.db.clone();
+
+        let app_state = AppState::new(&context, "");
+        let router = Router::new()
+            .route(
+                "/users/{pubkey}/tier",
+                put(set_user_tier).delete(delete_user_tier),
+            )
+            .with_state(app_state);
+        let server = axum_test::TestServer::new(router).unwrap();
+        let url = format!("/users/{pubkey}/tier");
+
+        // Unknown user
+        let response = server
+            .put(&url)
+            .json(&serde_json::json!({"tier": "paid"}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+
+        db.create_user(&pubkey).unwrap();
+        let response = server
+            .put(&url)
+            .json(&serde_json::json!({"tier": "paid"}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        assert_eq!(db.get_user_tier(&pubkey).unwrap(), Some("paid".to_string()));
+
+        let response = server.delete(&url).await;
+        assert_eq!(response.status_code(), StatusCode::NO_CONTENT);
+        assert_eq!(db.get_user_tier(&pubkey).unwrap(), None);
+    }
+}
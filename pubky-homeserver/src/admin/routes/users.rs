@@ -0,0 +1,435 @@
+use super::super::app_state::AppState;
+use crate::constants::DEFAULT_MAX_LIST_LIMIT;
+use crate::persistence::lmdb::tables::users::User;
+use crate::persistence::lmdb::LmDB;
+use crate::shared::webdav::{EntryPath, WebDavPath};
+use crate::shared::{HttpError, HttpResult, Z32Pubkey};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use heed::RoTxn;
+use pkarr::PublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Default number of users per page.
+const DEFAULT_USERS_LIMIT: usize = 100;
+/// Maximum number of users per page.
+const MAX_USERS_LIMIT: usize = 1000;
+/// Default number of recent events in the user detail.
+const DEFAULT_RECENT_EVENTS: usize = 20;
+/// Maximum number of recent events in the user detail.
+const MAX_RECENT_EVENTS: usize = 100;
+
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum UserSort {
+    #[default]
+    CreatedAt,
+    UsedBytes,
+    Pubkey,
+}
+
+#[derive(Deserialize, Default)]
+pub(crate) struct ListUsersQuery {
+    /// Number of users to skip.
+    #[serde(default)]
+    offset: usize,
+    /// Number of users to return. Defaults to 100, capped at 1000.
+    limit: Option<usize>,
+    /// Only return disabled (`true`) or enabled (`false`) users.
+    disabled: Option<bool>,
+    /// Only return users whose pubkey contains this string.
+    search: Option<String>,
+    /// Field to sort by.
+    #[serde(default)]
+    sort: UserSort,
+    /// Sort in descending order.
+    #[serde(default)]
+    reverse: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct UserInfo {
+    pubkey: String,
+    created_at: u64,
+    disabled: bool,
+    used_bytes: u64,
+    session_count: usize,
+    /// Latest event (entry write or delete) or session creation.
+    last_activity: Option<u64>,
+    /// Rate limit tier of the user.
+    tier: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct ListUsersResponse {
+    /// Number of users matching the filters.
+    total: usize,
+    /// Offset of the next page, if there is one.
+    next_offset: Option<usize>,
+    users: Vec<UserInfo>,
+}
+
+/// List users with pagination, filtering and sorting.
+pub async fn list_users(
+    State(state): State<AppState>,
+    Query(query): Query<ListUsersQuery>,
+) -> HttpResult<(StatusCode, Json<ListUsersResponse>)> {
+    let rtxn = state.db.env.read_txn()?;
+
+    let search = query.search.map(|search| search.to_lowercase());
+    let mut users: Vec<(PublicKey, User)> = vec![];
+    for result in state.db.tables.users.iter(&rtxn)? {
+        let (pubkey, user) = result?;
+        if query
+            .disabled
+            .is_some_and(|disabled| disabled != user.disabled)
+        {
+            continue;
+        }
+        if let Some(search) = &search {
+            if !pubkey.to_string().contains(search.as_str()) {
+                continue;
+            }
+        }
+        users.push((pubkey, user));
+    }
+
+    match query.sort {
+        UserSort::CreatedAt => users.sort_by_key(|(_, user)| user.created_at),
+        UserSort::UsedBytes => users.sort_by_key(|(_, user)| user.used_bytes),
+        // The users table is already ordered by pubkey.
+        UserSort::Pubkey => {}
+    }
+    if query.reverse {
+        users.reverse();
+    }
+
+    let total = users.len();
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_USERS_LIMIT)
+        .min(MAX_USERS_LIMIT);
+    let page: Vec<(PublicKey, User)> = users.into_iter().skip(query.offset).take(limit).collect();
+    let end = query.offset.saturating_add(page.len());
+    let next_offset = (end < total).then_some(end);
+
+    let users = page
+        .into_iter()
+        .map(|(pubkey, user)| {
+            let stats = session_stats(&state.db, &rtxn, &pubkey)?;
+            user_info(&state.db, &rtxn, pubkey, user, stats)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok((
+        StatusCode::OK,
+        Json(ListUsersResponse {
+            total,
+            next_offset,
+            users,
+        }),
+    ))
+}
+
+#[derive(Deserialize, Default)]
+pub(crate) struct UserDetailQuery {
+    /// Number of recent events to return. Defaults to 20, capped at 100.
+    events: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct UserEvent {
+    cursor: String,
+    operation: String,
+    url: String,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct UserDetailResponse {
+    #[serde(flatten)]
+    user: UserInfo,
+    /// Top-level directories as pubky urls.
+    directories: Vec<String>,
+    /// Latest events, newest first.
+    recent_events: Vec<UserEvent>,
+}
+
+/// Inspect a single user.
+///
+/// # Errors
+///
+/// - `400` if the pubkey is invalid.
+/// - `404` if the user does not exist.
+///
+pub async fn get_user(
+    State(state): State<AppState>,
+    Path(pubkey): Path<Z32Pubkey>,
+    Query(query): Query<UserDetailQuery>,
+) -> HttpResult<(StatusCode, Json<UserDetailResponse>)> {
+    let pubkey = pubkey.0;
+    let rtxn = state.db.env.read_txn()?;
+
+    let user = state
+        .db
+        .get_user(&pubkey, &rtxn)?
+        .ok_or(HttpError::new_with_message(
+            StatusCode::NOT_FOUND,
+            "User not found",
+        ))?;
+
+    let stats = session_stats(&state.db, &rtxn, &pubkey)?;
+
+    let root = EntryPath::new(pubkey.clone(), WebDavPath::new("/")?);
+    let directories = state
+        .db
+        .list_entries(
+            &rtxn,
+            &root,
+            false,
+            Some(DEFAULT_MAX_LIST_LIMIT),
+            None,
+            true,
+        )?
+        .into_iter()
+        .filter(|url| url.ends_with('/'))
+        .collect();
+
+    let limit = query
+        .events
+        .unwrap_or(DEFAULT_RECENT_EVENTS)
+        .min(MAX_RECENT_EVENTS);
+    let recent_events = state
+        .db
+        .list_recent_user_events(&rtxn, &pubkey, limit)?
+        .into_iter()
+        .map(|(cursor, event)| UserEvent {
+            cursor,
+            operation: event.operation().to_string(),
+            url: event.url().to_string(),
+        })
+        .collect();
+
+    let user = user_info(&state.db, &rtxn, pubkey, user, stats)?;
+
+    Ok((
+        StatusCode::OK,
+        Json(UserDetailResponse {
+            user,
+            directories,
+            recent_events,
+        }),
+    ))
+}
+
+/// Number of sessions and latest session creation per user.
+#[derive(Default, Clone, Copy)]
+struct SessionStats {
+    count: usize,
+    last_created_at: Option<u64>,
+}
+
+fn session_stats(db: &LmDB, rtxn: &RoTxn, pubkey: &PublicKey) -> anyhow::Result<SessionStats> {
+    let sessions = db.list_user_sessions(rtxn, pubkey)?;
+    Ok(SessionStats {
+        count: sessions.len(),
+        last_created_at: sessions.iter().map(|session| session.created_at()).max(),
+    })
+}
+
+fn user_info(
+    db: &LmDB,
+    rtxn: &RoTxn,
+    pubkey: PublicKey,
+    user: User,
+    sessions: SessionStats,
+) -> anyhow::Result<UserInfo> {
+    let last_event = db
+        .get_last_user_event_timestamp(rtxn, &pubkey)?
+        .map(|timestamp| timestamp.as_u64());
+    let tier = db.tables.user_tiers.get(rtxn, &pubkey)?.map(str::to_string);
+    Ok(UserInfo {
+        pubkey: pubkey.to_string(),
+        created_at: user.created_at,
+        disabled: user.disabled,
+        used_bytes: user.used_bytes,
+        session_count: sessions.count,
+        last_activity: last_event.max(sessions.last_created_at),
+        tier,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppContext;
+    use axum::response::IntoResponse;
+    use pkarr::Keypair;
+    use pubky_common::{session::Session, timestamp::Timestamp};
+
+    fn set_user(db: &LmDB, pubkey: &PublicKey, created_at: u64, disabled: bool, used_bytes: u64) {
+        let mut wtxn = db.env.write_txn().unwrap();
+        let user = User {
+            created_at,
+            disabled,
+            used_bytes,
+        };
+        db.tables.users.put(&mut wtxn, pubkey, &user).unwrap();
+        wtxn.commit().unwrap();
+    }
+
+    async fn list(state: &AppState, query: ListUsersQuery) -> ListUsersResponse {
+        let (status, Json(body)) = list_users(State(state.clone()), Query(query))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        body
+    }
+
+    #[tokio::test]
+    async fn test_list_users_filter_sort_paginate() {
+        let context = AppContext::test();
+        let db = context.db.clone();
+        let key1 = Keypair::random().public_key();
+        let key2 = Keypair::random().public_key();
+        let key3 = Keypair::random().public_key();
+        set_user(&db, &key1, 1, false, 300);
+        set_user(&db, &key2, 2, true, 100);
+        set_user(&db, &key3, 3, false, 200);
+
+        // Two sessions for key1
+        {
+            let mut wtxn = db.env.write_txn().unwrap();
+            for secret in ["a", "b"] {
+                let session = Session::new(&key1, &[], None);
+                db.put_session(&mut wtxn, secret, &session).unwrap();
+            }
+            wtxn.commit().unwrap();
+        }
+
+        let state = AppState::new(&context, "");
+
+        // Default sort by created_at, paginated
+        let body = list(
+            &state,
+            ListUsersQuery {
+                limit: Some(2),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(body.total, 3);
+        assert_eq!(body.next_offset, Some(2));
+        assert_eq!(body.users[0].pubkey, key1.to_string());
+        assert_eq!(body.users[0].session_count, 2);
+        assert!(body.users[0].last_activity.is_some());
+        assert_eq!(body.users[1].pubkey, key2.to_string());
+        assert_eq!(body.users[1].session_count, 0);
+        assert_eq!(body.users[1].last_activity, None);
+
+        let body = list(
+            &state,
+            ListUsersQuery {
+                limit: Some(2),
+                offset: 2,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(body.users.len(), 1);
+        assert_eq!(body.users[0].pubkey, key3.to_string());
+        assert_eq!(body.next_offset, None);
+
+        // Only enabled users, largest first
+        let body = list(
+            &state,
+            ListUsersQuery {
+                disabled: Some(false),
+                sort: UserSort::UsedBytes,
+                reverse: true,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(body.total, 2);
+        assert_eq!(body.users[0].pubkey, key1.to_string());
+        assert_eq!(body.users[1].pubkey, key3.to_string());
+
+        // Search by pubkey
+        let body = list(
+            &state,
+            ListUsersQuery {
+                search: Some(key2.to_string()[..20].to_uppercase()),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(body.total, 1);
+        assert!(body.users[0].disabled);
+    }
+
+    #[tokio::test]
+    async fn test_get_user() {
+        let context = AppContext::test();
+        let pubkey = Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+
+        for path in ["/pub/a.txt", "/pub/b.txt", "/priv/c.txt"] {
+            let entry_path = EntryPath::new(pubkey.clone(), WebDavPath::new(path).unwrap());
+            context
+                .file_service
+                .write(&entry_path, vec![0u8; 4].into())
+                .await
+                .unwrap();
+        }
+        // A newer event of another user.
+        let other = Keypair::random().public_key();
+        context.db.create_user(&other).unwrap();
+        let other = EntryPath::new(other, WebDavPath::new("/pub/d.txt").unwrap());
+        context
+            .file_service
+            .write(&other, vec![0u8; 4].into())
+            .await
+            .unwrap();
+
+        let state = AppState::new(&context, "");
+        let (status, Json(body)) = get_user(
+            State(state.clone()),
+            Path(Z32Pubkey(pubkey.clone())),
+            Query(UserDetailQuery { events: Some(2) }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.user.pubkey, pubkey.to_string());
+        assert!(body.user.used_bytes > 0);
+        assert_eq!(
+            body.directories,
+            vec![
+                format!("pubky://{pubkey}/priv/"),
+                format!("pubky://{pubkey}/pub/")
+            ]
+        );
+        assert_eq!(body.recent_events.len(), 2);
+        assert_eq!(body.recent_events[0].operation, "PUT");
+        assert_eq!(
+            body.recent_events[0].url,
+            format!("pubky://{pubkey}/priv/c.txt")
+        );
+        let last_event = Timestamp::try_from(body.recent_events[0].cursor.clone()).unwrap();
+        assert_eq!(body.user.last_activity, Some(last_event.as_u64()));
+
+        // Unknown user
+        let unknown = Keypair::random().public_key();
+        let error = get_user(
+            State(state),
+            Path(Z32Pubkey(unknown)),
+            Query(UserDetailQuery::default()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.into_response().status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -8,11 +8,17 @@
 #[cfg(any(test, feature = "testing"))]
 use crate::MockDataDir;
 use crate::{
+    core::layers::{global_limits::GlobalLimits, rate_limiter::RateLimits},
     persistence::{
-        files::{FileIoError, FileService},
-        lmdb::LmDB,
+        files::{
+            FileIoError, FileService, StorageMigrationState, StorageMigrator, UploadService,
+            UserQuota,
+        },
+        lmdb::{tables::rate_limits::LmdbRateLimitStore, LmDB},
     },
-    ConfigToml, DataDir, PersistentDataDir,
+    quota_config::RateLimitBackend,
+    shared::{health::PublishStatus, metrics::Metrics},
+    ConfigReloader, ConfigToml, DataDir, PersistentDataDir, RateLimitStore,
 };
 use pkarr::Keypair;
 use std::{sync::Arc, time::Duration};
@@ -38,6 +44,9 @@ pub enum AppContextConversionError {
     /// Failed to build pkarr client.
     #[error("Failed to build pkarr client: {0}")]
     Pkarr(pkarr::errors::BuildError),
+    /// Failed to open a virtual homeserver.
+    #[error("Failed to open virtual homeserver '{0}': {1}")]
+    VirtualHomeserver(String, anyhow::Error),
 }
 
 /// The application context shared between all components.
@@ -51,6 +60,21 @@ pub struct AppContext {
     pub(crate) db: LmDB,
     /// The storage operator to store files.
     pub(crate) file_service: FileService,
+    /// Migrates the files to another storage and switches the file service over.
+    pub(crate) storage_migrator: StorageMigrator,
+    /// Manages resumable uploads.
+    pub(crate) upload_service: UploadService,
+    /// Prometheus metrics shared by the homeserver and the admin server.
+    pub(crate) metrics: Metrics,
+    /// Server-wide limits shared by the listeners and the router.
+    pub(crate) global_limits: GlobalLimits,
+    /// Outcome of the last publish of the homeserver's pkarr packet.
+    pub(crate) publish_status: PublishStatus,
+    /// Maximum bytes a user can store. Changed by config reloads.
+    pub(crate) user_quota: UserQuota,
+    /// Applies config changes at runtime.
+    pub(crate) config_reloader: ConfigReloader,
+    /// The config at startup. Use the [ConfigReloader] for settings that can change at runtime.
     pub(crate) config_toml: ConfigToml,
     /// Keep data_dir alive. The mock dir will cleanup on drop.
     pub(crate) data_dir: Arc<dyn DataDir>,
@@ -64,6 +88,15 @@ pub struct AppContext {
 }
 
 impl AppContext {
+    /// Keep the state of the rate limiters in `store`.
+    ///
+    /// Use a store that is shared between homeserver instances to enforce
+    /// the rate limits across all of them.
+    pub fn with_rate_limit_store(self, store: Arc<dyn RateLimitStore>) -> Self {
+        self.config_reloader.rate_limits().set_store(Some(store));
+        self
+    }
+
     /// Create a new AppContext for testing.
     #[cfg(any(test, feature = "testing"))]
     pub fn test() -> Self {
@@ -72,31 +105,130 @@ impl AppContext {
     }
 }
 
-impl TryFrom<Arc<dyn DataDir>> for AppContext {
-    type Error = AppContextConversionError;
+impl AppContext {
+    /// Open the virtual homeservers of `general.virtual_homeservers`.
+    ///
+    /// Each one has its own config, keypair, LMDB and storage in `{data_dir}/virtual/{name}`.
+    /// The pkarr client, the metrics and the global limits are shared with this context.
+    pub(crate) fn open_virtual_homeservers(&self) -> Result<Vec<Self>, AppContextConversionError> {
+        let mut contexts: Vec<Self> = Vec::new();
+        for name in &self.config_toml.general.virtual_homeservers {
+            let error =
+                |e: anyhow::Error| AppContextConversionError::VirtualHomeserver(name.clone(), e);
+            let is_valid_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !is_valid_name {
+                return Err(error(anyhow::anyhow!(
+                    "Names may only contain letters, digits, '-' and '_'"
+                )));
+            }
+            let dir: Arc<dyn DataDir> = Arc::new(PersistentDataDir::new(
+                self.data_dir.path().join("virtual").join(name),
+            ));
+            let context = Self::open(dir, Some(self)).map_err(|e| error(e.into()))?;
+            let public_key = context.keypair.public_key();
+            let is_duplicate = public_key == self.keypair.public_key()
+                || contexts
+                    .iter()
+                    .any(|other| other.keypair.public_key() == public_key);
+            if is_duplicate {
+                return Err(error(anyhow::anyhow!(
+                    "The keypair {public_key} is already used by another homeserver"
+                )));
+            }
+            if !context.config_toml.general.virtual_homeservers.is_empty() {
+                tracing::warn!(
+                    "Virtual homeserver '{name}' can't host virtual homeservers itself. Ignoring its general.virtual_homeservers."
+                );
+            }
+            contexts.push(context);
+        }
+        Ok(contexts)
+    }
 
-    fn try_from(dir: Arc<dyn DataDir>) -> Result<Self, Self::Error> {
+    /// Open the data directory. Shares the runtime resources of `runtime` if set.
+    fn open(
+        dir: Arc<dyn DataDir>,
+        runtime: Option<&AppContext>,
+    ) -> Result<Self, AppContextConversionError> {
         dir.ensure_data_dir_exists_and_is_writable()
             .map_err(AppContextConversionError::DataDir)?;
-        let conf = dir
+        let mut conf = dir
             .read_or_create_config_file()
             .map_err(AppContextConversionError::Config)?;
+        StorageMigrationState::apply_to_config(&mut conf, dir.path())
+            .map_err(|e| AppContextConversionError::Config(e.into()))?;
         let keypair = dir
             .read_or_create_keypair()
             .map_err(AppContextConversionError::Keypair)?;
 
         let db_path = dir.path().join("data/lmdb");
         let db = unsafe { LmDB::open(&db_path).map_err(AppContextConversionError::LmDB)? };
-        let file_service = FileService::new_from_config(&conf, dir.path(), db.clone())
+        let user_quota = UserQuota::from_config(&conf);
+        let file_service = FileService::new_from_config(&conf, dir.path(), db.clone(), &user_quota)
             .map_err(AppContextConversionError::Storage)?;
-        let pkarr_builder = Self::build_pkarr_builder_from_config(&conf);
+        let upload_service = UploadService::new_from_config(
+            &conf,
+            dir.path(),
+            file_service.clone(),
+            user_quota.clone(),
+        )
+        .map_err(AppContextConversionError::Storage)?;
+        let storage_migrator = StorageMigrator::new(
+            file_service.clone(),
+            upload_service.clone(),
+            &conf,
+            dir.path(),
+            user_quota.clone(),
+        );
+        let rate_limit_store: Option<Arc<dyn RateLimitStore>> = match conf.drive.rate_limit_backend
+        {
+            RateLimitBackend::Memory => None,
+            RateLimitBackend::Lmdb => Some(Arc::new(
+                LmdbRateLimitStore::new(db.clone()).map_err(AppContextConversionError::LmDB)?,
+            )),
+        };
+        let rate_limits = RateLimits::with_store(conf.drive.rate_limits.clone(), rate_limit_store)
+            .with_db(db.clone());
+        let (pkarr_builder, pkarr_client, metrics, global_limits) = match runtime {
+            Some(runtime) => (
+                runtime.pkarr_builder.clone(),
+                runtime.pkarr_client.clone(),
+                runtime.metrics.clone(),
+                runtime.global_limits.clone(),
+            ),
+            None => {
+                let pkarr_builder = Self::build_pkarr_builder_from_config(&conf);
+                let pkarr_client = pkarr_builder
+                    .clone()
+                    .build()
+                    .map_err(AppContextConversionError::Pkarr)?;
+                (
+                    pkarr_builder,
+                    pkarr_client,
+                    Metrics::default(),
+                    GlobalLimits::from_config(&conf.drive.global_limits),
+                )
+            }
+        };
         Ok(Self {
             db,
-            pkarr_client: pkarr_builder
-                .clone()
-                .build()
-                .map_err(AppContextConversionError::Pkarr)?,
+            pkarr_client,
             file_service,
+            storage_migrator,
+            upload_service,
+            metrics,
+            global_limits,
+            publish_status: PublishStatus::default(),
+            config_reloader: ConfigReloader::new(
+                dir.clone(),
+                &conf,
+                user_quota.clone(),
+                rate_limits,
+            ),
+            user_quota,
             pkarr_builder,
             config_toml: conf,
             keypair,
@@ -105,6 +237,14 @@ impl TryFrom<Arc<dyn DataDir>> for AppContext {
     }
 }
 
+impl TryFrom<Arc<dyn DataDir>> for AppContext {
+    type Error = AppContextConversionError;
+
+    fn try_from(dir: Arc<dyn DataDir>) -> Result<Self, Self::Error> {
+        Self::open(dir, None)
+    }
+}
+
 impl TryFrom<PersistentDataDir> for AppContext {
     type Error = AppContextConversionError;
 
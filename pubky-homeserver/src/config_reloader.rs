@@ -0,0 +1,230 @@
+//!
+//! Reloads the config.toml at runtime without restarting the homeserver.
+//!
+//! Settings like the rate limits, the signup mode, the user quota and the log levels
+//! are applied immediately. Other settings, for example listen sockets or the storage backend,
+//! only take effect after a restart and are reported as such.
+//!
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    core::layers::rate_limiter::RateLimits,
+    persistence::files::{StorageMigrationState, UserQuota},
+    quota_config::PathLimit,
+    tracing::reload_tracing_log_levels,
+    ConfigToml, DataDir, SignupMode,
+};
+
+/// Settings that [ConfigReloader] applies without a restart.
+const SIGNUP_MODE: &str = "general.signup_mode";
+const USER_QUOTA: &str = "general.user_storage_quota_mb";
+const RATE_LIMITS: &str = "drive.rate_limits";
+const LOGGING: &str = "logging";
+
+/// Settings that changed in a config reload.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigReloadReport {
+    /// Settings that are applied, for example `general.signup_mode`.
+    pub applied: Vec<String>,
+    /// Settings that only take effect after a restart, for example `drive.pubky_listen_socket`.
+    pub requires_restart: Vec<String>,
+}
+
+/// Reloads the config.toml and applies the settings that can be changed at runtime.
+///
+/// Cheap to clone. All clones share the same settings.
+#[derive(Debug, Clone)]
+pub struct ConfigReloader {
+    data_dir: Arc<dyn DataDir>,
+    /// The config that is currently in effect.
+    /// Settings that require a restart keep their startup value.
+    running: Arc<Mutex<ConfigToml>>,
+    signup_mode: Arc<RwLock<SignupMode>>,
+    user_quota: UserQuota,
+    rate_limits: RateLimits,
+}
+
+impl ConfigReloader {
+    pub(crate) fn new(
+        data_dir: Arc<dyn DataDir>,
+        config: &ConfigToml,
+        user_quota: UserQuota,
+        rate_limits: RateLimits,
+    ) -> Self {
+        Self {
+            data_dir,
+            running: Arc::new(Mutex::new(config.clone())),
+            signup_mode: Arc::new(RwLock::new(config.general.signup_mode.clone())),
+            user_quota,
+            rate_limits,
+        }
+    }
+
+    /// The current signup mode.
+    pub fn signup_mode(&self) -> SignupMode {
+        self.signup_mode
+            .read()
+            .expect("signup mode lock poisoned")
+            .clone()
+    }
+
+    /// The rate limits of the homeserver routes.
+    pub(crate) fn rate_limits(&self) -> &RateLimits {
+        &self.rate_limits
+    }
+
+    /// Replace the rate limits until the next config reload.
+    pub(crate) fn set_rate_limits(&self, limits: Vec<PathLimit>) {
+        let mut running = self.running.lock().expect("running config lock poisoned");
+        self.rate_limits.set(limits.clone());
+        running.drive.rate_limits = limits;
+    }
+
+    /// Read the config file from the data directory again and apply it.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the config file can't be read or is invalid. Nothing is applied in this case.
+    pub fn reload(&self) -> anyhow::Result<ConfigReloadReport> {
+        let mut config = self.data_dir.read_or_create_config_file()?;
+        // The storage may have been switched by a migration since the file was written.
+        StorageMigrationState::apply_to_config(&mut config, self.data_dir.path())?;
+        self.apply(config)
+    }
+
+    /// Apply a new config.
+    ///
+    /// Nothing is applied if an error is returned.
+    pub(crate) fn apply(&self, config: ConfigToml) -> anyhow::Result<ConfigReloadReport> {
+        let mut running = self.running.lock().expect("running config lock poisoned");
+        let keys = changed_keys(&running, &config)?;
+        // The log levels are the only setting that can fail to apply.
+        // Apply them before anything else so a failure leaves everything untouched.
+        // Removing the logging section doesn't uninstall the subscriber.
+        let logging_applied = match &config.logging {
+            Some(logging) if keys.iter().any(|key| key == LOGGING) => {
+                reload_tracing_log_levels(logging)?
+            }
+            _ => false,
+        };
+
+        let mut report = ConfigReloadReport::default();
+        for key in keys {
+            let applied = match key.as_str() {
+                SIGNUP_MODE => {
+                    *self.signup_mode.write().expect("signup mode lock poisoned") =
+                        config.general.signup_mode.clone();
+                    running.general.signup_mode = config.general.signup_mode.clone();
+                    true
+                }
+                USER_QUOTA => {
+                    self.user_quota.set_mb(config.general.user_storage_quota_mb);
+                    running.general.user_storage_quota_mb = config.general.user_storage_quota_mb;
+                    true
+                }
+                RATE_LIMITS => {
+                    self.rate_limits.set(config.drive.rate_limits.clone());
+                    running.drive.rate_limits = config.drive.rate_limits.clone();
+                    true
+                }
+                LOGGING => {
+                    if logging_applied {
+                        running.logging = config.logging.clone();
+                    }
+                    logging_applied
+                }
+                _ => false,
+            };
+            if applied {
+                report.applied.push(key);
+            } else {
+                report.requires_restart.push(key);
+            }
+        }
+
+        if !report.applied.is_empty() {
+            tracing::info!("Config reloaded. Applied: {}", report.applied.join(", "));
+        }
+        if !report.requires_restart.is_empty() {
+            tracing::warn!(
+                "Config reloaded. Restart to apply: {}",
+                report.requires_restart.join(", ")
+            );
+        }
+        Ok(report)
+    }
+}
+
+/// Keys of the settings that differ between two configs, for example `admin.listen_socket`.
+///
+/// The storage and logging sections are compared as a whole.
+fn changed_keys(old: &ConfigToml, new: &ConfigToml) -> anyhow::Result<Vec<String>> {
+    let old = serde_json::to_value(old)?;
+    let new = serde_json::to_value(new)?;
+    let (Value::Object(old), Value::Object(new)) = (old, new) else {
+        anyhow::bail!("Config is not a table");
+    };
+    let mut keys = vec![];
+    for (section, new_value) in &new {
+        let old_value = old.get(section).unwrap_or(&Value::Null);
+        if old_value == new_value {
+            continue;
+        }
+        match (section.as_str(), old_value, new_value) {
+            ("storage" | LOGGING, _, _) => keys.push(section.clone()),
+            (_, Value::Object(old_fields), Value::Object(new_fields)) => {
+                for (field, new_field) in new_fields {
+                    if old_fields.get(field) != Some(new_field) {
+                        keys.push(format!("{section}.{field}"));
+                    }
+                }
+            }
+            _ => keys.push(section.clone()),
+        }
+    }
+    keys.sort();
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quota_config::{GlobPattern, LimitKeyType};
+    use crate::AppContext;
+    use axum::http::Method;
+
+    #[tokio::test]
+    async fn test_apply_config() {
+        let context = AppContext::test();
+        let reloader = context.config_reloader.clone();
+        assert_eq!(reloader.signup_mode(), SignupMode::Open);
+
+        let mut config = context.config_toml.clone();
+        config.general.signup_mode = SignupMode::TokenRequired;
+        config.general.user_storage_quota_mb = 5;
+        config.drive.rate_limits = vec![PathLimit::new(
+            GlobPattern::new("/pub/*"),
+            Method::GET,
+            "1r/s".parse().unwrap(),
+            LimitKeyType::Ip,
+            None,
+        )];
+        config.admin.listen_socket = "127.0.0.1:7777".parse().unwrap();
+        let report = reloader.apply(config.clone()).unwrap();
+
+        assert_eq!(report.applied, vec![RATE_LIMITS, SIGNUP_MODE, USER_QUOTA]);
+        assert_eq!(report.requires_restart, vec!["admin.listen_socket"]);
+        assert_eq!(reloader.signup_mode(), SignupMode::TokenRequired);
+        assert_eq!(context.user_quota.limit(), Some(5 * 1024 * 1024));
+        assert_eq!(reloader.rate_limits().limits(), config.drive.rate_limits);
+
+        // Settings that require a restart are reported until the restart.
+        let report = reloader.apply(config).unwrap();
+        assert!(report.applied.is_empty());
+        assert_eq!(report.requires_restart, vec!["admin.listen_socket"]);
+    }
+}
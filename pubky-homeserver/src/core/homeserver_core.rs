@@ -1,16 +1,23 @@
 use std::path::PathBuf;
 use std::time::Duration;
 
+use super::http3::Http3Server;
+use super::icann_tls::IcannTls;
 use super::key_republisher::HomeserverKeyRepublisher;
-use super::periodic_backup::PeriodicBackup;
+use super::periodic_backup::{do_backup, PeriodicBackup};
+use super::periodic_scrub::PeriodicScrub;
+use super::periodic_upload_cleanup::PeriodicUploadCleanup;
+use super::proxy_protocol::ProxyProtocolAcceptor;
+use super::unix_socket::{ServerHandle, UnixSocketServer, GRACEFUL_SHUTDOWN};
+use super::virtual_hosts::{ServerNameAcceptor, VirtualHomeserver, VirtualHosts};
 use crate::app_context::AppContextConversionError;
 use crate::core::user_keys_republisher::UserKeysRepublisher;
-use crate::persistence::files::FileService;
+use crate::persistence::files::{FileService, UploadService, UserQuota};
 use crate::persistence::lmdb::LmDB;
 #[cfg(any(test, feature = "testing"))]
 use crate::MockDataDir;
 use crate::{app_context::AppContext, PersistentDataDir};
-use crate::{DataDir, SignupMode};
+use crate::{ConfigReloader, DataDir, ListenAddr};
 use anyhow::Result;
 use axum::Router;
 use axum_server::{
@@ -18,20 +25,23 @@ use axum_server::{
     Handle,
 };
 use futures_util::TryFutureExt;
+use pkarr::PublicKey;
 use pubky_common::auth::AuthVerifier;
 use std::{
     net::{SocketAddr, TcpListener},
     sync::Arc,
 };
+use tokio::time::Instant;
 
 #[derive(Clone, Debug)]
 pub(crate) struct AppState {
     pub(crate) verifier: AuthVerifier,
     pub(crate) db: LmDB,
     pub(crate) file_service: FileService,
-    pub(crate) signup_mode: SignupMode,
-    /// If `Some(bytes)` the quota is enforced, else unlimited.
-    pub(crate) user_quota_bytes: Option<u64>,
+    pub(crate) upload_service: UploadService,
+    /// Provides the signup mode, which can change at runtime.
+    pub(crate) config_reloader: ConfigReloader,
+    pub(crate) user_quota: UserQuota,
 }
 
 const INITIAL_DELAY_BEFORE_REPUBLISH: Duration = Duration::from_secs(60);
@@ -45,9 +55,15 @@ pub enum HomeserverBuildError {
     /// Failed to run the ICANN web server.
     #[error("ICANN web server error: {0}")]
     IcannWebServer(anyhow::Error),
+    /// Failed to load or obtain the certificate of the ICANN web server.
+    #[error("ICANN TLS error: {0}")]
+    IcannTls(anyhow::Error),
     /// Failed to run the Pubky TLS web server.
     #[error("Pubky TLS web server error: {0}")]
     PubkyTlsServer(anyhow::Error),
+    /// Failed to run the Pubky HTTP/3 server.
+    #[error("Pubky HTTP/3 server error: {0}")]
+    PubkyHttp3Server(anyhow::Error),
     /// Failed to convert the data directory to an AppContext.
     #[error("AppContext conversion error: {0}")]
     AppContext(AppContextConversionError),
@@ -61,14 +77,27 @@ pub struct HomeserverCore {
     #[allow(dead_code)]
     // Keep this alive. Republishing is stopped when the HomeserverKeyRepublisher is dropped.
     pub(crate) key_republisher: HomeserverKeyRepublisher,
-    #[allow(dead_code)] // Keep this alive. Backup is stopped when the PeriodicBackup is dropped.
+    /// Keep this alive. Backup is stopped when the PeriodicBackup is dropped.
     pub(crate) periodic_backup: PeriodicBackup,
+    /// Keep this alive. Scrubbing is stopped when the PeriodicScrub is dropped.
+    pub(crate) periodic_scrub: PeriodicScrub,
+    #[allow(dead_code)]
+    // Keep this alive. Cleanup is stopped when the PeriodicUploadCleanup is dropped.
+    pub(crate) periodic_upload_cleanup: PeriodicUploadCleanup,
     /// Keep context alive.
     context: AppContext,
-    pub(crate) icann_http_handle: Handle,
+    pub(crate) icann_http_handle: ServerHandle,
+    /// Only set if `drive.icann_tls` is configured. Keeps the certificate renewal alive.
+    pub(crate) icann_tls: Option<IcannTls>,
     pub(crate) pubky_tls_handle: Handle,
-    pub(crate) icann_http_socket: SocketAddr,
+    /// Only running if `drive.pubky_http3` is enabled.
+    pub(crate) pubky_http3: Option<Http3Server>,
+    pub(crate) icann_http_socket: ListenAddr,
     pub(crate) pubky_tls_socket: SocketAddr,
+    /// The virtual homeservers of `general.virtual_homeservers`, served by the same listeners.
+    pub(crate) virtual_homeservers: Vec<VirtualHomeserver>,
+    /// Routes the requests of the virtual homeservers.
+    pub(crate) virtual_hosts: VirtualHosts,
 }
 
 impl HomeserverCore {
@@ -108,94 +137,162 @@ impl HomeserverCore {
     /// - (Optional) Publishes the user's keys to the DHT.
     /// - (Optional) Runs a periodic backup of the database.
     /// - Creates the web server (router) for testing. Use `listen` to start the server.
+    /// - (Optional) Hosts the virtual homeservers of `general.virtual_homeservers`.
     pub async fn new(context: AppContext) -> std::result::Result<Self, HomeserverBuildError> {
-        let router = Self::create_router(&context);
+        let virtual_contexts = context
+            .open_virtual_homeservers()
+            .map_err(HomeserverBuildError::AppContext)?;
+        let virtual_hosts = VirtualHosts::new(&context, &virtual_contexts);
+        let router = virtual_hosts.route(Self::create_router(&context));
 
+        let icann_tls = IcannTls::start(&context).map_err(HomeserverBuildError::IcannTls)?;
         let (icann_http_handle, icann_http_socket) =
-            Self::start_icann_http_server(&context, router.clone())
+            Self::start_icann_http_server(&context, router.clone(), icann_tls.as_ref())
                 .await
                 .map_err(HomeserverBuildError::IcannWebServer)?;
-        let (pubky_tls_handle, pubky_tls_socket) = Self::start_pubky_tls_server(&context, router)
-            .await
-            .map_err(HomeserverBuildError::PubkyTlsServer)?;
+        let (pubky_tls_handle, pubky_tls_socket) =
+            Self::start_pubky_tls_server(&context, router.clone(), &virtual_hosts)
+                .await
+                .map_err(HomeserverBuildError::PubkyTlsServer)?;
+        // Same port as the TLS server, so clients find it with the same SVCB record.
+        let pubky_http3 = if context.config_toml.drive.pubky_http3 {
+            let tls = virtual_hosts.rustls_config(&context.keypair);
+            let server = Http3Server::start(&context, router, pubky_tls_socket, tls)
+                .map_err(HomeserverBuildError::PubkyHttp3Server)?;
+            Some(server)
+        } else {
+            None
+        };
 
-        let key_republisher = HomeserverKeyRepublisher::start(
-            &context,
-            icann_http_socket.port(),
-            pubky_tls_socket.port(),
-        )
-        .await
-        .map_err(HomeserverBuildError::KeyRepublisher)?;
+        // Behind a Unix socket, the proxy in front usually serves port 80.
+        // `pkdns.public_icann_http_port` overrides it.
+        let icann_http_port = icann_http_socket.tcp().map_or(80, |socket| socket.port());
+        let key_republisher =
+            HomeserverKeyRepublisher::start(&context, icann_http_port, pubky_tls_socket.port())
+                .await
+                .map_err(HomeserverBuildError::KeyRepublisher)?;
         let user_keys_republisher =
             UserKeysRepublisher::start_delayed(&context, INITIAL_DELAY_BEFORE_REPUBLISH);
         let periodic_backup = PeriodicBackup::start(&context);
+        let periodic_scrub = PeriodicScrub::start(&context);
+        let periodic_upload_cleanup = PeriodicUploadCleanup::start(&context);
+        let mut virtual_homeservers = Vec::with_capacity(virtual_contexts.len());
+        for virtual_context in virtual_contexts {
+            let virtual_homeserver = VirtualHomeserver::start(
+                virtual_context,
+                icann_http_port,
+                pubky_tls_socket.port(),
+                INITIAL_DELAY_BEFORE_REPUBLISH,
+            )
+            .await
+            .map_err(HomeserverBuildError::KeyRepublisher)?;
+            virtual_homeservers.push(virtual_homeserver);
+        }
 
         Ok(Self {
             user_keys_republisher,
             key_republisher,
             periodic_backup,
+            periodic_scrub,
+            periodic_upload_cleanup,
             context,
             icann_http_handle,
+            icann_tls,
             pubky_tls_handle,
+            pubky_http3,
             icann_http_socket,
             pubky_tls_socket,
+            virtual_homeservers,
+            virtual_hosts,
         })
     }
 
     pub(crate) fn create_router(context: &AppContext) -> Router {
-        let quota_mb = context.config_toml.general.user_storage_quota_mb;
-        let quota_bytes = if quota_mb == 0 {
-            None
-        } else {
-            Some(quota_mb * 1024 * 1024)
-        };
-
         let state = AppState {
             verifier: AuthVerifier::default(),
             db: context.db.clone(),
             file_service: context.file_service.clone(),
-            signup_mode: context.config_toml.general.signup_mode.clone(),
-            user_quota_bytes: quota_bytes,
+            upload_service: context.upload_service.clone(),
+            config_reloader: context.config_reloader.clone(),
+            user_quota: context.user_quota.clone(),
         };
         super::routes::create_app(state.clone(), context)
     }
 
-    /// Start the ICANN HTTP server
+    /// Reads the PROXY protocol header of incoming connections if enabled.
+    fn proxy_protocol_acceptor(context: &AppContext) -> ProxyProtocolAcceptor {
+        ProxyProtocolAcceptor::new(
+            context.config_toml.drive.proxy_protocol,
+            context.config_toml.drive.trusted_proxies.clone(),
+            context.global_limits.clone(),
+        )
+    }
+
+    /// Start the ICANN HTTP server, serving HTTPS if `tls` is set.
     async fn start_icann_http_server(
         context: &AppContext,
         router: Router,
-    ) -> Result<(Handle, SocketAddr)> {
+        tls: Option<&IcannTls>,
+    ) -> Result<(ServerHandle, ListenAddr)> {
+        let socket = match &context.config_toml.drive.icann_listen_socket {
+            ListenAddr::Tcp(socket) => *socket,
+            ListenAddr::Unix(path) => {
+                if tls.is_some() {
+                    anyhow::bail!("drive.icann_tls needs a TCP drive.icann_listen_socket");
+                }
+                let server = UnixSocketServer::start(path, router)?;
+                return Ok((ServerHandle::Unix(server), ListenAddr::Unix(path.clone())));
+            }
+        };
         // Icann http server
-        let http_listener = TcpListener::bind(context.config_toml.drive.icann_listen_socket)?;
+        let http_listener = TcpListener::bind(socket)?;
         let http_socket = http_listener.local_addr()?;
         let http_handle = Handle::new();
-        tokio::spawn(
-            axum_server::from_tcp(http_listener)
-                .handle(http_handle.clone())
-                .serve(router.into_make_service_with_connect_info::<SocketAddr>())
-                .map_err(|error| {
-                    tracing::error!(?error, "Homeserver icann http server error");
-                    println!("Homeserver icann http server error: {:?}", error);
-                }),
-        );
+        let server = axum_server::from_tcp(http_listener).handle(http_handle.clone());
+        let make_service = router.into_make_service_with_connect_info::<SocketAddr>();
+        let log_error = |error| {
+            tracing::error!(?error, "Homeserver icann http server error");
+            println!("Homeserver icann http server error: {:?}", error);
+        };
+        match tls {
+            Some(tls) => tokio::spawn(
+                server
+                    .acceptor(
+                        RustlsAcceptor::new(tls.rustls_config())
+                            .acceptor(Self::proxy_protocol_acceptor(context)),
+                    )
+                    .serve(make_service)
+                    .map_err(log_error),
+            ),
+            None => tokio::spawn(
+                server
+                    .acceptor(Self::proxy_protocol_acceptor(context))
+                    .serve(make_service)
+                    .map_err(log_error),
+            ),
+        };
 
-        Ok((http_handle, http_socket))
+        Ok((ServerHandle::Tcp(http_handle), ListenAddr::Tcp(http_socket)))
     }
 
-    /// Start the Pubky TLS server
+    /// Start the Pubky TLS server. Presents the keypair of the virtual homeserver the client connects to.
     async fn start_pubky_tls_server(
         context: &AppContext,
         router: Router,
+        virtual_hosts: &VirtualHosts,
     ) -> Result<(Handle, SocketAddr)> {
         // Pubky tls server
         let https_listener = TcpListener::bind(context.config_toml.drive.pubky_listen_socket)?;
         let https_socket = https_listener.local_addr()?;
         let https_handle = Handle::new();
+        let mut tls = virtual_hosts.rustls_config(&context.keypair);
+        tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
         tokio::spawn(
             axum_server::from_tcp(https_listener)
-                .acceptor(RustlsAcceptor::new(RustlsConfig::from_config(Arc::new(
-                    context.keypair.to_rpk_rustls_server_config(),
-                ))))
+                .acceptor(ServerNameAcceptor(
+                    RustlsAcceptor::new(RustlsConfig::from_config(Arc::new(tls)))
+                        .acceptor(Self::proxy_protocol_acceptor(context)),
+                ))
                 .handle(https_handle.clone())
                 .serve(router.into_make_service_with_connect_info::<SocketAddr>())
                 .map_err(|error| {
@@ -207,9 +304,20 @@ impl HomeserverCore {
         Ok((https_handle, https_socket))
     }
 
-    /// Get the URL of the icann http server.
+    /// Get the URL of the icann http server. Uses `https` if `drive.icann_tls` is configured.
+    ///
+    /// On a Unix domain socket, this is its `unix:/path` address.
     pub fn icann_http_url(&self) -> String {
-        format!("http://{}", self.icann_http_socket)
+        let socket = match &self.icann_http_socket {
+            ListenAddr::Tcp(socket) => socket,
+            unix => return unix.to_string(),
+        };
+        let scheme = if self.icann_tls.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        format!("{scheme}://{socket}")
     }
 
     /// Get the URL of the pubky tls server with the Pubky DNS name.
@@ -217,6 +325,22 @@ impl HomeserverCore {
         format!("https://{}", self.context.keypair.public_key())
     }
 
+    /// The contexts of the virtual homeservers.
+    pub(crate) fn virtual_contexts(&self) -> Vec<AppContext> {
+        self.virtual_homeservers
+            .iter()
+            .map(|virtual_homeserver| virtual_homeserver.context.clone())
+            .collect()
+    }
+
+    /// Get the public keys of the virtual homeservers.
+    pub fn virtual_homeservers(&self) -> Vec<PublicKey> {
+        self.virtual_homeservers
+            .iter()
+            .map(|virtual_homeserver| virtual_homeserver.context.keypair.public_key())
+            .collect()
+    }
+
     /// Get the URL of the pubky tls server with the Pubky IP address.
     pub fn pubky_tls_ip_url(&self) -> String {
         format!("https://{}", self.pubky_tls_socket)
@@ -224,10 +348,87 @@ impl HomeserverCore {
 
     /// Shutdown the http and tls servers.
     pub fn shutdown(&self) {
-        self.icann_http_handle
-            .graceful_shutdown(Some(Duration::from_secs(5)));
-        self.pubky_tls_handle
-            .graceful_shutdown(Some(Duration::from_secs(5)));
+        self.stop_accepting(GRACEFUL_SHUTDOWN);
+        if let Some(http3) = &self.pubky_http3 {
+            http3.shutdown();
+        }
+    }
+
+    /// Stop accepting connections and give open ones `grace` to finish.
+    fn stop_accepting(&self, grace: Duration) {
+        self.icann_http_handle.graceful_shutdown(grace);
+        self.pubky_tls_handle.graceful_shutdown(Some(grace));
+        if let Some(http3) = &self.pubky_http3 {
+            http3.stop_accepting();
+        }
+    }
+
+    /// Shutdown gracefully.
+    ///
+    /// All steps share one deadline of `general.shutdown_timeout_s`.
+    ///
+    /// 1. Stops accepting new connections.
+    /// 2. Waits for the writes in progress, also the ones of the admin server.
+    /// 3. Stops the republishers, also of the virtual homeservers. A homeserver key publish
+    ///    in progress is finished, a user keys run is cancelled.
+    /// 4. Stops the periodic backup, the storage scrub and a storage migration in progress.
+    ///    A backup in progress is finished, a migration resumes on the next start.
+    /// 5. Writes the buffered rate limit state, flushes LMDB to disk and writes a final backup if `general.backup_on_shutdown` is set.
+    pub async fn graceful_shutdown(&mut self) {
+        let timeout = Duration::from_secs(self.context.config_toml.general.shutdown_timeout_s);
+        let deadline = Instant::now() + timeout;
+        self.stop_accepting(timeout);
+
+        let still_running = self.context.global_limits.drain_writes(deadline).await;
+        if still_running > 0 {
+            tracing::warn!("Shutdown deadline reached with {still_running} writes in progress.");
+        }
+        if let Some(http3) = &self.pubky_http3 {
+            http3.shutdown();
+        }
+
+        self.key_republisher.graceful_stop(deadline).await;
+        self.user_keys_republisher.graceful_stop(deadline).await;
+        self.periodic_backup.stop(deadline).await;
+        self.periodic_scrub.stop(deadline).await;
+        self.context.storage_migrator.stop(deadline).await;
+
+        for virtual_homeserver in &mut self.virtual_homeservers {
+            virtual_homeserver.graceful_stop(deadline).await;
+        }
+
+        flush_lmdb(&self.context).await;
+        for virtual_homeserver in &self.virtual_homeservers {
+            flush_lmdb(&virtual_homeserver.context).await;
+        }
+    }
+}
+
+/// Flush LMDB to disk and write a backup if `general.backup_on_shutdown` is set.
+async fn flush_lmdb(context: &AppContext) {
+    let db = context.db.clone();
+    let rate_limits = context.config_reloader.rate_limits().clone();
+    let backup_path = context
+        .config_toml
+        .general
+        .backup_on_shutdown
+        .then(|| context.data_dir.path().join("backup"));
+    let flushed = tokio::task::spawn_blocking(move || {
+        if let Err(e) = rate_limits.flush_store() {
+            tracing::error!("Failed to write rate limit state: {e}");
+        }
+        db.env.force_sync()?;
+        if let Some(backup_path) = backup_path {
+            do_backup(db, backup_path);
+        }
+        Ok::<_, heed::Error>(())
+    })
+    .await;
+    let path = context.data_dir.path().display();
+    match flushed {
+        Ok(Ok(())) => tracing::info!("Flushed LMDB of {path} to disk."),
+        Ok(Err(e)) => tracing::error!("Failed to flush LMDB of {path} to disk: {e}"),
+        Err(e) => tracing::error!("LMDB flush of {path} panicked: {e}"),
     }
 }
 
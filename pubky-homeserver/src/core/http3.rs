@@ -0,0 +1,233 @@
+//!
+//! HTTP/3 listener for the Pubky TLS server.
+//!
+//! Serves the same router over QUIC on the UDP port of the Pubky TLS listener,
+//! with the same raw public key identity. Clients on lossy networks benefit from
+//! QUIC because a lost packet doesn't block the other streams of the connection.
+//!
+
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use axum::{body::Body, extract::ConnectInfo, http::Request, Router};
+use bytes::{Buf, Bytes};
+use h3::server::RequestStream;
+use http_body_util::BodyExt;
+use quinn::crypto::rustls::{HandshakeData, QuicServerConfig};
+use tower::ServiceExt;
+
+use super::{
+    layers::global_limits::GlobalLimits, proxy_protocol::PeerAddr, virtual_hosts::TlsServerName,
+};
+use crate::app_context::AppContext;
+
+/// ALPN protocol id of HTTP/3.
+pub(crate) const ALPN_H3: &[u8] = b"h3";
+
+/// A running HTTP/3 listener. Closed with [Http3Server::shutdown].
+#[derive(Debug)]
+pub(crate) struct Http3Server {
+    endpoint: quinn::Endpoint,
+}
+
+impl Http3Server {
+    /// Start serving `router` on the UDP `socket` with the Pubky TLS config `tls`.
+    pub fn start(
+        context: &AppContext,
+        router: Router,
+        socket: SocketAddr,
+        mut tls: rustls::ServerConfig,
+    ) -> Result<Self> {
+        tls.alpn_protocols = vec![ALPN_H3.to_vec()];
+        let crypto = QuicServerConfig::try_from(tls)?;
+        let endpoint =
+            quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), socket)?;
+
+        let global_limits = context.global_limits.clone();
+        let accepting = endpoint.clone();
+        tokio::spawn(async move {
+            while let Some(incoming) = accepting.accept().await {
+                tokio::spawn(serve_connection(
+                    incoming,
+                    router.clone(),
+                    global_limits.clone(),
+                ));
+            }
+        });
+        Ok(Self { endpoint })
+    }
+
+    /// Refuse new connections. Open ones keep being served.
+    pub fn stop_accepting(&self) {
+        self.endpoint.set_server_config(None);
+    }
+
+    /// Close all connections and stop accepting new ones.
+    pub fn shutdown(&self) {
+        self.endpoint.close(0u32.into(), b"shutdown");
+    }
+}
+
+async fn serve_connection(incoming: quinn::Incoming, router: Router, global_limits: GlobalLimits) {
+    let connection = match incoming.await {
+        Ok(connection) => connection,
+        Err(e) => {
+            tracing::debug!("HTTP/3 handshake failed: {e}");
+            return;
+        }
+    };
+    let remote = connection.remote_address();
+    let server_name = connection
+        .handshake_data()
+        .and_then(|data| data.downcast::<HandshakeData>().ok())
+        .and_then(|data| data.server_name)
+        .map(TlsServerName);
+    // Counted until all requests of the connection are done.
+    let slot = Some(global_limits.open_connection(remote.ip()));
+    let mut connection =
+        match h3::server::Connection::new(h3_quinn::Connection::new(connection)).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                tracing::debug!("HTTP/3 connection from {remote} failed: {e}");
+                return;
+            }
+        };
+
+    loop {
+        match connection.accept().await {
+            Ok(Some(resolver)) => {
+                let router = router.clone();
+                let slot = slot.clone();
+                let server_name = server_name.clone();
+                tokio::spawn(async move {
+                    let (mut req, stream) = match resolver.resolve_request().await {
+                        Ok(request) => request,
+                        Err(e) => {
+                            tracing::debug!("Invalid HTTP/3 request from {remote}: {e}");
+                            return;
+                        }
+                    };
+                    req.extensions_mut().insert(ConnectInfo(remote));
+                    req.extensions_mut().insert(PeerAddr(remote));
+                    req.extensions_mut().insert(slot);
+                    req.extensions_mut().insert(server_name);
+                    if let Err(e) = serve_request(req, stream, router).await {
+                        tracing::debug!("HTTP/3 request from {remote} failed: {e}");
+                    }
+                });
+            }
+            Ok(None) => break,
+            Err(e) => {
+                tracing::debug!("HTTP/3 connection from {remote} closed: {e}");
+                break;
+            }
+        }
+    }
+}
+
+async fn serve_request<S>(
+    req: Request<()>,
+    stream: RequestStream<S, Bytes>,
+    router: Router,
+) -> Result<()>
+where
+    S: h3::quic::BidiStream<Bytes> + Send + 'static,
+    S::RecvStream: Send,
+{
+    let (mut send, recv) = stream.split();
+
+    let body = futures_util::stream::unfold(Some(recv), |recv| async move {
+        let mut recv = recv?;
+        match recv.recv_data().await {
+            Ok(Some(mut chunk)) => Some((Ok(chunk.copy_to_bytes(chunk.remaining())), Some(recv))),
+            Ok(None) => None,
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+    let (parts, ()) = req.into_parts();
+    let req = Request::from_parts(parts, Body::from_stream(body));
+
+    let response = router.oneshot(req).await?;
+    let (parts, mut body) = response.into_parts();
+    send.send_response(axum::http::Response::from_parts(parts, ()))
+        .await?;
+    while let Some(frame) = body.frame().await {
+        let frame = frame?;
+        match frame.into_data() {
+            Ok(data) => send.send_data(data).await?,
+            Err(frame) => {
+                if let Ok(trailers) = frame.into_trailers() {
+                    send.send_trailers(trailers).await?;
+                }
+            }
+        }
+    }
+    send.finish().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::StatusCode,
+        routing::{get, put},
+    };
+    use quinn::crypto::rustls::QuicClientConfig;
+
+    use super::*;
+    use crate::core::key_republisher::create_signed_packet;
+
+    #[tokio::test]
+    async fn test_serve_http3() {
+        let context = AppContext::test();
+        let router = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route("/echo", put(|body: Bytes| async move { body }));
+        let server = Http3Server::start(
+            &context,
+            router,
+            "127.0.0.1:0".parse().unwrap(),
+            context.keypair.to_rpk_rustls_server_config(),
+        )
+        .unwrap();
+        let addr = server.endpoint.local_addr().unwrap();
+
+        // Verify the raw public key like the pubky client does,
+        // with the packet of the homeserver in the cache instead of the DHT.
+        let pubkey = context.keypair.public_key();
+        let packet = create_signed_packet(&context, 0, addr.port()).unwrap();
+        context
+            .pkarr_client
+            .cache()
+            .unwrap()
+            .put(&(&pubkey).into(), &packet);
+        let mut tls: quinn::rustls::ClientConfig = context.pkarr_client.clone().into();
+        tls.alpn_protocols = vec![ALPN_H3.to_vec()];
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+            QuicClientConfig::try_from(tls).unwrap(),
+        )));
+        let pubkey = pubkey.to_string();
+        let connection = endpoint.connect(addr, &pubkey).unwrap().await.unwrap();
+        let (mut driver, mut send_request) = h3::client::new(h3_quinn::Connection::new(connection))
+            .await
+            .unwrap();
+        tokio::spawn(async move { driver.wait_idle().await });
+
+        let request = Request::put(format!("https://{pubkey}/echo"))
+            .body(())
+            .unwrap();
+        let mut stream = send_request.send_request(request).await.unwrap();
+        stream
+            .send_data(Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        stream.finish().await.unwrap();
+        let response = stream.recv_response().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = stream.recv_data().await.unwrap().unwrap();
+        assert_eq!(body.copy_to_bytes(body.remaining()), "hello");
+
+        server.shutdown();
+    }
+}
@@ -0,0 +1,654 @@
+//!
+//! TLS for the ICANN listener.
+//!
+//! The certificate is either loaded from PEM files or obtained from an ACME server
+//! like Let's Encrypt with the HTTP-01 challenge and renewed in the background.
+//! ACME state is kept in the `acme` folder of the data directory, so restarts
+//! reuse the account and the certificate.
+//!
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    routing::get,
+    Router,
+};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, OrderStatus, RetryPolicy,
+};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::{app_context::AppContext, AcmeToml, IcannTlsToml};
+
+/// Renewal age of certificates whose validity can't be read.
+const RENEW_AFTER_ISSUANCE: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+/// How often the certificate is checked for renewal.
+const RENEWAL_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// Minimum delay between orders, so failing or short lived certificates don't hammer the ACME server.
+const RENEWAL_RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long the ACME server gets to validate the challenges and to issue the certificate.
+const ORDER_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Key authorizations of pending HTTP-01 challenges by token.
+///
+/// Served on `/.well-known/acme-challenge/{token}` while the ACME server validates them.
+type Challenges = Arc<RwLock<HashMap<String, String>>>;
+
+/// The TLS config of the ICANN listener and the tasks that keep its certificate fresh.
+pub(crate) struct IcannTls {
+    rustls_config: RustlsConfig,
+    /// Only with ACME.
+    challenge_server: Option<Handle>,
+    renewal: Option<JoinHandle<()>>,
+}
+
+impl IcannTls {
+    /// Load the certificate of the ICANN listener if TLS is enabled.
+    ///
+    /// With ACME, the challenge server is started and the stored certificate is served.
+    /// Without a stored certificate, a self-signed one is served until the ACME certificate
+    /// is issued in the background, so the homeserver never waits for the ACME server.
+    pub fn start(context: &AppContext) -> Result<Option<Self>> {
+        let data_dir = context.data_dir.path();
+        match &context.config_toml.drive.icann_tls {
+            None => Ok(None),
+            Some(IcannTlsToml::Files {
+                cert_path,
+                key_path,
+            }) => {
+                let chain = std::fs::read(data_dir.join(cert_path))
+                    .with_context(|| format!("Failed to read {}", cert_path.display()))?;
+                let key = std::fs::read(data_dir.join(key_path))
+                    .with_context(|| format!("Failed to read {}", key_path.display()))?;
+                let rustls_config =
+                    RustlsConfig::from_config(Arc::new(server_config(&chain, &key)?));
+                Ok(Some(Self {
+                    rustls_config,
+                    challenge_server: None,
+                    renewal: None,
+                }))
+            }
+            Some(IcannTlsToml::Acme(config)) => {
+                let manager = AcmeManager::new(context, config)?;
+                let challenge_server = manager.start_challenge_server()?;
+                let stored =
+                    manager
+                        .load()
+                        .and_then(|(chain, key)| match server_config(&chain, &key) {
+                            Ok(server_config) => Some((chain, server_config)),
+                            Err(e) => {
+                                tracing::warn!("Ignoring the stored ACME certificate: {e}");
+                                None
+                            }
+                        });
+                let (chain, server_config) = match stored {
+                    Some((chain, server_config)) => (Some(chain), server_config),
+                    None => {
+                        tracing::info!(
+                            "Serving a self-signed certificate until the ACME certificate is issued"
+                        );
+                        let (chain, key) = self_signed(&manager.domains)?;
+                        (None, server_config(&chain, &key)?)
+                    }
+                };
+                let rustls_config = RustlsConfig::from_config(Arc::new(server_config));
+                let renewal =
+                    tokio::spawn(renew_periodically(manager, chain, rustls_config.clone()));
+                Ok(Some(Self {
+                    rustls_config,
+                    challenge_server: Some(challenge_server),
+                    renewal: Some(renewal),
+                }))
+            }
+        }
+    }
+
+    /// The TLS config of the listener. Updated when the certificate is renewed.
+    pub fn rustls_config(&self) -> RustlsConfig {
+        self.rustls_config.clone()
+    }
+}
+
+impl Drop for IcannTls {
+    fn drop(&mut self) {
+        if let Some(renewal) = self.renewal.take() {
+            renewal.abort();
+        }
+        if let Some(handle) = &self.challenge_server {
+            handle.shutdown();
+        }
+    }
+}
+
+/// Build the rustls config of a PEM encoded certificate chain and private key.
+fn server_config(chain_pem: &[u8], key_pem: &[u8]) -> Result<rustls::ServerConfig> {
+    let certs = rustls_pemfile::certs(&mut &chain_pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .context("Invalid certificate chain")?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificate in the certificate chain"));
+    }
+    let key = rustls_pemfile::private_key(&mut &key_pem[..])
+        .context("Invalid private key")?
+        .ok_or_else(|| anyhow!("No private key in the key file"))?;
+    let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// A PEM encoded self-signed certificate and key for `domains`.
+fn self_signed(domains: &[String]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let certified = rcgen::generate_simple_self_signed(domains.to_vec())?;
+    Ok((
+        certified.cert.pem().into_bytes(),
+        certified.signing_key.serialize_pem().into_bytes(),
+    ))
+}
+
+/// The validity period of a DER encoded certificate.
+fn validity(der: &[u8]) -> Result<(SystemTime, SystemTime)> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der)?;
+    let to_system_time = |timestamp: i64| {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(timestamp).unwrap_or_default())
+    };
+    let validity = cert.validity();
+    Ok((
+        to_system_time(validity.not_before.timestamp()),
+        to_system_time(validity.not_after.timestamp()),
+    ))
+}
+
+/// The ACME account with the directory it was created on.
+#[derive(Serialize, Deserialize)]
+struct StoredAccount {
+    directory_url: String,
+    credentials: AccountCredentials,
+}
+
+/// Obtains certificates from the ACME server and keeps them in the data directory.
+struct AcmeManager {
+    config: AcmeToml,
+    domains: Vec<String>,
+    dir: PathBuf,
+    /// Root certificate of the ACME server if it isn't publicly trusted.
+    ca_cert_path: Option<PathBuf>,
+    challenges: Challenges,
+}
+
+impl AcmeManager {
+    fn new(context: &AppContext, config: &AcmeToml) -> Result<Self> {
+        let domains: Vec<String> = if config.domains.is_empty() {
+            context
+                .config_toml
+                .pkdns
+                .icann_domain
+                .iter()
+                .map(|domain| domain.to_string())
+                .collect()
+        } else {
+            config
+                .domains
+                .iter()
+                .map(|domain| domain.to_string())
+                .collect()
+        };
+        if domains.is_empty() {
+            return Err(anyhow!(
+                "ACME needs drive.icann_tls.domains or pkdns.icann_domain"
+            ));
+        }
+        let data_dir = context.data_dir.path();
+        let dir = data_dir.join("acme");
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            config: config.clone(),
+            domains,
+            dir,
+            ca_cert_path: config.ca_cert_path.as_ref().map(|path| data_dir.join(path)),
+            challenges: Default::default(),
+        })
+    }
+
+    fn cert_path(&self) -> PathBuf {
+        self.dir.join("cert.pem")
+    }
+
+    fn key_path(&self) -> PathBuf {
+        self.dir.join("key.pem")
+    }
+
+    fn account_path(&self) -> PathBuf {
+        self.dir.join("account.json")
+    }
+
+    /// Serve the HTTP-01 challenges on the configured socket.
+    fn start_challenge_server(&self) -> Result<Handle> {
+        let listener = std::net::TcpListener::bind(self.config.challenge_listen_socket)
+            .context("Failed to bind the ACME challenge socket")?;
+        listener.set_nonblocking(true)?;
+        let handle = Handle::new();
+        let router = Router::new()
+            .route("/.well-known/acme-challenge/{token}", get(challenge))
+            .with_state(self.challenges.clone());
+        tokio::spawn(
+            axum_server::from_tcp(listener)
+                .handle(handle.clone())
+                .serve(router.into_make_service()),
+        );
+        Ok(handle)
+    }
+
+    /// The stored certificate chain and key, if any.
+    fn load(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let chain = std::fs::read(self.cert_path()).ok()?;
+        let key = std::fs::read(self.key_path()).ok()?;
+        Some((chain, key))
+    }
+
+    /// When the certificate chain should be renewed: after two thirds of its lifetime.
+    fn renew_at(&self, chain_pem: &[u8]) -> SystemTime {
+        let validity = rustls_pemfile::certs(&mut &chain_pem[..])
+            .next()
+            .and_then(|cert| cert.ok())
+            .and_then(|cert| validity(&cert).ok());
+        match validity {
+            Some((not_before, not_after)) => {
+                let lifetime = not_after.duration_since(not_before).unwrap_or_default();
+                not_after - lifetime / 3
+            }
+            None => {
+                tracing::warn!("Failed to read the validity of the ACME certificate");
+                let issued = std::fs::metadata(self.cert_path())
+                    .and_then(|metadata| metadata.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                issued + RENEW_AFTER_ISSUANCE
+            }
+        }
+    }
+
+    /// The stored account, or a new one if there is none for the directory yet.
+    async fn account(&self) -> Result<Account> {
+        let directory_url = self.config.directory_url.to_string();
+        let builder = || match &self.ca_cert_path {
+            Some(path) => Account::builder_with_root(path),
+            None => Account::builder(),
+        };
+        if let Ok(json) = std::fs::read(self.account_path()) {
+            match serde_json::from_slice::<StoredAccount>(&json) {
+                Ok(stored) if stored.directory_url == directory_url => {
+                    return Ok(builder()?.from_credentials(stored.credentials).await?);
+                }
+                Ok(_) => tracing::info!("Creating an ACME account for the new directory"),
+                Err(e) => tracing::warn!("Ignoring the stored ACME account: {e}"),
+            }
+        }
+        let contact = self
+            .config
+            .contact_email
+            .as_ref()
+            .map(|email| format!("mailto:{email}"));
+        let contact: Vec<&str> = contact.iter().map(String::as_str).collect();
+        let (account, credentials) = builder()?
+            .create(
+                &NewAccount {
+                    contact: &contact,
+                    terms_of_service_agreed: true,
+                    only_return_existing: false,
+                },
+                directory_url.clone(),
+                None,
+            )
+            .await?;
+        let stored = StoredAccount {
+            directory_url,
+            credentials,
+        };
+        write_private(&self.account_path(), &serde_json::to_vec(&stored)?)?;
+        Ok(account)
+    }
+
+    /// Order a new certificate and store it.
+    async fn obtain(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        tracing::info!("Ordering a certificate for {:?} via ACME", self.domains);
+        let account = self.account().await?;
+        let mut tokens = vec![];
+        let issued = self.order(&account, &mut tokens).await;
+        let mut challenges = self.challenges.write().expect("challenges lock poisoned");
+        for token in tokens {
+            challenges.remove(&token);
+        }
+        drop(challenges);
+        let (chain, key) = issued?;
+        // Validate before storing.
+        server_config(&chain, &key)?;
+        write_private(&self.key_path(), &key)?;
+        std::fs::write(self.cert_path(), &chain)?;
+        tracing::info!("Obtained a certificate for {:?}", self.domains);
+        Ok((chain, key))
+    }
+
+    /// Answer the HTTP-01 challenges of a new order and download the certificate.
+    ///
+    /// The tokens of the challenges are added to `tokens`, so they can be removed afterwards.
+    async fn order(
+        &self,
+        account: &Account,
+        tokens: &mut Vec<String>,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let identifiers: Vec<Identifier> = self
+            .domains
+            .iter()
+            .map(|domain| Identifier::Dns(domain.clone()))
+            .collect();
+        let mut order = account.new_order(&NewOrder::new(&identifiers)).await?;
+        let mut authorizations = order.authorizations();
+        while let Some(authorization) = authorizations.next().await {
+            let mut authorization = authorization?;
+            match authorization.status {
+                AuthorizationStatus::Pending => {}
+                AuthorizationStatus::Valid => continue,
+                status => bail!("ACME authorization is {status:?}"),
+            }
+            let mut challenge = authorization
+                .challenge(ChallengeType::Http01)
+                .ok_or_else(|| anyhow!("The ACME server offers no HTTP-01 challenge"))?;
+            self.challenges
+                .write()
+                .expect("challenges lock poisoned")
+                .insert(
+                    challenge.token.clone(),
+                    challenge.key_authorization().as_str().to_string(),
+                );
+            tokens.push(challenge.token.clone());
+            challenge.set_ready().await?;
+        }
+
+        let retries = RetryPolicy::new().timeout(ORDER_TIMEOUT);
+        let status = order.poll_ready(&retries).await?;
+        if status != OrderStatus::Ready {
+            bail!("ACME order is {status:?}");
+        }
+        let key = order.finalize().await?;
+        let chain = order.poll_certificate(&retries).await?;
+        Ok((chain.into_bytes(), key.into_bytes()))
+    }
+}
+
+/// Write a file only the current user can read.
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(path)?, contents)?;
+    Ok(())
+}
+
+/// Answer an HTTP-01 challenge with its key authorization.
+async fn challenge(
+    State(challenges): State<Challenges>,
+    UrlPath(token): UrlPath<String>,
+) -> Result<String, StatusCode> {
+    challenges
+        .read()
+        .expect("challenges lock poisoned")
+        .get(&token)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Keep the certificate fresh. Without `chain`, a certificate is ordered right away.
+async fn renew_periodically(
+    manager: AcmeManager,
+    mut chain: Option<Vec<u8>>,
+    config: RustlsConfig,
+) {
+    loop {
+        if let Some(wait) = chain.as_ref().and_then(|chain| {
+            manager
+                .renew_at(chain)
+                .duration_since(SystemTime::now())
+                .ok()
+        }) {
+            tokio::time::sleep(wait.min(RENEWAL_CHECK_INTERVAL)).await;
+            continue;
+        }
+        let renewed = async {
+            let (new_chain, key) = manager.obtain().await?;
+            config.reload_from_config(Arc::new(server_config(&new_chain, &key)?));
+            Ok::<_, anyhow::Error>(new_chain)
+        }
+        .await;
+        match renewed {
+            Ok(new_chain) => chain = Some(new_chain),
+            Err(e) => tracing::error!("Failed to obtain the ACME certificate: {e}"),
+        }
+        tokio::time::sleep(RENEWAL_RETRY_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_directory::Domain;
+    use std::net::SocketAddr;
+
+    fn acme_config(
+        directory_url: &str,
+        domain: &str,
+        challenge_port: u16,
+        ca_cert_path: Option<PathBuf>,
+    ) -> IcannTlsToml {
+        IcannTlsToml::Acme(AcmeToml {
+            directory_url: directory_url.parse().unwrap(),
+            contact_email: Some("admin@example.com".to_string()),
+            domains: vec![Domain::new(domain.to_string()).unwrap()],
+            challenge_listen_socket: SocketAddr::from(([127, 0, 0, 1], challenge_port)),
+            ca_cert_path,
+        })
+    }
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// A self-signed certificate for localhost, valid from `age` ago for `lifetime`.
+    fn certificate(age: Duration, lifetime: Duration) -> (Vec<u8>, Vec<u8>) {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params.not_before = time::OffsetDateTime::now_utc() - age;
+        params.not_after = params.not_before + lifetime;
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        (cert.pem().into_bytes(), key.serialize_pem().into_bytes())
+    }
+
+    fn is_self_signed(der: &[u8]) -> bool {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).unwrap();
+        cert.issuer() == cert.subject()
+    }
+
+    /// Serve with the TLS config and return the leaf certificate presented to clients.
+    async fn fetch_certificate(tls: &IcannTls) -> Vec<u8> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = listener.local_addr().unwrap();
+        let router = Router::new().route("/", get(|| async { "ok" }));
+        let handle = Handle::new();
+        tokio::spawn(
+            axum_server::from_tcp_rustls(listener, tls.rustls_config())
+                .handle(handle.clone())
+                .serve(router.into_make_service()),
+        );
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .tls_info(true)
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!("https://localhost:{}/", socket.port()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        handle.shutdown();
+        let info = response
+            .extensions()
+            .get::<reqwest::tls::TlsInfo>()
+            .unwrap();
+        info.peer_certificate().unwrap().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_serve_before_the_certificate_is_issued() {
+        let mut context = AppContext::test();
+        // Nothing listens here, so the order fails.
+        context.config_toml.drive.icann_tls = Some(acme_config(
+            "https://127.0.0.1:1/dir",
+            "localhost",
+            free_port(),
+            None,
+        ));
+
+        let tls = IcannTls::start(&context).unwrap().unwrap();
+        assert!(is_self_signed(&fetch_certificate(&tls).await));
+        assert!(!context.data_dir.path().join("acme/cert.pem").exists());
+    }
+
+    #[tokio::test]
+    async fn test_reuse_stored_certificate() {
+        let mut context = AppContext::test();
+        let dir = context.data_dir.path().join("acme");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (chain, key) = certificate(Duration::ZERO, Duration::from_secs(90 * 24 * 60 * 60));
+        std::fs::write(dir.join("cert.pem"), &chain).unwrap();
+        std::fs::write(dir.join("key.pem"), &key).unwrap();
+        context.config_toml.drive.icann_tls = Some(acme_config(
+            "https://127.0.0.1:1/dir",
+            "localhost",
+            free_port(),
+            None,
+        ));
+
+        let tls = IcannTls::start(&context).unwrap().unwrap();
+        let expected = rustls_pemfile::certs(&mut &chain[..])
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(fetch_certificate(&tls).await, expected.to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_renew_at() {
+        let context = AppContext::test();
+        let config = acme_config("https://127.0.0.1:1/dir", "localhost", 0, None);
+        let IcannTlsToml::Acme(config) = config else {
+            unreachable!()
+        };
+        let manager = AcmeManager::new(&context, &config).unwrap();
+        let lifetime = Duration::from_secs(90 * 24 * 60 * 60);
+
+        let (fresh, _) = certificate(Duration::ZERO, lifetime);
+        let renew_at = manager.renew_at(&fresh);
+        let expected = SystemTime::now() + lifetime * 2 / 3;
+        let tolerance = Duration::from_secs(60);
+        assert!(renew_at > expected - tolerance && renew_at < expected + tolerance);
+
+        // Certificates past two thirds of their lifetime are renewed right away.
+        let (expiring, _) = certificate(Duration::from_secs(70 * 24 * 60 * 60), lifetime);
+        assert!(manager.renew_at(&expiring) < SystemTime::now());
+    }
+
+    #[tokio::test]
+    async fn test_certificate_files() {
+        let mut context = AppContext::test();
+        let (chain, key) = self_signed(&["localhost".to_string()]).unwrap();
+        let dir = context.data_dir.path();
+        std::fs::write(dir.join("cert.pem"), &chain).unwrap();
+        std::fs::write(dir.join("key.pem"), &key).unwrap();
+        context.config_toml.drive.icann_tls = Some(IcannTlsToml::Files {
+            cert_path: PathBuf::from("cert.pem"),
+            key_path: PathBuf::from("key.pem"),
+        });
+
+        let tls = IcannTls::start(&context).unwrap().unwrap();
+        let expected = rustls_pemfile::certs(&mut &chain[..])
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(fetch_certificate(&tls).await, expected.to_vec());
+    }
+
+    /// Obtains a certificate from a running [Pebble](https://github.com/letsencrypt/pebble)
+    /// test server. Configured with
+    /// - `PEBBLE_DIRECTORY_URL`, for example `https://localhost:14000/dir`,
+    /// - `PEBBLE_CA_CERT`, the path of `test/certs/pebble.minica.pem` of the Pebble repository,
+    /// - `PEBBLE_HTTP_PORT`, the port Pebble validates HTTP-01 challenges on. Defaults to 5002.
+    ///
+    /// Pebble must resolve `pubky.example.com` to 127.0.0.1,
+    /// for example with `pebble-challtestsrv -defaultIPv4 127.0.0.1`.
+    #[tokio::test]
+    #[ignore = "needs a running Pebble server"]
+    async fn test_pebble() {
+        let directory_url =
+            std::env::var("PEBBLE_DIRECTORY_URL").expect("PEBBLE_DIRECTORY_URL is not set");
+        let ca_cert_path = std::env::var("PEBBLE_CA_CERT").expect("PEBBLE_CA_CERT is not set");
+        let http_port = std::env::var("PEBBLE_HTTP_PORT")
+            .map(|port| port.parse().unwrap())
+            .unwrap_or(5002);
+        let mut context = AppContext::test();
+        let config = acme_config(
+            &directory_url,
+            "pubky.example.com",
+            http_port,
+            Some(PathBuf::from(ca_cert_path)),
+        );
+        context.config_toml.drive.icann_tls = Some(config.clone());
+
+        let tls = IcannTls::start(&context).unwrap().unwrap();
+        let certificate = tokio::time::timeout(Duration::from_secs(120), async {
+            loop {
+                let certificate = fetch_certificate(&tls).await;
+                if !is_self_signed(&certificate) {
+                    return certificate;
+                }
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+        })
+        .await
+        .expect("Pebble didn't issue a certificate");
+        let (_, cert) = x509_parser::parse_x509_certificate(&certificate).unwrap();
+        let names = cert.subject_alternative_name().unwrap().unwrap();
+        assert_eq!(
+            names.value.general_names,
+            vec![x509_parser::extensions::GeneralName::DNSName(
+                "pubky.example.com"
+            )]
+        );
+        drop(tls);
+
+        // A restart reuses the stored account and certificate.
+        let dir = context.data_dir.path().join("acme");
+        assert!(dir.join("account.json").exists());
+        let tls = IcannTls::start(&context).unwrap().unwrap();
+        assert_eq!(fetch_certificate(&tls).await, certificate);
+    }
+}
@@ -4,22 +4,28 @@
 //!
 //! The task is responsible for:
 //! - Republishing the homeserver's pkarr packet to the DHT every hour.
-//! - Stopping the task when the homeserver is stopped.
+//! - Stopping the task when the homeserver is stopped. A publish in progress is finished first.
 
 use std::net::IpAddr;
 
 use anyhow::Result;
-use pkarr::dns::Name;
+use pkarr::dns::{CharacterString, Name};
 use pkarr::errors::PublishError;
 use pkarr::{dns::rdata::SVCB, SignedPacket};
 
 use crate::app_context::AppContext;
+use crate::shared::{
+    health::PublishStatus,
+    metrics::{Metrics, RepublishOutcome, Republisher},
+};
 use tokio::task::JoinHandle;
-use tokio::time::{interval, Duration};
+use tokio::time::{interval, Duration, Instant};
+use tokio_util::sync::CancellationToken;
 
 /// Republishes the homeserver's pkarr packet to the DHT every hour.
 pub struct HomeserverKeyRepublisher {
     join_handle: JoinHandle<()>,
+    cancel: CancellationToken,
 }
 
 impl HomeserverKeyRepublisher {
@@ -29,24 +35,40 @@ impl HomeserverKeyRepublisher {
         pubky_tls_port: u16,
     ) -> Result<Self> {
         let signed_packet = create_signed_packet(context, icann_http_port, pubky_tls_port)?;
-        let join_handle =
-            Self::start_periodic_republish(context.pkarr_client.clone(), &signed_packet).await?;
-        Ok(Self { join_handle })
+        let cancel = CancellationToken::new();
+        let join_handle = Self::start_periodic_republish(
+            context.pkarr_client.clone(),
+            &signed_packet,
+            context.metrics.clone(),
+            context.publish_status.clone(),
+            cancel.clone(),
+        )
+        .await?;
+        Ok(Self {
+            join_handle,
+            cancel,
+        })
     }
 
     async fn publish_once(
         client: &pkarr::Client,
         signed_packet: &SignedPacket,
+        metrics: &Metrics,
+        publish_status: &PublishStatus,
     ) -> Result<(), PublishError> {
         let res = client.publish(signed_packet, None).await;
-        if let Err(e) = &res {
+        let outcome = if let Err(e) = &res {
             tracing::warn!(
                 "Failed to publish the homeserver's pkarr packet to the DHT: {}",
                 e
             );
+            RepublishOutcome::Failure
         } else {
             tracing::info!("Published the homeserver's pkarr packet to the DHT.");
-        }
+            RepublishOutcome::Success
+        };
+        metrics.inc_republished_keys(Republisher::Homeserver, outcome, 1);
+        publish_status.record(&res);
         res
     }
 
@@ -58,11 +80,14 @@ impl HomeserverKeyRepublisher {
     async fn start_periodic_republish(
         client: pkarr::Client,
         signed_packet: &SignedPacket,
+        metrics: Metrics,
+        publish_status: PublishStatus,
+        cancel: CancellationToken,
     ) -> anyhow::Result<JoinHandle<()>> {
         // Publish once to make sure the packet is published to the DHT before this
         // function returns.
         // Throws an error if the packet is not published to the DHT.
-        Self::publish_once(&client, signed_packet).await?;
+        Self::publish_once(&client, signed_packet, &metrics, &publish_status).await?;
 
         // Start the periodic republish task.
         let signed_packet = signed_packet.clone();
@@ -70,8 +95,13 @@ impl HomeserverKeyRepublisher {
             let mut interval = interval(Duration::from_secs(60 * 60)); // 1 hour in seconds
             interval.tick().await; // This ticks immediatly. Wait for first interval before starting the loop.
             loop {
-                interval.tick().await;
-                let _ = Self::publish_once(&client, &signed_packet).await;
+                tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                // Not cancelled. A started publish is finished.
+                let _ =
+                    Self::publish_once(&client, &signed_packet, &metrics, &publish_status).await;
             }
         });
 
@@ -82,6 +112,23 @@ impl HomeserverKeyRepublisher {
     pub fn stop(&self) {
         self.join_handle.abort();
     }
+
+    /// Stop the periodic republish task after the publish in progress is done.
+    ///
+    /// Waits until `deadline` at most before the task is aborted.
+    pub async fn graceful_stop(&mut self, deadline: Instant) {
+        self.cancel.cancel();
+        if self.join_handle.is_finished() {
+            return;
+        }
+        if tokio::time::timeout_at(deadline, &mut self.join_handle)
+            .await
+            .is_err()
+        {
+            tracing::warn!("Homeserver key republisher did not stop in time. Aborting.");
+            self.stop();
+        }
+    }
 }
 
 impl Drop for HomeserverKeyRepublisher {
@@ -117,6 +164,16 @@ pub fn create_signed_packet(
     // This is what is used in all applications expect for browsers.
     let mut svcb = SVCB::new(1, root_name.clone());
     svcb.set_port(public_pubky_tls_port);
+    // Clients prefer HTTP/3 if it is advertised. It is served on the same port over UDP.
+    let alpn: &[&str] = if context.config_toml.drive.pubky_http3 {
+        &["h3", "h2", "http/1.1"]
+    } else {
+        &["h2", "http/1.1"]
+    };
+    svcb.set_alpn(
+        alpn.iter()
+            .map(|id| CharacterString::new(id.as_bytes()).expect("ALPN ids are valid")),
+    )?;
     match &public_ip {
         IpAddr::V4(ip) => {
             svcb.set_ipv4hint([ip.to_bits()])?;
@@ -163,6 +220,27 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_advertise_http3() {
+        let alpn = |context: &AppContext| {
+            let packet = create_signed_packet(context, 8080, 8443).unwrap();
+            let alpn = packet
+                .all_resource_records()
+                .find_map(|rr| match &rr.rdata {
+                    pkarr::dns::rdata::RData::HTTPS(https) if https.0.priority == 1 => {
+                        https.0.get_param(SVCB::ALPN).map(|alpn| alpn.to_vec())
+                    }
+                    _ => None,
+                })
+                .unwrap();
+            alpn
+        };
+        let mut context = AppContext::test();
+        assert_eq!(alpn(&context), b"\x02h2\x08http/1.1");
+        context.config_toml.drive.pubky_http3 = true;
+        assert_eq!(alpn(&context), b"\x02h3\x02h2\x08http/1.1");
+    }
+
     #[tokio::test]
     async fn test_resolve_https_endpoint_with_pkarr_client() {
         let context = AppContext::test();
@@ -8,6 +8,7 @@ use axum::{
 };
 use futures_util::future::BoxFuture;
 use pkarr::PublicKey;
+use pubky_common::capabilities::Action;
 use std::{convert::Infallible, task::Poll};
 use tower::{Layer, Service};
 use tower_cookies::Cookies;
@@ -96,7 +97,7 @@ where
     }
 }
 
-/// Authorize write (PUT or DELETE) for Public paths.
+/// Authorize write (PUT or DELETE) for Public paths and the archive routes.
 fn authorize(
     state: &AppState,
     method: &Method,
@@ -104,9 +105,18 @@ fn authorize(
     public_key: &PublicKey,
     path: &str,
 ) -> HttpResult<()> {
+    // The archive routes cover all files in `/pub/`.
+    let (scope_path, action) = match path {
+        "/export" => ("/pub/", Action::Read),
+        "/import" => ("/pub/", Action::Write),
+        _ => (path, Action::Write),
+    };
+
     if path == "/session" {
         // Checking (or deleting) one's session is ok for everyone
         return Ok(());
+    } else if path == "/export" || path == "/import" {
+        // Requires a session, checked below.
     } else if path.starts_with("/pub/") {
         if method == Method::GET {
             return Ok(());
@@ -160,22 +170,22 @@ fn authorize(
         ));
     }
 
-    if session.capabilities().iter().any(|cap| {
-        path.starts_with(&cap.scope)
-            && cap
-                .actions
-                .contains(&pubky_common::capabilities::Action::Write)
-    }) {
+    if session
+        .capabilities()
+        .iter()
+        .any(|cap| scope_path.starts_with(&cap.scope) && cap.actions.contains(&action))
+    {
         Ok(())
     } else {
         tracing::warn!(
-            "Session {} pubkey {} does not have write access to {}. Access forbidden",
+            "Session {} pubkey {} does not have {:?} access to {}. Access forbidden",
             session_secret,
             public_key,
+            action,
             path
         );
         Err(HttpError::forbidden_with_message(
-            "Session does not have write access to path",
+            "Session does not have access to path",
         ))
     }
 }
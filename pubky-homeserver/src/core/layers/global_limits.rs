@@ -0,0 +1,467 @@
+//!
+//! Server-wide limits that protect the homeserver from overload.
+//!
+//! The [super::rate_limiter::RateLimiterLayer] limits single clients and users.
+//! These limits apply to all of them together. Requests over a concurrency limit
+//! are shed with `503 Service Unavailable`, transfers over a bandwidth limit are throttled.
+//!
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::IpAddr,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{
+        header::{CONNECTION, RETRY_AFTER},
+        HeaderValue, Method, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::{future::BoxFuture, StreamExt};
+use governor::{DefaultDirectRateLimiter, Jitter, RateLimiter};
+use tokio::{sync::Semaphore, time::Instant};
+use tokio_util::task::TaskTracker;
+use tower::{Layer, Service};
+
+use crate::{
+    quota_config::QuotaValue,
+    shared::{
+        metrics::{LoadShedReason, Metrics},
+        HttpError,
+    },
+    GlobalLimitsToml,
+};
+
+/// Seconds a shed client should wait before it tries again.
+const RETRY_AFTER_SECS: u64 = 5;
+
+/// The state of the global limits. Cheap to clone.
+///
+/// Shared by the listeners, which count the connections, and the [GlobalLimitsLayer].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GlobalLimits {
+    upload: Option<Arc<DefaultDirectRateLimiter>>,
+    download: Option<Arc<DefaultDirectRateLimiter>>,
+    uploads: Option<Arc<Semaphore>>,
+    max_connections_per_ip: Option<NonZeroUsize>,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    /// Writes in progress of the homeserver and admin routers. Drained on shutdown.
+    in_flight_writes: TaskTracker,
+}
+
+impl GlobalLimits {
+    pub fn from_config(config: &GlobalLimitsToml) -> Self {
+        let limiter = |quota: &Option<QuotaValue>| {
+            quota
+                .clone()
+                .map(|quota| Arc::new(RateLimiter::direct(quota.into())))
+        };
+        Self {
+            upload: limiter(&config.upload_speed),
+            download: limiter(&config.download_speed),
+            uploads: config
+                .max_concurrent_uploads
+                .map(|max| Arc::new(Semaphore::new(max.get()))),
+            max_connections_per_ip: config.max_connections_per_ip,
+            connections: Default::default(),
+            in_flight_writes: TaskTracker::new(),
+        }
+    }
+
+    /// Wait until the writes in progress are done, at most until `deadline`.
+    ///
+    /// Call after the listeners stopped accepting requests.
+    /// Returns the number of writes that are still running after the deadline.
+    pub async fn drain_writes(&self, deadline: Instant) -> usize {
+        self.in_flight_writes.close();
+        let _ = tokio::time::timeout_at(deadline, self.in_flight_writes.wait()).await;
+        self.in_flight_writes.len()
+    }
+
+    /// Count a new connection of a client.
+    ///
+    /// The connection is counted until the returned slot and all its clones are dropped.
+    pub fn open_connection(&self, ip: IpAddr) -> ConnectionSlot {
+        let ip = ip.to_canonical();
+        let mut connections = self.connections.lock().expect("connections lock poisoned");
+        let count = connections.entry(ip).or_default();
+        *count += 1;
+        let over_limit = self
+            .max_connections_per_ip
+            .is_some_and(|max| *count > max.get());
+        ConnectionSlot(Arc::new(ConnectionSlotInner {
+            ip,
+            over_limit,
+            connections: self.connections.clone(),
+        }))
+    }
+}
+
+/// An open connection counted by [GlobalLimits::open_connection].
+///
+/// Added to the request extensions by the listeners.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionSlot(Arc<ConnectionSlotInner>);
+
+#[derive(Debug)]
+struct ConnectionSlotInner {
+    ip: IpAddr,
+    /// The client already had the maximum number of connections open.
+    over_limit: bool,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for ConnectionSlotInner {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock().expect("connections lock poisoned");
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// A Tower Layer that enforces the [GlobalLimits].
+#[derive(Debug, Clone)]
+pub struct GlobalLimitsLayer {
+    limits: GlobalLimits,
+    metrics: Metrics,
+}
+
+impl GlobalLimitsLayer {
+    /// Create a new layer. Shed requests are counted in `metrics`.
+    pub(crate) fn new(limits: GlobalLimits, metrics: Metrics) -> Self {
+        Self { limits, metrics }
+    }
+}
+
+impl<S> Layer<S> for GlobalLimitsLayer {
+    type Service = GlobalLimitsMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GlobalLimitsMiddleware {
+            inner,
+            limits: self.limits.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GlobalLimitsMiddleware<S> {
+    inner: S,
+    limits: GlobalLimits,
+    metrics: Metrics,
+}
+
+impl<S> GlobalLimitsMiddleware<S> {
+    /// Respond with 503 and a `Retry-After`.
+    fn shed(&self, reason: LoadShedReason) -> Response {
+        tracing::debug!("Shed request: {reason:?} limit reached");
+        self.metrics.inc_load_shed(reason);
+        let mut response =
+            HttpError::new_with_message(StatusCode::SERVICE_UNAVAILABLE, "Server is overloaded")
+                .into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECS));
+        if reason == LoadShedReason::Connections {
+            // Ask the client to close the excess connection.
+            response
+                .headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+        }
+        response
+    }
+}
+
+/// PUT and PATCH requests write file contents.
+fn is_upload(req: &Request<Body>) -> bool {
+    matches!(*req.method(), Method::PUT | Method::PATCH)
+}
+
+/// Every request but GET, HEAD, OPTIONS and TRACE may write, e.g. a POST upload or an import.
+fn is_write<B>(req: &Request<B>) -> bool {
+    !req.method().is_safe()
+}
+
+/// Middleware that tracks the writes of routers without the [GlobalLimitsLayer],
+/// like the admin WebDAV and archive import, so they are drained on shutdown.
+pub(crate) async fn track_writes(
+    State(limits): State<GlobalLimits>,
+    request: axum::extract::Request,
+    next: Next,
+) -> Response {
+    let _write_token = is_write(&request).then(|| limits.in_flight_writes.token());
+    next.run(request).await
+}
+
+/// Throttle a body to the bandwidth of a limiter that counts kilobytes.
+fn throttle_body(body: Body, limiter: Arc<DefaultDirectRateLimiter>) -> Body {
+    let throttled = body.into_data_stream().then(move |chunk| {
+        let limiter = limiter.clone();
+        async move {
+            let bytes = chunk?;
+            // Same as the speed rate limits: wait between 25ms and 500ms against thundering herds.
+            let jitter = Jitter::new(Duration::from_millis(25), Duration::from_millis(500));
+            for _ in 0..bytes.len().div_ceil(1024) {
+                limiter.until_ready_with_jitter(jitter).await;
+            }
+            Ok::<_, axum::Error>(bytes)
+        }
+    });
+    Body::from_stream(throttled)
+}
+
+impl<S> Service<Request<Body>> for GlobalLimitsMiddleware<S>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible> + Send + 'static + Clone,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let mut inner = self.inner.clone();
+
+        let over_limit = req
+            .extensions()
+            .get::<Option<ConnectionSlot>>()
+            .is_some_and(|slot| slot.as_ref().is_some_and(|slot| slot.0.over_limit));
+        if over_limit {
+            let response = self.shed(LoadShedReason::Connections);
+            return Box::pin(async move { Ok(response) });
+        }
+
+        // Held until the write is processed.
+        let write_token = is_write(&req).then(|| self.limits.in_flight_writes.token());
+        let mut upload_permit = None;
+        if let Some(uploads) = self.limits.uploads.as_ref().filter(|_| is_upload(&req)) {
+            match uploads.clone().try_acquire_owned() {
+                Ok(permit) => upload_permit = Some(permit),
+                Err(_) => {
+                    let response = self.shed(LoadShedReason::Uploads);
+                    return Box::pin(async move { Ok(response) });
+                }
+            }
+        }
+
+        let req = match &self.limits.upload {
+            Some(limiter) => {
+                let (parts, body) = req.into_parts();
+                Request::from_parts(parts, throttle_body(body, limiter.clone()))
+            }
+            None => req,
+        };
+        let download = self.limits.download.clone();
+        Box::pin(async move {
+            let response = inner.call(req).await?;
+            drop(upload_permit);
+            drop(write_token);
+            let response = match download {
+                Some(limiter) => {
+                    let (parts, body) = response.into_parts();
+                    Response::from_parts(parts, throttle_body(body, limiter))
+                }
+                None => response,
+            };
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{middleware, routing::get, Router};
+    use reqwest::Client;
+
+    use super::*;
+    use crate::core::proxy_protocol::ProxyProtocolAcceptor;
+
+    async fn slow_upload_handler(body: Body) -> StatusCode {
+        let mut stream = body.into_data_stream();
+        while stream.next().await.is_some() {}
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        StatusCode::CREATED
+    }
+
+    // Start a server with the given limits on a random port.
+    async fn start_server(config: GlobalLimitsToml) -> SocketAddr {
+        start_server_with_limits(GlobalLimits::from_config(&config)).await
+    }
+
+    async fn start_server_with_limits(limits: GlobalLimits) -> SocketAddr {
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route(
+                "/upload",
+                axum::routing::put(slow_upload_handler).post(slow_upload_handler),
+            )
+            .layer(GlobalLimitsLayer::new(limits.clone(), Metrics::default()));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum_server::from_tcp(listener)
+                .acceptor(ProxyProtocolAcceptor::new(false, vec![], limits))
+                .serve(app.into_make_service()),
+        );
+        socket
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_uploads() {
+        let socket = start_server(GlobalLimitsToml {
+            max_concurrent_uploads: NonZeroUsize::new(1),
+            ..Default::default()
+        })
+        .await;
+        let client = Client::new();
+        let upload = || client.put(format!("http://{socket}/upload")).send();
+
+        let (first, second) = tokio::join!(upload(), async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            upload().await
+        });
+        assert_eq!(first.unwrap().status(), StatusCode::CREATED);
+        let second = second.unwrap();
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(second.headers().get(RETRY_AFTER).unwrap(), "5");
+
+        // The permit is released after the upload.
+        assert_eq!(upload().await.unwrap().status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_max_connections_per_ip() {
+        let socket = start_server(GlobalLimitsToml {
+            max_connections_per_ip: NonZeroUsize::new(1),
+            ..Default::default()
+        })
+        .await;
+        let url = format!("http://{socket}/");
+
+        // Each client keeps its own connection open.
+        let first = Client::new();
+        assert_eq!(
+            first.get(&url).send().await.unwrap().status(),
+            StatusCode::OK
+        );
+        let second = Client::new();
+        let response = second.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(RETRY_AFTER));
+
+        // The first connection is counted until it is closed.
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let third = Client::new();
+        assert_eq!(
+            third.get(&url).send().await.unwrap().status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_throttle_upload_bandwidth() {
+        let socket = start_server(GlobalLimitsToml {
+            upload_speed: Some("2kb/s".parse().unwrap()),
+            ..Default::default()
+        })
+        .await;
+        let start = std::time::Instant::now();
+        let response = Client::new()
+            .put(format!("http://{socket}/upload"))
+            .body(vec![0u8; 4 * 1024])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        // 2kb burst, the other 2kb take a second.
+        assert!(start.elapsed() >= Duration::from_millis(900));
+    }
+
+    #[tokio::test]
+    async fn test_drain_writes() {
+        let limits = GlobalLimits::default();
+        let socket = start_server_with_limits(limits.clone()).await;
+        let client = Client::new();
+        let upload = client.put(format!("http://{socket}/upload")).send();
+        let post = client.post(format!("http://{socket}/upload")).send();
+
+        let (upload, post, still_running) = tokio::join!(upload, post, async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            limits
+                .drain_writes(Instant::now() + Duration::from_secs(5))
+                .await
+        });
+        assert_eq!(still_running, 0);
+        assert_eq!(upload.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(post.unwrap().status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_drain_writes_deadline() {
+        let limits = GlobalLimits::default();
+        let socket = start_server_with_limits(limits.clone()).await;
+        let client = Client::new();
+        let upload = client.put(format!("http://{socket}/upload")).send();
+
+        let (_, still_running) = tokio::join!(upload, async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            limits
+                .drain_writes(Instant::now() + Duration::from_millis(50))
+                .await
+        });
+        assert_eq!(still_running, 1);
+    }
+
+    #[tokio::test]
+    async fn test_track_writes() {
+        let limits = GlobalLimits::default();
+        let app = Router::new()
+            .route("/dav/file", axum::routing::any(slow_upload_handler))
+            .layer(middleware::from_fn_with_state(limits.clone(), track_writes));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let client = Client::new();
+
+        // Reads are not tracked.
+        let read = client.get(format!("http://{socket}/dav/file")).send();
+        let (_, in_flight) = tokio::join!(read, async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            limits.in_flight_writes.len()
+        });
+        assert_eq!(in_flight, 0);
+
+        let write = client
+            .request(
+                Method::from_bytes(b"MOVE").unwrap(),
+                format!("http://{socket}/dav/file"),
+            )
+            .send();
+        let (response, in_flight) = tokio::join!(write, async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            limits.in_flight_writes.len()
+        });
+        assert_eq!(in_flight, 1);
+        assert_eq!(response.unwrap().status(), StatusCode::CREATED);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod authz;
+pub mod global_limits;
 pub mod pubky_host;
 pub mod rate_limiter;
 pub mod trace;
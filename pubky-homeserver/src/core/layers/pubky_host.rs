@@ -49,11 +49,15 @@ where
 }
 
 /// Extracts a PublicKey by checking, in order:
-/// 1. The "host" header.
-/// 2. The "pubky-host" header (which overwrites any previously found key).
-/// 3. The query parameter "pubky-host" if none was found in headers.
+/// 1. The host of the uri, which HTTP/2 and HTTP/3 send instead of the "host" header.
+/// 2. The "host" header.
+/// 3. The "pubky-host" header (which overwrites any previously found key).
+/// 4. The query parameter "pubky-host" if none was found in headers.
 fn extract_pubky(req: &Request<Body>) -> Option<PublicKey> {
-    let mut pubky = None;
+    let mut pubky = req
+        .uri()
+        .host()
+        .and_then(|host| PublicKey::try_from(host).ok());
     // Check headers in order: "host" then "pubky-host".
     for header in ["host", "pubky-host"].iter() {
         if let Some(val) = req.headers().get(*header) {
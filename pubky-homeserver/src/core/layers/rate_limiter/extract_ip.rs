@@ -1,37 +1,256 @@
 use axum::extract::Request;
 use axum::http::HeaderMap;
-use std::net::{IpAddr, SocketAddr};
+use ipnet::IpNet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
-// From https://github.com/benwis/tower-governor/blob/main/src/key_extractor.rs#L121
+use crate::core::proxy_protocol::PeerAddr;
+use crate::core::unix_socket::UnixSocketPeer;
+use crate::quota_config::ClientIpHeader;
+
+const FORWARDED: &str = "forwarded";
 const X_REAL_IP: &str = "x-real-ip";
 const X_FORWARDED_FOR: &str = "x-forwarded-for";
 
-/// Tries to parse the `x-forwarded-for` header
-fn maybe_x_forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
-    headers
-        .get(X_FORWARDED_FOR)
-        .and_then(|hv| hv.to_str().ok())
-        .and_then(|s| s.split(',').find_map(|s| s.trim().parse::<IpAddr>().ok()))
+/// Parses a node of the `Forwarded` header, for example `192.0.2.60`,
+/// `"192.0.2.60:4711"` or `"[2001:db8:cafe::17]:4711"`.
+///
+/// Returns `None` for `unknown` and obfuscated identifiers.
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// The `for` addresses of the `Forwarded` header (RFC 7239), leftmost first.
+/// `None` if a hop has no parsable address.
+fn forwarded(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let mut chain = vec![];
+    for value in headers.get_all(FORWARDED) {
+        for element in value.to_str().ok()?.split(',') {
+            let node = element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim().eq_ignore_ascii_case("for").then_some(value)
+            });
+            chain.push(node.and_then(parse_forwarded_node));
+        }
+    }
+    (!chain.is_empty()).then_some(chain)
+}
+
+/// The addresses of the `x-forwarded-for` header, leftmost first.
+/// `None` if a hop is not a valid ip address.
+fn x_forwarded_for(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let mut chain = vec![];
+    for value in headers.get_all(X_FORWARDED_FOR) {
+        for hop in value.to_str().ok()?.split(',') {
+            chain.push(hop.trim().parse::<IpAddr>().ok());
+        }
+    }
+    (!chain.is_empty()).then_some(chain)
 }
 
 /// Tries to parse the `x-real-ip` header
-fn maybe_x_real_ip(headers: &HeaderMap) -> Option<IpAddr> {
+fn x_real_ip(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
     headers
         .get(X_REAL_IP)
         .and_then(|hv| hv.to_str().ok())
-        .and_then(|s| s.parse::<IpAddr>().ok())
+        .map(|s| vec![s.trim().parse::<IpAddr>().ok()])
 }
 
-fn maybe_connect_info<T>(req: &Request<T>) -> Option<IpAddr> {
+/// The address of the connection. Read from the PROXY protocol header if enabled.
+///
+/// Peers of Unix domain sockets are local processes and count as `127.0.0.1`,
+/// so their forwarding headers are honored if the loopback address is a trusted proxy.
+fn peer_ip<T>(req: &Request<T>) -> Option<IpAddr> {
+    if let Some(PeerAddr(addr)) = req.extensions().get::<PeerAddr>() {
+        return Some(addr.ip());
+    }
+    if req.extensions().get::<UnixSocketPeer>().is_some() {
+        return Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
     req.extensions()
         .get::<axum::extract::ConnectInfo<SocketAddr>>()
         .map(|addr| addr.ip())
 }
 
-pub fn extract_ip<T>(req: &Request<T>) -> anyhow::Result<IpAddr> {
+fn is_trusted(ip: &IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    // Compare IPv4-mapped IPv6 addresses like `::ffff:10.0.0.1` as IPv4.
+    let ip = ip.to_canonical();
+    trusted_proxies.iter().any(|net| net.contains(&ip))
+}
+
+/// Extract the ip address of the client.
+///
+/// The `header` is only honored if the request comes from a trusted proxy.
+/// Other forwarding headers are ignored as the proxy passes them through from the client.
+/// The forwarded chain is walked from the right, skipping trusted proxies,
+/// so the first untrusted hop is the client. Hops left of it could be spoofed by the client.
+pub fn extract_ip<T>(
+    req: &Request<T>,
+    trusted_proxies: &[IpNet],
+    header: ClientIpHeader,
+) -> anyhow::Result<IpAddr> {
+    let peer = peer_ip(req).ok_or(anyhow::anyhow!("Failed to extract ip."))?;
+    if !is_trusted(&peer, trusted_proxies) {
+        return Ok(peer);
+    }
     let headers = req.headers();
-    maybe_x_forwarded_for(headers)
-        .or_else(|| maybe_x_real_ip(headers))
-        .or_else(|| maybe_connect_info(req))
-        .ok_or(anyhow::anyhow!("Failed to extract ip."))
+    let chain = match header {
+        ClientIpHeader::Forwarded => forwarded(headers),
+        ClientIpHeader::XForwardedFor => x_forwarded_for(headers),
+        ClientIpHeader::XRealIp => x_real_ip(headers),
+    };
+    let Some(chain) = chain else {
+        return Ok(peer);
+    };
+
+    let mut client = peer;
+    for hop in chain.into_iter().rev() {
+        match hop {
+            Some(ip) => {
+                client = ip;
+                if !is_trusted(&ip, trusted_proxies) {
+                    break;
+                }
+            }
+            // Can't tell who is behind an invalid hop. Stop at the proxy that reported it.
+            None => break,
+        }
+    }
+    Ok(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::ConnectInfo};
+
+    fn request(peer: &str, headers: &[(&str, &str)]) -> Request<Body> {
+        let mut req = Request::builder();
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let mut req = req.body(Body::empty()).unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 1234)));
+        req
+    }
+
+    const XFF: ClientIpHeader = ClientIpHeader::XForwardedFor;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let req = request(
+            "1.1.1.1",
+            &[(X_FORWARDED_FOR, "2.2.2.2"), (X_REAL_IP, "3.3.3.3")],
+        );
+        assert_eq!(extract_ip(&req, &[], XFF).unwrap(), ip("1.1.1.1"));
+    }
+
+    #[test]
+    fn test_walk_chain_from_the_right() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        // The client prepended a spoofed address.
+        let req = request(
+            "10.0.0.1",
+            &[(X_FORWARDED_FOR, "6.6.6.6, 2.2.2.2, 10.0.0.2")],
+        );
+        assert_eq!(extract_ip(&req, &trusted, XFF).unwrap(), ip("2.2.2.2"));
+
+        // Only trusted proxies in the chain.
+        let req = request("10.0.0.1", &[(X_FORWARDED_FOR, "10.0.0.3, 10.0.0.2")]);
+        assert_eq!(extract_ip(&req, &trusted, XFF).unwrap(), ip("10.0.0.3"));
+
+        // Invalid hop
+        let req = request("10.0.0.1", &[(X_FORWARDED_FOR, "2.2.2.2, garbage")]);
+        assert_eq!(extract_ip(&req, &trusted, XFF).unwrap(), ip("10.0.0.1"));
+
+        let req = request("::ffff:10.0.0.1", &[(X_REAL_IP, "2.2.2.2")]);
+        assert_eq!(
+            extract_ip(&req, &trusted, ClientIpHeader::XRealIp).unwrap(),
+            ip("2.2.2.2")
+        );
+    }
+
+    #[test]
+    fn test_forwarded_header() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let req = request(
+            "10.0.0.1",
+            &[
+                (
+                    FORWARDED,
+                    r#"for=6.6.6.6, for="[2001:db8:cafe::17]:4711";proto=https"#,
+                ),
+                (X_FORWARDED_FOR, "3.3.3.3"),
+            ],
+        );
+        assert_eq!(
+            extract_ip(&req, &trusted, ClientIpHeader::Forwarded).unwrap(),
+            ip("2001:db8:cafe::17")
+        );
+
+        let req = request("10.0.0.1", &[(FORWARDED, "for=unknown")]);
+        assert_eq!(
+            extract_ip(&req, &trusted, ClientIpHeader::Forwarded).unwrap(),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn test_only_read_the_configured_header() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        // The proxy appended to `X-Forwarded-For` and passed the client's `Forwarded` through.
+        let req = request(
+            "10.0.0.1",
+            &[
+                (FORWARDED, "for=6.6.6.6"),
+                (X_REAL_IP, "7.7.7.7"),
+                (X_FORWARDED_FOR, "2.2.2.2"),
+            ],
+        );
+        assert_eq!(extract_ip(&req, &trusted, XFF).unwrap(), ip("2.2.2.2"));
+
+        // Without the configured header, the proxy is the client.
+        let req = request("10.0.0.1", &[(FORWARDED, "for=6.6.6.6")]);
+        assert_eq!(extract_ip(&req, &trusted, XFF).unwrap(), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_proxy_protocol_peer() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let mut req = request("10.0.0.1", &[(X_FORWARDED_FOR, "2.2.2.2")]);
+        req.extensions_mut()
+            .insert(PeerAddr("4.4.4.4:1234".parse().unwrap()));
+        assert_eq!(extract_ip(&req, &trusted, XFF).unwrap(), ip("4.4.4.4"));
+    }
+
+    #[test]
+    fn test_unix_socket_peer() {
+        let trusted: Vec<IpNet> = vec!["127.0.0.1/32".parse().unwrap()];
+        let unix_request = |headers: &[(&str, &str)]| {
+            let mut req = Request::builder();
+            for (name, value) in headers {
+                req = req.header(*name, *value);
+            }
+            let mut req = req.body(Body::empty()).unwrap();
+            req.extensions_mut().insert(UnixSocketPeer);
+            req
+        };
+
+        let req = unix_request(&[(X_FORWARDED_FOR, "2.2.2.2")]);
+        assert_eq!(extract_ip(&req, &trusted, XFF).unwrap(), ip("2.2.2.2"));
+        assert_eq!(extract_ip(&req, &[], XFF).unwrap(), ip("127.0.0.1"));
+
+        let req = unix_request(&[]);
+        assert_eq!(extract_ip(&req, &trusted, XFF).unwrap(), ip("127.0.0.1"));
+    }
 }
@@ -9,24 +9,157 @@
 use axum::response::{IntoResponse, Response};
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    http::{header::RETRY_AFTER, HeaderMap, HeaderValue, Request, StatusCode},
 };
 use futures_util::future::BoxFuture;
-use governor::clock::QuantaClock;
-use governor::state::keyed::DashMapStateStore;
+use std::collections::HashMap;
 use std::num::NonZero;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use std::{convert::Infallible, task::Poll};
 use tower::{Layer, Service};
 
 use crate::core::extractors::PubkyHost;
-use crate::quota_config::{LimitKey, LimitKeyType, PathLimit, RateUnit};
+use crate::persistence::lmdb::LmDB;
+use crate::quota_config::{ClientIpHeader, LimitKey, LimitKeyType, PathLimit, RateUnit};
+use crate::shared::metrics::Metrics;
 use crate::shared::HttpError;
 use futures_util::StreamExt;
-use governor::{Jitter, Quota, RateLimiter};
+use governor::middleware::StateSnapshot;
+use governor::nanos::Nanos;
+use governor::{clock::Clock, Jitter, NotUntil};
+use ipnet::IpNet;
+use pkarr::PublicKey;
+use tower_cookies::cookie::Cookie;
 
 use super::extract_ip::extract_ip;
+use super::state::{build_limiter, Limiter, RateLimitStore};
+
+/// The configured rate limits with their limiter state.
+///
+/// Shared between all [RateLimiterLayer] instances so the limits can be
+/// swapped at runtime when the config is reloaded.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimits {
+    tuples: Arc<RwLock<Arc<Vec<LimitTuple>>>>,
+    /// Keeps the limiter state outside of memory if set.
+    store: Arc<RwLock<Option<Arc<dyn RateLimitStore>>>>,
+    /// Resolves sessions and looks up the tiers of users.
+    /// Without it, session limits fall back to the ip address and all users get the default quota.
+    db: Option<LmDB>,
+}
+
+impl RateLimits {
+    /// Create the rate limits with their state in `store`, or in memory if `None`.
+    ///
+    /// If `limits` is empty, rate limiting is disabled.
+    pub fn with_store(limits: Vec<PathLimit>, store: Option<Arc<dyn RateLimitStore>>) -> Self {
+        let rate_limits = Self::default();
+        *rate_limits
+            .store
+            .write()
+            .expect("rate limits lock poisoned") = store;
+        rate_limits.set(limits);
+        rate_limits
+    }
+
+    /// Resolve sessions and look up the tiers of users in `db`.
+    pub(crate) fn with_db(mut self, db: LmDB) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// The user of the valid session the request is sent with.
+    ///
+    /// The secret must belong to a session of the pubky-host in `db`,
+    /// so random cookies don't count as sessions.
+    fn session_user(&self, req: &Request<Body>) -> Option<PublicKey> {
+        let db = self.db.as_ref()?;
+        let host = req.extensions().get::<PubkyHost>()?.public_key();
+        let secret = session_secret(req)?;
+        match db.get_session(&secret) {
+            Ok(Some(session)) if session.pubky() == host => Some(session.pubky().clone()),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::warn!("Failed to read the session of {host}: {e}");
+                None
+            }
+        }
+    }
+
+    /// Move the state of all limits to `store`, or to memory if `None`.
+    ///
+    /// The state of the in memory limiters is not carried over.
+    pub fn set_store(&self, store: Option<Arc<dyn RateLimitStore>>) {
+        *self.store.write().expect("rate limits lock poisoned") = store.clone();
+        let tuples = self
+            .current()
+            .iter()
+            .map(|tuple| LimitTuple::new(tuple.limit.clone(), store.clone()))
+            .collect();
+        *self.tuples.write().expect("rate limits lock poisoned") = Arc::new(tuples);
+    }
+
+    /// Replace the limits.
+    ///
+    /// Limits that didn't change keep their state. The state of changed limits is reset.
+    pub fn set(&self, limits: Vec<PathLimit>) {
+        if limits.is_empty() {
+            tracing::info!("Rate limiting is disabled.");
+        } else {
+            let limits_str = limits
+                .iter()
+                .map(|limit| format!("\"{limit}\""))
+                .collect::<Vec<String>>();
+            tracing::info!("Rate limits configured: {}", limits_str.join(", "));
+        }
+        let current = self.current();
+        let store = self
+            .store
+            .read()
+            .expect("rate limits lock poisoned")
+            .clone();
+        let tuples = limits
+            .into_iter()
+            .map(|limit| {
+                current
+                    .iter()
+                    .find(|tuple| tuple.limit == limit)
+                    .cloned()
+                    .unwrap_or_else(|| LimitTuple::new(limit, store.clone()))
+            })
+            .collect();
+        *self.tuples.write().expect("rate limits lock poisoned") = Arc::new(tuples);
+    }
+
+    /// Persist the buffered state of the store, if any.
+    pub(crate) fn flush_store(&self) -> anyhow::Result<()> {
+        let store = self
+            .store
+            .read()
+            .expect("rate limits lock poisoned")
+            .clone();
+        match store {
+            Some(store) => store.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// The currently active limits.
+    pub fn limits(&self) -> Vec<PathLimit> {
+        self.current()
+            .iter()
+            .map(|tuple| tuple.limit.clone())
+            .collect()
+    }
+
+    fn current(&self) -> Arc<Vec<LimitTuple>> {
+        self.tuples
+            .read()
+            .expect("rate limits lock poisoned")
+            .clone()
+    }
+}
 
 /// A Tower Layer to handle general rate limiting.
 ///
@@ -39,24 +172,33 @@ use super::extract_ip::extract_ip;
 ///
 #[derive(Debug, Clone)]
 pub struct RateLimiterLayer {
-    limits: Vec<PathLimit>,
+    limits: RateLimits,
+    trusted_proxies: Arc<Vec<IpNet>>,
+    client_ip_header: ClientIpHeader,
+    metrics: Metrics,
+    cleanup: Arc<LimiterCleanup>,
 }
 
 impl RateLimiterLayer {
-    /// Create a new rate limiter layer with the given quota.
+    /// Create a new rate limiter layer with the given limits.
     ///
-    /// If quota is None, rate limiting is disabled.
-    pub fn new(limits: Vec<PathLimit>) -> Self {
-        if limits.is_empty() {
-            tracing::info!("Rate limiting is disabled.");
-        } else {
-            let limits_str = limits
-                .iter()
-                .map(|limit| format!("\"{limit}\""))
-                .collect::<Vec<String>>();
-            tracing::info!("Rate limits configured: {}", limits_str.join(", "));
+    /// Forwarding headers are only honored for requests from `trusted_proxies`.
+    /// Rejections are counted in `metrics`.
+    pub fn new(limits: RateLimits, trusted_proxies: Vec<IpNet>, metrics: Metrics) -> Self {
+        let cleanup = Arc::new(LimiterCleanup::start(&limits));
+        Self {
+            limits,
+            trusted_proxies: Arc::new(trusted_proxies),
+            client_ip_header: ClientIpHeader::default(),
+            metrics,
+            cleanup,
         }
-        Self { limits }
+    }
+
+    /// Read the client address of requests from trusted proxies from `header`.
+    pub fn with_client_ip_header(mut self, header: ClientIpHeader) -> Self {
+        self.client_ip_header = header;
+        self
     }
 }
 
@@ -64,56 +206,191 @@ impl<S> Layer<S> for RateLimiterLayer {
     type Service = RateLimiterMiddleware<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        let tuples = self
-            .limits
-            .iter()
-            .map(|path| LimitTuple::new(path.clone()))
-            .collect();
-
         RateLimiterMiddleware {
             inner,
-            limits: tuples,
+            limits: self.limits.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            client_ip_header: self.client_ip_header,
+            metrics: self.metrics.clone(),
+            _cleanup: self.cleanup.clone(),
         }
     }
 }
 
-/// A tuple of a path limit and the actual governor rate limiter.
-#[derive(Debug, Clone)]
-struct LimitTuple {
-    pub limit: PathLimit,
-    pub limiter: Arc<RateLimiter<LimitKey, DashMapStateStore<LimitKey>, QuantaClock>>,
-}
-
-impl LimitTuple {
-    pub fn new(path_limit: PathLimit) -> Self {
-        let quota: Quota = path_limit.clone().into();
-        let limiter = Arc::new(RateLimiter::keyed(quota));
+/// Forgets keys that are not used anymore. This is to prevent memory leaks.
+///
+/// Shared by the layer and all its middlewares. Stops once the last one is dropped.
+#[derive(Debug)]
+struct LimiterCleanup(tokio::task::JoinHandle<()>);
 
-        // Forget keys that are not used anymore. This is to prevent memory leaks.
-        let limiter_clone = limiter.clone();
-        tokio::spawn(async move {
+impl LimiterCleanup {
+    fn start(limits: &RateLimits) -> Self {
+        let tuples = limits.tuples.clone();
+        Self(tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(60));
             interval.tick().await;
             loop {
                 interval.tick().await;
-                limiter_clone.retain_recent();
-                limiter_clone.shrink_to_fit();
+                let current = tuples.read().expect("rate limits lock poisoned").clone();
+                for limiter in current.iter().flat_map(LimitTuple::limiters) {
+                    limiter.retain_recent();
+                    limiter.shrink_to_fit();
+                }
             }
-        });
+        }))
+    }
+}
 
+impl Drop for LimiterCleanup {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+const RATELIMIT_LIMIT: &str = "ratelimit-limit";
+const RATELIMIT_REMAINING: &str = "ratelimit-remaining";
+const RATELIMIT_RESET: &str = "ratelimit-reset";
+
+/// The state of a request limit as reported in the `RateLimit-*` headers.
+///
+/// See <https://datatracker.ietf.org/doc/draft-ietf-httpapi-ratelimit-headers/>.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RateLimitInfo {
+    /// The number of requests that can be sent in a burst.
+    limit: u32,
+    /// The number of requests that can be sent right now.
+    remaining: u32,
+    /// Time until the full burst is available again.
+    reset: Duration,
+    /// Time until the next request is allowed. Only set if the request was rejected.
+    retry_after: Option<Duration>,
+}
+
+impl RateLimitInfo {
+    fn allowed(snapshot: &StateSnapshot) -> Self {
+        let quota = snapshot.quota();
+        let limit = quota.burst_size().get();
+        let remaining = snapshot.remaining_burst_capacity().min(limit);
+        Self {
+            limit,
+            remaining,
+            reset: quota.replenish_interval() * (limit - remaining),
+            retry_after: None,
+        }
+    }
+
+    fn rejected(not_until: &NotUntil<Nanos>, now: Nanos) -> Self {
+        let quota = not_until.quota();
+        let limit = quota.burst_size().get();
+        let wait = not_until.wait_time_from(now);
+        Self {
+            limit,
+            remaining: 0,
+            reset: wait + quota.replenish_interval() * (limit - 1),
+            retry_after: Some(wait),
+        }
+    }
+
+    fn add_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATELIMIT_RESET, HeaderValue::from(ceil_secs(self.reset)));
+        if let Some(retry_after) = self.retry_after {
+            // Retrying right away would be rejected again.
+            headers.insert(
+                RETRY_AFTER,
+                HeaderValue::from(ceil_secs(retry_after).max(1)),
+            );
+        }
+    }
+}
+
+/// Round up to whole seconds so clients never retry too early.
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// A tuple of a path limit and the actual governor rate limiters.
+#[derive(Debug, Clone)]
+struct LimitTuple {
+    pub limit: PathLimit,
+    /// Limiter of users without a tier.
+    pub limiter: Arc<Limiter>,
+    /// Limiters of the user tiers.
+    pub tiers: HashMap<String, Arc<Limiter>>,
+}
+
+impl LimitTuple {
+    pub fn new(path_limit: PathLimit, store: Option<Arc<dyn RateLimitStore>>) -> Self {
+        let namespace = format!(
+            "{} {} {}",
+            path_limit.method, path_limit.path, path_limit.key
+        );
+        let tiers = path_limit
+            .tiers
+            .keys()
+            .map(|tier| {
+                let limiter = build_limiter(
+                    path_limit.tier_quota(Some(tier)),
+                    format!("{namespace} tier {tier}"),
+                    store.clone(),
+                );
+                (tier.clone(), Arc::new(limiter))
+            })
+            .collect();
+        let limiter = Arc::new(build_limiter(path_limit.tier_quota(None), namespace, store));
         Self {
             limit: path_limit,
             limiter,
+            tiers,
+        }
+    }
+
+    /// All limiters of this limit.
+    fn limiters(&self) -> impl Iterator<Item = &Arc<Limiter>> {
+        std::iter::once(&self.limiter).chain(self.tiers.values())
+    }
+
+    /// The limiter for the tier of the authenticated user.
+    ///
+    /// Requests without a valid session get the default quota.
+    fn select_limiter(&self, session_user: Option<&PublicKey>, db: Option<&LmDB>) -> &Arc<Limiter> {
+        if self.tiers.is_empty() {
+            return &self.limiter;
         }
+        let (Some(db), Some(user)) = (db, session_user) else {
+            return &self.limiter;
+        };
+        match db.get_user_tier(user) {
+            Ok(Some(tier)) => self.tiers.get(&tier).unwrap_or(&self.limiter),
+            Ok(None) => &self.limiter,
+            Err(e) => {
+                tracing::warn!("Failed to read the tier of {user}: {e}");
+                &self.limiter
+            }
+        }
+    }
+
+    /// Whether the limit needs the user of the session.
+    fn needs_session(&self) -> bool {
+        self.limit.key == LimitKeyType::Session || !self.tiers.is_empty()
     }
 
     /// Extract the key from the request.
     ///
-    /// The key is either the ip address of the client
-    /// or the user pubkey.
-    fn extract_key(&self, req: &Request<Body>) -> anyhow::Result<LimitKey> {
+    /// The key is either the ip address of the client,
+    /// the user pubkey or the user of the session.
+    fn extract_key(
+        &self,
+        req: &Request<Body>,
+        trusted_proxies: &[IpNet],
+        client_ip_header: ClientIpHeader,
+        session_user: Option<&PublicKey>,
+    ) -> anyhow::Result<LimitKey> {
         match self.limit.key {
-            LimitKeyType::Ip => extract_ip(req).map(LimitKey::Ip),
+            LimitKeyType::Ip => {
+                extract_ip(req, trusted_proxies, client_ip_header).map(LimitKey::Ip)
+            }
             LimitKeyType::User => {
                 // Extract the user pubkey from the request.
                 req.extensions()
@@ -121,6 +398,10 @@ impl LimitTuple {
                     .map(|pk| LimitKey::User(pk.public_key().clone()))
                     .ok_or(anyhow::anyhow!("Failed to extract user pubkey."))
             }
+            LimitKeyType::Session => match session_user {
+                Some(user) => Ok(LimitKey::User(user.clone())),
+                None => extract_ip(req, trusted_proxies, client_ip_header).map(LimitKey::Ip),
+            },
         }
     }
 
@@ -133,10 +414,31 @@ impl LimitTuple {
     }
 }
 
+/// The session secret from the cookie named after the pubky-host.
+fn session_secret(req: &Request<Body>) -> Option<String> {
+    let name = req
+        .extensions()
+        .get::<PubkyHost>()?
+        .public_key()
+        .to_string();
+    req.headers()
+        .get_all(axum::http::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(Cookie::split_parse)
+        .filter_map(Result::ok)
+        .find(|cookie| cookie.name() == name)
+        .map(|cookie| cookie.value().to_string())
+}
+
 #[derive(Debug, Clone)]
 pub struct RateLimiterMiddleware<S> {
     inner: S,
-    limits: Vec<LimitTuple>,
+    limits: RateLimits,
+    trusted_proxies: Arc<Vec<IpNet>>,
+    client_ip_header: ClientIpHeader,
+    metrics: Metrics,
+    _cleanup: Arc<LimiterCleanup>,
 }
 
 impl<S> RateLimiterMiddleware<S> {
@@ -144,7 +446,7 @@ impl<S> RateLimiterMiddleware<S> {
     fn throttle_upload(
         req: Request<Body>,
         key: &LimitKey,
-        limiter: &Arc<RateLimiter<LimitKey, DashMapStateStore<LimitKey>, QuantaClock>>,
+        limiter: &Arc<Limiter>,
     ) -> Request<Body> {
         let (parts, body) = req.into_parts();
         let new_body = Self::throttle_body(body, key, limiter);
@@ -155,7 +457,7 @@ impl<S> RateLimiterMiddleware<S> {
     fn throttle_download(
         res: Response<Body>,
         key: &LimitKey,
-        limiter: &Arc<RateLimiter<LimitKey, DashMapStateStore<LimitKey>, QuantaClock>>,
+        limiter: &Arc<Limiter>,
     ) -> Response<Body> {
         let (parts, body) = res.into_parts();
         let new_body = Self::throttle_body(body, key, limiter);
@@ -167,11 +469,7 @@ impl<S> RateLimiterMiddleware<S> {
     /// Important: The speed quotas are always in kilobytes, not bytes.
     /// Counting bytes is not practical.
     ///
-    fn throttle_body(
-        body: Body,
-        key: &LimitKey,
-        limiter: &Arc<RateLimiter<LimitKey, DashMapStateStore<LimitKey>, QuantaClock>>,
-    ) -> Body {
+    fn throttle_body(body: Body, key: &LimitKey, limiter: &Arc<Limiter>) -> Body {
         let body_stream = body.into_data_stream();
         let limiter = limiter.clone();
         let key = key.clone();
@@ -234,10 +532,12 @@ impl<S> RateLimiterMiddleware<S> {
     }
 
     /// Get the limits that match the request.
-    fn get_limit_matches(&self, req: &Request<Body>) -> Vec<&LimitTuple> {
+    fn get_limit_matches(&self, req: &Request<Body>) -> Vec<LimitTuple> {
         self.limits
+            .current()
             .iter()
             .filter(|limit| limit.is_match(req))
+            .cloned()
             .collect()
     }
 }
@@ -268,9 +568,26 @@ where
             return Box::pin(async move { inner.call(req).await.map_err(|_| unreachable!()) });
         }
 
+        // The most restrictive request limit is reported to the client.
+        let mut info: Option<RateLimitInfo> = None;
+        // The download is throttled with the same keys as the upload.
+        let mut download_limiters: Vec<(LimitKey, Arc<Limiter>)> = vec![];
+
+        // Resolve the session once for all limits.
+        let session_user = limits
+            .iter()
+            .any(LimitTuple::needs_session)
+            .then(|| self.limits.session_user(&req))
+            .flatten();
+
         // Go through all the limits and check if we need to throttle or reject the request.
-        for limit in limits.clone() {
-            let key = match limit.extract_key(&req) {
+        for limit in &limits {
+            let key = match limit.extract_key(
+                &req,
+                &self.trusted_proxies,
+                self.client_ip_header,
+                session_user.as_ref(),
+            ) {
                 Ok(key) => key,
                 Err(e) => {
                     // Failed to extract the key, so we reject the request.
@@ -294,43 +611,53 @@ where
             if limit.limit.is_whitelisted(&key) {
                 continue;
             }
+            let key = limit.limit.bucket_key(key);
+            let limiter = limit.select_limiter(session_user.as_ref(), self.limits.db.as_ref());
 
             match limit.limit.quota.rate_unit {
                 RateUnit::SpeedRateUnit(_) => {
                     // Speed limiting is enabled, so we need to throttle the upload.
-                    req = Self::throttle_upload(req, &key, &limit.limiter);
+                    req = Self::throttle_upload(req, &key, limiter);
+                    download_limiters.push((key, limiter.clone()));
                 }
                 RateUnit::Request => {
                     // Request limiting is enabled, so we need to limit the number of requests.
-                    if let Err(e) = limit.limiter.check_key(&key) {
-                        tracing::debug!(
-                            "Rate limit of {} exceeded for {key}: {}",
-                            limit.limit.quota,
-                            e
-                        );
-                        return Box::pin(async move {
-                            Ok(HttpError::new_with_message(
-                                StatusCode::TOO_MANY_REQUESTS,
-                                "Rate limit exceeded",
-                            )
-                            .into_response())
-                        });
+                    match limiter.check_key(&key) {
+                        Ok(snapshot) => {
+                            let allowed = RateLimitInfo::allowed(&snapshot);
+                            if info
+                                .as_ref()
+                                .is_none_or(|info| allowed.remaining < info.remaining)
+                            {
+                                info = Some(allowed);
+                            }
+                        }
+                        Err(e) => {
+                            tracing::debug!(
+                                "Rate limit of {} exceeded for {key}: {}",
+                                limit.limit.quota,
+                                e
+                            );
+                            self.metrics.inc_rate_limit_rejection(
+                                limit.limit.method.0.as_str(),
+                                &limit.limit.path.0,
+                            );
+                            let rejected = RateLimitInfo::rejected(&e, limiter.clock().now());
+                            return Box::pin(async move {
+                                let mut response = HttpError::new_with_message(
+                                    StatusCode::TOO_MANY_REQUESTS,
+                                    "Rate limit exceeded",
+                                )
+                                .into_response();
+                                rejected.add_headers(response.headers_mut());
+                                Ok(response)
+                            });
+                        }
                     };
                 }
             };
         }
 
-        // Create a clone of the request without the body.
-        // This way, we can extract the keys for the response too.
-        let (parts, body) = req.into_parts();
-        let req_clone = Request::from_parts(parts.clone(), Body::empty());
-        let req = Request::from_parts(parts, body);
-
-        let speed_limits = limits
-            .into_iter()
-            .filter(|limit| limit.limit.quota.rate_unit.is_speed_rate_unit())
-            .cloned()
-            .collect::<Vec<_>>();
         Box::pin(async move {
             // Call the next layer and receive the response.
             let mut response = match inner.call(req).await.map_err(|_| unreachable!()) {
@@ -338,10 +665,11 @@ where
                 Err(e) => return Err(e),
             };
             // Rate limit the download speed.
-            for limit in speed_limits {
-                if let Ok(key) = limit.extract_key(&req_clone) {
-                    response = Self::throttle_download(response, &key, &limit.limiter);
-                };
+            for (key, limiter) in download_limiters {
+                response = Self::throttle_download(response, &key, &limiter);
+            }
+            if let Some(info) = info {
+                info.add_headers(response.headers_mut());
             }
             Ok(response)
         })
@@ -362,7 +690,9 @@ mod tests {
     use reqwest::{Client, Response};
     use tokio::{task::JoinHandle, time::Instant};
 
+    use crate::persistence::lmdb::{tables::rate_limits::LmdbRateLimitStore, LmDB};
     use crate::shared::HttpResult;
+    use crate::InMemoryRateLimitStore;
     use crate::{core::layers::pubky_host::PubkyHostLayer, quota_config::GlobPattern};
 
     use super::*;
@@ -385,10 +715,15 @@ mod tests {
 
     // Start a server with the given quota config on a random port.
     async fn start_server(config: Vec<PathLimit>) -> SocketAddr {
+        start_server_with_limits(RateLimits::with_store(config, None)).await
+    }
+
+    // Start a server with shared limits on a random port.
+    async fn start_server_with_limits(limits: RateLimits) -> SocketAddr {
         let app = Router::new()
             .route("/upload", post(upload_handler))
             .route("/download", get(download_handler))
-            .layer(RateLimiterLayer::new(config))
+            .layer(RateLimiterLayer::new(limits, vec![], Metrics::default()))
             .layer(PubkyHostLayer);
 
         // Create a TCP listener to bind to the socket first
@@ -415,6 +750,22 @@ mod tests {
         socket
     }
 
+    #[tokio::test]
+    async fn test_single_cleanup_task() {
+        let layer = RateLimiterLayer::new(RateLimits::default(), vec![], Metrics::default());
+        let cleanup = Arc::downgrade(&layer.cleanup);
+        // axum calls `layer()` once per route.
+        let services = (0..10).map(|_| layer.layer(())).collect::<Vec<_>>();
+        assert!(services
+            .iter()
+            .all(|service| Arc::ptr_eq(&service._cleanup, &layer.cleanup)));
+
+        drop(layer);
+        assert!(cleanup.upgrade().is_some());
+        drop(services);
+        assert!(cleanup.upgrade().is_none(), "The task is stopped");
+    }
+
     #[tokio::test]
     async fn test_throttle_upload() {
         let path_limit = PathLimit::new(
@@ -525,6 +876,149 @@ mod tests {
         assert_eq!(res2.status(), StatusCode::TOO_MANY_REQUESTS);
     }
 
+    #[tokio::test]
+    async fn test_rate_limit_headers() {
+        let path_limit = PathLimit::new(
+            GlobPattern::new("/upload"),
+            Method::POST,
+            "2r/m".parse().unwrap(),
+            LimitKeyType::Ip,
+            None,
+        );
+        let socket = start_server(vec![path_limit]).await;
+        let client = Client::new();
+        let send_request = || client.post(format!("http://{socket}/upload")).send();
+        let header = |response: &Response, name: &str| -> Option<u64> {
+            response
+                .headers()
+                .get(name)
+                .map(|value| value.to_str().unwrap().parse().unwrap())
+        };
+
+        let response = send_request().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(header(&response, RATELIMIT_LIMIT), Some(2));
+        assert_eq!(header(&response, RATELIMIT_REMAINING), Some(1));
+        assert_eq!(header(&response, RATELIMIT_RESET), Some(30));
+        assert_eq!(header(&response, RETRY_AFTER.as_str()), None);
+
+        let response = send_request().await.unwrap();
+        assert_eq!(header(&response, RATELIMIT_REMAINING), Some(0));
+
+        let response = send_request().await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&response, RATELIMIT_REMAINING), Some(0));
+        let retry_after = header(&response, RETRY_AFTER.as_str()).unwrap();
+        assert!((29..=30).contains(&retry_after), "{retry_after}");
+        let reset = header(&response, RATELIMIT_RESET).unwrap();
+        assert!((59..=60).contains(&reset), "{reset}");
+    }
+
+    #[tokio::test]
+    async fn test_swap_limits_at_runtime() {
+        let limits = RateLimits::default();
+        let socket = start_server_with_limits(limits.clone()).await;
+        let client = Client::new();
+        let send_request = || client.post(format!("http://{}/upload", socket)).send();
+
+        assert_eq!(send_request().await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(send_request().await.unwrap().status(), StatusCode::CREATED);
+
+        let path_limit = PathLimit::new(
+            GlobPattern::new("/upload"),
+            Method::POST,
+            "1r/m".parse().unwrap(),
+            LimitKeyType::Ip,
+            None,
+        );
+        limits.set(vec![path_limit.clone()]);
+        assert_eq!(send_request().await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(
+            send_request().await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // Unchanged limits keep their state.
+        limits.set(vec![path_limit]);
+        assert_eq!(
+            send_request().await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        limits.set(vec![]);
+        assert_eq!(send_request().await.unwrap().status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_share_state_between_instances() {
+        let path_limit = PathLimit::new(
+            GlobPattern::new("/upload"),
+            Method::POST,
+            "1r/m".parse().unwrap(),
+            LimitKeyType::Ip,
+            None,
+        );
+        let store: Arc<dyn RateLimitStore> = Arc::new(InMemoryRateLimitStore::default());
+        let first = start_server_with_limits(RateLimits::with_store(
+            vec![path_limit.clone()],
+            Some(store.clone()),
+        ))
+        .await;
+        let second =
+            start_server_with_limits(RateLimits::with_store(vec![path_limit], Some(store))).await;
+        let client = Client::new();
+        let send_request =
+            |socket: SocketAddr| client.post(format!("http://{socket}/upload")).send();
+
+        assert_eq!(
+            send_request(first).await.unwrap().status(),
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            send_request(second).await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_persist_state_across_restarts() {
+        let path_limit = PathLimit::new(
+            GlobPattern::new("/upload"),
+            Method::POST,
+            "1r/m".parse().unwrap(),
+            LimitKeyType::Ip,
+            None,
+        );
+        let db = LmDB::test();
+        let store = Arc::new(LmdbRateLimitStore::new(db.clone()).unwrap());
+        let client = Client::new();
+
+        let socket = start_server_with_limits(RateLimits::with_store(
+            vec![path_limit.clone()],
+            Some(store.clone()),
+        ))
+        .await;
+        let response = client
+            .post(format!("http://{socket}/upload"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        store.flush().unwrap();
+
+        // Fresh limiters and state loaded from the same database.
+        let store = Arc::new(LmdbRateLimitStore::new(db).unwrap());
+        let socket =
+            start_server_with_limits(RateLimits::with_store(vec![path_limit], Some(store))).await;
+        let response = client
+            .post(format!("http://{socket}/upload"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
     #[tokio::test]
     async fn test_limit_parallel_requests_with_user_key() {
         let path_limit = PathLimit::new(
@@ -561,4 +1055,127 @@ mod tests {
         assert_eq!(res2.status(), StatusCode::TOO_MANY_REQUESTS);
         assert_eq!(res3.status(), StatusCode::CREATED);
     }
+
+    /// Store a session of `user` in `db` and return its secret.
+    fn add_session(db: &LmDB, user: &PublicKey) -> String {
+        let secret = format!("secret-{user}");
+        let session = pubky_common::session::Session::new(user, &[], None);
+        let mut wtxn = db.env.write_txn().unwrap();
+        db.put_session(&mut wtxn, &secret, &session).unwrap();
+        wtxn.commit().unwrap();
+        secret
+    }
+
+    #[tokio::test]
+    async fn test_limit_by_user_tier() {
+        let mut path_limit = PathLimit::new(
+            GlobPattern::new("/upload"),
+            Method::POST,
+            "1r/m".parse().unwrap(),
+            LimitKeyType::User,
+            None,
+        );
+        path_limit
+            .tiers
+            .insert("paid".to_string(), "2r/m".parse().unwrap());
+        let db = LmDB::test();
+        let paid_user = Keypair::random().public_key();
+        db.set_user_tier(&paid_user, Some("paid")).unwrap();
+        let paid_session = add_session(&db, &paid_user);
+        let free_user = Keypair::random().public_key();
+        let socket =
+            start_server_with_limits(RateLimits::with_store(vec![path_limit], None).with_db(db))
+                .await;
+
+        let client = Client::new();
+        let upload = |user: &PublicKey, session: Option<&str>| {
+            let mut request = client.post(format!("http://{socket}/upload?pubky-host={user}"));
+            if let Some(session) = session {
+                request = request.header("cookie", format!("{user}={session}"));
+            }
+            request.send()
+        };
+        assert_eq!(
+            upload(&free_user, None).await.unwrap().status(),
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            upload(&free_user, None).await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        for _ in 0..2 {
+            assert_eq!(
+                upload(&paid_user, Some(&paid_session))
+                    .await
+                    .unwrap()
+                    .status(),
+                StatusCode::CREATED
+            );
+        }
+        assert_eq!(
+            upload(&paid_user, Some(&paid_session))
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // Without a valid session, the paid user's pubky-host gets the default quota.
+        assert_eq!(
+            upload(&paid_user, Some("random")).await.unwrap().status(),
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            upload(&paid_user, Some("random")).await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limit_by_session() {
+        let path_limit = PathLimit::new(
+            GlobPattern::new("/upload"),
+            Method::POST,
+            "1r/m".parse().unwrap(),
+            LimitKeyType::Session,
+            None,
+        );
+        let db = LmDB::test();
+        let user = Keypair::random().public_key();
+        let session = add_session(&db, &user);
+        let other_user = Keypair::random().public_key();
+        let other_session = add_session(&db, &other_user);
+        let socket =
+            start_server_with_limits(RateLimits::with_store(vec![path_limit], None).with_db(db))
+                .await;
+
+        let client = Client::new();
+        let upload = |cookie: Option<&str>| {
+            let mut request = client.post(format!("http://{socket}/upload?pubky-host={user}"));
+            if let Some(cookie) = cookie {
+                request = request.header("cookie", format!("{user}={cookie}"));
+            }
+            request.send()
+        };
+        assert_eq!(
+            upload(Some(&session)).await.unwrap().status(),
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            upload(Some(&session)).await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // Random cookies and sessions of other users fall back to the ip address.
+        assert_eq!(
+            upload(Some("random1")).await.unwrap().status(),
+            StatusCode::CREATED
+        );
+        for cookie in [Some("random2"), Some(other_session.as_str()), None] {
+            assert_eq!(
+                upload(cookie).await.unwrap().status(),
+                StatusCode::TOO_MANY_REQUESTS
+            );
+        }
+    }
 }
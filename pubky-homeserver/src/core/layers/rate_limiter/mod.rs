@@ -1,3 +1,5 @@
 mod extract_ip;
 mod layer;
+mod state;
 pub use layer::*;
+pub use state::{InMemoryRateLimitStore, RateLimitStore};
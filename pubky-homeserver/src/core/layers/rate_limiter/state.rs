@@ -0,0 +1,215 @@
+//!
+//! Where the rate limiters keep their state.
+//!
+//! By default, the state lives in memory and is lost on restart.
+//! A [RateLimitStore] persists it, for example in LMDB, or shares it between homeserver instances.
+//!
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use governor::clock::{Clock, ReasonablyRealtime};
+use governor::middleware::StateInformationMiddleware;
+use governor::nanos::Nanos;
+use governor::state::keyed::{DashMapStateStore, ShrinkableKeyedStateStore};
+use governor::state::StateStore;
+use governor::{Quota, RateLimiter};
+
+use crate::quota_config::LimitKey;
+
+/// A key-value store for the state of the rate limiters.
+///
+/// The values are the theoretical arrival times of the
+/// [GCRA](https://en.wikipedia.org/wiki/Generic_cell_rate_algorithm)
+/// in nanoseconds since the UNIX epoch, so they stay valid across restarts and machines.
+/// A store that is shared between homeserver instances enforces the limits across all of them.
+///
+/// If the store fails, the request is allowed and the error is logged.
+pub trait RateLimitStore: std::fmt::Debug + Send + Sync {
+    /// Get the value of a key.
+    fn get(&self, key: &str) -> anyhow::Result<Option<u64>>;
+
+    /// Set the value of a key to `new` if it is still `current`.
+    ///
+    /// Returns `false` if the value changed in the meantime.
+    fn compare_and_swap(&self, key: &str, current: Option<u64>, new: u64) -> anyhow::Result<bool>;
+
+    /// Remove all keys with a value below `now`.
+    ///
+    /// Their limits are not exhausted anymore and behave like unused keys.
+    fn remove_expired(&self, now: u64) -> anyhow::Result<()>;
+
+    /// Persist changes that are still buffered. Called on shutdown.
+    fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// A [RateLimitStore] in memory. Stands in for a shared store in tests.
+#[derive(Debug, Default)]
+pub struct InMemoryRateLimitStore {
+    values: Mutex<HashMap<String, u64>>,
+}
+
+impl RateLimitStore for InMemoryRateLimitStore {
+    fn get(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        Ok(self
+            .values
+            .lock()
+            .expect("rate limit store lock poisoned")
+            .get(key)
+            .copied())
+    }
+
+    fn compare_and_swap(&self, key: &str, current: Option<u64>, new: u64) -> anyhow::Result<bool> {
+        let mut values = self.values.lock().expect("rate limit store lock poisoned");
+        if values.get(key).copied() != current {
+            return Ok(false);
+        }
+        values.insert(key.to_string(), new);
+        Ok(true)
+    }
+
+    fn remove_expired(&self, now: u64) -> anyhow::Result<()> {
+        self.values
+            .lock()
+            .expect("rate limit store lock poisoned")
+            .retain(|_, tat| *tat >= now);
+        Ok(())
+    }
+}
+
+pub(crate) type Limiter =
+    RateLimiter<LimitKey, LimiterState, EpochClock, StateInformationMiddleware>;
+
+/// Create a limiter. Keeps the state in `store` under `namespace` if set, otherwise in memory.
+pub(crate) fn build_limiter(
+    quota: Quota,
+    namespace: String,
+    store: Option<Arc<dyn RateLimitStore>>,
+) -> Limiter {
+    let clock = EpochClock::default();
+    let state = match store {
+        None => LimiterState::Memory(DashMapStateStore::default()),
+        Some(store) => LimiterState::Store {
+            store,
+            namespace,
+            start: clock.start.clone(),
+        },
+    };
+    RateLimiter::new(quota, state, clock)
+}
+
+/// Clock with the nanoseconds since the UNIX epoch.
+///
+/// Governor measures time relative to the first reading of the clock when the limiter is created.
+/// This reading is kept so the state can be converted to absolute times.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EpochClock {
+    start: Arc<OnceLock<Nanos>>,
+}
+
+impl Clock for EpochClock {
+    type Instant = Nanos;
+
+    fn now(&self) -> Nanos {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let now = Nanos::from(now);
+        self.start.get_or_init(|| now);
+        now
+    }
+}
+
+impl ReasonablyRealtime for EpochClock {}
+
+#[derive(Debug)]
+pub(crate) enum LimiterState {
+    Memory(DashMapStateStore<LimitKey>),
+    Store {
+        store: Arc<dyn RateLimitStore>,
+        /// Separates the keys of the different limits.
+        namespace: String,
+        start: Arc<OnceLock<Nanos>>,
+    },
+}
+
+impl LimiterState {
+    fn start(start: &OnceLock<Nanos>) -> u64 {
+        start.get().map(|start| start.as_u64()).unwrap_or_default()
+    }
+}
+
+impl StateStore for LimiterState {
+    type Key = LimitKey;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        let (store, namespace, start) = match self {
+            LimiterState::Memory(state) => return state.measure_and_replace(key, f),
+            LimiterState::Store {
+                store,
+                namespace,
+                start,
+            } => (store, namespace, Self::start(start)),
+        };
+        let store_key = format!("{namespace} {key}");
+        loop {
+            let current = match store.get(&store_key) {
+                Ok(current) => current,
+                Err(e) => {
+                    tracing::warn!("Failed to read rate limit state of {store_key}: {e}");
+                    return f(None).map(|(result, _)| result);
+                }
+            };
+            // State from before the limiter was created is in the past and as good as fresh.
+            let relative = current.map(|tat| Nanos::from(tat.saturating_sub(start)));
+            let (result, new) = f(relative)?;
+            match store.compare_and_swap(&store_key, current, start + new.as_u64()) {
+                Ok(true) => return Ok(result),
+                // Somebody else was faster. Measure again.
+                Ok(false) => continue,
+                Err(e) => {
+                    tracing::warn!("Failed to write rate limit state of {store_key}: {e}");
+                    return Ok(result);
+                }
+            }
+        }
+    }
+}
+
+impl ShrinkableKeyedStateStore<LimitKey> for LimiterState {
+    fn retain_recent(&self, drop_below: Nanos) {
+        match self {
+            LimiterState::Memory(state) => state.retain_recent(drop_below),
+            LimiterState::Store { store, start, .. } => {
+                let drop_below = Self::start(start) + drop_below.as_u64();
+                if let Err(e) = store.remove_expired(drop_below) {
+                    tracing::warn!("Failed to remove expired rate limit state: {e}");
+                }
+            }
+        }
+    }
+
+    fn shrink_to_fit(&self) {
+        if let LimiterState::Memory(state) = self {
+            state.shrink_to_fit();
+        }
+    }
+
+    /// The number of keys in memory. Unknown for stores.
+    fn len(&self) -> usize {
+        match self {
+            LimiterState::Memory(state) => state.len(),
+            LimiterState::Store { .. } => 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -1,9 +1,17 @@
 mod err_if_user_is_invalid;
 mod extractors;
 mod homeserver_core;
+mod http3;
+mod icann_tls;
 mod key_republisher;
-mod layers;
+pub(crate) mod layers;
 mod periodic_backup;
+mod periodic_scrub;
+mod periodic_upload_cleanup;
+pub(crate) mod proxy_protocol;
 mod routes;
+pub(crate) mod unix_socket;
 mod user_keys_republisher;
+mod virtual_hosts;
 pub use homeserver_core::*;
+pub(crate) use virtual_hosts::VirtualHosts;
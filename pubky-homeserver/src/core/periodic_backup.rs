@@ -2,11 +2,16 @@ use crate::{app_context::AppContext, persistence::lmdb::LmDB};
 use heed::CompactionOption;
 use std::path::PathBuf;
 use std::time::Duration;
-use tokio::{task::JoinHandle, time::interval};
+use tokio::{
+    task::JoinHandle,
+    time::{interval, Instant},
+};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info};
 
 pub(crate) struct PeriodicBackup {
     handle: Option<JoinHandle<()>>,
+    cancel: CancellationToken,
 }
 
 const BACKUP_INTERVAL_DANGERZONE: Duration = Duration::from_secs(30);
@@ -18,7 +23,10 @@ impl PeriodicBackup {
         let is_disabled = backup_interval.as_secs() == 0;
         if is_disabled {
             tracing::info!("LMDB backup is disabled.");
-            return Self { handle: None };
+            return Self {
+                handle: None,
+                cancel: CancellationToken::new(),
+            };
         }
         if backup_interval < BACKUP_INTERVAL_DANGERZONE {
             tracing::warn!(
@@ -32,11 +40,28 @@ impl PeriodicBackup {
             "Starting LMDB backup with interval {}s",
             backup_interval.as_secs()
         );
+        let cancel = CancellationToken::new();
+        let task_cancel = cancel.clone();
         let handle = tokio::spawn(async move {
-            backup_lmdb_periodically(db, backup_path, backup_interval).await;
+            backup_lmdb_periodically(db, backup_path, backup_interval, task_cancel).await;
         });
         Self {
             handle: Some(handle),
+            cancel,
+        }
+    }
+
+    /// Stop the periodic backup. A backup in progress is finished first
+    /// so it doesn't race with the backup on shutdown.
+    ///
+    /// Waits until `deadline` at most.
+    pub async fn stop(&mut self, deadline: Instant) {
+        self.cancel.cancel();
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        if tokio::time::timeout_at(deadline, handle).await.is_err() {
+            tracing::warn!("LMDB backup did not finish in time.");
         }
     }
 }
@@ -60,14 +85,23 @@ impl Drop for PeriodicBackup {
 ///
 /// * `db` - The LMDB database handle.
 /// * `backup_path` - The base path for the backup file (extensions will be appended).
-pub async fn backup_lmdb_periodically(db: LmDB, backup_path: PathBuf, period: Duration) {
+/// * `cancel` - Stops the loop between two backups.
+pub async fn backup_lmdb_periodically(
+    db: LmDB,
+    backup_path: PathBuf,
+    period: Duration,
+    cancel: CancellationToken,
+) {
     let mut interval_timer = interval(period);
 
     interval_timer.tick().await; // Ignore the first tick as it is instant.
 
     loop {
         // Wait for the next backup tick.
-        interval_timer.tick().await;
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = interval_timer.tick() => {}
+        }
 
         // Clone the database handle and backup path for use in the blocking task.
         let db_clone = db.clone();
@@ -92,7 +126,7 @@ pub async fn backup_lmdb_periodically(db: LmDB, backup_path: PathBuf, period: Du
 ///
 /// * `db` - The LMDB database handle.
 /// * `backup_path` - The base path for the backup file (extensions will be appended).
-fn do_backup(db: LmDB, backup_path: PathBuf) {
+pub(crate) fn do_backup(db: LmDB, backup_path: PathBuf) {
     // Define file paths for the temporary and final backup files.
     let final_backup_path = backup_path.with_extension("mdb");
     let temp_backup_path = backup_path.with_extension("tmp");
@@ -0,0 +1,88 @@
+use crate::{app_context::AppContext, persistence::files::StorageScrubber};
+use std::time::Duration;
+use tokio::{
+    task::JoinHandle,
+    time::{interval, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Periodically verifies all stored files against their content hash.
+pub(crate) struct PeriodicScrub {
+    handle: Option<JoinHandle<()>>,
+    cancel: CancellationToken,
+}
+
+impl PeriodicScrub {
+    pub fn start(context: &AppContext) -> Self {
+        let cancel = CancellationToken::new();
+        let scrub_interval =
+            Duration::from_secs(context.config_toml.general.storage_scrub_interval_s);
+        let is_disabled = scrub_interval.as_secs() == 0;
+        if is_disabled {
+            tracing::info!("Storage scrubbing is disabled.");
+            return Self {
+                handle: None,
+                cancel,
+            };
+        }
+        let quarantine = context.config_toml.general.storage_scrub_quarantine;
+        let scrubber = StorageScrubber::from_context(context);
+        tracing::info!(
+            "Starting storage scrubbing with interval {}s",
+            scrub_interval.as_secs()
+        );
+        let task_cancel = cancel.clone();
+        let handle = tokio::spawn(async move {
+            scrub_periodically(scrubber, scrub_interval, quarantine, task_cancel).await;
+        });
+        Self {
+            handle: Some(handle),
+            cancel,
+        }
+    }
+
+    /// Stop scrubbing after the file in progress.
+    ///
+    /// Waits until `deadline` at most.
+    pub async fn stop(&mut self, deadline: Instant) {
+        self.cancel.cancel();
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        if tokio::time::timeout_at(deadline, handle).await.is_err() {
+            tracing::warn!("Storage scrub did not stop in time.");
+        }
+    }
+}
+
+impl Drop for PeriodicScrub {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+async fn scrub_periodically(
+    scrubber: StorageScrubber,
+    period: Duration,
+    quarantine: bool,
+    cancel: CancellationToken,
+) {
+    let mut interval_timer = interval(period);
+    interval_timer.tick().await; // Ignore the first tick as it is instant.
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = interval_timer.tick() => {}
+        }
+        match scrubber.run_until_cancelled(quarantine, &cancel).await {
+            Ok(report) if cancel.is_cancelled() => {
+                tracing::info!("Storage scrub stopped. {}", report)
+            }
+            Ok(report) => tracing::info!("Storage scrub finished. {}", report),
+            Err(e) => tracing::error!("Storage scrub failed: {}", e),
+        }
+    }
+}
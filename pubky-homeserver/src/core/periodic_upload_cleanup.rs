@@ -0,0 +1,52 @@
+use crate::{app_context::AppContext, persistence::files::UploadService};
+use std::time::Duration;
+use tokio::{task::JoinHandle, time::interval};
+
+/// How often expired uploads are looked for at most.
+const MAX_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically deletes resumable uploads that have been abandoned.
+pub(crate) struct PeriodicUploadCleanup {
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PeriodicUploadCleanup {
+    pub fn start(context: &AppContext) -> Self {
+        let expiry = Duration::from_secs(context.config_toml.general.resumable_upload_expiry_s);
+        let is_disabled = expiry.is_zero();
+        if is_disabled {
+            tracing::info!("Resumable uploads never expire.");
+            return Self { handle: None };
+        }
+        let cleanup_interval = expiry.min(MAX_CLEANUP_INTERVAL);
+        let upload_service = context.upload_service.clone();
+        let handle = tokio::spawn(async move {
+            cleanup_periodically(upload_service, cleanup_interval).await;
+        });
+        Self {
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for PeriodicUploadCleanup {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+async fn cleanup_periodically(upload_service: UploadService, period: Duration) {
+    let mut interval_timer = interval(period);
+    interval_timer.tick().await; // Ignore the first tick as it is instant.
+
+    loop {
+        interval_timer.tick().await;
+        match upload_service.delete_expired().await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("Deleted {} expired uploads", count),
+            Err(e) => tracing::error!("Failed to delete expired uploads: {}", e),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//!
+//! PROXY protocol v1 and v2 support for the listeners.
+//!
+//! Load balancers like HAProxy send the address of the client in a header
+//! before the actual connection data. See <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>.
+//!
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{middleware::AddExtension, Extension};
+use axum_server::accept::Accept;
+use futures_util::future::BoxFuture;
+use ipnet::IpNet;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::TcpStream,
+};
+use tower::Layer;
+
+use super::layers::global_limits::{ConnectionSlot, GlobalLimits};
+
+/// Time a proxy has to send the header after connecting.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Maximum length of a v1 header including the CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+/// Address of the client of a connection.
+///
+/// The TCP peer or, with the PROXY protocol, the source address of the header.
+/// Added to the request extensions by the [ProxyProtocolAcceptor].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PeerAddr(pub SocketAddr);
+
+/// Acceptor that reads the PROXY protocol header of every connection if enabled.
+///
+/// Connections from outside the trusted proxies or without a valid header are closed.
+/// Also counts the open connections of each client for the [GlobalLimits].
+#[derive(Debug, Clone)]
+pub(crate) struct ProxyProtocolAcceptor {
+    enabled: bool,
+    trusted_proxies: Arc<Vec<IpNet>>,
+    global_limits: GlobalLimits,
+}
+
+impl ProxyProtocolAcceptor {
+    pub fn new(enabled: bool, trusted_proxies: Vec<IpNet>, global_limits: GlobalLimits) -> Self {
+        Self {
+            enabled,
+            trusted_proxies: Arc::new(trusted_proxies),
+            global_limits,
+        }
+    }
+
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    async fn peer_addr(&self, stream: &mut TcpStream) -> io::Result<SocketAddr> {
+        let peer = stream.peer_addr()?;
+        if !self.enabled {
+            return Ok(peer);
+        }
+        if !self.is_trusted_proxy(peer.ip()) {
+            return Err(invalid_data(format!(
+                "PROXY protocol connection from untrusted address {peer}"
+            )));
+        }
+        let header = tokio::time::timeout(HEADER_TIMEOUT, read_header(stream))
+            .await
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::TimedOut, "PROXY protocol header timeout")
+            })??;
+        // Health checks of the proxy itself have no client address.
+        Ok(header.unwrap_or(peer))
+    }
+}
+
+impl<S: Send + 'static> Accept<TcpStream, S> for ProxyProtocolAcceptor {
+    type Stream = TcpStream;
+    type Service = AddExtension<AddExtension<S, PeerAddr>, Option<ConnectionSlot>>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, mut stream: TcpStream, service: S) -> Self::Future {
+        let acceptor = self.clone();
+        Box::pin(async move {
+            let addr = match acceptor.peer_addr(&mut stream).await {
+                Ok(addr) => addr,
+                Err(e) => {
+                    tracing::debug!("Rejected connection: {e}");
+                    return Err(e);
+                }
+            };
+            // Connections of a proxy carry many clients, so they are not counted.
+            let slot = (!acceptor.is_trusted_proxy(addr.ip()))
+                .then(|| acceptor.global_limits.open_connection(addr.ip()));
+            let service = Extension(PeerAddr(addr)).layer(service);
+            Ok((stream, Extension(slot).layer(service)))
+        })
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Read a v1 or v2 header without consuming any data after it.
+///
+/// Returns the source address or `None` if the header has none, for example `UNKNOWN` or `LOCAL`.
+async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    // Both versions are at least 12 bytes long.
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+    if start == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start).await
+    } else {
+        Err(invalid_data("Missing PROXY protocol header"))
+    }
+}
+
+/// `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`
+async fn read_v1<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    start: &[u8],
+) -> io::Result<Option<SocketAddr>> {
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(invalid_data("PROXY protocol v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid_data("Invalid PROXY protocol v1 header"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", src, _dst, src_port, _dst_port] => {
+            let ip: IpAddr = src
+                .parse()
+                .map_err(|_| invalid_data("Invalid PROXY protocol v1 source address"))?;
+            let port: u16 = src_port
+                .parse()
+                .map_err(|_| invalid_data("Invalid PROXY protocol v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid_data("Invalid PROXY protocol v1 header")),
+    }
+}
+
+async fn read_v2<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    let version_command = stream.read_u8().await?;
+    let family = stream.read_u8().await?;
+    let length = stream.read_u16().await? as usize;
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid_data("Unsupported PROXY protocol version"));
+    }
+    match version_command & 0x0F {
+        // LOCAL
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        _ => return Err(invalid_data("Invalid PROXY protocol v2 command")),
+    }
+    match family >> 4 {
+        // AF_INET
+        0x1 if payload.len() >= 12 => {
+            let ip = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            let port = u16::from_be_bytes([payload[8], payload[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // AF_INET6
+        0x2 if payload.len() >= 36 => {
+            let octets: [u8; 16] = payload[..16].try_into().expect("16 bytes");
+            let port = u16::from_be_bytes([payload[32], payload[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        // AF_UNSPEC, AF_UNIX
+        0x0 | 0x3 => Ok(None),
+        _ => Err(invalid_data("Invalid PROXY protocol v2 address")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(header: &[u8]) -> io::Result<Option<SocketAddr>> {
+        let mut reader = header;
+        let result = read_header(&mut reader).await;
+        // Nothing after the header is consumed.
+        if result.is_ok() {
+            assert_eq!(reader, b"GET");
+        }
+        result
+    }
+
+    #[tokio::test]
+    async fn test_read_v1() {
+        let addr = parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET")
+            .await
+            .unwrap();
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+        let addr = parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\nGET")
+            .await
+            .unwrap();
+        assert_eq!(addr, Some("[2001:db8::1]:56324".parse().unwrap()));
+        assert_eq!(parse(b"PROXY UNKNOWN\r\nGET").await.unwrap(), None);
+
+        assert!(parse(b"GET / HTTP/1.1\r\n").await.is_err());
+        assert!(parse(b"PROXY TCP4 nonsense 198.51.100.1 1 2\r\nGET")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_v2() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x21, 0x11, 0, 12]);
+        header.extend([192, 0, 2, 1, 198, 51, 100, 1]);
+        header.extend(56324u16.to_be_bytes());
+        header.extend(443u16.to_be_bytes());
+        header.extend(b"GET");
+        assert_eq!(
+            parse(&header).await.unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+
+        // LOCAL with a TLV
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x20, 0x00, 0, 3, 0x04, 0, 0]);
+        header.extend(b"GET");
+        assert_eq!(parse(&header).await.unwrap(), None);
+    }
+}
@@ -7,6 +7,7 @@ use axum::{
     extract::{Query, State},
     http::StatusCode,
     response::IntoResponse,
+    Extension,
 };
 use axum_extra::{extract::Host, headers::UserAgent, TypedHeader};
 use base32::{encode, Alphabet};
@@ -20,6 +21,11 @@ use tower_cookies::{
     Cookie, Cookies,
 };
 
+/// The user created by a signup. Added to the response extensions
+/// so the virtual hosts can route the requests of the user.
+#[derive(Debug, Clone)]
+pub(crate) struct SignedUpUser(pub PublicKey);
+
 /// Creates a brand-new user if they do not exist, then logs them in by creating a session.
 /// 1) Check if signup tokens are required (signup mode is token_required).
 /// 2) Ensure the user *does not* already exist.
@@ -49,7 +55,7 @@ pub async fn signup(
     txn.commit()?;
 
     // 3) If signup_mode == token_required, require & validate a `signup_token` param.
-    if state.signup_mode == SignupMode::TokenRequired {
+    if state.config_reloader.signup_mode() == SignupMode::TokenRequired {
         let signup_token_param = params
             .get("signup_token")
             .ok_or(HttpError::new_with_message(
@@ -88,14 +94,15 @@ pub async fn signup(
     wtxn.commit()?;
 
     // 5) Create session & set cookie
-    create_session_and_cookie(
+    let session = create_session_and_cookie(
         &state,
         cookies,
         &host,
         public_key,
         token.capabilities(),
         user_agent,
-    )
+    )?;
+    Ok((Extension(SignedUpUser(public_key.clone())), session))
 }
 
 /// Fails if user doesn’t exist, otherwise logs them in by creating a session.
@@ -150,16 +157,11 @@ fn create_session_and_cookie(
         public_key,
         capabilities,
         user_agent.map(|ua| ua.to_string()),
-    )
-    .serialize();
+    );
 
     // 2) Insert session into DB
     let mut wtxn = state.db.env.write_txn()?;
-    state
-        .db
-        .tables
-        .sessions
-        .put(&mut wtxn, &session_secret, &session)?;
+    state.db.put_session(&mut wtxn, &session_secret, &session)?;
     wtxn.commit()?;
 
     // 3) Build and set cookie
@@ -179,7 +181,7 @@ fn create_session_and_cookie(
     cookie.set_expires(expiry);
     cookies.add(cookie);
 
-    Ok(session)
+    Ok(session.serialize())
 }
 
 /// Determines if the host requires secure cookie attributes.
@@ -13,10 +13,11 @@ use tower::ServiceBuilder;
 use tower_cookies::CookieManagerLayer;
 use tower_http::cors::CorsLayer;
 
-use crate::{core::AppState, AppContext};
+use crate::{core::AppState, shared::metrics::track_http_metrics, AppContext};
 
 use super::layers::{
-    pubky_host::PubkyHostLayer, rate_limiter::RateLimiterLayer, trace::with_trace_layer,
+    global_limits::GlobalLimitsLayer, pubky_host::PubkyHostLayer, rate_limiter::RateLimiterLayer,
+    trace::with_trace_layer,
 };
 
 mod auth;
@@ -24,6 +25,8 @@ mod feed;
 mod root;
 mod tenants;
 
+pub(crate) use auth::SignedUpUser;
+
 static HOMESERVER_VERSION: &str = concat!("pubky.org", "@", env!("CARGO_PKG_VERSION"),);
 const TRACING_EXCLUDED_PATHS: [&str; 1] = ["/events/"];
 
@@ -45,10 +48,24 @@ pub fn create_app(state: AppState, context: &AppContext) -> Router {
         .layer(CookieManagerLayer::new())
         .layer(CorsLayer::very_permissive())
         .layer(ServiceBuilder::new().layer(middleware::from_fn(add_server_header)))
-        .layer(RateLimiterLayer::new(
-            context.config_toml.drive.rate_limits.clone(),
+        .layer(
+            RateLimiterLayer::new(
+                context.config_reloader.rate_limits().clone(),
+                context.config_toml.drive.trusted_proxies.clone(),
+                context.metrics.clone(),
+            )
+            .with_client_ip_header(context.config_toml.drive.client_ip_header),
+        )
+        // Shed load before the rate limiters count the request.
+        .layer(GlobalLimitsLayer::new(
+            context.global_limits.clone(),
+            context.metrics.clone(),
         ))
         .layer(PubkyHostLayer)
+        .layer(middleware::from_fn_with_state(
+            (context.metrics.clone(), "homeserver"),
+            track_http_metrics,
+        ))
         .with_state(state);
 
     // Apply trace and pubky host layers to the complete router.
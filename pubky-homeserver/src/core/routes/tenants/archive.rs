@@ -0,0 +1,128 @@
+//! Export and import of all files of the tenant.
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use futures_util::TryStreamExt;
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use tokio_util::io::StreamReader;
+
+use crate::{
+    core::{err_if_user_is_invalid::err_if_user_is_invalid, extractors::PubkyHost, AppState},
+    persistence::files::{max_archive_size, ArchiveError, ImportReport, UserArchiver},
+    shared::{HttpError, HttpResult},
+};
+
+/// Download a tar archive of the public files of the tenant.
+pub async fn export(
+    State(state): State<AppState>,
+    pubky: PubkyHost,
+) -> HttpResult<impl IntoResponse> {
+    let public_key = pubky.public_key();
+    err_if_user_is_invalid(public_key, &state.db, false)?;
+    let stream = UserArchiver::public(state.file_service.clone()).export(public_key)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{public_key}.tar\""),
+            ),
+        ],
+        Body::from_stream(stream),
+    ))
+}
+
+/// Restore an archive created by [export], possibly on another homeserver.
+///
+/// The archive can't be larger than the user quota plus the archive overhead.
+pub async fn import(
+    State(state): State<AppState>,
+    pubky: PubkyHost,
+    headers: HeaderMap,
+    body: Body,
+) -> HttpResult<(StatusCode, Json<ImportReport>)> {
+    let public_key = pubky.public_key();
+    err_if_user_is_invalid(public_key, &state.db, true)?;
+
+    let max_size = max_archive_size(state.user_quota.bytes());
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > max_size) {
+        return Err(archive_too_large(max_size));
+    }
+
+    let body = Limited::new(body, usize::try_from(max_size).unwrap_or(usize::MAX));
+    let reader = StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+    let result = UserArchiver::public(state.file_service.clone())
+        .import(public_key, reader)
+        .await;
+    match result {
+        Ok(report) => Ok((StatusCode::OK, Json(report))),
+        Err(ArchiveError::Io(e)) if is_length_limit_error(&e) => Err(archive_too_large(max_size)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn archive_too_large(max_size: u64) -> HttpError {
+    HttpError::new_with_message(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("The archive exceeds the maximum size of {max_size} bytes"),
+    )
+}
+
+/// Whether reading the body failed because it exceeded the limit.
+fn is_length_limit_error(error: &std::io::Error) -> bool {
+    let mut source = error
+        .get_ref()
+        .map(|e| e as &(dyn std::error::Error + 'static));
+    while let Some(e) = source {
+        if e.is::<LengthLimitError>() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use crate::core::routes::tenants::read::tests::create_environment;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_import_too_large() {
+        let (context, _, server, public_key, cookie) = create_environment().await.unwrap();
+        context.user_quota.set_mb(1);
+
+        let response = server
+            .post("/import")
+            .add_header("host", public_key.to_string())
+            .add_header(header::COOKIE, cookie)
+            .add_header(
+                header::CONTENT_LENGTH,
+                (max_archive_size(1024 * 1024) + 1).to_string(),
+            )
+            .bytes(vec![0u8; 16].into())
+            .await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_detect_length_limit_error() {
+        let body = Limited::new(Body::from(vec![0u8; 10]), 5);
+        let mut reader = StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+        let error = reader.read_to_end(&mut vec![]).await.unwrap_err();
+        assert!(is_length_limit_error(&error));
+        assert!(!is_length_limit_error(&std::io::Error::other("other")));
+    }
+}
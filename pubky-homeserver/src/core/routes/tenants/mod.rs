@@ -3,23 +3,37 @@
 //! Every route here is relative to a tenant's Pubky host,
 //! as opposed to routes relative to the Homeserver's owner.
 
-use axum::{extract::DefaultBodyLimit, routing::get, Router};
+use axum::{
+    extract::DefaultBodyLimit,
+    routing::{get, post},
+    Router,
+};
 
 use crate::core::{layers::authz::AuthorizationLayer, AppState};
 
+pub mod archive;
 pub mod read;
 pub mod session;
+pub mod upload;
 pub mod write;
 
 pub fn router(state: AppState) -> Router<AppState> {
     Router::new()
         .route("/session", get(session::session).delete(session::signout))
+        .route("/export", get(archive::export))
+        .route(
+            "/import",
+            // The handler limits archives according to the user quota.
+            post(archive::import).layer(DefaultBodyLimit::disable()),
+        )
         .route(
             "/{*path}",
             get(read::get)
                 .head(read::head)
                 .put(write::put)
-                .delete(write::delete),
+                .delete(write::delete)
+                .post(upload::create)
+                .patch(upload::append),
         )
         // TODO: different max size for sessions and other routes?
         .layer(DefaultBodyLimit::max(100 * 1024 * 1024))
@@ -149,7 +149,7 @@ impl Entry {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use axum::http::{header, StatusCode};
     use axum::Router;
     use axum_test::TestServer;
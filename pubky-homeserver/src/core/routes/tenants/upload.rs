@@ -0,0 +1,158 @@
+//! Resumable uploads of large files.
+//!
+//! 1. `POST /pub/file` with the `Upload-Length` header creates an upload and returns its `Upload-Id`.
+//! 2. `PATCH /pub/file` with the `Upload-Id` and `Upload-Offset` headers appends a chunk and
+//!    returns the new `Upload-Offset`. The file is created once all bytes are received
+//!    (`201 Created`). Before that, `204 No Content` is returned.
+//! 3. A chunk with the wrong offset is rejected with `409 Conflict` and the current `Upload-Offset`.
+//!    An empty `PATCH` can be used to find out where to resume.
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::stream::StreamExt;
+use pubky_common::constants::headers::{UPLOAD_ID, UPLOAD_LENGTH, UPLOAD_OFFSET};
+
+use crate::{
+    core::{err_if_user_is_invalid::err_if_user_is_invalid, extractors::PubkyHost, AppState},
+    persistence::files::{UploadError, WriteStreamError},
+    shared::{
+        webdav::{EntryPath, WebDavPathPubAxum},
+        HttpError, HttpResult,
+    },
+};
+
+use super::write::{fail_if_size_bigger_than_user_quota, parse_expected_content_hash};
+
+pub async fn create(
+    State(state): State<AppState>,
+    pubky: PubkyHost,
+    Path(path): Path<WebDavPathPubAxum>,
+    headers: HeaderMap,
+) -> HttpResult<impl IntoResponse> {
+    let public_key = pubky.public_key();
+    err_if_user_is_invalid(public_key, &state.db, true)?;
+    let entry_path = EntryPath::new(public_key.clone(), path.inner().to_owned());
+    let length = parse_u64_header(&headers, UPLOAD_LENGTH)?;
+    let expected_hash = parse_expected_content_hash(&headers)?;
+
+    // Fail early instead of after the last chunk.
+    fail_if_size_bigger_than_user_quota(length, &state.db, state.user_quota.limit(), &entry_path)?;
+
+    let upload = state
+        .upload_service
+        .create(&entry_path, length, expected_hash)?;
+    Ok((
+        StatusCode::CREATED,
+        [(UPLOAD_ID, upload.id), (UPLOAD_OFFSET, "0".to_string())],
+    ))
+}
+
+pub async fn append(
+    State(state): State<AppState>,
+    pubky: PubkyHost,
+    Path(path): Path<WebDavPathPubAxum>,
+    headers: HeaderMap,
+    body: Body,
+) -> HttpResult<Response> {
+    let public_key = pubky.public_key();
+    err_if_user_is_invalid(public_key, &state.db, true)?;
+    let entry_path = EntryPath::new(public_key.clone(), path.inner().to_owned());
+    let id = headers
+        .get(UPLOAD_ID)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| HttpError::bad_request(format!("Missing {UPLOAD_ID} header")))?;
+    let offset = parse_u64_header(&headers, UPLOAD_OFFSET)?;
+
+    let chunk = body
+        .into_data_stream()
+        .map(|chunk_result| chunk_result.map_err(WriteStreamError::Axum));
+    let upload = match state
+        .upload_service
+        .append(id, &entry_path, offset, chunk)
+        .await
+    {
+        Ok(upload) => upload,
+        Err(UploadError::OffsetMismatch(current)) => {
+            return Ok(
+                (StatusCode::CONFLICT, [(UPLOAD_OFFSET, current.to_string())]).into_response(),
+            );
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let status = if upload.is_complete() {
+        StatusCode::CREATED
+    } else {
+        StatusCode::NO_CONTENT
+    };
+    Ok((status, [(UPLOAD_OFFSET, upload.offset().to_string())]).into_response())
+}
+
+fn parse_u64_header(headers: &HeaderMap, name: &str) -> HttpResult<u64> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| HttpError::bad_request(format!("Missing or invalid {name} header")))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header;
+
+    use crate::core::routes::tenants::read::tests::create_environment;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let (_, _, server, public_key, cookie) = create_environment().await.unwrap();
+        let data = b"0123456789".to_vec();
+
+        let response = server
+            .post("/pub/big.bin")
+            .add_header("host", public_key.to_string())
+            .add_header(header::COOKIE, cookie.clone())
+            .add_header(UPLOAD_LENGTH, data.len().to_string())
+            .await;
+        response.assert_status(StatusCode::CREATED);
+        let id = response.header(UPLOAD_ID).to_str().unwrap().to_string();
+
+        let patch = |offset: usize, chunk: &[u8]| {
+            server
+                .patch("/pub/big.bin")
+                .add_header("host", public_key.to_string())
+                .add_header(header::COOKIE, cookie.clone())
+                .add_header(UPLOAD_ID, id.clone())
+                .add_header(UPLOAD_OFFSET, offset.to_string())
+                .bytes(chunk.to_vec().into())
+        };
+
+        let response = patch(0, &data[..4]).await;
+        response.assert_status(StatusCode::NO_CONTENT);
+        assert_eq!(response.header(UPLOAD_OFFSET), "4");
+
+        // Probe the offset after a failure.
+        let response = patch(0, &[]).await;
+        response.assert_status(StatusCode::CONFLICT);
+        assert_eq!(response.header(UPLOAD_OFFSET), "4");
+
+        let response = patch(4, &data[4..]).await;
+        response.assert_status(StatusCode::CREATED);
+        assert_eq!(response.header(UPLOAD_OFFSET), "10");
+
+        let response = server
+            .get("/pub/big.bin")
+            .add_header("host", public_key.to_string())
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.as_bytes().to_vec(), data);
+
+        // The upload is gone after it completed.
+        patch(10, &[]).await.assert_status(StatusCode::NOT_FOUND);
+    }
+}
@@ -1,14 +1,15 @@
 use axum::{
     body::{Body, HttpBody},
     extract::{Path, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
 };
 use futures_util::stream::StreamExt;
+use pubky_common::{constants::headers::CONTENT_HASH, crypto::Hash};
 
 use crate::{
     core::{err_if_user_is_invalid::err_if_user_is_invalid, extractors::PubkyHost, AppState},
-    persistence::files::WriteStreamError,
+    persistence::files::{ContentHashVerifier, WriteStreamError},
     shared::{
         webdav::{EntryPath, WebDavPathPubAxum},
         HttpError, HttpResult,
@@ -35,17 +36,19 @@ pub async fn put(
     State(state): State<AppState>,
     pubky: PubkyHost,
     Path(path): Path<WebDavPathPubAxum>,
+    headers: HeaderMap,
     body: Body,
 ) -> HttpResult<impl IntoResponse> {
     let public_key = pubky.public_key();
     err_if_user_is_invalid(public_key, &state.db, true)?;
     let entry_path = EntryPath::new(public_key.clone(), path.inner().to_owned());
+    let expected_hash = parse_expected_content_hash(&headers)?;
 
     // Check if the size hint exceeds the quota so we can fail early
     fail_if_size_hint_bigger_than_user_quota(
         &body,
         &state.db,
-        state.user_quota_bytes,
+        state.user_quota.limit(),
         &entry_path,
     )?;
 
@@ -54,13 +57,42 @@ pub async fn put(
     let converted_stream =
         body_stream.map(|chunk_result| chunk_result.map_err(WriteStreamError::Axum));
 
-    state
-        .file_service
-        .write_stream(&entry_path, converted_stream)
-        .await?;
+    match expected_hash {
+        Some(expected) => {
+            let verified_stream = ContentHashVerifier::new(converted_stream, expected);
+            state
+                .file_service
+                .write_stream(&entry_path, verified_stream)
+                .await?;
+        }
+        None => {
+            state
+                .file_service
+                .write_stream(&entry_path, converted_stream)
+                .await?;
+        }
+    };
     Ok((StatusCode::CREATED, ()))
 }
 
+/// Parse the optional expected content hash header.
+pub(crate) fn parse_expected_content_hash(headers: &HeaderMap) -> HttpResult<Option<Hash>> {
+    let value = match headers.get(CONTENT_HASH) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| Hash::from_hex(value.trim()).ok())
+        .map(Some)
+        .ok_or_else(|| {
+            HttpError::bad_request(format!(
+                "Invalid {CONTENT_HASH} header. Expected a hex encoded Blake3 hash."
+            ))
+        })
+}
+
 /// Checks if the size hint exceeds the quota so we can fail early.
 /// Will return an error if the size hint exceeds the quota.
 /// Will return Ok if the size hint is smaller than the quota.
@@ -76,6 +108,17 @@ pub fn fail_if_size_hint_bigger_than_user_quota(
         Some(size_hint) => size_hint,
         None => return Ok(()), // No size hint, so we can't check
     };
+    fail_if_size_bigger_than_user_quota(content_size_hint, db, user_quota_bytes, entry_path)
+}
+
+/// Checks if writing a file of `size` bytes to `entry_path` exceeds the quota.
+/// Will return Ok if there is no quota.
+pub fn fail_if_size_bigger_than_user_quota(
+    size: u64,
+    db: &LmDB,
+    user_quota_bytes: Option<u64>,
+    entry_path: &EntryPath,
+) -> HttpResult<()> {
     let max_allowed_bytes = match user_quota_bytes {
         Some(user_quota_bytes) => user_quota_bytes,
         None => return Ok(()), // No quota, so all good
@@ -87,9 +130,8 @@ pub fn fail_if_size_hint_bigger_than_user_quota(
         None => return Err(FileIoError::NotFound.into()),
     };
 
-    let is_quota_exceeded = user_already_used_bytes
-        + content_size_hint.saturating_sub(existing_entry_bytes)
-        > max_allowed_bytes;
+    let is_quota_exceeded =
+        user_already_used_bytes + size.saturating_sub(existing_entry_bytes) > max_allowed_bytes;
 
     if is_quota_exceeded {
         let max_allowed_mb = max_allowed_bytes as f64 / 1024.0 / 1024.0;
@@ -104,12 +146,58 @@ pub fn fail_if_size_hint_bigger_than_user_quota(
 
 #[cfg(test)]
 mod tests {
+    use axum::http::header;
     use pkarr::Keypair;
+    use pubky_common::crypto::hash;
 
-    use crate::shared::webdav::WebDavPath;
+    use crate::{
+        core::routes::tenants::read::tests::create_environment, shared::webdav::WebDavPath,
+    };
 
     use super::*;
 
+    #[tokio::test]
+    async fn test_put_with_expected_content_hash() {
+        let (context, _, server, public_key, cookie) = create_environment().await.unwrap();
+        let data = vec![1_u8, 2, 3, 4, 5];
+
+        // Matching hash
+        server
+            .put("/pub/foo")
+            .add_header("host", public_key.to_string())
+            .add_header(header::COOKIE, cookie.clone())
+            .add_header(CONTENT_HASH, hash(&data).to_hex().to_string())
+            .bytes(data.clone().into())
+            .await
+            .assert_status(StatusCode::CREATED);
+
+        // Mismatching hash
+        server
+            .put("/pub/bar")
+            .add_header("host", public_key.to_string())
+            .add_header(header::COOKIE, cookie.clone())
+            .add_header(CONTENT_HASH, hash(b"other").to_hex().to_string())
+            .bytes(data.clone().into())
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        let path = EntryPath::new(public_key.clone(), WebDavPath::new("/pub/bar").unwrap());
+        assert!(matches!(
+            context.db.get_entry(&path),
+            Err(FileIoError::NotFound)
+        ));
+        assert!(!context.file_service.opendal.exists(&path).await.unwrap());
+
+        // Invalid hash
+        server
+            .put("/pub/bar")
+            .add_header("host", public_key.to_string())
+            .add_header(header::COOKIE, cookie)
+            .add_header(CONTENT_HASH, "not a hash")
+            .bytes(data.into())
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+
     #[test]
     fn test_if_size_hint_all_good() {
         let db = LmDB::test();
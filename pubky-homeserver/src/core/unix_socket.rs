@@ -0,0 +1,162 @@
+//!
+//! Serving routers on Unix domain sockets.
+//!
+//! Used for the ICANN and admin servers when they are only reached through a
+//! reverse proxy on the same host, like nginx with `proxy_pass http://unix:/path`.
+//!
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{Extension, Router};
+use axum_server::Handle;
+use tokio::sync::watch;
+
+/// Marks requests that came in over a Unix domain socket.
+///
+/// The peer is a local process, usually a reverse proxy, and has no IP address.
+/// It counts as `127.0.0.1` when the client IP is extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct UnixSocketPeer;
+
+/// Time open connections get to finish when a server is dropped, like the TCP listeners.
+pub(crate) const GRACEFUL_SHUTDOWN: Duration = Duration::from_secs(5);
+
+/// A server on a Unix domain socket. Stopped and the socket file removed when dropped.
+#[derive(Debug)]
+pub(crate) struct UnixSocketServer {
+    path: PathBuf,
+    /// Set to the grace period of open connections to shut down.
+    shutdown: watch::Sender<Option<Duration>>,
+}
+
+impl UnixSocketServer {
+    /// Bind `path` and serve `router` on it.
+    ///
+    /// A socket file left behind by a previous run is replaced.
+    #[cfg(unix)]
+    pub fn start(path: &Path, router: Router) -> Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = tokio::net::UnixListener::bind(path)
+            .map_err(|e| anyhow::anyhow!("Failed to bind {}: {e}", path.display()))?;
+        let (shutdown, mut signal) = watch::channel(None);
+        let mut grace = signal.clone();
+        let service = router.layer(Extension(UnixSocketPeer)).into_make_service();
+        let display_path = path.display().to_string();
+        tokio::spawn(async move {
+            let server = axum::serve(listener, service).with_graceful_shutdown(async move {
+                let _ = signal.wait_for(Option::is_some).await;
+            });
+            let deadline = async move {
+                // The sender is only dropped after the grace period is set.
+                let grace = grace
+                    .wait_for(Option::is_some)
+                    .await
+                    .ok()
+                    .and_then(|grace| *grace)
+                    .unwrap_or_default();
+                tokio::time::sleep(grace).await;
+            };
+            tokio::select! {
+                result = server => {
+                    if let Err(error) = result {
+                        tracing::error!(?error, "Unix socket server error on {display_path}");
+                    }
+                }
+                _ = deadline => {}
+            }
+        });
+        Ok(Self {
+            path: path.to_path_buf(),
+            shutdown,
+        })
+    }
+
+    /// Unix domain sockets are only supported on unix.
+    #[cfg(not(unix))]
+    pub fn start(path: &Path, _router: Router) -> Result<Self> {
+        anyhow::bail!(
+            "Unix domain sockets are not supported on this platform: {}",
+            path.display()
+        )
+    }
+
+    /// Stop accepting connections and give open ones `grace` to finish.
+    ///
+    /// Only the first call counts.
+    pub fn shutdown(&self, grace: Duration) {
+        self.shutdown.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(grace);
+            true
+        });
+    }
+}
+
+impl Drop for UnixSocketServer {
+    fn drop(&mut self) {
+        self.shutdown(GRACEFUL_SHUTDOWN);
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Handle of a server on a TCP or a Unix domain socket.
+#[derive(Debug)]
+pub(crate) enum ServerHandle {
+    Tcp(Handle),
+    Unix(UnixSocketServer),
+}
+
+impl ServerHandle {
+    /// Stop accepting connections and give open ones `grace` to finish.
+    pub fn graceful_shutdown(&self, grace: Duration) {
+        match self {
+            Self::Tcp(handle) => handle.graceful_shutdown(Some(grace)),
+            Self::Unix(server) => server.shutdown(grace),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_serve_on_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.sock");
+        let router = Router::new().route(
+            "/",
+            get(|peer: Option<Extension<UnixSocketPeer>>| async move {
+                format!("unix peer: {}", peer.is_some())
+            }),
+        );
+        // A socket file left behind by a previous run.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let server = UnixSocketServer::start(&path, router).unwrap();
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("unix peer: true"));
+
+        drop(server);
+        assert!(!path.exists());
+    }
+}
@@ -8,8 +8,13 @@ use tokio::{
     task::JoinHandle,
     time::{interval, Instant},
 };
+use tokio_util::sync::CancellationToken;
 
-use crate::{app_context::AppContext, persistence::lmdb::LmDB};
+use crate::{
+    app_context::AppContext,
+    persistence::lmdb::LmDB,
+    shared::metrics::{Metrics, RepublishOutcome, Republisher},
+};
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum UserKeysRepublisherError {
@@ -24,16 +29,21 @@ const MIN_REPUBLISH_INTERVAL: Duration = Duration::from_secs(30 * 60);
 /// Publishes the pkarr keys of all users to the Mainline DHT.
 pub(crate) struct UserKeysRepublisher {
     handle: Option<JoinHandle<()>>,
+    cancel: CancellationToken,
 }
 
 impl UserKeysRepublisher {
     /// Run the user keys republisher with an initial delay.
     pub fn start_delayed(context: &AppContext, initial_delay: Duration) -> Self {
         let db = context.db.clone();
+        let metrics = context.metrics.clone();
         let is_disabled = context.config_toml.pkdns.user_keys_republisher_interval == 0;
         if is_disabled {
             tracing::info!("User keys republisher is disabled.");
-            return Self { handle: None };
+            return Self {
+                handle: None,
+                cancel: CancellationToken::new(),
+            };
         }
         let mut republish_interval =
             Duration::from_secs(context.config_toml.pkdns.user_keys_republisher_interval);
@@ -59,12 +69,36 @@ impl UserKeysRepublisher {
 
         let mut pkarr_builder = context.pkarr_builder.clone();
         pkarr_builder.no_relays(); // Disable relays to avoid their rate limiting.
+        let cancel = CancellationToken::new();
+        let cancelled = cancel.clone();
         let handle = tokio::spawn(async move {
-            tokio::time::sleep(initial_delay).await;
-            Self::run_loop(db, republish_interval, pkarr_builder).await
+            tokio::select! {
+                _ = cancelled.cancelled() => {}
+                _ = async {
+                    tokio::time::sleep(initial_delay).await;
+                    Self::run_loop(db, republish_interval, pkarr_builder, metrics).await
+                } => {}
+            }
         });
         Self {
             handle: Some(handle),
+            cancel,
+        }
+    }
+
+    /// Stop republishing. A run in progress is cancelled, the keys not published yet
+    /// are republished by the next start.
+    ///
+    /// Waits until `deadline` at most before the task is aborted.
+    pub async fn graceful_stop(&mut self, deadline: Instant) {
+        self.cancel.cancel();
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        let abort = handle.abort_handle();
+        if tokio::time::timeout_at(deadline, handle).await.is_err() {
+            tracing::warn!("User keys republisher did not stop in time. Aborting.");
+            abort.abort();
         }
     }
 
@@ -109,7 +143,12 @@ impl UserKeysRepublisher {
     }
 
     /// Internal run loop that publishes all user pkarr keys to the Mainline DHT continuously.
-    async fn run_loop(db: LmDB, republish_interval: Duration, pkarr_builder: pkarr::ClientBuilder) {
+    async fn run_loop(
+        db: LmDB,
+        republish_interval: Duration,
+        pkarr_builder: pkarr::ClientBuilder,
+        metrics: Metrics,
+    ) {
         let mut interval = interval(republish_interval);
         loop {
             interval.tick().await;
@@ -126,6 +165,13 @@ impl UserKeysRepublisher {
             if result.is_empty() {
                 continue;
             }
+            for (outcome, count) in [
+                (RepublishOutcome::Success, result.success().len()),
+                (RepublishOutcome::Missing, result.missing().len()),
+                (RepublishOutcome::Failure, result.publishing_failed().len()),
+            ] {
+                metrics.inc_republished_keys(Republisher::UserKeys, outcome, count as u64);
+            }
             if result.missing().is_empty() {
                 tracing::debug!(
                     "Republished {} user keys within {:.1}s. {} success, {} missing, {} failed.",
@@ -159,6 +205,10 @@ impl Drop for UserKeysRepublisher {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    use crate::app_context::AppContext;
     use crate::core::user_keys_republisher::UserKeysRepublisher;
     use crate::persistence::lmdb::tables::users::User;
     use crate::persistence::lmdb::LmDB;
@@ -189,4 +239,23 @@ mod tests {
         assert_eq!(result.missing().len(), 10);
         assert_eq!(result.publishing_failed().len(), 0);
     }
+
+    /// Test that a graceful stop cancels the republisher without waiting for the next run.
+    #[tokio::test]
+    async fn test_graceful_stop() {
+        let mut context = AppContext::test();
+        context.config_toml.pkdns.user_keys_republisher_interval = 60 * 60;
+        let mut republisher =
+            UserKeysRepublisher::start_delayed(&context, Duration::from_secs(60 * 60));
+        let handle = republisher.handle.as_ref().unwrap().abort_handle();
+
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            republisher.graceful_stop(Instant::now() + Duration::from_secs(5)),
+        )
+        .await
+        .expect("stops without waiting for the initial delay");
+        assert!(handle.is_finished());
+        assert!(republisher.handle.is_none());
+    }
 }
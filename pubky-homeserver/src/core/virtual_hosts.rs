@@ -0,0 +1,454 @@
+//!
+//! Virtual homeservers hosted next to the main homeserver in the same process.
+//!
+//! Each virtual homeserver has its own keypair, config, LMDB and storage in `{data_dir}/virtual/{name}`.
+//! They share the listeners, the runtime and the admin server of the main homeserver.
+//! Requests are routed by the TLS server name (SNI) of Pubky TLS connections or by the host of the request.
+//!
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    io,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{
+    body::Body,
+    http::{uri::Authority, Request},
+    middleware::{map_response, AddExtension},
+    response::Response,
+    Extension, Router,
+};
+use axum_server::accept::Accept;
+use futures_util::future::BoxFuture;
+use pkarr::{Keypair, PublicKey};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use tokio::time::Instant;
+use tokio_rustls::server::TlsStream;
+use tower::{Layer, Service, ServiceExt};
+
+use super::{
+    key_republisher::HomeserverKeyRepublisher, periodic_backup::PeriodicBackup,
+    periodic_scrub::PeriodicScrub, periodic_upload_cleanup::PeriodicUploadCleanup,
+    user_keys_republisher::UserKeysRepublisher, HomeserverCore,
+};
+use crate::{
+    admin::DeletedUser, app_context::AppContext, core::routes::SignedUpUser,
+    persistence::lmdb::LmDB,
+};
+
+/// The TLS server name (SNI) the client connected with.
+///
+/// Added to the request extensions by the [ServerNameAcceptor] and the HTTP/3 listener.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TlsServerName(pub String);
+
+/// The background tasks of a virtual homeserver. Stopped when dropped.
+pub(crate) struct VirtualHomeserver {
+    pub(crate) context: AppContext,
+    key_republisher: HomeserverKeyRepublisher,
+    user_keys_republisher: UserKeysRepublisher,
+    periodic_backup: PeriodicBackup,
+    periodic_scrub: PeriodicScrub,
+    _periodic_upload_cleanup: PeriodicUploadCleanup,
+}
+
+impl VirtualHomeserver {
+    /// Publish the pkarr packet with the ports of the shared listeners and start the background tasks.
+    pub async fn start(
+        context: AppContext,
+        icann_http_port: u16,
+        pubky_tls_port: u16,
+        initial_delay_before_republish: Duration,
+    ) -> Result<Self> {
+        let key_republisher =
+            HomeserverKeyRepublisher::start(&context, icann_http_port, pubky_tls_port).await?;
+        Ok(Self {
+            user_keys_republisher: UserKeysRepublisher::start_delayed(
+                &context,
+                initial_delay_before_republish,
+            ),
+            key_republisher,
+            periodic_backup: PeriodicBackup::start(&context),
+            periodic_scrub: PeriodicScrub::start(&context),
+            _periodic_upload_cleanup: PeriodicUploadCleanup::start(&context),
+            context,
+        })
+    }
+
+    /// Stop the background tasks like the ones of the main homeserver.
+    pub async fn graceful_stop(&mut self, deadline: Instant) {
+        self.key_republisher.graceful_stop(deadline).await;
+        self.user_keys_republisher.graceful_stop(deadline).await;
+        self.periodic_backup.stop(deadline).await;
+        self.periodic_scrub.stop(deadline).await;
+        self.context.storage_migrator.stop(deadline).await;
+    }
+}
+
+#[derive(Debug)]
+struct VirtualHost {
+    certified_key: Arc<CertifiedKey>,
+    router: Router,
+}
+
+/// Routes requests and TLS handshakes to the virtual homeservers. Cheap to clone.
+///
+/// All lookups are in memory, a TLS handshake or request never reads LMDB.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct VirtualHosts(Arc<VirtualHostsInner>);
+
+#[derive(Debug, Default)]
+struct VirtualHostsInner {
+    hosts: Vec<VirtualHost>,
+    /// Index of the virtual homeserver by its ICANN domain.
+    /// Only set if no other homeserver uses the same domain.
+    domains: HashMap<String, usize>,
+    /// Index of the virtual homeserver by its public key.
+    keys: HashMap<PublicKey, usize>,
+    /// Index of the virtual homeserver of each user.
+    /// Loaded on start, updated on every signup on a virtual homeserver
+    /// and pruned when the admin of a virtual homeserver deletes a user.
+    users: RwLock<HashMap<PublicKey, usize>>,
+}
+
+impl VirtualHosts {
+    pub fn new(main: &AppContext, virtual_homeservers: &[AppContext]) -> Self {
+        let mut inner = VirtualHostsInner::default();
+        let main_domain = main.config_toml.pkdns.icann_domain.clone();
+        let mut users = HashMap::new();
+        for (index, context) in virtual_homeservers.iter().enumerate() {
+            let public_key = context.keypair.public_key();
+            if let Some(domain) = &context.config_toml.pkdns.icann_domain {
+                if main_domain.as_ref() == Some(domain) || inner.domains.contains_key(&domain.0) {
+                    tracing::warn!(
+                        "The ICANN domain {} of virtual homeserver {} is already used. Its ICANN requests are only routed by the pubky-host header.",
+                        domain.0,
+                        public_key
+                    );
+                } else {
+                    inner.domains.insert(domain.0.clone(), index);
+                }
+            }
+            match load_users(&context.db) {
+                Ok(keys) => users.extend(keys.into_iter().map(|key| (key, index))),
+                Err(e) => tracing::error!(
+                    "Failed to load the users of virtual homeserver {public_key}: {e}"
+                ),
+            }
+            inner.keys.insert(public_key.clone(), index);
+            inner.hosts.push(VirtualHost {
+                certified_key: Arc::new(context.keypair.to_rpk_certified_key()),
+                router: HomeserverCore::create_router(context),
+            });
+        }
+        inner.users = RwLock::new(users);
+        Self(Arc::new(inner))
+    }
+
+    /// Find the virtual homeserver of a TLS server name or host.
+    ///
+    /// Matches, in order:
+    /// 1. The ICANN domain of a virtual homeserver.
+    /// 2. The public key of a virtual homeserver, like `<homeserver>`.
+    /// 3. A user of a virtual homeserver, like `_pubky.<user>` which the pubky client connects to.
+    fn find(&self, name: &str) -> Option<usize> {
+        let name = name.trim_end_matches('.');
+        if let Some(index) = self.0.domains.get(name) {
+            return Some(*index);
+        }
+        let public_key = PublicKey::try_from(name.rsplit('.').next()?).ok()?;
+        if let Some(index) = self.0.keys.get(&public_key) {
+            return Some(*index);
+        }
+        let users = self.0.users.read().expect("users lock poisoned");
+        users.get(&public_key).copied()
+    }
+
+    /// Find the virtual homeserver of a request by its TLS server name, host or `pubky-host` header.
+    fn find_by_request(&self, req: &Request<Body>) -> Option<usize> {
+        if let Some(Some(TlsServerName(name))) = req.extensions().get::<Option<TlsServerName>>() {
+            return self.find(name);
+        }
+        let host = req.uri().host().map(str::to_string).or_else(|| {
+            let header = req.headers().get("host")?.to_str().ok()?;
+            Some(header.parse::<Authority>().ok()?.host().to_string())
+        });
+        if let Some(index) = host.as_deref().and_then(|host| self.find(host)) {
+            return Some(index);
+        }
+        let pubky_host = req.headers().get("pubky-host")?.to_str().ok()?;
+        self.find(pubky_host)
+    }
+
+    /// Route the users that sign up on the virtual homeserver with `index` to it.
+    fn add_user(&self, public_key: PublicKey, index: usize) {
+        let mut users = self.0.users.write().expect("users lock poisoned");
+        users.insert(public_key, index);
+    }
+
+    /// Stop routing a user deleted from the virtual homeserver with `index`.
+    fn remove_user(&self, public_key: &PublicKey, index: usize) {
+        let mut users = self.0.users.write().expect("users lock poisoned");
+        if users.get(public_key) == Some(&index) {
+            users.remove(public_key);
+        }
+    }
+
+    /// Remove the users deleted through the `admin` router of the virtual homeserver
+    /// `homeserver` from the routing.
+    pub fn track_deleted_users(&self, homeserver: &PublicKey, admin: Router) -> Router {
+        let Some(index) = self.0.keys.get(homeserver).copied() else {
+            return admin;
+        };
+        let hosts = self.clone();
+        admin.layer(map_response(move |response: Response| {
+            if let Some(DeletedUser(public_key)) = response.extensions().get::<DeletedUser>() {
+                hosts.remove_user(public_key, index);
+            }
+            std::future::ready(response)
+        }))
+    }
+
+    /// Route the requests of the virtual homeservers to their routers and all others to `main`.
+    pub fn route(&self, main: Router) -> Router {
+        if self.0.hosts.is_empty() {
+            return main;
+        }
+        Router::new().fallback_service(VirtualHostsService {
+            hosts: self.clone(),
+            main,
+        })
+    }
+
+    /// The Pubky TLS config. Presents the raw public key of the virtual homeserver the
+    /// client connects to, or the one of `main`.
+    pub fn rustls_config(&self, main: &Keypair) -> rustls::ServerConfig {
+        rustls::ServerConfig::builder_with_provider(rustls::crypto::ring::default_provider().into())
+            .with_safe_default_protocol_versions()
+            .expect("version supported by ring")
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(ServerNameResolver {
+                hosts: self.clone(),
+                main: Arc::new(main.to_rpk_certified_key()),
+            }))
+    }
+}
+
+/// Resolves the raw public key by the TLS server name.
+#[derive(Debug)]
+struct ServerNameResolver {
+    hosts: VirtualHosts,
+    main: Arc<CertifiedKey>,
+}
+
+impl ResolvesServerCert for ServerNameResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let host = client_hello
+            .server_name()
+            .and_then(|name| self.hosts.find(name))
+            .map(|index| &self.hosts.0.hosts[index]);
+        Some(host.map_or(self.main.clone(), |host| host.certified_key.clone()))
+    }
+
+    fn only_raw_public_keys(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+struct VirtualHostsService {
+    hosts: VirtualHosts,
+    main: Router,
+}
+
+impl Service<Request<Body>> for VirtualHostsService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let Some(index) = self.hosts.find_by_request(&req) else {
+            return Box::pin(self.main.clone().oneshot(req));
+        };
+        let router = self.hosts.0.hosts[index].router.clone();
+        let hosts = self.hosts.clone();
+        Box::pin(async move {
+            let response = router.oneshot(req).await?;
+            if let Some(SignedUpUser(public_key)) = response.extensions().get::<SignedUpUser>() {
+                hosts.add_user(public_key.clone(), index);
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// Read the public keys of all users of a homeserver.
+fn load_users(db: &LmDB) -> Result<Vec<PublicKey>, heed::Error> {
+    let rtxn = db.env.read_txn()?;
+    let keys = db
+        .tables
+        .users
+        .iter(&rtxn)?
+        .map(|result| result.map(|(key, _)| key))
+        .collect();
+    keys
+}
+
+/// Acceptor that adds the [TlsServerName] of TLS connections to their requests.
+#[derive(Debug, Clone)]
+pub(crate) struct ServerNameAcceptor<A>(pub A);
+
+impl<I, S, A> Accept<I, S> for ServerNameAcceptor<A>
+where
+    A: Accept<I, S, Stream = TlsStream<I>>,
+    A::Future: Send + 'static,
+    A::Service: Send + 'static,
+    I: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<A::Service, Option<TlsServerName>>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let accept = self.0.accept(stream, service);
+        Box::pin(async move {
+            let (stream, service) = accept.await?;
+            let name = stream
+                .get_ref()
+                .1
+                .server_name()
+                .map(|name| TlsServerName(name.to_string()));
+            Ok((stream, Extension(name).layer(service)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::get};
+    use pubky_common::{auth::AuthToken, capabilities::Capability};
+
+    use super::*;
+
+    fn request(host: &str) -> Request<Body> {
+        Request::get("/")
+            .header("host", host)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_find_virtual_host() {
+        let main = AppContext::test();
+        let mut alice = AppContext::test();
+        alice.keypair = Keypair::random();
+        alice.config_toml.pkdns.icann_domain = Some("alice.example.com".parse().unwrap());
+        let mut bob = AppContext::test();
+        bob.keypair = Keypair::random();
+        let user = Keypair::random().public_key();
+        bob.db.create_user(&user).unwrap();
+        let hosts = VirtualHosts::new(&main, &[alice.clone(), bob.clone()]);
+        let (alice_index, bob_index) = (Some(0), Some(1));
+
+        assert_eq!(hosts.find("alice.example.com"), alice_index);
+        assert_eq!(
+            hosts.find(&alice.keypair.public_key().to_string()),
+            alice_index
+        );
+        assert_eq!(hosts.find(&format!("_pubky.{user}")), bob_index);
+        assert_eq!(hosts.find(&main.keypair.public_key().to_string()), None);
+        // Bob uses the same domain as the main homeserver.
+        assert_eq!(hosts.find("localhost"), None);
+
+        let mut req = request("localhost:6286");
+        req.extensions_mut()
+            .insert(Some(TlsServerName(alice.keypair.public_key().to_string())));
+        assert_eq!(hosts.find_by_request(&req), alice_index);
+        let mut req = request("localhost:6286");
+        req.headers_mut()
+            .insert("pubky-host", user.to_string().parse().unwrap());
+        assert_eq!(hosts.find_by_request(&req), bob_index);
+        assert!(hosts.find_by_request(&request("localhost:6286")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_route_users_after_signup() {
+        let main = AppContext::test();
+        let mut bob = AppContext::test();
+        bob.keypair = Keypair::random();
+        let hosts = VirtualHosts::new(&main, &[bob.clone()]);
+        let router = hosts.route(Router::new());
+
+        let user = Keypair::random();
+        let user_host = format!("_pubky.{}", user.public_key());
+        assert_eq!(hosts.find(&user_host), None);
+
+        let token = AuthToken::sign(&user, vec![Capability::root()]);
+        let signup = Request::post("/signup")
+            .header("host", bob.keypair.public_key().to_string())
+            .body(Body::from(token.serialize()))
+            .unwrap();
+        let response = router.oneshot(signup).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hosts.find(&user_host), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_remove_deleted_users() {
+        let main = AppContext::test();
+        let mut bob = AppContext::test();
+        bob.keypair = Keypair::random();
+        let user = Keypair::random().public_key();
+        bob.db.create_user(&user).unwrap();
+        let hosts = VirtualHosts::new(&main, &[bob.clone()]);
+        let user_host = format!("_pubky.{user}");
+        assert_eq!(hosts.find(&user_host), Some(0));
+
+        let admin = hosts.track_deleted_users(
+            &bob.keypair.public_key(),
+            Router::new().route(
+                "/users/{pubkey}",
+                axum::routing::delete(|| async move { Extension(DeletedUser(user)) }),
+            ),
+        );
+        let delete = Request::delete("/users/user").body(Body::empty()).unwrap();
+        let response = admin.oneshot(delete).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hosts.find(&user_host), None);
+    }
+
+    #[tokio::test]
+    async fn test_route_by_host() {
+        let main = AppContext::test();
+        let mut alice = AppContext::test();
+        alice.keypair = Keypair::random();
+        alice.config_toml.pkdns.icann_domain = Some("alice.example.com".parse().unwrap());
+        let hosts = VirtualHosts::new(&main, &[alice]);
+        let router = hosts.route(Router::new().route("/", get(|| async { "main" })));
+
+        let body = |response: Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+        let response = router.clone().oneshot(request("localhost")).await.unwrap();
+        assert_eq!(body(response).await, "main");
+        // The homeserver router of alice answers with the server header.
+        let response = router.oneshot(request("alice.example.com")).await.unwrap();
+        assert!(response.headers().contains_key("server"));
+        assert_ne!(body(response).await, "main");
+    }
+}
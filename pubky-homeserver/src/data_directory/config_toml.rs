@@ -5,21 +5,25 @@
 //! and lets callers optionally layer their own TOML on top.
 
 use super::{
-    domain_port::DomainPort, quota_config::PathLimit, storage_config::StorageConfigToml, Domain,
-    SignupMode,
+    domain_port::DomainPort,
+    listen_addr::ListenAddr,
+    quota_config::{ClientIpHeader, PathLimit, QuotaValue, RateLimitBackend},
+    storage_config::StorageConfigToml,
+    Domain, SignupMode,
 };
 
 use crate::{
     data_directory::log_level::{LogLevel, TargetLevel},
     shared::toml_merge,
 };
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use std::{
     fmt::Debug,
     fs,
     net::{IpAddr, SocketAddr},
-    num::NonZeroU64,
-    path::Path,
+    num::{NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
     str::FromStr,
 };
 use url::Url;
@@ -61,13 +65,111 @@ pub struct PkdnsToml {
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct DriveToml {
     pub pubky_listen_socket: SocketAddr,
-    pub icann_listen_socket: SocketAddr,
+    /// TCP socket or `unix:/path` of the ICANN HTTP server.
+    pub icann_listen_socket: ListenAddr,
+    /// Reverse proxies whose forwarded client addresses are trusted.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+    /// The header the trusted proxies put the client address in.
+    #[serde(default)]
+    pub client_ip_header: ClientIpHeader,
+    /// Expect a PROXY protocol header on every connection of both listeners.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    /// Also serve HTTP/3 on the UDP port of `pubky_listen_socket`.
+    #[serde(default)]
+    pub pubky_http3: bool,
+    /// Serve the ICANN listener over TLS instead of clear-text HTTP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icann_tls: Option<IcannTlsToml>,
     pub rate_limits: Vec<PathLimit>,
+    /// Where the rate limiters keep their state.
+    #[serde(default)]
+    pub rate_limit_backend: RateLimitBackend,
+    /// Server-wide limits that protect the homeserver from overload.
+    #[serde(default)]
+    pub global_limits: GlobalLimitsToml,
+}
+
+/// Server-wide limits across all users and clients. Unset limits are disabled.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct GlobalLimitsToml {
+    /// Total upload bandwidth of all requests, for example "100mb/s".
+    #[serde(
+        default,
+        deserialize_with = "deserialize_speed",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub upload_speed: Option<QuotaValue>,
+    /// Total download bandwidth of all responses, for example "100mb/s".
+    #[serde(
+        default,
+        deserialize_with = "deserialize_speed",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub download_speed: Option<QuotaValue>,
+    /// Maximum number of PUT and PATCH requests in progress.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_uploads: Option<NonZeroUsize>,
+    /// Maximum number of open connections of a single client ip address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections_per_ip: Option<NonZeroUsize>,
+}
+
+/// Where the certificate of the ICANN TLS listener comes from.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IcannTlsToml {
+    /// Certificate chain and private key from PEM files.
+    /// Relative paths are relative to the data directory.
+    Files {
+        /// The certificate chain, leaf first.
+        cert_path: PathBuf,
+        /// The private key of the leaf certificate.
+        key_path: PathBuf,
+    },
+    /// Obtain and renew a certificate via ACME with the HTTP-01 challenge.
+    Acme(AcmeToml),
+}
+
+/// ACME settings of the ICANN TLS listener.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AcmeToml {
+    /// Directory url of the ACME server, for example Let's Encrypt.
+    pub directory_url: Url,
+    /// Contact email of the ACME account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact_email: Option<String>,
+    /// Domains of the certificate. Defaults to `pkdns.icann_domain`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub domains: Vec<Domain>,
+    /// Where the HTTP-01 challenges are answered.
+    /// Must be reachable on port 80 of all domains.
+    pub challenge_listen_socket: SocketAddr,
+    /// PEM root certificate of the ACME server if it isn't publicly trusted,
+    /// for example a private CA or Pebble. Relative to the data directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert_path: Option<PathBuf>,
+}
+
+/// Only accept speed quotas like "100mb/s" for the bandwidth limits.
+fn deserialize_speed<'de, D>(deserializer: D) -> Result<Option<QuotaValue>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let quota = Option::<QuotaValue>::deserialize(deserializer)?;
+    match quota {
+        Some(quota) if !quota.rate_unit.is_speed_rate_unit() => Err(serde::de::Error::custom(
+            format!("'{quota}' is not a speed. Use kb, mb or gb, for example \"100mb/s\"."),
+        )),
+        quota => Ok(quota),
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct AdminToml {
-    pub listen_socket: SocketAddr,
+    /// TCP socket or `unix:/path` of the admin server.
+    pub listen_socket: ListenAddr,
     pub admin_password: String,
 }
 
@@ -76,6 +178,22 @@ pub struct GeneralToml {
     pub signup_mode: SignupMode,
     pub lmdb_backup_interval_s: u64,
     pub user_storage_quota_mb: u64,
+    pub storage_scrub_interval_s: u64,
+    pub storage_scrub_quarantine: bool,
+    pub resumable_upload_expiry_s: u64,
+    /// Seconds open requests and uploads get to finish when the homeserver stops.
+    pub shutdown_timeout_s: u64,
+    /// Write a final LMDB backup to `{data_dir}/backup.mdb` when the homeserver stops.
+    pub backup_on_shutdown: bool,
+    /// Names of the virtual homeservers hosted in `{data_dir}/virtual/{name}`.
+    pub virtual_homeservers: Vec<String>,
+}
+
+/// Local disk cache in front of the storage backend.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StorageCacheToml {
+    /// Maximum size of the cache in MB. 0 disables the cache.
+    pub max_size_mb: u64,
 }
 
 /// A config for Homeserver tracing subscriber configuration
@@ -96,6 +214,8 @@ pub struct ConfigToml {
     pub drive: DriveToml,
     /// Storage configuration. Files can be stored in a file system, in memory, or in a Google bucket.
     pub storage: StorageConfigToml,
+    /// Local disk read-through cache for files from the storage backend.
+    pub storage_cache: StorageCacheToml,
     /// Administrative API settings (listen socket and password).
     pub admin: AdminToml,
     /// Peer‐to‐peer DHT / PKDNS settings (public endpoints, bootstrap, relays).
@@ -182,9 +302,9 @@ impl ConfigToml {
         let mut config = Self::default();
         config.general.signup_mode = SignupMode::Open;
         // Use ephemeral ports (0) so parallel tests don't collide.
-        config.drive.icann_listen_socket = SocketAddr::from(([127, 0, 0, 1], 0));
+        config.drive.icann_listen_socket = SocketAddr::from(([127, 0, 0, 1], 0)).into();
         config.drive.pubky_listen_socket = SocketAddr::from(([127, 0, 0, 1], 0));
-        config.admin.listen_socket = SocketAddr::from(([127, 0, 0, 1], 0));
+        config.admin.listen_socket = SocketAddr::from(([127, 0, 0, 1], 0)).into();
         config.pkdns.icann_domain =
             Some(Domain::from_str("localhost").expect("localhost is a valid domain"));
         config.pkdns.dht_relay_nodes = None;
@@ -218,9 +338,18 @@ mod tests {
         assert_eq!(c.general.signup_mode, SignupMode::TokenRequired);
         assert_eq!(c.general.user_storage_quota_mb, 0);
         assert_eq!(c.general.lmdb_backup_interval_s, 0);
+        assert_eq!(c.general.storage_scrub_interval_s, 0);
+        assert!(!c.general.storage_scrub_quarantine);
+        assert_eq!(c.general.resumable_upload_expiry_s, 86400);
+        assert_eq!(c.general.shutdown_timeout_s, 30);
+        assert!(!c.general.backup_on_shutdown);
+        assert!(c.general.virtual_homeservers.is_empty());
         assert_eq!(
             c.drive.icann_listen_socket,
-            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 6286))
+            ListenAddr::Tcp(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::new(127, 0, 0, 1),
+                6286
+            )))
         );
         assert_eq!(
             c.pkdns.icann_domain,
@@ -232,7 +361,10 @@ mod tests {
         );
         assert_eq!(
             c.admin.listen_socket,
-            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 6288))
+            ListenAddr::Tcp(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::new(127, 0, 0, 1),
+                6288
+            )))
         );
         assert_eq!(c.admin.admin_password, "admin");
         assert_eq!(c.pkdns.public_ip, IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)));
@@ -242,7 +374,10 @@ mod tests {
         assert_eq!(c.pkdns.dht_bootstrap_nodes, None);
         assert_eq!(c.pkdns.dht_request_timeout_ms, None);
         assert_eq!(c.drive.rate_limits, vec![]);
+        assert!(c.drive.trusted_proxies.is_empty());
+        assert_eq!(c.drive.client_ip_header, ClientIpHeader::XForwardedFor);
         assert_eq!(c.storage, StorageConfigToml::FileSystem);
+        assert_eq!(c.storage_cache.max_size_mb, 0);
         assert_eq!(
             c.logging,
             Some(LoggingToml {
@@ -295,4 +430,57 @@ mod tests {
         });
         assert_eq!(merged.logging, expected_logging);
     }
+
+    #[test]
+    fn test_global_limits() {
+        let s = "[drive.global_limits]\nupload_speed = \"10mb/s\"\nmax_concurrent_uploads = 20";
+        let config = ConfigToml::from_str_with_defaults(s).unwrap();
+        let limits = config.drive.global_limits;
+        assert_eq!(limits.upload_speed, Some("10mb/s".parse().unwrap()));
+        assert_eq!(limits.download_speed, None);
+        assert_eq!(limits.max_concurrent_uploads, NonZeroUsize::new(20));
+
+        // Bandwidth limits must be speeds.
+        let s = "[drive.global_limits]\ndownload_speed = \"10r/s\"";
+        assert!(ConfigToml::from_str_with_defaults(s).is_err());
+    }
+
+    #[test]
+    fn test_icann_tls() {
+        assert_eq!(ConfigToml::default().drive.icann_tls, None);
+
+        let s =
+            "[drive.icann_tls]\ntype = \"files\"\ncert_path = \"cert.pem\"\nkey_path = \"key.pem\"";
+        let config = ConfigToml::from_str_with_defaults(s).unwrap();
+        assert_eq!(
+            config.drive.icann_tls,
+            Some(IcannTlsToml::Files {
+                cert_path: PathBuf::from("cert.pem"),
+                key_path: PathBuf::from("key.pem"),
+            })
+        );
+
+        let s = "[drive.icann_tls]\ntype = \"acme\"\ndirectory_url = \"https://acme.example.com/directory\"\ndomains = [\"example.com\"]\nchallenge_listen_socket = \"0.0.0.0:80\"";
+        let config = ConfigToml::from_str_with_defaults(s).unwrap();
+        let Some(IcannTlsToml::Acme(acme)) = config.drive.icann_tls else {
+            panic!("expected acme config");
+        };
+        assert_eq!(acme.domains, vec![Domain::from_str("example.com").unwrap()]);
+        assert_eq!(acme.contact_email, None);
+        assert_eq!(acme.ca_cert_path, None);
+    }
+
+    #[test]
+    fn test_unix_listen_sockets() {
+        let s = "[drive]\nicann_listen_socket = \"unix:/run/pubky/icann.sock\"\n[admin]\nlisten_socket = \"unix:/run/pubky/admin.sock\"";
+        let config = ConfigToml::from_str_with_defaults(s).unwrap();
+        assert_eq!(
+            config.drive.icann_listen_socket,
+            ListenAddr::Unix(PathBuf::from("/run/pubky/icann.sock"))
+        );
+        assert_eq!(
+            config.admin.listen_socket,
+            ListenAddr::Unix(PathBuf::from("/run/pubky/admin.sock"))
+        );
+    }
 }
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::result::Result;
+use std::str::FromStr;
+
+/// Prefix of Unix domain socket addresses.
+const UNIX_PREFIX: &str = "unix:";
+
+/// Where a server listens.
+///
+/// Either a TCP socket like `127.0.0.1:6286` or a Unix domain socket like `unix:/run/pubky/icann.sock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// A TCP socket address.
+    Tcp(SocketAddr),
+    /// The path of a Unix domain socket.
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    /// The TCP socket address, if this is not a Unix domain socket.
+    pub fn tcp(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(socket) => Some(*socket),
+            Self::Unix(_) => None,
+        }
+    }
+}
+
+impl From<SocketAddr> for ListenAddr {
+    fn from(socket: SocketAddr) -> Self {
+        Self::Tcp(socket)
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(socket) => write!(f, "{socket}"),
+            Self::Unix(path) => write!(f, "{UNIX_PREFIX}{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix(UNIX_PREFIX) {
+            if path.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Invalid unix socket address. Expected 'unix:/path/to.sock'"
+                ));
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        let socket = s.parse::<SocketAddr>().map_err(|e| {
+            anyhow::anyhow!("Invalid listen address '{s}': {e}. Expected 'ip:port' or 'unix:/path'")
+        })?;
+        Ok(Self::Tcp(socket))
+    }
+}
+
+impl Serialize for ListenAddr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ListenAddr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(|e| serde::de::Error::custom(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_addr_from_str() {
+        assert_eq!(
+            ListenAddr::from_str("127.0.0.1:6286").unwrap(),
+            ListenAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 6286)))
+        );
+        assert_eq!(
+            ListenAddr::from_str("unix:/run/pubky/icann.sock").unwrap(),
+            ListenAddr::Unix(PathBuf::from("/run/pubky/icann.sock"))
+        );
+        assert!(ListenAddr::from_str("unix:").is_err());
+        assert!(ListenAddr::from_str("/run/pubky/icann.sock").is_err());
+        assert!(ListenAddr::from_str("localhost:6286").is_err());
+    }
+
+    #[test]
+    fn test_listen_addr_display_roundtrip() {
+        for s in ["[::1]:6288", "unix:/run/pubky/admin.sock"] {
+            assert_eq!(ListenAddr::from_str(s).unwrap().to_string(), s);
+        }
+    }
+}
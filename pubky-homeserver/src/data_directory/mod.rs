@@ -2,6 +2,7 @@ mod config_toml;
 mod data_dir;
 mod domain;
 mod domain_port;
+mod listen_addr;
 #[cfg(any(test, feature = "testing"))]
 mod mock_data_dir;
 mod persistent_data_dir;
@@ -13,10 +14,13 @@ pub mod storage_config;
 
 mod log_level;
 
-pub use config_toml::{ConfigReadError, ConfigToml, LoggingToml};
+pub use config_toml::{
+    AcmeToml, ConfigReadError, ConfigToml, GlobalLimitsToml, IcannTlsToml, LoggingToml,
+};
 pub use data_dir::DataDir;
 pub use domain::Domain;
 pub use domain_port::DomainPort;
+pub use listen_addr::ListenAddr;
 #[cfg(any(test, feature = "testing"))]
 pub use mock_data_dir::MockDataDir;
 pub use persistent_data_dir::PersistentDataDir;
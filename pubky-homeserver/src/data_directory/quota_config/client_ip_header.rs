@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+/// The header a trusted proxy puts the client address in.
+///
+/// Only this header is read. Proxies pass other forwarding headers of the client through
+/// untouched, so falling back to them would let clients pick their own address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientIpHeader {
+    /// `Forwarded` (RFC 7239).
+    Forwarded,
+    /// `X-Forwarded-For`.
+    #[default]
+    XForwardedFor,
+    /// `X-Real-IP` with a single address.
+    XRealIp,
+}
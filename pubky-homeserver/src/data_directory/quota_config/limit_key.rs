@@ -80,6 +80,8 @@ pub enum LimitKeyType {
     User,
     /// Limit on the ip address
     Ip,
+    /// Limit on the user of the session cookie. Falls back to the ip address without a valid session.
+    Session,
 }
 
 impl fmt::Display for LimitKeyType {
@@ -90,6 +92,7 @@ impl fmt::Display for LimitKeyType {
             match self {
                 LimitKeyType::User => "user",
                 LimitKeyType::Ip => "ip",
+                LimitKeyType::Session => "session",
             }
         )
     }
@@ -102,6 +105,7 @@ impl FromStr for LimitKeyType {
         match s {
             "user" => Ok(LimitKeyType::User),
             "ip" => Ok(LimitKeyType::Ip),
+            "session" => Ok(LimitKeyType::Session),
             _ => Err(format!("Invalid limit key: {}", s)),
         }
     }
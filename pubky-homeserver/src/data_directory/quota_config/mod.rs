@@ -1,15 +1,19 @@
+mod client_ip_header;
 mod glob_pattern;
 mod http_method;
 mod limit_key;
 mod path_limit;
 mod quota_value;
+mod rate_limit_backend;
 mod rate_unit;
 mod time_unit;
 
+pub use client_ip_header::ClientIpHeader;
 pub use glob_pattern::GlobPattern;
 pub use http_method::HttpMethod;
 pub use limit_key::{LimitKey, LimitKeyType};
 pub use path_limit::*;
 pub use quota_value::QuotaValue;
+pub use rate_limit_backend::RateLimitBackend;
 pub use rate_unit::RateUnit;
 pub use time_unit::TimeUnit;
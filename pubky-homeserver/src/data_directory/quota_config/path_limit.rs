@@ -1,8 +1,9 @@
 use super::{limit_key::LimitKey, GlobPattern, HttpMethod, LimitKeyType, QuotaValue};
 use axum::http::Method;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use serde::{Deserialize, Serialize};
 use serde_valid::Validate;
-use std::num::NonZeroU32;
+use std::{collections::BTreeMap, num::NonZeroU32};
 
 /// Make sure all whitelist keys are of the same type as the limit key type.
 fn serde_validate_path_limit(limit: &PathLimit) -> Result<(), serde_valid::validation::Error> {
@@ -29,6 +30,15 @@ pub struct PathLimit {
     /// The whitelist of keys to limit.
     #[serde(default)]
     pub whitelist: Vec<LimitKey>,
+    /// Limit all IPv4 addresses of a network of this prefix length together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4_prefix: Option<u8>,
+    /// Limit all IPv6 addresses of a network of this prefix length together, for example 64.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6_prefix: Option<u8>,
+    /// Quotas of user tiers. Users without a tier get `quota`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tiers: BTreeMap<String, QuotaValue>,
 }
 
 impl PathLimit {
@@ -47,6 +57,9 @@ impl PathLimit {
             key,
             burst,
             whitelist: vec![],
+            ipv4_prefix: None,
+            ipv6_prefix: None,
+            tiers: BTreeMap::new(),
         }
     }
 
@@ -55,16 +68,70 @@ impl PathLimit {
         self.whitelist.iter().any(|k| k == key)
     }
 
+    /// The key the requests are counted on.
+    ///
+    /// Ip addresses are truncated to their network if a prefix length is set.
+    pub fn bucket_key(&self, key: LimitKey) -> LimitKey {
+        match key {
+            LimitKey::Ip(ip) => {
+                let prefix = match ip {
+                    std::net::IpAddr::V4(_) => self.ipv4_prefix,
+                    std::net::IpAddr::V6(_) => self.ipv6_prefix,
+                };
+                match prefix.and_then(|prefix| IpNet::new(ip, prefix).ok()) {
+                    Some(net) => LimitKey::Ip(net.network()),
+                    None => LimitKey::Ip(ip),
+                }
+            }
+            key => key,
+        }
+    }
+
     /// Validate the path limit.
     pub fn validate(&self) -> anyhow::Result<()> {
-        if let Some(k) = self.whitelist.iter().find(|k| k.get_type() != self.key) {
+        // Sessions are counted per user and fall back to the ip address.
+        let allowed_whitelist_type = |k: &LimitKey| match self.key {
+            LimitKeyType::Session => true,
+            _ => k.get_type() == self.key,
+        };
+        if let Some(k) = self.whitelist.iter().find(|k| !allowed_whitelist_type(k)) {
             let should_type = self.key.to_string();
             let is_type = k.get_type().to_string();
             let msg = format!("Whitelist key type mismatch for '{k}'. Expected type '{should_type}' but got '{is_type}'. Full path limit: {self}");
             return Err(anyhow::anyhow!(msg));
         }
+        if self.key == LimitKeyType::User
+            && (self.ipv4_prefix.is_some() || self.ipv6_prefix.is_some())
+        {
+            anyhow::bail!("Ip prefixes can't be used with the user key. Full path limit: {self}");
+        }
+        if let Some(prefix) = self.ipv4_prefix {
+            Ipv4Net::new(std::net::Ipv4Addr::UNSPECIFIED, prefix)
+                .map_err(|_| anyhow::anyhow!("Invalid ipv4_prefix {prefix}. Must be <= 32."))?;
+        }
+        if let Some(prefix) = self.ipv6_prefix {
+            Ipv6Net::new(std::net::Ipv6Addr::UNSPECIFIED, prefix)
+                .map_err(|_| anyhow::anyhow!("Invalid ipv6_prefix {prefix}. Must be <= 128."))?;
+        }
+        if self.key == LimitKeyType::Ip && !self.tiers.is_empty() {
+            anyhow::bail!(
+                "Tiers require the user or session key to know the user. Full path limit: {self}"
+            );
+        }
         Ok(())
     }
+
+    /// The governor quota of a tier, or the default quota if the tier has none.
+    pub fn tier_quota(&self, tier: Option<&str>) -> governor::Quota {
+        let quota = tier
+            .and_then(|tier| self.tiers.get(tier))
+            .unwrap_or(&self.quota);
+        let quota: governor::Quota = quota.clone().into();
+        if let Some(burst) = self.burst {
+            quota.allow_burst(burst);
+        }
+        quota
+    }
 }
 
 impl std::fmt::Display for PathLimit {
@@ -83,11 +150,7 @@ impl std::fmt::Display for PathLimit {
 
 impl From<PathLimit> for governor::Quota {
     fn from(value: PathLimit) -> Self {
-        let quota: governor::Quota = value.quota.into();
-        if let Some(burst) = value.burst {
-            quota.allow_burst(burst);
-        }
-        quota
+        value.tier_quota(None)
     }
 }
 
@@ -121,4 +184,79 @@ mod tests {
             .push(LimitKey::User(Keypair::random().public_key()));
         assert!(limit.validate().is_err());
     }
+
+    #[test]
+    fn test_validate_prefixes_and_tiers() {
+        let mut limit = PathLimit::new(
+            GlobPattern::new("*"),
+            Method::GET,
+            QuotaValue::from_str("10r/s").unwrap(),
+            LimitKeyType::Ip,
+            None,
+        );
+        limit.ipv6_prefix = Some(64);
+        assert!(limit.validate().is_ok());
+        limit.ipv4_prefix = Some(33);
+        assert!(limit.validate().is_err());
+        limit.ipv4_prefix = None;
+
+        // Tiers need to know the user.
+        limit
+            .tiers
+            .insert("paid".to_string(), QuotaValue::from_str("100r/s").unwrap());
+        assert!(limit.validate().is_err());
+        limit.key = LimitKeyType::Session;
+        assert!(limit.validate().is_ok());
+
+        limit.key = LimitKeyType::User;
+        assert!(limit.validate().is_err());
+        limit.ipv6_prefix = None;
+        assert!(limit.validate().is_ok());
+    }
+
+    #[test]
+    fn test_bucket_key() {
+        let mut limit = PathLimit::new(
+            GlobPattern::new("*"),
+            Method::GET,
+            QuotaValue::from_str("10r/s").unwrap(),
+            LimitKeyType::Ip,
+            None,
+        );
+        let ipv6 = LimitKey::Ip("2001:db8:1:2:3:4:5:6".parse().unwrap());
+        let ipv4 = LimitKey::Ip("192.0.2.77".parse().unwrap());
+        assert_eq!(limit.bucket_key(ipv6.clone()), ipv6);
+
+        limit.ipv4_prefix = Some(24);
+        limit.ipv6_prefix = Some(64);
+        assert_eq!(
+            limit.bucket_key(ipv6),
+            LimitKey::Ip("2001:db8:1:2::".parse().unwrap())
+        );
+        assert_eq!(
+            limit.bucket_key(ipv4),
+            LimitKey::Ip("192.0.2.0".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_deserialize_tiers() {
+        let limit: PathLimit = toml::from_str(
+            r#"
+            path = "/session"
+            method = "POST"
+            quota = "10r/m"
+            key = "session"
+            ipv6_prefix = 64
+            tiers = { paid = "100r/m" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(limit.key, LimitKeyType::Session);
+        assert_eq!(limit.ipv6_prefix, Some(64));
+        assert_eq!(
+            limit.tiers.get("paid"),
+            Some(&QuotaValue::from_str("100r/m").unwrap())
+        );
+    }
 }
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+/// Where the rate limiters keep their state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitBackend {
+    /// In memory. The limits reset on restart.
+    #[default]
+    Memory,
+    /// In LMDB. The limits survive restarts.
+    Lmdb,
+}
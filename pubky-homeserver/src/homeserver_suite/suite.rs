@@ -4,9 +4,12 @@ use crate::tracing::init_tracing_logs_with_config_if_set;
 #[cfg(any(test, feature = "testing"))]
 use crate::MockDataDir;
 use crate::{app_context::AppContext, data_directory::PersistentDataDir};
+use crate::{ConfigReloadReport, ConfigReloader};
 use anyhow::Result;
 use pkarr::PublicKey;
 use std::path::PathBuf;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 
 /// Errors that can occur when building a `HomeserverSuite`.
 #[derive(thiserror::Error, Debug)]
@@ -22,13 +25,16 @@ pub enum HomeserverSuiteBuildError {
 /// Homeserver with all bells and whistles.
 /// Core + Admin server.
 ///
-/// When dropped, the homeserver will stop.
+/// When dropped, the homeserver will stop. Use [HomeserverSuite::shutdown]
+/// to let uploads finish and flush the database first.
 pub struct HomeserverSuite {
     context: AppContext,
     #[allow(dead_code)] // Keep this alive. When dropped, the homeserver will stop.
     core: HomeserverCore,
     #[allow(dead_code)] // Keep this alive. When dropped, the admin server will stop.
     admin_server: AdminServer,
+    /// Reloads the config on SIGHUP.
+    reload_on_sighup: Option<JoinHandle<()>>,
 }
 
 impl HomeserverSuite {
@@ -60,15 +66,70 @@ impl HomeserverSuite {
         tracing::debug!("Homeserver data dir: {}", context.data_dir.path().display());
 
         let core = HomeserverCore::new(context.clone()).await?;
-        let admin_server = AdminServer::start(&context).await?;
+        let admin_server = AdminServer::start_with_virtual_homeservers(
+            &context,
+            &core.virtual_contexts(),
+            &core.virtual_hosts,
+        )
+        .await?;
+        let reload_on_sighup = Self::reload_on_sighup(context.config_reloader.clone());
 
         Ok(Self {
             context,
             core,
             admin_server,
+            reload_on_sighup,
         })
     }
 
+    /// Reload the config.toml from the data directory.
+    ///
+    /// Applies the settings that can be changed at runtime and reports the ones that need a restart.
+    pub fn reload_config(&self) -> Result<ConfigReloadReport> {
+        self.context.config_reloader.reload()
+    }
+
+    #[cfg(unix)]
+    fn reload_on_sighup(reloader: ConfigReloader) -> Option<JoinHandle<()>> {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGHUP. Config reloads are only available via the admin server: {e}");
+                return None;
+            }
+        };
+        Some(tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                tracing::info!("Received SIGHUP. Reloading config.");
+                let reloader = reloader.clone();
+                match tokio::task::spawn_blocking(move || reloader.reload()).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => tracing::error!("Failed to reload config: {e}"),
+                    Err(e) => tracing::error!("Config reload panicked: {e}"),
+                }
+            }
+        }))
+    }
+
+    #[cfg(not(unix))]
+    fn reload_on_sighup(_reloader: ConfigReloader) -> Option<JoinHandle<()>> {
+        None
+    }
+
+    /// Shutdown gracefully.
+    ///
+    /// Stops accepting requests on all servers, waits up to `general.shutdown_timeout_s`
+    /// for the uploads in progress, stops the republishers and flushes LMDB to disk.
+    pub async fn shutdown(mut self) {
+        let deadline = Duration::from_secs(self.context.config_toml.general.shutdown_timeout_s);
+        if let Some(handle) = self.reload_on_sighup.take() {
+            handle.abort();
+        }
+        self.admin_server.stop_accepting(deadline);
+        self.core.graceful_shutdown().await;
+    }
+
     /// Get the core of the homeserver suite.
     pub fn core(&self) -> &HomeserverCore {
         &self.core
@@ -79,6 +140,11 @@ impl HomeserverSuite {
         &self.admin_server
     }
 
+    /// Returns the public keys of the virtual homeservers hosted next to this server.
+    pub fn virtual_homeservers(&self) -> Vec<PublicKey> {
+        self.core.virtual_homeservers()
+    }
+
     /// Returns the public_key of this server.
     pub fn public_key(&self) -> PublicKey {
         self.context.keypair.public_key()
@@ -94,3 +160,11 @@ impl HomeserverSuite {
         url::Url::parse(&self.core.icann_http_url()).expect("valid url")
     }
 }
+
+impl Drop for HomeserverSuite {
+    fn drop(&mut self) {
+        if let Some(handle) = self.reload_on_sighup.take() {
+            handle.abort();
+        }
+    }
+}
@@ -13,6 +13,7 @@
 
 mod admin;
 mod app_context;
+mod config_reloader;
 mod constants;
 mod core;
 mod data_directory;
@@ -23,6 +24,12 @@ pub mod tracing;
 
 pub use admin::{AdminServer, AdminServerBuildError};
 pub use app_context::{AppContext, AppContextConversionError};
+pub use config_reloader::{ConfigReloadReport, ConfigReloader};
+pub use core::layers::rate_limiter::{InMemoryRateLimitStore, RateLimitStore};
 pub use core::{HomeserverBuildError, HomeserverCore};
 pub use data_directory::*;
 pub use homeserver_suite::{HomeserverSuite, HomeserverSuiteBuildError};
+pub use persistence::files::{
+    ReconciliationReport, ScrubReport, StorageMigrationError, StorageMigrationProgress,
+    StorageMigrator, StorageReconciler, StorageScrubber, UsageMismatch,
+};
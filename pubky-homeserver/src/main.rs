@@ -1,8 +1,11 @@
 use std::path::PathBuf;
 
 use anyhow::Result;
-use clap::Parser;
-use pubky_homeserver::{tracing::init_tracing_logs_if_set, HomeserverSuite};
+use clap::{Parser, Subcommand};
+use pubky_homeserver::{
+    storage_config::StorageConfigToml, tracing::init_tracing_logs_if_set, AppContext,
+    HomeserverSuite, PersistentDataDir, StorageMigrator, StorageReconciler,
+};
 
 fn default_config_dir_path() -> PathBuf {
     dirs::home_dir().unwrap_or_default().join(".pubky")
@@ -24,6 +27,76 @@ struct Cli {
     /// Path to config file. Defaults to ~/.pubky/config.toml
     #[clap(short, long, default_value_os_t = default_config_dir_path(), value_parser = validate_config_dir_path)]
     data_dir: PathBuf,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check the recorded user storage usage against the stored files and entries.
+    /// Best run while the homeserver is stopped.
+    ReconcileStorage {
+        /// Repair the found issues instead of only reporting them.
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Copy all files to another storage and switch to it.
+    /// Only run while the homeserver is stopped. Use the admin API to migrate a running homeserver.
+    MigrateStorage {
+        /// Path to a toml file with a `[storage]` section in the config.toml format.
+        #[arg(long)]
+        target: PathBuf,
+    },
+}
+
+/// Run the storage reconciliation on the data directory and print the report.
+async fn reconcile_storage(data_dir: PathBuf, repair: bool) -> Result<()> {
+    let context = AppContext::try_from(PersistentDataDir::new(data_dir))?;
+    let report = StorageReconciler::from_context(&context)
+        .run(repair)
+        .await?;
+    println!("{}", report);
+    Ok(())
+}
+
+/// The target storage file of the migrate-storage command.
+#[derive(serde::Deserialize)]
+struct StorageTargetToml {
+    storage: StorageConfigToml,
+}
+
+/// Migrate all files to the target storage.
+async fn migrate_storage(data_dir: PathBuf, target: PathBuf) -> Result<()> {
+    let raw = std::fs::read_to_string(&target)?;
+    let target: StorageTargetToml = toml::from_str(&raw)?;
+    let context = AppContext::try_from(PersistentDataDir::new(data_dir))?;
+    let migrator = StorageMigrator::from_context(&context);
+    migrator.run(target.storage).await?;
+    let progress = migrator.progress();
+    println!(
+        "Migrated {} entries and replayed {} events. Update the [storage] section in the config.toml.",
+        progress.copied_entries, progress.replayed_events
+    );
+    Ok(())
+}
+
+/// Wait for Ctrl+C (SIGINT) or SIGTERM, e.g. sent by systemd or docker on stop.
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => tracing::info!("Received SIGTERM."),
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> Result<()> {
+    tokio::signal::ctrl_c().await?;
+    Ok(())
 }
 
 #[tokio::main]
@@ -31,6 +104,16 @@ async fn main() -> Result<()> {
     let args = Cli::parse();
     init_tracing_logs_if_set(&args.data_dir)?;
 
+    match args.command {
+        Some(Command::ReconcileStorage { repair }) => {
+            return reconcile_storage(args.data_dir, repair).await;
+        }
+        Some(Command::MigrateStorage { target }) => {
+            return migrate_storage(args.data_dir, target).await;
+        }
+        None => {}
+    }
+
     tracing::info!("Use data directory: {}", args.data_dir.display());
     let server = HomeserverSuite::start_with_persistent_data_dir_path(args.data_dir).await?;
 
@@ -48,14 +131,15 @@ async fn main() -> Result<()> {
         server.core().pubky_tls_ip_url()
     );
     tracing::info!(
-        "Admin server listening on http://{}",
+        "Admin server listening on {}",
         server.admin().listen_socket()
     );
 
-    tracing::info!("Press Ctrl+C to stop the Homeserver");
-    tokio::signal::ctrl_c().await?;
+    tracing::info!("Press Ctrl+C or send SIGTERM to stop the Homeserver");
+    shutdown_signal().await?;
 
     tracing::info!("Shutting down Homeserver");
+    server.shutdown().await;
 
     Ok(())
 }
@@ -0,0 +1,64 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_util::Stream;
+use pubky_common::crypto::{Hash, Hasher};
+
+use super::WriteStreamError;
+
+/// Hashes the stream while it is written and fails at the end of the stream
+/// if the content doesn't match the expected hash.
+/// Failing before the stream ends aborts the write so nothing is stored.
+pub(crate) struct ContentHashVerifier<S> {
+    inner: S,
+    hasher: Hasher,
+    expected: Hash,
+    done: bool,
+}
+
+impl<S> ContentHashVerifier<S> {
+    pub fn new(inner: S, expected: Hash) -> Self {
+        Self {
+            inner,
+            hasher: Hasher::new(),
+            expected,
+            done: false,
+        }
+    }
+}
+
+impl<S> Stream for ContentHashVerifier<S>
+where
+    S: Stream<Item = Result<Bytes, WriteStreamError>> + Unpin,
+{
+    type Item = Result<Bytes, WriteStreamError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        match Pin::new(&mut this.inner).poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                this.hasher.update(&chunk);
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(None) => {
+                this.done = true;
+                let actual = this.hasher.finalize();
+                if actual == this.expected {
+                    Poll::Ready(None)
+                } else {
+                    Poll::Ready(Some(Err(WriteStreamError::ContentHashMismatch {
+                        expected: this.expected,
+                        actual,
+                    })))
+                }
+            }
+            other => other,
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use crate::persistence::files::entry_service::EntryService;
-use crate::persistence::files::FileMetadataBuilder;
+use crate::persistence::files::{FileIoError, FileMetadataBuilder};
 use crate::persistence::lmdb::LmDB;
 use crate::shared::webdav::EntryPath;
 use opendal::raw::*;
@@ -138,9 +138,21 @@ impl<R: oio::Write> oio::Write for WriterWrapper<R> {
     async fn close(&mut self) -> Result<opendal::Metadata> {
         self.metadata_builder
             .guess_mime_type_from_path(self.entry_path.path().as_str());
+        let file_metadata = self.metadata_builder.clone().finalize();
+        // Reject blocked content before the file is committed.
+        if let Err(e) = self
+            .entry_service
+            .check_content_allowed(&file_metadata.hash)
+        {
+            self.inner.abort().await?;
+            let kind = match e {
+                FileIoError::ContentBlocked => opendal::ErrorKind::PermissionDenied,
+                _ => opendal::ErrorKind::Unexpected,
+            };
+            return Err(opendal::Error::new(kind, e.to_string()));
+        }
         let metadata = self.inner.close().await?;
         // Write successful, update the entry in the database.
-        let file_metadata = self.metadata_builder.clone().finalize();
         if let Err(e) = self
             .entry_service
             .write_entry(&self.entry_path, &file_metadata)
@@ -175,8 +187,15 @@ impl<R: oio::Delete> oio::Delete for DeleterWrapper<R> {
             .collect::<Vec<_>>();
 
         for path in deleted_paths {
-            if let Err(e) = self.entry_service.delete_entry(&path) {
-                tracing::error!("Failed to delete entry {} from database: {:?}", path, e);
+            match self.entry_service.delete_entry(&path) {
+                Ok(()) => {}
+                Err(FileIoError::NotFound) => {
+                    // Orphan file without an entry. Nothing to clean up in the database.
+                    tracing::debug!("No entry found for deleted file {}", path);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to delete entry {} from database: {:?}", path, e);
+                }
             }
         }
         Ok(deleted_files_count)
@@ -1,4 +1,4 @@
-use pubky_common::timestamp::Timestamp;
+use pubky_common::{crypto::Hash, timestamp::Timestamp};
 
 use crate::{
     persistence::{
@@ -22,6 +22,14 @@ impl EntryService {
         Self { db }
     }
 
+    /// Fails with [FileIoError::ContentBlocked] if the content hash is on the blocklist.
+    pub fn check_content_allowed(&self, hash: &Hash) -> Result<(), FileIoError> {
+        if self.db.is_content_blocked(hash)? {
+            return Err(FileIoError::ContentBlocked);
+        }
+        Ok(())
+    }
+
     /// Write an entry to the database.
     ///
     /// This includes all associated operations:
@@ -49,11 +57,8 @@ impl EntryService {
         // Write a public [Event].
         let url = format!("pubky://{}", entry_key);
         let event = Event::put(&url);
-        let value = event.serialize();
         self.db
-            .tables
-            .events
-            .put(&mut wtxn, metadata.modified_at.to_string().as_str(), &value)?;
+            .put_event(&mut wtxn, metadata.modified_at.to_string().as_str(), &event)?;
 
         wtxn.commit()?;
 
@@ -78,9 +83,8 @@ impl EntryService {
         // create DELETE event
         let url = format!("pubky://{}", path.as_str());
         let event = Event::delete(&url);
-        let value = event.serialize();
         let key = Timestamp::now().to_string();
-        self.db.tables.events.put(&mut wtxn, &key, &value)?;
+        self.db.put_event(&mut wtxn, &key, &event)?;
 
         wtxn.commit()?;
         Ok(())
@@ -0,0 +1,452 @@
+//! Local disk read-through cache for the file storage.
+//!
+//! Remote backends like Google buckets are slow and costly to read from.
+//! The cache keeps the most recently read files on the local disk and evicts
+//! the least recently used ones once the configured size cap is reached.
+//!
+//! Files are addressed by their content hash. A cached file can therefore never
+//! be stale. Writes and deletes still invalidate the old hash to free up disk space early.
+//!
+//! A cache miss is written to the disk on a blocking thread. The read is never slowed down:
+//! if the disk can't keep up, the file is not cached.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_util::Stream;
+use pubky_common::crypto::{Hash, Hasher};
+use tempfile::NamedTempFile;
+use tokio::sync::mpsc;
+use tokio_util::{io::ReaderStream, task::TaskTracker};
+
+use super::FileStream;
+
+/// The chunk size used to stream cached files.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Number of chunks buffered for the cache writer before the file is skipped.
+const FILL_QUEUE_SIZE: usize = 64;
+
+/// A cached file.
+#[derive(Debug)]
+struct CachedFile {
+    size: u64,
+    /// Logical access time. Higher is more recent.
+    tick: u64,
+}
+
+/// The in-memory index of the cache.
+#[derive(Debug, Default)]
+struct CacheState {
+    files: HashMap<String, CachedFile>,
+    /// Access tick -> hash. The first element is the least recently used file.
+    lru: BTreeMap<u64, String>,
+    total_bytes: u64,
+    next_tick: u64,
+}
+
+impl CacheState {
+    fn next_tick(&mut self) -> u64 {
+        self.next_tick += 1;
+        self.next_tick
+    }
+
+    /// Mark the file as recently used. Returns false if the file is not cached.
+    fn touch(&mut self, key: &str) -> bool {
+        let tick = self.next_tick();
+        let Some(file) = self.files.get_mut(key) else {
+            return false;
+        };
+        self.lru.remove(&file.tick);
+        file.tick = tick;
+        self.lru.insert(tick, key.to_string());
+        true
+    }
+
+    fn insert(&mut self, key: String, size: u64) {
+        self.remove(&key);
+        let tick = self.next_tick();
+        self.lru.insert(tick, key.clone());
+        self.files.insert(key, CachedFile { size, tick });
+        self.total_bytes += size;
+    }
+
+    fn remove(&mut self, key: &str) -> bool {
+        match self.files.remove(key) {
+            Some(file) => {
+                self.lru.remove(&file.tick);
+                self.total_bytes -= file.size;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Evict the least recently used files until the cache fits into `max_bytes`.
+    /// Returns the keys of the evicted files.
+    fn evict(&mut self, max_bytes: u64) -> Vec<String> {
+        let mut evicted = vec![];
+        while self.total_bytes > max_bytes {
+            let Some((_, key)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(file) = self.files.remove(&key) {
+                self.total_bytes -= file.size;
+            }
+            evicted.push(key);
+        }
+        evicted
+    }
+}
+
+#[derive(Debug)]
+struct FileCacheInner {
+    dir: PathBuf,
+    max_bytes: u64,
+    state: Mutex<CacheState>,
+    /// The blocking tasks writing cache misses to the disk.
+    fills: TaskTracker,
+}
+
+/// Local disk LRU cache of file contents, addressed by the content hash.
+#[derive(Debug, Clone)]
+pub(crate) struct FileCache {
+    inner: Arc<FileCacheInner>,
+}
+
+impl FileCache {
+    /// Create a new cache in `dir` with a maximum size of `max_bytes`.
+    ///
+    /// Files that already exist in the directory are reused.
+    pub fn new(dir: PathBuf, max_bytes: u64) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+
+        // Index the files left over from the last run, oldest first.
+        let mut existing = vec![];
+        for dir_entry in std::fs::read_dir(&dir)? {
+            let dir_entry = dir_entry?;
+            let path = dir_entry.path();
+            let metadata = dir_entry.metadata()?;
+            let is_cache_file = metadata.is_file()
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| Hash::from_hex(name).is_ok());
+            if !is_cache_file {
+                // Leftover temp file of an interrupted fill.
+                remove_file(&path);
+                continue;
+            }
+            let modified = metadata.modified()?;
+            let key = dir_entry.file_name().to_string_lossy().to_string();
+            existing.push((modified, key, metadata.len()));
+        }
+        existing.sort();
+
+        let mut state = CacheState::default();
+        for (_, key, size) in existing {
+            state.insert(key, size);
+        }
+        let cache = Self {
+            inner: Arc::new(FileCacheInner {
+                dir,
+                max_bytes,
+                state: Mutex::new(state),
+                fills: TaskTracker::new(),
+            }),
+        };
+        cache.evict();
+        Ok(cache)
+    }
+
+    fn file_path(&self, key: &str) -> PathBuf {
+        self.inner.dir.join(key)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.inner.state.lock().expect("File cache lock poisoned")
+    }
+
+    /// Get a cached file as a stream.
+    /// Returns None if the file is not cached.
+    pub async fn get(&self, hash: &Hash) -> Option<FileStream> {
+        let key = hash.to_hex().to_string();
+        if !self.lock().touch(&key) {
+            return None;
+        }
+        match tokio::fs::File::open(self.file_path(&key)).await {
+            Ok(file) => Some(Box::new(ReaderStream::with_capacity(file, CHUNK_SIZE))),
+            Err(e) => {
+                // Evicted in the meantime or removed from disk by someone else.
+                tracing::debug!("Failed to open cached file {}: {}", key, e);
+                self.lock().remove(&key);
+                None
+            }
+        }
+    }
+
+    /// Wrap a stream of a file read from the storage backend.
+    /// The file is added to the cache once the stream is read to the end
+    /// and the content matches the expected hash.
+    pub fn fill(&self, hash: Hash, length: u64, stream: FileStream) -> FileStream {
+        if length > self.inner.max_bytes {
+            return stream;
+        }
+        let (sender, receiver) = mpsc::channel(FILL_QUEUE_SIZE);
+        let cache = self.clone();
+        self.inner
+            .fills
+            .spawn_blocking(move || cache.write_fill(hash, length, receiver));
+        Box::new(CacheFillStream {
+            inner: stream,
+            sender: Some(sender),
+        })
+    }
+
+    /// Write the chunks of a cache miss to a temp file until the stream ends.
+    /// Moves the file into the cache if it is complete and matches the expected hash.
+    ///
+    /// Blocking, runs on a blocking thread.
+    fn write_fill(&self, hash: Hash, length: u64, mut receiver: mpsc::Receiver<Bytes>) {
+        let mut temp_file = match NamedTempFile::with_prefix_in(".fill", &self.inner.dir) {
+            Ok(file) => file,
+            Err(e) => {
+                tracing::warn!("Failed to create file cache temp file: {}", e);
+                return;
+            }
+        };
+        let mut hasher = Hasher::new();
+        let mut written: u64 = 0;
+        while let Some(chunk) = receiver.blocking_recv() {
+            if let Err(e) = temp_file.write_all(&chunk) {
+                tracing::warn!("Failed to write to file cache: {}", e);
+                // Dropping the temp file deletes it.
+                return;
+            }
+            hasher.update(&chunk);
+            written += chunk.len() as u64;
+        }
+        // The stream failed, was dropped before the end or the entry has been
+        // overwritten while we were reading the file.
+        // Only cache the content if it is exactly what we expected.
+        if written != length || hasher.finalize() != hash {
+            tracing::debug!("Content of {} incomplete or changed. Skip caching.", hash);
+            return;
+        }
+        self.insert(&hash, temp_file, written);
+    }
+
+    /// Remove a file from the cache.
+    pub fn invalidate(&self, hash: &Hash) {
+        let key = hash.to_hex().to_string();
+        if self.lock().remove(&key) {
+            remove_file(&self.file_path(&key));
+        }
+    }
+
+    /// Move a completely written temp file into the cache.
+    fn insert(&self, hash: &Hash, temp_file: NamedTempFile, size: u64) {
+        let key = hash.to_hex().to_string();
+        if let Err(e) = temp_file.persist(self.file_path(&key)) {
+            tracing::warn!("Failed to persist cached file {}: {}", key, e.error);
+            return;
+        }
+        self.lock().insert(key, size);
+        self.evict();
+    }
+
+    fn evict(&self) {
+        let evicted = self.lock().evict(self.inner.max_bytes);
+        for key in evicted {
+            remove_file(&self.file_path(&key));
+        }
+    }
+
+    /// Wait until the files read so far are written to the cache.
+    #[cfg(test)]
+    async fn wait_for_fills(&self) {
+        self.inner.fills.close();
+        self.inner.fills.wait().await;
+        self.inner.fills.reopen();
+    }
+
+    /// Total size of all cached files.
+    #[cfg(test)]
+    pub async fn size(&self) -> u64 {
+        self.wait_for_fills().await;
+        self.lock().total_bytes
+    }
+
+    /// Check if a file is cached.
+    #[cfg(test)]
+    pub async fn contains(&self, hash: &Hash) -> bool {
+        self.wait_for_fills().await;
+        self.lock().files.contains_key(hash.to_hex().as_str())
+    }
+}
+
+fn remove_file(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            tracing::warn!("Failed to remove cached file {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Stream that passes the chunks through and hands them to the cache writer on the side.
+struct CacheFillStream {
+    inner: FileStream,
+    /// None if caching the file has been given up.
+    sender: Option<mpsc::Sender<Bytes>>,
+}
+
+impl Stream for CacheFillStream {
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                let is_sent = this
+                    .sender
+                    .as_ref()
+                    .is_some_and(|sender| sender.try_send(chunk.clone()).is_ok());
+                if !is_sent {
+                    // The writer failed or can't keep up. The incomplete file is not cached.
+                    this.sender = None;
+                }
+            }
+            // Closing the channel lets the writer check and cache the file.
+            Poll::Ready(Some(Err(_))) | Poll::Ready(None) => this.sender = None,
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use pubky_common::crypto::hash;
+
+    use super::*;
+
+    fn stream_of(data: &'static [u8]) -> FileStream {
+        Box::new(futures_util::stream::iter(
+            data.chunks(3)
+                .map(|chunk| Ok(Bytes::from_static(chunk)))
+                .collect::<Vec<_>>(),
+        ))
+    }
+
+    async fn collect(mut stream: FileStream) -> Vec<u8> {
+        let mut content = vec![];
+        while let Some(chunk) = stream.next().await {
+            content.extend_from_slice(&chunk.unwrap());
+        }
+        content
+    }
+
+    #[tokio::test]
+    async fn test_fill_and_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FileCache::new(dir.path().to_path_buf(), 1000).unwrap();
+        let data: &[u8] = b"hello world";
+        let hash = hash(data);
+
+        assert!(cache.get(&hash).await.is_none());
+        let stream = cache.fill(hash, data.len() as u64, stream_of(data));
+        assert_eq!(collect(stream).await, data);
+        assert!(cache.contains(&hash).await);
+        assert_eq!(cache.size().await, data.len() as u64);
+
+        let cached = cache.get(&hash).await.expect("Should be cached");
+        assert_eq!(collect(cached).await, data);
+
+        cache.invalidate(&hash);
+        assert!(cache.get(&hash).await.is_none());
+        assert_eq!(cache.size().await, 0);
+        assert!(!dir.path().join(hash.to_hex().as_str()).exists());
+    }
+
+    #[tokio::test]
+    async fn test_fill_hash_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FileCache::new(dir.path().to_path_buf(), 1000).unwrap();
+        let data: &[u8] = b"hello world";
+        let other_hash = hash(b"other content");
+
+        let stream = cache.fill(other_hash, data.len() as u64, stream_of(data));
+        assert_eq!(collect(stream).await, data);
+        assert!(!cache.contains(&other_hash).await);
+        // Temp file is cleaned up.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_fill_aborted() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FileCache::new(dir.path().to_path_buf(), 1000).unwrap();
+        let data: &[u8] = b"hello world";
+        let hash = hash(data);
+
+        let mut stream = cache.fill(hash, data.len() as u64, stream_of(data));
+        stream.next().await.unwrap().unwrap();
+        drop(stream);
+        assert!(!cache.contains(&hash).await);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FileCache::new(dir.path().to_path_buf(), 10).unwrap();
+        let files: [&'static [u8]; 3] = [b"aaaa", b"bbbb", b"cccc"];
+        let hashes = files.map(hash);
+
+        for (data, hash) in files.iter().zip(hashes.iter()) {
+            collect(cache.fill(*hash, data.len() as u64, stream_of(data))).await;
+            cache.wait_for_fills().await;
+            // Keep the first file hot.
+            cache.get(&hashes[0]).await;
+        }
+
+        assert!(cache.contains(&hashes[0]).await);
+        assert!(!cache.contains(&hashes[1]).await);
+        assert!(cache.contains(&hashes[2]).await);
+        assert_eq!(cache.size().await, 8);
+
+        // Files larger than the cache are not cached at all.
+        let large: &[u8] = b"larger than ten bytes";
+        let large_hash = hash(large);
+        collect(cache.fill(large_hash, large.len() as u64, stream_of(large))).await;
+        assert!(!cache.contains(&large_hash).await);
+        assert_eq!(cache.size().await, 8);
+    }
+
+    #[tokio::test]
+    async fn test_reuse_existing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: &[u8] = b"hello world";
+        let hash = hash(data);
+        {
+            let cache = FileCache::new(dir.path().to_path_buf(), 1000).unwrap();
+            collect(cache.fill(hash, data.len() as u64, stream_of(data))).await;
+            cache.wait_for_fills().await;
+        }
+        std::fs::write(dir.path().join(".fill_leftover"), b"garbage").unwrap();
+
+        let cache = FileCache::new(dir.path().to_path_buf(), 1000).unwrap();
+        assert!(cache.contains(&hash).await);
+        assert_eq!(cache.size().await, data.len() as u64);
+        assert!(!dir.path().join(".fill_leftover").exists());
+    }
+}
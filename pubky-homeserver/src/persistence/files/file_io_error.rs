@@ -15,6 +15,8 @@ pub enum FileIoError {
     StreamBroken(#[from] WriteStreamError),
     #[error("Disk space quota exceeded")]
     DiskSpaceQuotaExceeded,
+    #[error("Content is blocked on this homeserver")]
+    ContentBlocked,
 }
 
 /// A unified error type for writing streams.
@@ -24,4 +26,9 @@ pub enum WriteStreamError {
     Axum(#[from] axum::Error),
     #[error("Other error: {0}")]
     Other(#[from] anyhow::Error),
+    #[error("Content hash mismatch. Expected {expected}, got {actual}")]
+    ContentHashMismatch {
+        expected: pubky_common::crypto::Hash,
+        actual: pubky_common::crypto::Hash,
+    },
 }
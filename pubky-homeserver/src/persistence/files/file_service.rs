@@ -11,9 +11,12 @@ use futures_util::Stream;
 use futures_util::StreamExt;
 #[cfg(test)]
 use opendal::Buffer;
+use pubky_common::crypto::Hash;
 use std::path::Path;
 
-use super::{FileIoError, FileStream, OpendalService, WriteStreamError};
+use super::{
+    file_cache::FileCache, FileIoError, FileStream, OpendalService, UserQuota, WriteStreamError,
+};
 
 /// The file service creates an abstraction layer over the LMDB and OpenDAL services.
 /// This way, files can be managed in a unified way.
@@ -21,6 +24,8 @@ use super::{FileIoError, FileStream, OpendalService, WriteStreamError};
 pub struct FileService {
     pub(crate) opendal: OpendalService,
     pub(crate) db: LmDB,
+    /// Optional local disk cache in front of the storage backend.
+    pub(crate) cache: Option<FileCache>,
 }
 
 impl FileService {
@@ -28,25 +33,38 @@ impl FileService {
         Self {
             opendal: opendal_service,
             db,
+            cache: None,
         }
     }
 
+    /// Serve reads from a local disk cache.
+    pub(crate) fn with_cache(mut self, cache: FileCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
     pub fn new_from_config(
         config: &ConfigToml,
         data_directory: &Path,
         db: LmDB,
+        user_quota: &UserQuota,
     ) -> Result<Self, FileIoError> {
-        let user_quota_bytes = match config.general.user_storage_quota_mb {
-            0 => u64::MAX,
-            other => other * 1024 * 1024,
-        };
-        let opendal_service = OpendalService::new_from_config(
-            &config.storage,
-            data_directory,
-            &db,
-            user_quota_bytes,
-        )?;
-        Ok(Self::new(opendal_service, db))
+        let opendal_service =
+            OpendalService::new_from_config(&config.storage, data_directory, &db, user_quota)?;
+        let service = Self::new(opendal_service, db);
+        match config.storage_cache.max_size_mb {
+            0 => Ok(service),
+            max_size_mb => {
+                let cache_dir = data_directory.join("data/cache");
+                tracing::info!(
+                    "Cache files on the local disk in {} (max {}MB)",
+                    cache_dir.display(),
+                    max_size_mb
+                );
+                let cache = FileCache::new(cache_dir, max_size_mb * 1024 * 1024)?;
+                Ok(service.with_cache(cache))
+            }
+        }
     }
 
     /// Get the metadata of a file.
@@ -58,8 +76,16 @@ impl FileService {
     /// The stream is chunked.
     /// Errors if the file does not exist.
     pub async fn get_stream(&self, path: &EntryPath) -> Result<FileStream, FileIoError> {
+        let Some(cache) = &self.cache else {
+            return self.opendal.get_stream(path).await;
+        };
+
+        let entry = self.db.get_entry(path)?;
+        if let Some(stream) = cache.get(entry.content_hash()).await {
+            return Ok(stream);
+        }
         let stream: FileStream = self.opendal.get_stream(path).await?;
-        Ok(stream)
+        Ok(cache.fill(*entry.content_hash(), entry.content_length() as u64, stream))
     }
 
     /// Write a file to the database and storage depending on the selected target location.
@@ -68,7 +94,13 @@ impl FileService {
         path: &EntryPath,
         stream: impl Stream<Item = Result<Bytes, WriteStreamError>> + Unpin + Send,
     ) -> Result<Entry, FileIoError> {
-        self.opendal.write_stream(path, stream).await?;
+        let old_hash = self.cached_content_hash(path);
+        let metadata = self.opendal.write_stream(path, stream).await?;
+        if let (Some(cache), Some(old_hash)) = (&self.cache, old_hash) {
+            if old_hash != metadata.hash {
+                cache.invalidate(&old_hash);
+            }
+        }
         self.db.get_entry(path)
     }
 
@@ -77,9 +109,22 @@ impl FileService {
         if !self.opendal.exists(path).await? {
             return Err(FileIoError::NotFound);
         }
+        let old_hash = self.cached_content_hash(path);
         self.opendal.delete(path).await?;
+        if let (Some(cache), Some(old_hash)) = (&self.cache, old_hash) {
+            cache.invalidate(&old_hash);
+        }
         Ok(())
     }
+
+    /// Content hash of the current entry if the cache is enabled.
+    fn cached_content_hash(&self, path: &EntryPath) -> Option<Hash> {
+        self.cache.as_ref()?;
+        self.db
+            .get_entry(path)
+            .ok()
+            .map(|entry| *entry.content_hash())
+    }
 }
 
 #[cfg(test)]
@@ -386,4 +431,46 @@ mod tests {
             Some(test_data.len() as u64 + FILE_METADATA_SIZE)
         );
     }
+
+    #[tokio::test]
+    async fn test_read_through_cache() {
+        let context = AppContext::test();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = FileCache::new(cache_dir.path().to_path_buf(), 1024 * 1024).unwrap();
+        let file_service = FileService::new_from_context(&context)
+            .unwrap()
+            .with_cache(cache.clone());
+
+        let pubkey = pkarr::Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+        let path = EntryPath::new(pubkey, WebDavPath::new("/pub/cached.txt").unwrap());
+
+        let entry = file_service
+            .write(&path, Buffer::from(vec![1u8; 100]))
+            .await
+            .unwrap();
+        assert!(!cache.contains(entry.content_hash()).await);
+
+        // First read fills the cache.
+        assert_eq!(file_service.get(&path).await.unwrap(), vec![1u8; 100]);
+        assert!(cache.contains(entry.content_hash()).await);
+
+        // Second read is served from the cache.
+        assert_eq!(file_service.get(&path).await.unwrap(), vec![1u8; 100]);
+        assert_eq!(cache.size().await, 100);
+
+        // Overwriting the file invalidates the old content.
+        let new_entry = file_service
+            .write(&path, Buffer::from(vec![2u8; 50]))
+            .await
+            .unwrap();
+        assert!(!cache.contains(entry.content_hash()).await);
+        assert_eq!(file_service.get(&path).await.unwrap(), vec![2u8; 50]);
+        assert!(cache.contains(new_entry.content_hash()).await);
+
+        // Deleting the file invalidates the content too.
+        file_service.delete(&path).await.unwrap();
+        assert!(!cache.contains(new_entry.content_hash()).await);
+        assert_eq!(cache.size().await, 0);
+    }
 }
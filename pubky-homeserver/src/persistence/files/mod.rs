@@ -1,5 +1,7 @@
+mod content_hash_verifier;
 mod entry_layer;
 mod entry_service;
+mod file_cache;
 mod file_io_error;
 mod file_metadata;
 mod file_service;
@@ -7,10 +9,27 @@ mod file_stream_type;
 mod opendal_service;
 #[cfg(test)]
 pub(crate) mod opendal_test_operators;
+mod storage_migrator;
+mod storage_reconciler;
+mod storage_scrubber;
+mod upload_service;
+mod user_archive;
+mod user_deleter;
 mod user_quota_layer;
 
+pub(crate) use content_hash_verifier::ContentHashVerifier;
+pub(crate) use entry_service::EntryService;
 pub use file_io_error::{FileIoError, WriteStreamError};
 pub(crate) use file_metadata::{FileMetadata, FileMetadataBuilder};
 pub use file_service::FileService;
 pub use file_stream_type::FileStream;
 pub use opendal_service::OpendalService;
+pub(crate) use storage_migrator::StorageMigrationState;
+pub use storage_migrator::{StorageMigrationError, StorageMigrationProgress, StorageMigrator};
+pub use storage_reconciler::{ReconciliationReport, StorageReconciler, UsageMismatch};
+pub use storage_scrubber::{ScrubReport, StorageScrubber};
+pub(crate) use upload_service::STAGING_DIR;
+pub use upload_service::{UploadError, UploadService};
+pub use user_archive::{max_archive_size, ArchiveError, ImportReport, UserArchiver};
+pub use user_deleter::{UserDeleter, UserDeletionError, UserDeletionReport};
+pub use user_quota_layer::UserQuota;
@@ -1,10 +1,20 @@
-use std::path::Path;
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
 
 #[cfg(test)]
 use crate::AppContext;
 use crate::{
     persistence::{
-        files::{entry_layer::EntryLayer, user_quota_layer::UserQuotaLayer},
+        files::{
+            entry_layer::EntryLayer,
+            user_quota_layer::{UserQuota, UserQuotaLayer},
+        },
         lmdb::LmDB,
     },
     shared::webdav::EntryPath,
@@ -18,32 +28,31 @@ use opendal::Operator;
 
 use super::{FileIoError, FileMetadata, FileMetadataBuilder, FileStream, WriteStreamError};
 
-/// Build the storage operator based on the config.
+/// Build the plain storage operator based on the config.
 /// Data dir path is used to expand the data directory placeholder in the config.
-pub fn build_storage_operator(
+///
+/// The operator does NOT track entries or user quotas.
+/// Use [build_storage_operator] for regular file operations.
+pub fn build_base_operator(
     storage_config: &StorageConfigToml,
     data_directory: &Path,
-    db: &LmDB,
-    user_quota_bytes: u64,
 ) -> Result<Operator, FileIoError> {
-    let user_quota_layer = UserQuotaLayer::new(db.clone(), user_quota_bytes);
-    let entry_layer = EntryLayer::new(db.clone());
-    let builder = match storage_config {
+    let operator = match storage_config {
         StorageConfigToml::FileSystem => {
-            let files_dir = match data_directory.join("data/files").to_str() {
-                Some(path) => path.to_string(),
-                None => {
-                    return Err(FileIoError::OpenDAL(opendal::Error::new(
-                        opendal::ErrorKind::Unexpected,
-                        "Invalid path",
-                    )))
-                }
+            let files_dir = data_directory.join("data/files");
+            let tmp_dir = data_directory.join("data/files_tmp");
+            let (Some(files_dir), Some(tmp_dir)) = (files_dir.to_str(), tmp_dir.to_str()) else {
+                return Err(FileIoError::OpenDAL(opendal::Error::new(
+                    opendal::ErrorKind::Unexpected,
+                    "Invalid path",
+                )));
             };
-            let builder = opendal::services::Fs::default().root(files_dir.as_str());
-            opendal::Operator::new(builder)?
-                .layer(user_quota_layer)
-                .layer(entry_layer)
-                .finish()
+            // Files are written to the tmp dir and moved in place once complete,
+            // so a write in progress never shows up as a partial file.
+            let builder = opendal::services::Fs::default()
+                .root(files_dir)
+                .atomic_write_dir(tmp_dir);
+            opendal::Operator::new(builder)?.finish()
         }
         #[cfg(feature = "storage-gcs")]
         StorageConfigToml::GoogleBucket(config) => {
@@ -52,51 +61,85 @@ pub fn build_storage_operator(
                 config.bucket_name
             );
             let builder = config.to_builder()?;
-            opendal::Operator::new(builder)?
-                .layer(user_quota_layer)
-                .layer(entry_layer)
-                .finish()
+            opendal::Operator::new(builder)?.finish()
         }
         #[cfg(any(feature = "storage-memory", test))]
         StorageConfigToml::InMemory => {
             tracing::info!("Store files in memory");
             let builder = opendal::services::Memory::default();
-            opendal::Operator::new(builder)?
-                .layer(user_quota_layer)
-                .layer(entry_layer)
-                .finish()
+            opendal::Operator::new(builder)?.finish()
         }
     };
-    Ok(builder)
+    Ok(operator)
+}
+
+/// Build the storage operator based on the config.
+/// Data dir path is used to expand the data directory placeholder in the config.
+pub fn build_storage_operator(
+    storage_config: &StorageConfigToml,
+    data_directory: &Path,
+    db: &LmDB,
+    user_quota: &UserQuota,
+) -> Result<Operator, FileIoError> {
+    let user_quota_layer = UserQuotaLayer::new(db.clone(), user_quota.clone());
+    let entry_layer = EntryLayer::new(db.clone());
+    let operator = build_base_operator(storage_config, data_directory)?
+        .layer(user_quota_layer)
+        .layer(entry_layer);
+    Ok(operator)
 }
 
 /// Build the storage operator based on the config.
 /// Data dir path is used to expand the data directory placeholder in the config.
 #[cfg(test)]
 pub fn build_storage_operator_from_context(context: &AppContext) -> Result<Operator, FileIoError> {
-    let quota_bytes = match context.config_toml.general.user_storage_quota_mb {
-        0 => u64::MAX,
-        other => other * 1024 * 1024,
-    };
     build_storage_operator(
         &context.config_toml.storage,
         context.data_dir.path(),
         &context.db,
-        quota_bytes,
+        &UserQuota::from_config(&context.config_toml),
     )
 }
 
+/// Copy the file at `path` from one operator to another in chunks.
+pub(crate) async fn copy_file(
+    source: &Operator,
+    target: &Operator,
+    path: &str,
+) -> Result<(), FileIoError> {
+    let reader = source.reader_with(path).chunk(CHUNK_SIZE).await?;
+    let mut stream = reader.into_bytes_stream(..).await?;
+    let mut writer = target.writer(path).await?;
+    while let Some(chunk) = stream.next().await {
+        writer.write(chunk.map_err(FileIoError::from)?).await?;
+    }
+    writer.close().await?;
+    Ok(())
+}
+
 /// The chunk size to use for reading and writing files.
 /// This is used to avoid reading and writing the entire file at once.
 /// Important: Not all opendal providers will respect this chunk size.
 /// For example, Google Cloud Buckets will deliver chunks anything from
 /// 200B to 16KB but max CHUNK_SIZE.
-const CHUNK_SIZE: usize = 16 * 1024;
+pub(crate) const CHUNK_SIZE: usize = 16 * 1024;
+
+/// How long [OpendalService::pause_writes] waits for the ongoing writes in one attempt.
+/// New writes queue behind a waiting pause, so it gives up quickly and tries again later.
+const PAUSE_ATTEMPT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Maximum backoff between two attempts to pause the writes.
+const MAX_PAUSE_BACKOFF: Duration = Duration::from_secs(30);
 
 /// The service to write and read files to and from the configured opendal storage.
 #[derive(Debug, Clone)]
 pub struct OpendalService {
-    pub(crate) operator: Operator,
+    /// The operator can be swapped at runtime when the storage is migrated.
+    operator: Arc<RwLock<Operator>>,
+    /// Held by every write and delete. Acquire it exclusively to pause all writes.
+    write_lock: Arc<tokio::sync::RwLock<()>>,
+    /// Incremented every time the operator is swapped.
+    generation: Arc<AtomicU64>,
 }
 
 impl OpendalService {
@@ -104,16 +147,78 @@ impl OpendalService {
         config: &StorageConfigToml,
         data_directory: &Path,
         db: &LmDB,
-        user_quota_bytes: u64,
+        user_quota: &UserQuota,
     ) -> Result<Self, FileIoError> {
-        let operator = build_storage_operator(config, data_directory, db, user_quota_bytes)?;
-        Ok(Self { operator })
+        let operator = build_storage_operator(config, data_directory, db, user_quota)?;
+        Ok(Self::new_from_operator(operator))
+    }
+
+    /// Create a new opendal service from an existing operator.
+    pub fn new_from_operator(operator: Operator) -> Self {
+        Self {
+            operator: Arc::new(RwLock::new(operator)),
+            write_lock: Arc::new(tokio::sync::RwLock::new(())),
+            generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// The current storage operator.
+    pub(crate) fn operator(&self) -> Operator {
+        self.operator
+            .read()
+            .expect("Operator lock poisoned")
+            .clone()
+    }
+
+    /// Replace the storage operator.
+    /// Should only be called while writes are paused. See [Self::pause_writes].
+    pub(crate) fn set_operator(&self, operator: Operator) {
+        *self.operator.write().expect("Operator lock poisoned") = operator;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Changes every time the operator is swapped.
+    ///
+    /// A write that received its content on the operator of an older generation
+    /// must move the file to the current operator.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Wait for all ongoing writes and deletes to finish and block new ones
+    /// until the returned guard is dropped.
+    ///
+    /// Gives up after a short timeout if the ongoing writes take longer, so new writes
+    /// don't queue behind the pause, and tries again with an increasing backoff.
+    pub(crate) async fn pause_writes(&self) -> tokio::sync::OwnedRwLockWriteGuard<()> {
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            let pause = self.write_lock.clone().write_owned();
+            if let Ok(guard) = tokio::time::timeout(PAUSE_ATTEMPT_TIMEOUT, pause).await {
+                return guard;
+            }
+            tracing::debug!(
+                "Writes in progress. Try to pause the writes again in {}s.",
+                backoff.as_secs()
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_PAUSE_BACKOFF);
+        }
+    }
+
+    /// Hold while writing or deleting files outside of this service.
+    /// Blocks while writes are paused.
+    ///
+    /// Only hold it while committing a write, not while receiving the content.
+    pub(crate) async fn write_guard(&self) -> tokio::sync::RwLockReadGuard<'_, ()> {
+        self.write_lock.read().await
     }
 
     /// Delete a file.
     /// Deleting a non-existing file will NOT return an error.
     pub async fn delete(&self, path: &EntryPath) -> Result<(), FileIoError> {
-        self.operator
+        let _write_guard = self.write_guard().await;
+        self.operator()
             .delete(path.as_str())
             .await
             .map_err(FileIoError::OpenDAL)
@@ -121,6 +226,9 @@ impl OpendalService {
 
     /// Write a stream to the storage.
     ///
+    /// The content is received without blocking a pause of the writes. Only closing
+    /// the writer, which creates the entry, waits while writes are paused.
+    ///
     /// # Arguments
     ///
     /// * `path` - The path to the file.
@@ -131,7 +239,9 @@ impl OpendalService {
         path: &EntryPath,
         mut stream: impl Stream<Item = Result<Bytes, WriteStreamError>> + Unpin + Send,
     ) -> Result<FileMetadata, FileIoError> {
-        let mut writer = self.operator.writer(path.as_str()).await?;
+        let generation = self.generation();
+        let operator = self.operator();
+        let mut writer = operator.writer(path.as_str()).await?;
         let mut metadata_builder = FileMetadataBuilder::default();
         metadata_builder.guess_mime_type_from_path(path.path().as_str());
 
@@ -149,6 +259,7 @@ impl OpendalService {
         // Let's close the writer properly depending on if the stream write was successful.
         match write_result {
             Ok(()) => {
+                let _write_guard = self.write_guard().await;
                 // Close the writer to finalize the write operation
                 writer.close().await.map_err(|e| {
                     // The UserQuotaLayer will return a RateLimited error if the user has exceeded the quota.
@@ -157,10 +268,20 @@ impl OpendalService {
                         && e.to_string().contains("User quota exceeded")
                     {
                         FileIoError::DiskSpaceQuotaExceeded
+                    } else if e.kind() == opendal::ErrorKind::PermissionDenied
+                        && e.to_string()
+                            .contains(&FileIoError::ContentBlocked.to_string())
+                    {
+                        // The EntryLayer rejects content on the blocklist.
+                        FileIoError::ContentBlocked
                     } else {
                         FileIoError::OpenDAL(e)
                     }
                 })?;
+                if self.generation() != generation {
+                    // The storage was migrated while the content was received.
+                    self.copy_from(&operator, path).await?;
+                }
                 Ok(metadata_builder.finalize())
             }
             Err(e) => {
@@ -171,11 +292,20 @@ impl OpendalService {
         }
     }
 
+    /// Copy a file from the operator of a previous generation to the current one.
+    /// Must be called while holding the write guard.
+    ///
+    /// The file is left in the previous storage, deleting it through the previous
+    /// operator would delete the entry as well.
+    async fn copy_from(&self, previous: &Operator, path: &EntryPath) -> Result<(), FileIoError> {
+        copy_file(previous, &self.operator(), path.as_str()).await
+    }
+
     /// Get the stream of a file.
     /// Helper method because the NOT_FOUND error can happen in two different places.
     async fn get_stream_inner(&self, path: &EntryPath) -> Result<FileStream, opendal::Error> {
         let reader = self
-            .operator
+            .operator()
             .reader_with(path.as_str())
             .chunk(CHUNK_SIZE)
             .await?;
@@ -208,7 +338,7 @@ impl OpendalService {
 
     /// Check if a file exists.
     pub async fn exists(&self, path: &EntryPath) -> Result<bool, opendal::Error> {
-        self.operator.exists(path.as_str()).await
+        self.operator().exists(path.as_str()).await
     }
 }
 
@@ -216,13 +346,7 @@ impl OpendalService {
 impl OpendalService {
     pub fn new(context: &AppContext) -> Result<Self, FileIoError> {
         let operator = build_storage_operator_from_context(context)?;
-        Ok(Self { operator })
-    }
-
-    /// Create a new opendal service from an existing operator.
-    /// This is useful for testing.
-    pub fn new_from_operator(operator: Operator) -> Self {
-        Self { operator }
+        Ok(Self::new_from_operator(operator))
     }
 
     /// Get the content of a file as a single Bytes object.
@@ -413,4 +537,48 @@ mod tests {
             );
         }
     }
+
+    fn memory_operator() -> Operator {
+        Operator::new(opendal::services::Memory::default())
+            .unwrap()
+            .finish()
+    }
+
+    /// A write that receives its content doesn't block pausing the writes.
+    /// If the operator is swapped in the meantime, the file ends up in the new storage.
+    #[tokio::test]
+    async fn test_pause_writes_during_write() {
+        let service = OpendalService::new_from_operator(memory_operator());
+        let path = EntryPath::new(
+            pkarr::Keypair::random().public_key(),
+            WebDavPath::new("/pub/test.txt").unwrap(),
+        );
+        let (sender, receiver) = tokio::sync::mpsc::channel::<Bytes>(1);
+        let stream = Box::pin(futures_util::stream::unfold(
+            receiver,
+            |mut receiver| async {
+                let chunk = receiver.recv().await?;
+                Some((Ok(chunk), receiver))
+            },
+        ));
+        let write = tokio::spawn({
+            let service = service.clone();
+            let path = path.clone();
+            async move { service.write_stream(&path, stream).await }
+        });
+        sender.send(Bytes::from_static(b"first ")).await.unwrap();
+
+        let guard = tokio::time::timeout(Duration::from_secs(1), service.pause_writes())
+            .await
+            .expect("The write in progress must not block the pause");
+        let new_operator = memory_operator();
+        service.set_operator(new_operator.clone());
+        drop(guard);
+
+        sender.send(Bytes::from_static(b"second")).await.unwrap();
+        drop(sender);
+        write.await.unwrap().unwrap();
+        let content = new_operator.read(path.as_str()).await.unwrap();
+        assert_eq!(content.to_vec(), b"first second");
+    }
 }
@@ -0,0 +1,726 @@
+//! Migrates all files from the current storage backend to another one while the homeserver keeps running.
+//!
+//! The migration runs in three phases:
+//! 1. Copy every entry to the target and verify its content hash.
+//! 2. Replay the events that happened during the copy on the target and copy the staged
+//!    chunks of uploads in progress.
+//! 3. Pause all writes, replay the last events, copy the chunks staged since then
+//!    and switch the storage and staging operators.
+//!
+//! The progress is persisted to `{data_dir}/data/storage_migration.toml` so an interrupted
+//! migration resumes where it stopped. Once completed, the target storage is used on every
+//! restart until the `[storage]` section of the config.toml is updated to match.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use futures_util::{StreamExt, TryStreamExt};
+use opendal::Operator;
+use pubky_common::crypto::Hasher;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::{
+    persistence::lmdb::{tables::events::Event, LmDB},
+    shared::webdav::EntryPath,
+    storage_config::StorageConfigToml,
+    AppContext, ConfigToml,
+};
+
+use super::{
+    opendal_service::{build_base_operator, build_storage_operator, copy_file},
+    FileIoError, FileService, UploadService, UserQuota, STAGING_DIR,
+};
+
+/// Location of the migration state file relative to the data directory.
+const STATE_FILE: &str = "data/storage_migration.toml";
+
+/// Number of entries/events processed between two state saves.
+const BATCH_SIZE: usize = 100;
+
+/// Initial event cursor. Same as the default cursor of the events feed.
+const EVENTS_START_CURSOR: &str = "0000000000000";
+
+/// Errors that can occur during a storage migration.
+#[derive(Debug, thiserror::Error)]
+pub enum StorageMigrationError {
+    /// A migration is already running.
+    #[error("A storage migration is already running")]
+    AlreadyRunning,
+    /// The target is the storage that is currently in use.
+    #[error("The target storage is already in use")]
+    SameStorage,
+    /// The copied content doesn't match the hash of the entry.
+    #[error("Content hash mismatch for {0}")]
+    HashMismatch(String),
+    /// Failed to read or write the migration state file.
+    #[error("Migration state file error: {0}")]
+    State(String),
+    /// The migration has been stopped by the shutdown. It resumes on the next start.
+    #[error("The storage migration has been stopped")]
+    Stopped,
+    /// File operation failed.
+    #[error(transparent)]
+    FileIo(#[from] FileIoError),
+}
+
+impl From<opendal::Error> for StorageMigrationError {
+    fn from(e: opendal::Error) -> Self {
+        Self::FileIo(FileIoError::OpenDAL(e))
+    }
+}
+
+impl From<heed::Error> for StorageMigrationError {
+    fn from(e: heed::Error) -> Self {
+        Self::FileIo(FileIoError::Db(e))
+    }
+}
+
+/// Persisted progress of a storage migration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct StorageMigrationState {
+    /// True once the homeserver switched to the target storage.
+    pub completed: bool,
+    /// Key of the last entry that has been copied.
+    pub entry_cursor: Option<String>,
+    /// Events after this cursor still need to be replayed on the target.
+    pub event_cursor: String,
+    /// The storage the files are migrated to.
+    pub target: StorageConfigToml,
+}
+
+impl StorageMigrationState {
+    fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(STATE_FILE)
+    }
+
+    /// Read the migration state from the data directory if it exists.
+    pub fn read(data_dir: &Path) -> Result<Option<Self>, StorageMigrationError> {
+        let path = Self::path(data_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let raw = std::fs::read_to_string(&path)
+            .map_err(|e| StorageMigrationError::State(e.to_string()))?;
+        let state =
+            toml::from_str(&raw).map_err(|e| StorageMigrationError::State(e.to_string()))?;
+        Ok(Some(state))
+    }
+
+    /// Write the state atomically so a crash never leaves a half written file behind.
+    pub fn write(&self, data_dir: &Path) -> Result<(), StorageMigrationError> {
+        let path = Self::path(data_dir);
+        let raw = toml::to_string(self).map_err(|e| StorageMigrationError::State(e.to_string()))?;
+        let temp_path = path.with_extension("toml.tmp");
+        std::fs::write(&temp_path, raw)
+            .and_then(|_| std::fs::rename(&temp_path, &path))
+            .map_err(|e| StorageMigrationError::State(e.to_string()))
+    }
+
+    /// Use the target storage of a completed migration.
+    ///
+    /// The state file is removed once the config points to the target storage.
+    pub fn apply_to_config(
+        config: &mut ConfigToml,
+        data_dir: &Path,
+    ) -> Result<(), StorageMigrationError> {
+        let state = match Self::read(data_dir)? {
+            Some(state) if state.completed => state,
+            _ => return Ok(()),
+        };
+        if config.storage == state.target {
+            std::fs::remove_file(Self::path(data_dir))
+                .map_err(|e| StorageMigrationError::State(e.to_string()))?;
+            return Ok(());
+        }
+        tracing::warn!(
+            "Files have been migrated to a new storage. Use it instead of the configured one. Update the [storage] section in the config.toml to remove this warning."
+        );
+        config.storage = state.target;
+        Ok(())
+    }
+}
+
+/// Progress of the current or last storage migration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StorageMigrationProgress {
+    /// Whether a migration is currently running.
+    pub running: bool,
+    /// Whether the last migration completed and the target storage is in use.
+    pub completed: bool,
+    /// Number of entries copied in this run.
+    pub copied_entries: u64,
+    /// Number of events replayed in this run.
+    pub replayed_events: u64,
+    /// Error of the last run if it failed.
+    pub error: Option<String>,
+}
+
+/// Migrates the files of the homeserver to another storage backend.
+#[derive(Debug, Clone)]
+pub struct StorageMigrator {
+    file_service: FileService,
+    upload_service: UploadService,
+    current_storage: Arc<Mutex<StorageConfigToml>>,
+    data_dir: PathBuf,
+    user_quota: UserQuota,
+    progress: Arc<Mutex<StorageMigrationProgress>>,
+    /// Cancelled by [Self::stop].
+    cancel: CancellationToken,
+    /// Tracks the migrations started in the background.
+    tasks: TaskTracker,
+}
+
+impl StorageMigrator {
+    pub(crate) fn new(
+        file_service: FileService,
+        upload_service: UploadService,
+        config: &ConfigToml,
+        data_dir: &Path,
+        user_quota: UserQuota,
+    ) -> Self {
+        Self {
+            file_service,
+            upload_service,
+            current_storage: Arc::new(Mutex::new(config.storage.clone())),
+            data_dir: data_dir.to_path_buf(),
+            user_quota,
+            progress: Arc::new(Mutex::new(StorageMigrationProgress::default())),
+            cancel: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+        }
+    }
+
+    /// Create a new migrator from the app context.
+    pub fn from_context(context: &AppContext) -> Self {
+        context.storage_migrator.clone()
+    }
+
+    fn db(&self) -> &LmDB {
+        &self.file_service.db
+    }
+
+    /// The progress of the current or last migration.
+    pub fn progress(&self) -> StorageMigrationProgress {
+        self.progress
+            .lock()
+            .expect("Progress lock poisoned")
+            .clone()
+    }
+
+    fn update_progress(&self, f: impl FnOnce(&mut StorageMigrationProgress)) {
+        f(&mut self.progress.lock().expect("Progress lock poisoned"));
+    }
+
+    /// Start the migration in the background.
+    /// Use [Self::progress] to follow it.
+    pub fn start(&self, target: StorageConfigToml) -> Result<(), StorageMigrationError> {
+        self.mark_running()?;
+        let migrator = self.clone();
+        self.tasks.spawn(async move {
+            let result = migrator.run_inner(target).await;
+            migrator.finish(&result);
+        });
+        Ok(())
+    }
+
+    /// Run the migration to the target storage and switch to it once all files are copied.
+    ///
+    /// Resumes a previously interrupted migration to the same target.
+    pub async fn run(&self, target: StorageConfigToml) -> Result<(), StorageMigrationError> {
+        self.mark_running()?;
+        let result = self.run_inner(target).await;
+        self.finish(&result);
+        result
+    }
+
+    /// Stop the migration in progress after the entry or event it is working on.
+    /// The persisted progress is kept so the migration resumes with the next [Self::run].
+    ///
+    /// Waits until `deadline` at most. Migrations can't be started anymore afterwards.
+    pub(crate) async fn stop(&self, deadline: Instant) {
+        self.cancel.cancel();
+        self.tasks.close();
+        if tokio::time::timeout_at(deadline, self.tasks.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!("Storage migration did not stop in time.");
+        }
+    }
+
+    fn ensure_not_stopped(&self) -> Result<(), StorageMigrationError> {
+        if self.cancel.is_cancelled() {
+            return Err(StorageMigrationError::Stopped);
+        }
+        Ok(())
+    }
+
+    fn mark_running(&self) -> Result<(), StorageMigrationError> {
+        self.ensure_not_stopped()?;
+        let mut progress = self.progress.lock().expect("Progress lock poisoned");
+        if progress.running {
+            return Err(StorageMigrationError::AlreadyRunning);
+        }
+        *progress = StorageMigrationProgress {
+            running: true,
+            ..Default::default()
+        };
+        Ok(())
+    }
+
+    fn finish(&self, result: &Result<(), StorageMigrationError>) {
+        if let Err(e) = result {
+            tracing::error!("Storage migration failed: {}", e);
+        }
+        self.update_progress(|progress| {
+            progress.running = false;
+            progress.completed = result.is_ok();
+            progress.error = result.as_ref().err().map(|e| e.to_string());
+        });
+    }
+
+    async fn run_inner(&self, target: StorageConfigToml) -> Result<(), StorageMigrationError> {
+        if *self.current_storage.lock().expect("Storage lock poisoned") == target {
+            return Err(StorageMigrationError::SameStorage);
+        }
+        let target_operator = build_base_operator(&target, &self.data_dir)?;
+
+        let mut state = match StorageMigrationState::read(&self.data_dir)? {
+            Some(state) if !state.completed && state.target == target => {
+                tracing::info!(
+                    "Resume storage migration after entry {:?}",
+                    state.entry_cursor
+                );
+                state
+            }
+            _ => {
+                let state = StorageMigrationState {
+                    completed: false,
+                    entry_cursor: None,
+                    // Everything that happens from now on is replayed after the copy.
+                    event_cursor: self.latest_event_cursor()?,
+                    target: target.clone(),
+                };
+                state.write(&self.data_dir)?;
+                state
+            }
+        };
+
+        tracing::info!("Storage migration: Copy entries.");
+        self.copy_entries(&mut state, &target_operator).await?;
+
+        tracing::info!("Storage migration: Replay events that happened during the copy.");
+        self.replay_events(&mut state, &target_operator).await?;
+
+        tracing::info!("Storage migration: Copy staged chunks of uploads in progress.");
+        let mut staged_chunks = HashMap::new();
+        self.copy_staged_chunks(&target_operator, &mut staged_chunks)
+            .await?;
+
+        tracing::info!("Storage migration: Pause writes and switch storage.");
+        let _write_guard = tokio::select! {
+            guard = self.file_service.opendal.pause_writes() => guard,
+            _ = self.cancel.cancelled() => return Err(StorageMigrationError::Stopped),
+        };
+        // Only the changes since the copies above are left.
+        self.replay_events(&mut state, &target_operator).await?;
+        self.copy_staged_chunks(&target_operator, &mut staged_chunks)
+            .await?;
+        let operator =
+            build_storage_operator(&target, &self.data_dir, self.db(), &self.user_quota)?;
+        state.completed = true;
+        state.write(&self.data_dir)?;
+        self.file_service.opendal.set_operator(operator);
+        self.upload_service.set_staging(target_operator);
+        *self.current_storage.lock().expect("Storage lock poisoned") = target;
+        tracing::info!("Storage migration completed.");
+        Ok(())
+    }
+
+    fn latest_event_cursor(&self) -> Result<String, StorageMigrationError> {
+        let rtxn = self.db().env.read_txn()?;
+        let cursor = match self.db().tables.events.last(&rtxn)? {
+            Some((key, _)) => key.to_string(),
+            None => EVENTS_START_CURSOR.to_string(),
+        };
+        Ok(cursor)
+    }
+
+    /// Copy all entries after the entry cursor.
+    async fn copy_entries(
+        &self,
+        state: &mut StorageMigrationState,
+        target: &Operator,
+    ) -> Result<(), StorageMigrationError> {
+        loop {
+            let keys = {
+                let rtxn = self.db().env.read_txn()?;
+                let lower = match &state.entry_cursor {
+                    Some(cursor) => Bound::Excluded(cursor.as_str()),
+                    None => Bound::Unbounded,
+                };
+                let keys = self
+                    .db()
+                    .tables
+                    .entries
+                    .range(&rtxn, &(lower, Bound::Unbounded))?
+                    .take(BATCH_SIZE)
+                    .map(|result| result.map(|(key, _)| key.to_string()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                keys
+            };
+            let Some(last_key) = keys.last().cloned() else {
+                return Ok(());
+            };
+            for key in keys {
+                self.ensure_not_stopped()?;
+                self.copy_entry(&key, target).await?;
+                self.update_progress(|progress| progress.copied_entries += 1);
+            }
+            state.entry_cursor = Some(last_key);
+            state.write(&self.data_dir)?;
+        }
+    }
+
+    /// Replay all events after the event cursor on the target.
+    async fn replay_events(
+        &self,
+        state: &mut StorageMigrationState,
+        target: &Operator,
+    ) -> Result<(), StorageMigrationError> {
+        loop {
+            let events = {
+                let rtxn = self.db().env.read_txn()?;
+                let lower = Bound::Excluded(state.event_cursor.as_str());
+                let mut events = vec![];
+                for result in self
+                    .db()
+                    .tables
+                    .events
+                    .range(&rtxn, &(lower, Bound::Unbounded))?
+                    .take(BATCH_SIZE)
+                {
+                    let (key, bytes) = result?;
+                    let event = Event::deserialize(bytes).map_err(FileIoError::from)?;
+                    events.push((key.to_string(), event));
+                }
+                events
+            };
+            let Some((last_key, _)) = events.last().cloned() else {
+                return Ok(());
+            };
+            for (_, event) in events {
+                self.ensure_not_stopped()?;
+                let Some(key) = event.url().strip_prefix("pubky://") else {
+                    continue;
+                };
+                match event {
+                    // Copy the current content. Later events are replayed anyway.
+                    Event::Put(_) => self.copy_entry(key, target).await?,
+                    Event::Delete(_) => target.delete(key).await?,
+                };
+                self.update_progress(|progress| progress.replayed_events += 1);
+            }
+            state.event_cursor = last_key;
+            state.write(&self.data_dir)?;
+        }
+    }
+
+    /// Copy the staged chunks of the uploads in progress so they can be resumed on the target.
+    ///
+    /// `copied` holds the size of every chunk copied by a previous call. Only new or grown
+    /// chunks are copied again, and the ones removed from the staging since are removed
+    /// from the target. The last call must happen while writes are paused.
+    async fn copy_staged_chunks(
+        &self,
+        target: &Operator,
+        copied: &mut HashMap<String, u64>,
+    ) -> Result<(), StorageMigrationError> {
+        let staging = self.upload_service.staging();
+        let mut staged = HashSet::new();
+        let mut lister = staging.lister_with(STAGING_DIR).recursive(true).await?;
+        while let Some(entry) = lister.try_next().await? {
+            if !entry.metadata().is_file() {
+                continue;
+            }
+            self.ensure_not_stopped()?;
+            let path = entry.path().to_string();
+            let size = staging.stat(&path).await?.content_length();
+            staged.insert(path.clone());
+            if copied.get(&path) == Some(&size) {
+                continue;
+            }
+            copy_file(&staging, target, &path).await?;
+            copied.insert(path, size);
+        }
+        let removed: Vec<String> = copied
+            .keys()
+            .filter(|path| !staged.contains(*path))
+            .cloned()
+            .collect();
+        for path in removed {
+            target.delete(&path).await?;
+            copied.remove(&path);
+        }
+        Ok(())
+    }
+
+    /// Copy a single entry to the target and verify its content hash.
+    async fn copy_entry(&self, key: &str, target: &Operator) -> Result<(), StorageMigrationError> {
+        let path: EntryPath = match key.parse() {
+            Ok(path) => path,
+            Err(e) => {
+                tracing::warn!("Skip invalid entry key {}: {}", key, e);
+                return Ok(());
+            }
+        };
+        let entry = match self.db().get_entry(&path) {
+            Ok(entry) => entry,
+            Err(FileIoError::NotFound) => {
+                // Deleted in the meantime.
+                target.delete(key).await?;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut stream = match self.file_service.opendal.get_stream(&path).await {
+            Ok(stream) => stream,
+            Err(FileIoError::NotFound) => {
+                tracing::warn!("Skip entry {} without a file in the storage.", key);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut writer = target.writer(key).await?;
+        let mut hasher = Hasher::new();
+        let copy_result: Result<(), StorageMigrationError> = async {
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk.map_err(FileIoError::from)?;
+                hasher.update(&chunk);
+                writer.write(chunk).await?;
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = copy_result {
+            writer.abort().await?;
+            return Err(e);
+        }
+        writer.close().await?;
+
+        if hasher.finalize() == *entry.content_hash() {
+            return Ok(());
+        }
+        // The file might have been overwritten while we copied it.
+        // The PUT event of the overwrite is replayed later.
+        match self.db().get_entry(&path) {
+            Ok(current) if current.content_hash() != entry.content_hash() => Ok(()),
+            Err(FileIoError::NotFound) => Ok(()),
+            _ => Err(StorageMigrationError::HashMismatch(key.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opendal::Buffer;
+
+    use crate::shared::webdav::WebDavPath;
+
+    use super::*;
+
+    async fn write_file(file_service: &FileService, path: &EntryPath, content: &[u8]) {
+        file_service
+            .write(path, Buffer::from(content.to_vec()))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migrate_to_file_system() {
+        let context = AppContext::test();
+        let file_service = context.file_service.clone();
+        let pubkey = pkarr::Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+
+        let paths = (0..250)
+            .map(|i| {
+                EntryPath::new(
+                    pubkey.clone(),
+                    WebDavPath::new(&format!("/pub/file_{i}.txt")).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        for path in &paths {
+            write_file(&file_service, path, path.as_str().as_bytes()).await;
+        }
+        let deleted = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/deleted.txt").unwrap());
+        write_file(&file_service, &deleted, b"deleted").await;
+        file_service.delete(&deleted).await.unwrap();
+
+        let migrator = StorageMigrator::from_context(&context);
+        migrator.run(StorageConfigToml::FileSystem).await.unwrap();
+        let progress = migrator.progress();
+        assert!(progress.completed);
+        assert_eq!(progress.copied_entries, 250);
+
+        // Files are read from the new storage.
+        let target =
+            build_base_operator(&StorageConfigToml::FileSystem, context.data_dir.path()).unwrap();
+        for path in &paths {
+            let content = target.read(path.as_str()).await.unwrap();
+            assert_eq!(content.to_vec(), path.as_str().as_bytes());
+            assert_eq!(
+                file_service.get(path).await.unwrap(),
+                path.as_str().as_bytes()
+            );
+        }
+        assert!(!target.exists(deleted.as_str()).await.unwrap());
+
+        // New writes go to the new storage.
+        let new_path = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/new.txt").unwrap());
+        write_file(&file_service, &new_path, b"new").await;
+        assert_eq!(
+            target.read(new_path.as_str()).await.unwrap().to_vec(),
+            b"new"
+        );
+
+        // The completed migration overrides the config on restart.
+        let mut config = context.config_toml.clone();
+        StorageMigrationState::apply_to_config(&mut config, context.data_dir.path()).unwrap();
+        assert_eq!(config.storage, StorageConfigToml::FileSystem);
+
+        // Migrating to the storage in use fails.
+        assert!(matches!(
+            migrator.run(StorageConfigToml::FileSystem).await,
+            Err(StorageMigrationError::SameStorage)
+        ));
+        assert!(migrator.progress().error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_resume_upload_after_migration() {
+        let context = AppContext::test();
+        let pubkey = pkarr::Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+        let path = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/big.bin").unwrap());
+        let chunk = |data: &'static [u8]| {
+            Box::pin(futures_util::stream::once(async move {
+                Ok(bytes::Bytes::from_static(data))
+            }))
+        };
+
+        let uploads = context.upload_service.clone();
+        let upload = uploads.create(&path, 6, None).unwrap();
+        uploads
+            .append(&upload.id, &path, 0, chunk(b"abc"))
+            .await
+            .unwrap();
+
+        StorageMigrator::from_context(&context)
+            .run(StorageConfigToml::FileSystem)
+            .await
+            .unwrap();
+
+        // The staged chunk moved to the new storage and the upload continues there.
+        let upload = uploads
+            .append(&upload.id, &path, 3, chunk(b"def"))
+            .await
+            .unwrap();
+        assert!(upload.is_complete());
+        let target =
+            build_base_operator(&StorageConfigToml::FileSystem, context.data_dir.path()).unwrap();
+        assert_eq!(
+            target.read(path.as_str()).await.unwrap().to_vec(),
+            b"abcdef"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resume_and_replay_events() {
+        let context = AppContext::test();
+        let file_service = context.file_service.clone();
+        let pubkey = pkarr::Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+
+        let first = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/a.txt").unwrap());
+        let second = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/b.txt").unwrap());
+        write_file(&file_service, &first, b"first").await;
+        write_file(&file_service, &second, b"second").await;
+
+        let migrator = StorageMigrator::from_context(&context);
+
+        // Simulate an interrupted migration that copied the first entry already.
+        let state = StorageMigrationState {
+            completed: false,
+            entry_cursor: Some(first.to_string()),
+            event_cursor: migrator.latest_event_cursor().unwrap(),
+            target: StorageConfigToml::FileSystem,
+        };
+        state.write(context.data_dir.path()).unwrap();
+        let target =
+            build_base_operator(&StorageConfigToml::FileSystem, context.data_dir.path()).unwrap();
+        target
+            .write(first.as_str(), b"first".to_vec())
+            .await
+            .unwrap();
+
+        // Changes after the interruption are replayed.
+        write_file(&file_service, &first, b"first updated").await;
+
+        migrator.run(StorageConfigToml::FileSystem).await.unwrap();
+        let progress = migrator.progress();
+        assert_eq!(progress.copied_entries, 1);
+        assert_eq!(progress.replayed_events, 1);
+        assert_eq!(
+            target.read(first.as_str()).await.unwrap().to_vec(),
+            b"first updated"
+        );
+        assert_eq!(
+            target.read(second.as_str()).await.unwrap().to_vec(),
+            b"second"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stop() {
+        let context = AppContext::test();
+        let file_service = context.file_service.clone();
+        let pubkey = pkarr::Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+        for i in 0..250 {
+            let path = EntryPath::new(
+                pubkey.clone(),
+                WebDavPath::new(&format!("/pub/file_{i}.txt")).unwrap(),
+            );
+            write_file(&file_service, &path, b"content").await;
+        }
+
+        let migrator = StorageMigrator::from_context(&context);
+        migrator.start(StorageConfigToml::FileSystem).unwrap();
+        migrator
+            .stop(Instant::now() + std::time::Duration::from_secs(5))
+            .await;
+        let progress = migrator.progress();
+        assert!(!progress.running);
+        assert!(!progress.completed);
+        assert!(progress.copied_entries < 250);
+
+        // The progress is kept for the next start.
+        let state = StorageMigrationState::read(context.data_dir.path())
+            .unwrap()
+            .unwrap();
+        assert!(!state.completed);
+        assert!(matches!(
+            migrator.run(StorageConfigToml::FileSystem).await,
+            Err(StorageMigrationError::Stopped)
+        ));
+    }
+}
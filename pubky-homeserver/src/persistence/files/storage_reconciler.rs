@@ -0,0 +1,521 @@
+//! Reconciles the recorded user storage usage with the entries table and the OpenDAL backend.
+//!
+//! `User::used_bytes` is maintained incrementally by the [super::user_quota_layer::UserQuotaLayer]
+//! and the entries are written by the [super::entry_layer::EntryLayer]. Both are updated in separate
+//! steps so a crash or a failed write can leave them out of sync with the actual files.
+//!
+//! The reconciler detects:
+//! - Users whose `used_bytes` doesn't match the sum of their entries and staged uploads.
+//! - Orphan blobs: Files in the storage backend without an entry.
+//! - Dangling entries: Entries without a file in the storage backend.
+//!
+//! and optionally repairs them.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use futures_util::TryStreamExt;
+use pkarr::PublicKey;
+use serde::Serialize;
+
+use crate::{
+    persistence::lmdb::{tables::entries::Entry, LmDB},
+    shared::webdav::EntryPath,
+    AppContext,
+};
+
+use super::{
+    entry_service::EntryService, user_quota_layer::FILE_METADATA_SIZE, FileIoError, FileService,
+    STAGING_DIR,
+};
+
+/// A user whose recorded storage usage doesn't match the entries table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UsageMismatch {
+    /// The user pubkey.
+    pub pubkey: String,
+    /// The `used_bytes` value stored in the users table.
+    pub recorded_bytes: u64,
+    /// The usage calculated from the entries table.
+    pub actual_bytes: u64,
+}
+
+/// The result of a storage reconciliation run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReconciliationReport {
+    /// Number of users checked.
+    pub users_checked: u64,
+    /// Number of entries checked.
+    pub entries_checked: u64,
+    /// Number of files found in the storage backend.
+    pub files_checked: u64,
+    /// Users with a wrong `used_bytes` value.
+    pub usage_mismatches: Vec<UsageMismatch>,
+    /// Files in the storage backend without an entry.
+    pub orphan_files: Vec<String>,
+    /// Entries without a file in the storage backend.
+    pub dangling_entries: Vec<String>,
+    /// Whether the found issues have been repaired.
+    pub repaired: bool,
+}
+
+impl ReconciliationReport {
+    /// Returns true if no drift has been detected.
+    pub fn is_consistent(&self) -> bool {
+        self.usage_mismatches.is_empty()
+            && self.orphan_files.is_empty()
+            && self.dangling_entries.is_empty()
+    }
+}
+
+impl fmt::Display for ReconciliationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Checked {} users, {} entries and {} files.",
+            self.users_checked, self.entries_checked, self.files_checked
+        )?;
+        writeln!(f, "Usage mismatches: {}", self.usage_mismatches.len())?;
+        for mismatch in &self.usage_mismatches {
+            writeln!(
+                f,
+                "  {} recorded {} bytes, actual {} bytes",
+                mismatch.pubkey, mismatch.recorded_bytes, mismatch.actual_bytes
+            )?;
+        }
+        writeln!(f, "Orphan files: {}", self.orphan_files.len())?;
+        for path in &self.orphan_files {
+            writeln!(f, "  {}", path)?;
+        }
+        writeln!(f, "Dangling entries: {}", self.dangling_entries.len())?;
+        for path in &self.dangling_entries {
+            writeln!(f, "  {}", path)?;
+        }
+        if self.is_consistent() {
+            write!(f, "Storage is consistent.")
+        } else if self.repaired {
+            write!(f, "All issues have been repaired.")
+        } else {
+            write!(f, "Run with repair enabled to fix the issues.")
+        }
+    }
+}
+
+/// Detects and repairs drift between the users table, the entries table and the storage backend.
+///
+/// The scan is not atomic. Writes that happen while the reconciler runs
+/// can be reported as false positives. A repair run checks the found issues again
+/// while writes are paused and only repairs the confirmed ones.
+#[derive(Debug, Clone)]
+pub struct StorageReconciler {
+    file_service: FileService,
+}
+
+impl StorageReconciler {
+    pub(crate) fn new(file_service: FileService) -> Self {
+        Self { file_service }
+    }
+
+    /// Create a new reconciler from the app context.
+    pub fn from_context(context: &AppContext) -> Self {
+        Self::new(context.file_service.clone())
+    }
+
+    fn db(&self) -> &LmDB {
+        &self.file_service.db
+    }
+
+    /// Run the reconciliation.
+    ///
+    /// If `repair` is true,
+    /// - orphan files are deleted from the storage backend,
+    /// - dangling entries are deleted from the entries table (including a `DEL` event),
+    /// - the `used_bytes` of every user is recomputed from the entries table.
+    ///
+    /// The scan doesn't block writes. A repair run pauses the writes only to check the
+    /// orphan files and dangling entries again and to repair them.
+    /// Otherwise an entry written between listing the files and reading the entries
+    /// would be considered dangling and deleted.
+    pub async fn run(&self, repair: bool) -> Result<ReconciliationReport, FileIoError> {
+        let mut report = self.scan().await?;
+        if repair && !report.is_consistent() {
+            let _write_guard = self.file_service.opendal.pause_writes().await;
+            self.recheck(&mut report).await?;
+            self.repair(&report).await?;
+            report.repaired = true;
+        }
+        Ok(report)
+    }
+
+    /// Scan the storage backend and the tables for drift.
+    async fn scan(&self) -> Result<ReconciliationReport, FileIoError> {
+        let mut report = ReconciliationReport::default();
+
+        let files = self.list_files().await?;
+        report.files_checked = files.len() as u64;
+
+        // Compare the entries with the files in the storage backend.
+        let mut entry_keys: HashSet<String> = HashSet::new();
+        {
+            let rtxn = self.db().env.read_txn()?;
+            for result in self.db().tables.entries.iter(&rtxn)? {
+                let (key, _) = result?;
+                report.entries_checked += 1;
+                if !files.contains(key) {
+                    report.dangling_entries.push(key.to_string());
+                }
+                entry_keys.insert(key.to_string());
+            }
+        }
+        report.orphan_files = files
+            .into_iter()
+            .filter(|path| !entry_keys.contains(path))
+            .collect();
+        report.orphan_files.sort();
+
+        // Compare the recorded usage with the usage calculated from the entries.
+        let dangling: HashSet<&str> = report.dangling_entries.iter().map(|s| s.as_str()).collect();
+        let actual_usage = self.calculate_usage(&dangling)?;
+        {
+            let rtxn = self.db().env.read_txn()?;
+            for result in self.db().tables.users.iter(&rtxn)? {
+                let (pubkey, user) = result?;
+                report.users_checked += 1;
+                let actual_bytes = actual_usage.get(&pubkey).copied().unwrap_or(0);
+                if user.used_bytes != actual_bytes {
+                    report.usage_mismatches.push(UsageMismatch {
+                        pubkey: pubkey.to_string(),
+                        recorded_bytes: user.used_bytes,
+                        actual_bytes,
+                    });
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Drop the orphan files and dangling entries that have been written since the scan.
+    /// Must be called while writes are paused.
+    async fn recheck(&self, report: &mut ReconciliationReport) -> Result<(), FileIoError> {
+        let operator = self.file_service.opendal.operator();
+        let mut orphan_files = Vec::with_capacity(report.orphan_files.len());
+        for path in report.orphan_files.drain(..) {
+            if operator.exists(&path).await? && !self.entry_exists(&path)? {
+                orphan_files.push(path);
+            }
+        }
+        report.orphan_files = orphan_files;
+
+        let mut dangling_entries = Vec::with_capacity(report.dangling_entries.len());
+        for key in report.dangling_entries.drain(..) {
+            if self.entry_exists(&key)? && !operator.exists(&key).await? {
+                dangling_entries.push(key);
+            }
+        }
+        report.dangling_entries = dangling_entries;
+        Ok(())
+    }
+
+    fn entry_exists(&self, key: &str) -> Result<bool, FileIoError> {
+        let rtxn = self.db().env.read_txn()?;
+        Ok(self.db().tables.entries.get(&rtxn, key)?.is_some())
+    }
+
+    /// List all files in the storage backend.
+    async fn list_files(&self) -> Result<HashSet<String>, FileIoError> {
+        let mut lister = self
+            .file_service
+            .opendal
+            .operator()
+            .lister_with("/")
+            .recursive(true)
+            .await?;
+        let mut files = HashSet::new();
+        while let Some(entry) = lister.try_next().await? {
+            let path = entry.path().trim_start_matches('/');
+            // Staged chunks of uploads in progress are tracked in the uploads table.
+            if entry.metadata().is_file() && !path.starts_with(STAGING_DIR) {
+                files.insert(path.to_string());
+            }
+        }
+        Ok(files)
+    }
+
+    /// Calculate the storage usage of every user from the entries table
+    /// and the staged chunks of their uploads in progress.
+    /// Entries in `skip` are ignored.
+    fn calculate_usage(
+        &self,
+        skip: &HashSet<&str>,
+    ) -> Result<HashMap<PublicKey, u64>, FileIoError> {
+        let mut usage: HashMap<PublicKey, u64> = HashMap::new();
+        for upload in self.db().list_uploads()? {
+            *usage.entry(upload.pubkey.clone()).or_default() += upload.offset();
+        }
+        let rtxn = self.db().env.read_txn()?;
+        for result in self.db().tables.entries.iter(&rtxn)? {
+            let (key, bytes) = result?;
+            if skip.contains(key) {
+                continue;
+            }
+            let entry_path: EntryPath = match key.parse() {
+                Ok(path) => path,
+                Err(e) => {
+                    tracing::warn!("Skip invalid entry key {}: {}", key, e);
+                    continue;
+                }
+            };
+            let entry = Entry::deserialize(bytes)?;
+            let bytes = entry.content_length() as u64 + FILE_METADATA_SIZE;
+            *usage.entry(entry_path.pubkey().clone()).or_default() += bytes;
+        }
+        Ok(usage)
+    }
+
+    /// Repair the issues found in the report.
+    /// Must be called while writes are paused, so the plain operator is used to delete the orphans.
+    async fn repair(&self, report: &ReconciliationReport) -> Result<(), FileIoError> {
+        for path in &report.orphan_files {
+            tracing::info!("Delete orphan file {}", path);
+            self.file_service.opendal.operator().delete(path).await?;
+        }
+
+        let entry_service = EntryService::new(self.db().clone());
+        for key in &report.dangling_entries {
+            let entry_path: EntryPath = match key.parse() {
+                Ok(path) => path,
+                Err(e) => {
+                    tracing::warn!("Skip invalid dangling entry key {}: {}", key, e);
+                    continue;
+                }
+            };
+            tracing::info!("Delete dangling entry {}", key);
+            match entry_service.delete_entry(&entry_path) {
+                Ok(()) | Err(FileIoError::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Recompute the usage after the files and entries have been cleaned up.
+        // Deleting orphan files already changed the usage of some users.
+        let actual_usage = self.calculate_usage(&HashSet::new())?;
+        let mut wtxn = self.db().env.write_txn()?;
+        let users = self
+            .db()
+            .tables
+            .users
+            .iter(&wtxn)?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (pubkey, mut user) in users {
+            let actual_bytes = actual_usage.get(&pubkey).copied().unwrap_or(0);
+            if user.used_bytes != actual_bytes {
+                tracing::info!(
+                    "Set used bytes of {} from {} to {}",
+                    pubkey,
+                    user.used_bytes,
+                    actual_bytes
+                );
+                user.used_bytes = actual_bytes;
+                self.db().tables.users.put(&mut wtxn, &pubkey, &user)?;
+            }
+        }
+        wtxn.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opendal::Buffer;
+    use pubky_common::timestamp::Timestamp;
+
+    use crate::{persistence::files::FileMetadataBuilder, shared::webdav::WebDavPath};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_consistent_storage() {
+        let context = AppContext::test();
+        let file_service = context.file_service.clone();
+        let pubkey = pkarr::Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+        let path = EntryPath::new(pubkey, WebDavPath::new("/pub/test.txt").unwrap());
+        file_service
+            .write(&path, Buffer::from(vec![0u8; 10]))
+            .await
+            .unwrap();
+
+        let report = StorageReconciler::new(file_service)
+            .run(false)
+            .await
+            .unwrap();
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(report.users_checked, 1);
+        assert_eq!(report.entries_checked, 1);
+        assert_eq!(report.files_checked, 1);
+    }
+
+    #[tokio::test]
+    async fn test_detect_and_repair() {
+        let context = AppContext::test();
+        let db = context.db.clone();
+        let file_service = context.file_service.clone();
+        let pubkey = pkarr::Keypair::random().public_key();
+        db.create_user(&pubkey).unwrap();
+
+        // Regular file.
+        let path = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/ok.txt").unwrap());
+        file_service
+            .write(&path, Buffer::from(vec![0u8; 10]))
+            .await
+            .unwrap();
+
+        // Orphan file: The entry is removed but the file stays.
+        let orphan = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/orphan.txt").unwrap());
+        file_service
+            .write(&orphan, Buffer::from(vec![0u8; 20]))
+            .await
+            .unwrap();
+        let mut wtxn = db.env.write_txn().unwrap();
+        db.tables
+            .entries
+            .delete(&mut wtxn, orphan.as_str())
+            .unwrap();
+        wtxn.commit().unwrap();
+
+        // Dangling entry: The entry exists but the file doesn't.
+        let dangling = EntryPath::new(
+            pubkey.clone(),
+            WebDavPath::new("/pub/dangling.txt").unwrap(),
+        );
+        let mut metadata = FileMetadataBuilder::default();
+        metadata.update(&[0u8; 30]);
+        let mut metadata = metadata.finalize();
+        metadata.modified_at = Timestamp::now();
+        EntryService::new(db.clone())
+            .write_entry(&dangling, &metadata)
+            .unwrap();
+
+        let reconciler = StorageReconciler::new(file_service.clone());
+        let report = reconciler.run(false).await.unwrap();
+        assert_eq!(report.orphan_files, vec![orphan.to_string()]);
+        assert_eq!(report.dangling_entries, vec![dangling.to_string()]);
+        assert_eq!(
+            report.usage_mismatches,
+            vec![UsageMismatch {
+                pubkey: pubkey.to_string(),
+                recorded_bytes: 30 + 2 * FILE_METADATA_SIZE,
+                actual_bytes: 10 + FILE_METADATA_SIZE,
+            }]
+        );
+        assert!(!report.repaired);
+
+        // Repair
+        let report = reconciler.run(true).await.unwrap();
+        assert!(report.repaired);
+        assert!(!file_service.opendal.exists(&orphan).await.unwrap());
+        assert!(matches!(
+            db.get_entry(&dangling),
+            Err(FileIoError::NotFound)
+        ));
+        assert_eq!(
+            db.get_user_data_usage(&pubkey).unwrap(),
+            Some(10 + FILE_METADATA_SIZE)
+        );
+
+        // Everything should be consistent now.
+        let report = reconciler.run(false).await.unwrap();
+        assert!(report.is_consistent(), "{}", report);
+    }
+
+    #[tokio::test]
+    async fn test_repair_skips_issues_fixed_since_the_scan() {
+        let context = AppContext::test();
+        let db = context.db.clone();
+        let file_service = context.file_service.clone();
+        let pubkey = pkarr::Keypair::random().public_key();
+        db.create_user(&pubkey).unwrap();
+
+        // Orphan file: The entry is removed but the file stays.
+        let orphan = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/orphan.txt").unwrap());
+        file_service
+            .write(&orphan, Buffer::from(vec![0u8; 20]))
+            .await
+            .unwrap();
+        let mut wtxn = db.env.write_txn().unwrap();
+        db.tables
+            .entries
+            .delete(&mut wtxn, orphan.as_str())
+            .unwrap();
+        wtxn.commit().unwrap();
+        // Dangling entry: The entry exists but the file doesn't.
+        let dangling = EntryPath::new(
+            pubkey.clone(),
+            WebDavPath::new("/pub/dangling.txt").unwrap(),
+        );
+        let mut metadata = FileMetadataBuilder::default();
+        metadata.update(&[0u8; 30]);
+        let metadata = metadata.finalize();
+        EntryService::new(db.clone())
+            .write_entry(&dangling, &metadata)
+            .unwrap();
+
+        let reconciler = StorageReconciler::new(file_service.clone());
+        let mut report = reconciler.scan().await.unwrap();
+        assert_eq!(report.orphan_files, vec![orphan.to_string()]);
+        assert_eq!(report.dangling_entries, vec![dangling.to_string()]);
+
+        // Both paths are written by users after the scan.
+        for path in [&orphan, &dangling] {
+            file_service
+                .write(path, Buffer::from(vec![0u8; 10]))
+                .await
+                .unwrap();
+        }
+
+        reconciler.recheck(&mut report).await.unwrap();
+        assert!(report.orphan_files.is_empty());
+        assert!(report.dangling_entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_repair_doesnt_wait_for_ongoing_writes() {
+        let context = AppContext::test();
+        let file_service = context.file_service.clone();
+        let pubkey = pkarr::Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+        let path = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/slow.txt").unwrap());
+
+        // A write that is still receiving its body when the repair starts.
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let body = Box::pin(futures_util::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|chunk| (Ok(chunk), rx))
+        }));
+        let write = tokio::spawn({
+            let file_service = file_service.clone();
+            let path = path.clone();
+            async move { file_service.write_stream(&path, body).await }
+        });
+        tx.send(bytes::Bytes::from(vec![0u8; 10])).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let reconciler = StorageReconciler::new(file_service.clone());
+        let report = tokio::time::timeout(std::time::Duration::from_secs(1), reconciler.run(true))
+            .await
+            .expect("Repair must not wait for the write")
+            .unwrap();
+        assert!(report.is_consistent(), "{}", report);
+
+        drop(tx);
+        write.await.unwrap().unwrap();
+        let report = reconciler.run(false).await.unwrap();
+        assert!(report.is_consistent(), "{}", report);
+        assert_eq!(report.entries_checked, 1);
+        assert!(file_service.opendal.exists(&path).await.unwrap());
+        assert_eq!(
+            context.db.get_user_data_usage(&pubkey).unwrap(),
+            Some(10 + FILE_METADATA_SIZE)
+        );
+    }
+}
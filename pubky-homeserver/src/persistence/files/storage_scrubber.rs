@@ -0,0 +1,276 @@
+//! Re-reads the stored files and compares them with the content hash of their entry.
+//!
+//! Storage backends can silently corrupt files (bit rot, partial writes, buggy migrations).
+//! The scrubber detects these files and optionally quarantines them: The corrupted file is moved
+//! to `{data_dir}/data/quarantine` for inspection and the entry is deleted so it isn't served anymore.
+
+use std::{fmt, path::PathBuf};
+
+use futures_util::StreamExt;
+use pubky_common::crypto::Hasher;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::{persistence::lmdb::LmDB, shared::webdav::EntryPath, AppContext};
+
+use super::{FileIoError, FileService};
+
+/// Number of entries read from the database at once.
+const BATCH_SIZE: usize = 100;
+
+/// The result of a scrub run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ScrubReport {
+    /// Number of files checked.
+    pub files_checked: u64,
+    /// Entries whose file content doesn't match the content hash.
+    pub corrupted_files: Vec<String>,
+    /// Entries without a file in the storage backend.
+    pub missing_files: Vec<String>,
+    /// Whether the corrupted files have been quarantined.
+    pub quarantined: bool,
+}
+
+impl fmt::Display for ScrubReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Checked {} files. {} corrupted, {} missing.",
+            self.files_checked,
+            self.corrupted_files.len(),
+            self.missing_files.len()
+        )
+    }
+}
+
+/// The outcome of checking a single file.
+enum FileCheck {
+    Valid,
+    Corrupted,
+    Missing,
+    /// Changed or deleted while checking.
+    Changed,
+}
+
+/// Verifies the stored files against the content hash of their entries.
+#[derive(Debug, Clone)]
+pub struct StorageScrubber {
+    file_service: FileService,
+    quarantine_dir: PathBuf,
+}
+
+impl StorageScrubber {
+    pub(crate) fn new(file_service: FileService, quarantine_dir: PathBuf) -> Self {
+        Self {
+            file_service,
+            quarantine_dir,
+        }
+    }
+
+    /// Create a new scrubber from the app context.
+    pub fn from_context(context: &AppContext) -> Self {
+        Self::new(
+            context.file_service.clone(),
+            context.data_dir.path().join("data/quarantine"),
+        )
+    }
+
+    fn db(&self) -> &LmDB {
+        &self.file_service.db
+    }
+
+    /// Check every file.
+    ///
+    /// If `quarantine` is true, corrupted files are moved to the quarantine directory
+    /// and their entries are deleted.
+    pub async fn run(&self, quarantine: bool) -> Result<ScrubReport, FileIoError> {
+        self.run_until_cancelled(quarantine, &CancellationToken::new())
+            .await
+    }
+
+    /// Like [Self::run] but stops after the file in progress once `cancel` is cancelled.
+    /// The report only covers the files checked so far.
+    pub(crate) async fn run_until_cancelled(
+        &self,
+        quarantine: bool,
+        cancel: &CancellationToken,
+    ) -> Result<ScrubReport, FileIoError> {
+        let mut report = ScrubReport {
+            quarantined: quarantine,
+            ..Default::default()
+        };
+        let mut cursor: Option<String> = None;
+        loop {
+            let keys = self.next_keys(cursor.as_deref())?;
+            let Some(last_key) = keys.last().cloned() else {
+                break;
+            };
+            for key in keys {
+                if cancel.is_cancelled() {
+                    return Ok(report);
+                }
+                let path: EntryPath = match key.parse() {
+                    Ok(path) => path,
+                    Err(e) => {
+                        tracing::warn!("Skip invalid entry key {}: {}", key, e);
+                        continue;
+                    }
+                };
+                report.files_checked += 1;
+                match self.check_file(&path).await? {
+                    FileCheck::Valid | FileCheck::Changed => {}
+                    FileCheck::Missing => {
+                        tracing::warn!("Scrub: File {} is missing in the storage.", path);
+                        report.missing_files.push(key);
+                    }
+                    FileCheck::Corrupted => {
+                        tracing::error!("Scrub: File {} doesn't match its content hash.", path);
+                        if quarantine {
+                            self.quarantine(&path).await?;
+                        }
+                        report.corrupted_files.push(key);
+                    }
+                }
+            }
+            cursor = Some(last_key);
+        }
+        Ok(report)
+    }
+
+    fn next_keys(&self, cursor: Option<&str>) -> Result<Vec<String>, FileIoError> {
+        let rtxn = self.db().env.read_txn()?;
+        let lower = match cursor {
+            Some(cursor) => std::ops::Bound::Excluded(cursor),
+            None => std::ops::Bound::Unbounded,
+        };
+        let keys = self
+            .db()
+            .tables
+            .entries
+            .range(&rtxn, &(lower, std::ops::Bound::Unbounded))?
+            .take(BATCH_SIZE)
+            .map(|result| result.map(|(key, _)| key.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(keys)
+    }
+
+    async fn check_file(&self, path: &EntryPath) -> Result<FileCheck, FileIoError> {
+        let entry = match self.db().get_entry(path) {
+            Ok(entry) => entry,
+            Err(FileIoError::NotFound) => return Ok(FileCheck::Changed),
+            Err(e) => return Err(e),
+        };
+        // Read from the storage directly. The cache is verified on fill anyway.
+        let mut stream = match self.file_service.opendal.get_stream(path).await {
+            Ok(stream) => stream,
+            Err(FileIoError::NotFound) => return Ok(FileCheck::Missing),
+            Err(e) => return Err(e),
+        };
+        let mut hasher = Hasher::new();
+        let mut length = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            length += chunk.len();
+        }
+        if length == entry.content_length() && hasher.finalize() == *entry.content_hash() {
+            return Ok(FileCheck::Valid);
+        }
+
+        // The file might have been overwritten while we read it.
+        match self.db().get_entry(path) {
+            Ok(current) if current.content_hash() == entry.content_hash() => {
+                Ok(FileCheck::Corrupted)
+            }
+            _ => Ok(FileCheck::Changed),
+        }
+    }
+
+    /// Move the corrupted file to the quarantine directory and delete the entry.
+    async fn quarantine(&self, path: &EntryPath) -> Result<(), FileIoError> {
+        let target = self.quarantine_dir.join(path.as_str());
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut stream = self.file_service.opendal.get_stream(path).await?;
+        let mut file = tokio::fs::File::create(&target).await?;
+        while let Some(chunk) = stream.next().await {
+            tokio::io::AsyncWriteExt::write_all(&mut file, &chunk?).await?;
+        }
+        tokio::io::AsyncWriteExt::flush(&mut file).await?;
+        self.file_service.delete(path).await?;
+        tracing::warn!(
+            "Scrub: Quarantined corrupted file {} to {}",
+            path,
+            target.display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opendal::Buffer;
+
+    use crate::shared::webdav::WebDavPath;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scrub() {
+        let context = AppContext::test();
+        let file_service = context.file_service.clone();
+        let pubkey = pkarr::Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+
+        let valid = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/valid.txt").unwrap());
+        let corrupted = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/bad.txt").unwrap());
+        file_service
+            .write(&valid, Buffer::from(b"valid".to_vec()))
+            .await
+            .unwrap();
+        file_service
+            .write(&corrupted, Buffer::from(b"original".to_vec()))
+            .await
+            .unwrap();
+
+        // Corrupt the file without updating the entry.
+        let entry = context.db.get_entry(&corrupted).unwrap();
+        file_service
+            .write(&corrupted, Buffer::from(b"corrupt".to_vec()))
+            .await
+            .unwrap();
+        let mut wtxn = context.db.env.write_txn().unwrap();
+        context
+            .db
+            .tables
+            .entries
+            .put(&mut wtxn, corrupted.as_str(), &entry.serialize())
+            .unwrap();
+        wtxn.commit().unwrap();
+
+        let quarantine_dir = tempfile::tempdir().unwrap();
+        let scrubber = StorageScrubber::new(file_service.clone(), quarantine_dir.path().into());
+
+        // Report only
+        let report = scrubber.run(false).await.unwrap();
+        assert_eq!(report.files_checked, 2);
+        assert_eq!(report.corrupted_files, vec![corrupted.to_string()]);
+        assert!(report.missing_files.is_empty());
+        assert!(context.db.get_entry(&corrupted).is_ok());
+
+        // Quarantine
+        let report = scrubber.run(true).await.unwrap();
+        assert_eq!(report.corrupted_files, vec![corrupted.to_string()]);
+        assert!(matches!(
+            context.db.get_entry(&corrupted),
+            Err(FileIoError::NotFound)
+        ));
+        let quarantined = std::fs::read(quarantine_dir.path().join(corrupted.as_str())).unwrap();
+        assert_eq!(quarantined, b"corrupt");
+
+        let report = scrubber.run(false).await.unwrap();
+        assert_eq!(report.files_checked, 1);
+        assert!(report.corrupted_files.is_empty());
+    }
+}
@@ -0,0 +1,474 @@
+//! Resumable uploads of large files.
+//!
+//! A file is uploaded in chunks. Every chunk is staged in the storage backend under
+//! [STAGING_DIR] and counted against the user quota right away. Once the last chunk
+//! arrived, the chunks are written to the final file which creates a single entry and event.
+//! Uploads that haven't received a chunk for a while are garbage collected.
+
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use base32::{encode, Alphabet};
+use bytes::Bytes;
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use opendal::Operator;
+use pkarr::PublicKey;
+use pubky_common::{
+    crypto::{random_bytes, Hash},
+    timestamp::Timestamp,
+};
+
+use crate::{
+    persistence::lmdb::{
+        tables::{entries::Entry, uploads::Upload},
+        LmDB,
+    },
+    shared::webdav::EntryPath,
+    ConfigToml,
+};
+
+use super::{
+    opendal_service::{build_base_operator, copy_file, CHUNK_SIZE},
+    ContentHashVerifier, FileIoError, FileService, UserQuota, WriteStreamError,
+};
+
+/// Directory in the storage backend where the chunks of uploads in progress are staged.
+pub(crate) const STAGING_DIR: &str = ".uploads/";
+
+/// Errors of resumable uploads.
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    /// The upload doesn't exist or has expired.
+    #[error("Upload not found")]
+    NotFound,
+    /// The chunk doesn't start where the previous one ended.
+    #[error("Upload offset mismatch. The homeserver received {0} bytes so far")]
+    OffsetMismatch(u64),
+    /// Another chunk of the same upload is currently being received.
+    #[error("Upload is busy receiving another chunk")]
+    Busy,
+    /// More bytes were sent than announced when the upload was created.
+    #[error("Upload exceeds its length of {0} bytes")]
+    LengthExceeded(u64),
+    /// File operation failed.
+    #[error(transparent)]
+    FileIo(#[from] FileIoError),
+}
+
+impl From<heed::Error> for UploadError {
+    fn from(e: heed::Error) -> Self {
+        Self::FileIo(e.into())
+    }
+}
+
+impl From<opendal::Error> for UploadError {
+    fn from(e: opendal::Error) -> Self {
+        Self::FileIo(e.into())
+    }
+}
+
+/// Manages resumable uploads.
+#[derive(Debug, Clone)]
+pub struct UploadService {
+    /// Plain storage operator without entry and quota tracking.
+    /// Swapped together with the file storage when the storage is migrated.
+    staging: Arc<RwLock<Operator>>,
+    file_service: FileService,
+    user_quota: UserQuota,
+    /// Uploads without a new chunk for longer than this are garbage collected.
+    expiry: Duration,
+    /// Ids of the uploads that currently receive a chunk.
+    active: Arc<Mutex<HashSet<String>>>,
+}
+
+impl UploadService {
+    pub fn new(
+        staging: Operator,
+        file_service: FileService,
+        user_quota: UserQuota,
+        expiry: Duration,
+    ) -> Self {
+        Self {
+            staging: Arc::new(RwLock::new(staging)),
+            file_service,
+            user_quota,
+            expiry,
+            active: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn new_from_config(
+        config: &ConfigToml,
+        data_directory: &Path,
+        file_service: FileService,
+        user_quota: UserQuota,
+    ) -> Result<Self, FileIoError> {
+        let staging = build_base_operator(&config.storage, data_directory)?;
+        let expiry = Duration::from_secs(config.general.resumable_upload_expiry_s);
+        Ok(Self::new(staging, file_service, user_quota, expiry))
+    }
+
+    fn db(&self) -> &LmDB {
+        &self.file_service.db
+    }
+
+    /// The current staging operator.
+    pub(crate) fn staging(&self) -> Operator {
+        self.staging.read().expect("Staging lock poisoned").clone()
+    }
+
+    /// Replace the staging operator.
+    /// Should only be called while writes are paused. See [super::OpendalService::pause_writes].
+    pub(crate) fn set_staging(&self, operator: Operator) {
+        *self.staging.write().expect("Staging lock poisoned") = operator;
+    }
+
+    /// Start a new upload of `length` bytes to `path`.
+    pub fn create(
+        &self,
+        path: &EntryPath,
+        length: u64,
+        content_hash: Option<Hash>,
+    ) -> Result<Upload, UploadError> {
+        let id = encode(Alphabet::Crockford, &random_bytes::<16>());
+        let upload = Upload::new(id, path, length, content_hash);
+        self.db().put_upload(&upload, 0)?;
+        Ok(upload)
+    }
+
+    /// Get the upload with `id` to `path`.
+    pub fn get(&self, id: &str, path: &EntryPath) -> Result<Upload, UploadError> {
+        let upload = match self.db().get_upload(id) {
+            Ok(upload) => upload,
+            Err(FileIoError::NotFound) => return Err(UploadError::NotFound),
+            Err(e) => return Err(e.into()),
+        };
+        if upload.pubkey != *path.pubkey() || upload.path != path.path().as_str() {
+            return Err(UploadError::NotFound);
+        }
+        Ok(upload)
+    }
+
+    /// Stage the next chunk of the upload. `offset` must match the bytes received so far.
+    ///
+    /// An empty chunk is allowed and only checks the offset.
+    /// The upload is committed to the final file once all bytes are received.
+    /// Returns the updated upload.
+    pub async fn append(
+        &self,
+        id: &str,
+        path: &EntryPath,
+        offset: u64,
+        mut chunk: impl Stream<Item = Result<Bytes, WriteStreamError>> + Unpin + Send,
+    ) -> Result<Upload, UploadError> {
+        let _guard = self.lock(id)?;
+        // Read the upload after locking so we see the latest state.
+        let mut upload = self.get(id, path)?;
+        let upload_offset = upload.offset();
+        if offset != upload_offset {
+            return Err(UploadError::OffsetMismatch(upload_offset));
+        }
+
+        let used_bytes = self
+            .db()
+            .get_user_data_usage(&upload.pubkey)?
+            .ok_or(FileIoError::NotFound)?;
+        let chunk_path = staging_path(&upload.id, upload.chunks.len());
+        let generation = self.file_service.opendal.generation();
+        let staging = self.staging();
+        let mut writer = staging.writer(&chunk_path).await?;
+        let mut received = 0;
+        let write_result: Result<(), UploadError> = async {
+            while let Some(bytes) = chunk.next().await {
+                let bytes = bytes.map_err(FileIoError::from)?;
+                received += bytes.len() as u64;
+                if upload_offset + received > upload.length {
+                    return Err(UploadError::LengthExceeded(upload.length));
+                }
+                if used_bytes.saturating_add(received) > self.user_quota.bytes() {
+                    return Err(FileIoError::DiskSpaceQuotaExceeded.into());
+                }
+                writer.write(bytes).await?;
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = write_result {
+            writer.abort().await?;
+            return Err(e);
+        }
+
+        if received > 0 {
+            // Staged bytes count towards the user usage so they must not change while writes are paused.
+            let _write_guard = self.file_service.opendal.write_guard().await;
+            writer.close().await?;
+            if self.file_service.opendal.generation() != generation {
+                // The storage was migrated while the chunk was received.
+                copy_file(&staging, &self.staging(), &chunk_path).await?;
+            }
+            upload.chunks.push(received);
+            upload.updated_at = Timestamp::now().as_u64();
+            self.db().put_upload(&upload, received as i64)?;
+        } else {
+            writer.abort().await?;
+        }
+
+        if upload.is_complete() {
+            self.commit(&upload).await?;
+        }
+        Ok(upload)
+    }
+
+    /// Write the staged chunks to the final file.
+    async fn commit(&self, upload: &Upload) -> Result<Entry, UploadError> {
+        let path = upload.entry_path()?;
+        let staged_bytes = upload.offset() as i64;
+        let chunks = Box::pin(
+            stream::iter(0..upload.chunks.len())
+                .then(|index| {
+                    let staging = self.staging();
+                    let chunk_path = staging_path(&upload.id, index);
+                    async move {
+                        let reader = staging.reader_with(&chunk_path).chunk(CHUNK_SIZE).await?;
+                        reader.into_bytes_stream(..).await
+                    }
+                })
+                .map_err(std::io::Error::from)
+                .try_flatten()
+                .map_err(|e| WriteStreamError::Other(e.into())),
+        );
+
+        // The staged bytes are replaced by the final file.
+        self.update_user_usage(upload, -staged_bytes)?;
+        let result = match upload.content_hash() {
+            Some(expected) => {
+                let verified = ContentHashVerifier::new(chunks, expected);
+                self.file_service.write_stream(&path, verified).await
+            }
+            None => self.file_service.write_stream(&path, chunks).await,
+        };
+
+        match result {
+            Ok(entry) => {
+                self.db().delete_upload(upload, 0)?;
+                self.remove_staged(upload).await;
+                Ok(entry)
+            }
+            Err(FileIoError::StreamBroken(e @ WriteStreamError::ContentHashMismatch { .. })) => {
+                // The upload can't be completed anymore.
+                self.db().delete_upload(upload, 0)?;
+                self.remove_staged(upload).await;
+                Err(FileIoError::StreamBroken(e).into())
+            }
+            Err(e) => {
+                // Keep the upload so the commit can be retried.
+                self.update_user_usage(upload, staged_bytes)?;
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Delete all uploads that didn't receive a chunk within the expiry.
+    /// Returns the number of deleted uploads.
+    pub async fn delete_expired(&self) -> Result<usize, UploadError> {
+        if self.expiry.is_zero() {
+            return Ok(0);
+        }
+        let now = Timestamp::now().as_u64();
+        let expiry = self.expiry.as_micros() as u64;
+        let mut deleted = 0;
+        for upload in self.db().list_uploads()? {
+            if now.saturating_sub(upload.updated_at) <= expiry {
+                continue;
+            }
+            let Ok(_guard) = self.lock(&upload.id) else {
+                // Currently receiving a chunk so not abandoned.
+                continue;
+            };
+            tracing::debug!("Delete expired upload {}", upload.id);
+            self.delete(&upload).await?;
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// Delete all uploads of `pubkey`.
+    /// Returns the number of deleted uploads.
+    ///
+    /// Fails with [UploadError::Busy] if one of the uploads is currently receiving a chunk.
+    pub async fn delete_user_uploads(&self, pubkey: &PublicKey) -> Result<usize, UploadError> {
+        let mut deleted = 0;
+        for upload in self.db().list_uploads()? {
+            if &upload.pubkey != pubkey {
+                continue;
+            }
+            let _guard = self.lock(&upload.id)?;
+            self.delete(&upload).await?;
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+
+    async fn delete(&self, upload: &Upload) -> Result<(), UploadError> {
+        self.db().delete_upload(upload, -(upload.offset() as i64))?;
+        self.remove_staged(upload).await;
+        Ok(())
+    }
+
+    /// Remove the staged chunks. Failing is not fatal as the upload is already gone.
+    async fn remove_staged(&self, upload: &Upload) {
+        let dir = format!("{STAGING_DIR}{}/", upload.id);
+        let _write_guard = self.file_service.opendal.write_guard().await;
+        if let Err(e) = self.staging().remove_all(&dir).await {
+            tracing::warn!("Failed to remove staged chunks {}: {}", dir, e);
+        }
+    }
+
+    fn update_user_usage(&self, upload: &Upload, bytes_delta: i64) -> Result<(), UploadError> {
+        let mut wtxn = self.db().env.write_txn()?;
+        self.db()
+            .update_user_usage(&mut wtxn, &upload.pubkey, bytes_delta)?;
+        wtxn.commit()?;
+        Ok(())
+    }
+
+    /// Mark the upload as busy until the guard is dropped.
+    fn lock(&self, id: &str) -> Result<UploadGuard, UploadError> {
+        let mut active = self.active.lock().expect("Upload lock poisoned");
+        if !active.insert(id.to_string()) {
+            return Err(UploadError::Busy);
+        }
+        Ok(UploadGuard {
+            id: id.to_string(),
+            active: self.active.clone(),
+        })
+    }
+}
+
+/// Path of a staged chunk.
+fn staging_path(id: &str, index: usize) -> String {
+    format!("{STAGING_DIR}{id}/{index:08}")
+}
+
+/// Releases the upload when dropped.
+struct UploadGuard {
+    id: String,
+    active: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        if let Ok(mut active) = self.active.lock() {
+            active.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{shared::webdav::WebDavPath, AppContext};
+
+    use super::*;
+
+    fn chunk(data: &[u8]) -> impl Stream<Item = Result<Bytes, WriteStreamError>> + Unpin + Send {
+        stream::iter(vec![Ok(Bytes::copy_from_slice(data))])
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let context = AppContext::test();
+        let service = context.upload_service.clone();
+        let pubkey = pkarr::Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+        let path = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/big.bin").unwrap());
+        let data = b"hello resumable world";
+
+        let upload = service
+            .create(
+                &path,
+                data.len() as u64,
+                Some(pubky_common::crypto::hash(data)),
+            )
+            .unwrap();
+        let upload = service
+            .append(&upload.id, &path, 0, chunk(&data[..5]))
+            .await
+            .unwrap();
+        assert_eq!(upload.offset(), 5);
+        // Staged bytes count against the quota.
+        assert_eq!(context.db.get_user_data_usage(&pubkey).unwrap(), Some(5));
+
+        // Wrong offset
+        let result = service
+            .append(&upload.id, &path, 3, chunk(&data[3..]))
+            .await;
+        assert!(matches!(result, Err(UploadError::OffsetMismatch(5))));
+
+        // Too long
+        let result = service.append(&upload.id, &path, 5, chunk(&[0; 100])).await;
+        assert!(matches!(result, Err(UploadError::LengthExceeded(_))));
+
+        let upload = service
+            .append(&upload.id, &path, 5, chunk(&data[5..]))
+            .await
+            .unwrap();
+        assert!(upload.is_complete());
+        assert_eq!(
+            context.file_service.get(&path).await.unwrap().as_ref(),
+            data
+        );
+        assert!(matches!(
+            context.db.get_upload(&upload.id),
+            Err(FileIoError::NotFound)
+        ));
+        let events = context.db.list_events(None, None).unwrap();
+        assert_eq!(events.len(), 2, "Single event plus cursor");
+        let entry = context.db.get_entry(&path).unwrap();
+        assert_eq!(
+            context.db.get_user_data_usage(&pubkey).unwrap(),
+            Some(
+                entry.content_length() as u64 + super::super::user_quota_layer::FILE_METADATA_SIZE
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired_uploads() {
+        let context = AppContext::test();
+        let service = UploadService::new(
+            build_base_operator(&context.config_toml.storage, context.data_dir.path()).unwrap(),
+            context.file_service.clone(),
+            UserQuota::new(u64::MAX),
+            Duration::from_secs(60),
+        );
+        let pubkey = pkarr::Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+        let path = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/big.bin").unwrap());
+
+        let upload = service.create(&path, 10, None).unwrap();
+        let mut upload = service
+            .append(&upload.id, &path, 0, chunk(&[1; 4]))
+            .await
+            .unwrap();
+        assert_eq!(service.delete_expired().await.unwrap(), 0);
+
+        // Pretend the last chunk arrived two minutes ago.
+        upload.updated_at -= 120_000_000;
+        context.db.put_upload(&upload, 0).unwrap();
+        assert_eq!(service.delete_expired().await.unwrap(), 1);
+        assert!(matches!(
+            service.get(&upload.id, &path),
+            Err(UploadError::NotFound)
+        ));
+        assert_eq!(context.db.get_user_data_usage(&pubkey).unwrap(), Some(0));
+        assert!(!service
+            .staging()
+            .exists(&staging_path(&upload.id, 0))
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,454 @@
+//! Export and import of all files of a user as a tar archive.
+//!
+//! Used to move a user from one homeserver to another.
+//! The archive starts with a `manifest.json` that lists the metadata of all entries,
+//! followed by the files under `files/`, for example `files/pub/example.com/file.txt`.
+
+use std::{collections::HashMap, io, path::Path};
+
+use futures_util::StreamExt;
+use pkarr::PublicKey;
+use pubky_common::{crypto::Hash, timestamp::Timestamp};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_tar::{Archive, Builder, EntryType, Header};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::{
+    persistence::lmdb::{tables::entries::Entry, LmDB},
+    shared::webdav::{EntryPath, WebDavPath},
+};
+
+use super::{ContentHashVerifier, FileIoError, FileService, FileStream, WriteStreamError};
+
+/// Path of the manifest in the archive.
+pub const MANIFEST_PATH: &str = "manifest.json";
+/// Directory of the files in the archive.
+const FILES_DIR: &str = "files";
+const MANIFEST_VERSION: u8 = 1;
+/// Maximum size of the manifest when importing.
+const MAX_MANIFEST_SIZE: u64 = 64 * 1024 * 1024;
+/// Maximum size of an archive on top of the file content, for the manifest and the tar headers.
+const MAX_ARCHIVE_OVERHEAD: u64 = 4 * MAX_MANIFEST_SIZE;
+/// Buffer between the archive writer and the response stream.
+const PIPE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Lists the metadata of all entries in the archive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveManifest {
+    pub version: u8,
+    /// The user the archive was exported from.
+    pub pubkey: String,
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Metadata of a single entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Path of the file, for example `/pub/example.com/file.txt`.
+    pub path: String,
+    /// Last modification in microseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub content_type: String,
+    /// Hex encoded Blake3 hash of the content.
+    pub content_hash: String,
+    pub content_length: u64,
+}
+
+/// Errors that can occur when exporting or importing an archive.
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    /// The archive doesn't have the expected layout.
+    #[error("Invalid archive: {0}")]
+    Invalid(String),
+    #[error(transparent)]
+    FileIo(#[from] FileIoError),
+    /// Reading or writing the archive stream failed.
+    #[error("Archive stream error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// The result of an import.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ImportReport {
+    pub files_imported: usize,
+    /// Entries of the manifest without a file in the archive.
+    pub missing_files: Vec<String>,
+}
+
+/// Maximum size of an archive to import for a user with `user_quota` bytes.
+pub fn max_archive_size(user_quota: u64) -> u64 {
+    user_quota.saturating_add(MAX_ARCHIVE_OVERHEAD)
+}
+
+/// Exports and imports the files of a user as a tar archive.
+#[derive(Debug, Clone)]
+pub struct UserArchiver {
+    file_service: FileService,
+    /// Only files in this directory are exported and imported.
+    root: &'static str,
+}
+
+impl UserArchiver {
+    /// Archive all files of the user.
+    pub fn new(file_service: FileService) -> Self {
+        Self {
+            file_service,
+            root: "/",
+        }
+    }
+
+    /// Archive the files in `/pub/` only.
+    pub fn public(file_service: FileService) -> Self {
+        Self {
+            file_service,
+            root: "/pub/",
+        }
+    }
+
+    fn db(&self) -> &LmDB {
+        &self.file_service.db
+    }
+
+    /// Stream a tar archive of all files of `pubkey`.
+    ///
+    /// The archive is written while it is streamed. Files that change in the meantime
+    /// are skipped. If the export fails midway, the stream ends with an error.
+    pub fn export(&self, pubkey: &PublicKey) -> Result<FileStream, FileIoError> {
+        let manifest = self.manifest(pubkey)?;
+        let (writer, reader) = tokio::io::duplex(PIPE_BUFFER_SIZE);
+        let this = self.clone();
+        let pubkey = pubkey.clone();
+        let handle =
+            tokio::spawn(async move { this.write_archive(writer, &pubkey, manifest).await });
+
+        let result = futures_util::stream::once(async move {
+            match handle.await {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => {
+                    tracing::warn!("Export failed: {}", e);
+                    Some(Err(io::Error::other(e)))
+                }
+                Err(e) => Some(Err(io::Error::other(e))),
+            }
+        })
+        .filter_map(|result| async move { result });
+        Ok(Box::new(Box::pin(ReaderStream::new(reader).chain(result))))
+    }
+
+    fn manifest(&self, pubkey: &PublicKey) -> Result<ArchiveManifest, FileIoError> {
+        let rtxn = self.db().env.read_txn()?;
+        let prefix = format!("{pubkey}/");
+        let root = format!("{pubkey}{}", self.root);
+        let mut entries = vec![];
+        for result in self.db().tables.entries.prefix_iter(&rtxn, &root)? {
+            let (key, bytes) = result?;
+            let entry = Entry::deserialize(bytes)?;
+            entries.push(ManifestEntry {
+                // Keep the leading slash.
+                path: key[prefix.len() - 1..].to_string(),
+                timestamp: entry.timestamp().as_u64(),
+                content_type: entry.content_type().to_string(),
+                content_hash: entry.content_hash().to_hex().to_string(),
+                content_length: entry.content_length() as u64,
+            });
+        }
+        Ok(ArchiveManifest {
+            version: MANIFEST_VERSION,
+            pubkey: pubkey.to_string(),
+            entries,
+        })
+    }
+
+    async fn write_archive(
+        &self,
+        writer: impl AsyncWrite + Unpin + Send + 'static,
+        pubkey: &PublicKey,
+        manifest: ArchiveManifest,
+    ) -> Result<(), ArchiveError> {
+        let mut builder = Builder::new(writer);
+
+        let bytes = serde_json::to_vec_pretty(&manifest).map_err(io::Error::other)?;
+        let mut header = file_header(bytes.len() as u64, Timestamp::now().as_u64());
+        builder
+            .append_data(&mut header, MANIFEST_PATH, bytes.as_slice())
+            .await?;
+
+        for item in manifest.entries {
+            let path = WebDavPath::new(&item.path)
+                .map_err(|e| ArchiveError::Invalid(format!("{}: {}", item.path, e)))?;
+            let path = EntryPath::new(pubkey.clone(), path);
+            // Skip files that changed or have been deleted since the manifest was created.
+            match self.db().get_entry(&path) {
+                Ok(entry) if entry.content_hash().to_hex().as_str() == item.content_hash => {}
+                Ok(_) | Err(FileIoError::NotFound) => continue,
+                Err(e) => return Err(e.into()),
+            }
+            let stream = match self.file_service.get_stream(&path).await {
+                Ok(stream) => stream,
+                Err(FileIoError::NotFound) => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut reader = StreamReader::new(stream).take(item.content_length);
+            let mut header = file_header(item.content_length, item.timestamp);
+            builder
+                .append_data(
+                    &mut header,
+                    format!("{FILES_DIR}{}", item.path),
+                    &mut reader,
+                )
+                .await?;
+            if reader.limit() != 0 {
+                // The archive is corrupted at this point.
+                return Err(ArchiveError::Invalid(format!(
+                    "{} changed while it was exported",
+                    item.path
+                )));
+            }
+        }
+
+        builder.finish().await?;
+        builder.into_inner().await?.shutdown().await?;
+        Ok(())
+    }
+
+    /// Import an archive created by [UserArchiver::export] into the files of `pubkey`.
+    ///
+    /// Existing files with the same path are overwritten. Every file is verified against the
+    /// content hash in the manifest, and the timestamp and content type of the manifest are restored.
+    pub async fn import(
+        &self,
+        pubkey: &PublicKey,
+        reader: impl AsyncRead + Unpin + Send,
+    ) -> Result<ImportReport, ArchiveError> {
+        let mut archive = Archive::new(reader);
+        let mut files = archive.entries()?;
+
+        let mut first = files
+            .next()
+            .await
+            .ok_or(ArchiveError::Invalid("The archive is empty".to_string()))??;
+        if first.path()?.as_ref() != Path::new(MANIFEST_PATH) {
+            return Err(ArchiveError::Invalid(format!(
+                "The archive must start with {MANIFEST_PATH}"
+            )));
+        }
+        let mut bytes = vec![];
+        (&mut first)
+            .take(MAX_MANIFEST_SIZE)
+            .read_to_end(&mut bytes)
+            .await?;
+        let manifest: ArchiveManifest = serde_json::from_slice(&bytes)
+            .map_err(|e| ArchiveError::Invalid(format!("Invalid manifest: {e}")))?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(ArchiveError::Invalid(format!(
+                "Unsupported manifest version {}",
+                manifest.version
+            )));
+        }
+        let mut pending: HashMap<String, ManifestEntry> = manifest
+            .entries
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+
+        let mut report = ImportReport::default();
+        while let Some(file) = files.next().await {
+            let mut file = file?;
+            if !file.header().entry_type().is_file() {
+                continue;
+            }
+            let name = file.path()?.to_string_lossy().to_string();
+            let Some(path) = name.strip_prefix(FILES_DIR) else {
+                return Err(ArchiveError::Invalid(format!("Unexpected file {name}")));
+            };
+            let Some(item) = pending.remove(path) else {
+                return Err(ArchiveError::Invalid(format!(
+                    "{path} is not in the manifest"
+                )));
+            };
+            let webdav_path =
+                WebDavPath::new(path).map_err(|e| ArchiveError::Invalid(format!("{path}: {e}")))?;
+            if !webdav_path.as_str().starts_with(self.root) {
+                return Err(ArchiveError::Invalid(format!(
+                    "{path} is not in the {} directory",
+                    self.root
+                )));
+            }
+            let hash = Hash::from_hex(&item.content_hash)
+                .map_err(|_| ArchiveError::Invalid(format!("Invalid content hash of {path}")))?;
+
+            let entry_path = EntryPath::new(pubkey.clone(), webdav_path);
+            let stream = ReaderStream::new(&mut file)
+                .map(|chunk| chunk.map_err(|e| WriteStreamError::Other(e.into())));
+            self.file_service
+                .write_stream(&entry_path, ContentHashVerifier::new(stream, hash))
+                .await?;
+            self.restore_metadata(&entry_path, &item)?;
+            report.files_imported += 1;
+        }
+
+        report.missing_files = pending.into_keys().collect();
+        report.missing_files.sort();
+        Ok(report)
+    }
+
+    /// Restore the timestamp and content type of the manifest.
+    fn restore_metadata(&self, path: &EntryPath, item: &ManifestEntry) -> Result<(), FileIoError> {
+        let mut wtxn = self.db().env.write_txn()?;
+        let mut entry = match self.db().tables.entries.get(&wtxn, path.as_str())? {
+            Some(bytes) => Entry::deserialize(bytes)?,
+            None => return Ok(()),
+        };
+        entry.set_timestamp(&Timestamp::from(item.timestamp));
+        if !item.content_type.is_empty() {
+            entry.set_content_type(item.content_type.clone());
+        }
+        self.db()
+            .tables
+            .entries
+            .put(&mut wtxn, path.as_str(), &entry.serialize())?;
+        wtxn.commit()?;
+        Ok(())
+    }
+}
+
+/// Header of a regular file. `timestamp` is in microseconds.
+fn file_header(size: u64, timestamp: u64) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(timestamp / 1_000_000);
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures_util::TryStreamExt;
+    use opendal::Buffer;
+    use pkarr::Keypair;
+
+    use crate::AppContext;
+
+    use super::*;
+
+    async fn collect(stream: FileStream) -> Vec<u8> {
+        stream.try_collect::<Vec<Bytes>>().await.unwrap().concat()
+    }
+
+    #[tokio::test]
+    async fn test_export_import() {
+        let source = AppContext::test();
+        let pubkey = Keypair::random().public_key();
+        source.db.create_user(&pubkey).unwrap();
+        let files = [
+            ("/pub/a.txt", b"hello".to_vec()),
+            ("/pub/dir/b.json", b"{}".to_vec()),
+            ("/pub/empty", vec![]),
+            ("/priv/secret.txt", b"secret".to_vec()),
+        ];
+        for (path, content) in &files {
+            let path = EntryPath::new(pubkey.clone(), WebDavPath::new(path).unwrap());
+            source
+                .file_service
+                .write(&path, Buffer::from(content.clone()))
+                .await
+                .unwrap();
+        }
+        let a = EntryPath::new(pubkey.clone(), WebDavPath::new("/pub/a.txt").unwrap());
+        let original = source.db.get_entry(&a).unwrap();
+
+        let archive = collect(
+            UserArchiver::new(source.file_service.clone())
+                .export(&pubkey)
+                .unwrap(),
+        )
+        .await;
+
+        // Import on another homeserver
+        let target = AppContext::test();
+        target.db.create_user(&pubkey).unwrap();
+        let report = UserArchiver::new(target.file_service.clone())
+            .import(&pubkey, archive.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(report.files_imported, 4);
+        assert!(report.missing_files.is_empty());
+
+        for (path, content) in &files {
+            let path = EntryPath::new(pubkey.clone(), WebDavPath::new(path).unwrap());
+            let imported = target.file_service.get(&path).await.unwrap();
+            assert_eq!(imported.as_ref(), content.as_slice());
+        }
+        let imported = target.db.get_entry(&a).unwrap();
+        assert_eq!(imported.timestamp(), original.timestamp());
+        assert_eq!(imported.content_type(), original.content_type());
+        assert_eq!(imported.content_hash(), original.content_hash());
+
+        // The public archive doesn't contain the private files and can't import them.
+        let public_archive = collect(
+            UserArchiver::public(source.file_service.clone())
+                .export(&pubkey)
+                .unwrap(),
+        )
+        .await;
+        let report = UserArchiver::public(target.file_service.clone())
+            .import(&pubkey, public_archive.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(report.files_imported, 3);
+        assert!(matches!(
+            UserArchiver::public(target.file_service.clone())
+                .import(&pubkey, archive.as_slice())
+                .await,
+            Err(ArchiveError::Invalid(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_hash_mismatch() {
+        let context = AppContext::test();
+        let pubkey = Keypair::random().public_key();
+        context.db.create_user(&pubkey).unwrap();
+
+        let manifest = ArchiveManifest {
+            version: MANIFEST_VERSION,
+            pubkey: pubkey.to_string(),
+            entries: vec![ManifestEntry {
+                path: "/pub/a.txt".to_string(),
+                timestamp: 0,
+                content_type: "text/plain".to_string(),
+                content_hash: pubky_common::crypto::hash(b"other").to_hex().to_string(),
+                content_length: 5,
+            }],
+        };
+        let mut builder = Builder::new(vec![]);
+        let bytes = serde_json::to_vec(&manifest).unwrap();
+        let mut header = file_header(bytes.len() as u64, 0);
+        builder
+            .append_data(&mut header, MANIFEST_PATH, bytes.as_slice())
+            .await
+            .unwrap();
+        let mut header = file_header(5, 0);
+        builder
+            .append_data(&mut header, "files/pub/a.txt", &b"hello"[..])
+            .await
+            .unwrap();
+        let archive = builder.into_inner().await.unwrap();
+
+        let result = UserArchiver::new(context.file_service.clone())
+            .import(&pubkey, archive.as_slice())
+            .await;
+        assert!(matches!(
+            result,
+            Err(ArchiveError::FileIo(FileIoError::StreamBroken(
+                WriteStreamError::ContentHashMismatch { .. }
+            )))
+        ));
+        let path = EntryPath::new(pubkey, WebDavPath::new("/pub/a.txt").unwrap());
+        assert!(context.db.get_entry(&path).is_err());
+    }
+}
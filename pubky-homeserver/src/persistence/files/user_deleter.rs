@@ -0,0 +1,248 @@
+//! Deletes a user together with all their data.
+//!
+//! The user is disabled first so no new data is written while the data is purged.
+//! The user record is deleted last. An interrupted deletion leaves a disabled user
+//! behind and continues where it left off when it is run again.
+
+use std::{fmt, str::FromStr};
+
+use pkarr::PublicKey;
+use serde::Serialize;
+
+use crate::{persistence::lmdb::LmDB, shared::webdav::EntryPath, AppContext};
+
+use super::{entry_service::EntryService, FileIoError, FileService, UploadError, UploadService};
+
+/// Number of entries read from the database at once.
+const BATCH_SIZE: usize = 100;
+
+/// Errors that can occur when deleting a user.
+#[derive(Debug, thiserror::Error)]
+pub enum UserDeletionError {
+    #[error(transparent)]
+    FileIo(#[from] FileIoError),
+    #[error(transparent)]
+    Upload(#[from] UploadError),
+    #[error(transparent)]
+    Db(#[from] anyhow::Error),
+}
+
+impl From<heed::Error> for UserDeletionError {
+    fn from(e: heed::Error) -> Self {
+        Self::FileIo(e.into())
+    }
+}
+
+/// The result of a user deletion.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct UserDeletionReport {
+    /// Whether the user record existed and has been deleted.
+    pub user_deleted: bool,
+    pub sessions_deleted: usize,
+    pub uploads_deleted: usize,
+    pub files_deleted: usize,
+    pub events_deleted: usize,
+}
+
+impl UserDeletionReport {
+    /// Whether anything of the user was found.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for UserDeletionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Deleted {} files, {} uploads, {} sessions and {} events.",
+            self.files_deleted, self.uploads_deleted, self.sessions_deleted, self.events_deleted
+        )
+    }
+}
+
+/// Deletes users with all their files, sessions and `PUT` events.
+#[derive(Debug, Clone)]
+pub struct UserDeleter {
+    file_service: FileService,
+    upload_service: UploadService,
+}
+
+impl UserDeleter {
+    pub(crate) fn new(file_service: FileService, upload_service: UploadService) -> Self {
+        Self {
+            file_service,
+            upload_service,
+        }
+    }
+
+    /// Create a new deleter from the app context.
+    pub fn from_context(context: &AppContext) -> Self {
+        Self::new(context.file_service.clone(), context.upload_service.clone())
+    }
+
+    fn db(&self) -> &LmDB {
+        &self.file_service.db
+    }
+
+    /// Delete the user and all their data.
+    ///
+    /// Deleting a user that doesn't exist anymore is a no-op and returns an empty report.
+    pub async fn delete(
+        &self,
+        pubkey: &PublicKey,
+    ) -> Result<UserDeletionReport, UserDeletionError> {
+        let mut report = UserDeletionReport::default();
+
+        // Block new writes and sign-ins while purging.
+        {
+            let mut wtxn = self.db().env.write_txn()?;
+            if self.db().get_user(pubkey, &wtxn)?.is_some() {
+                self.db()
+                    .disable_user(pubkey, &mut wtxn)
+                    .map_err(anyhow::Error::from)?;
+            }
+            wtxn.commit()?;
+        }
+
+        report.sessions_deleted = self.db().delete_user_sessions(pubkey)?;
+        report.uploads_deleted = self.upload_service.delete_user_uploads(pubkey).await?;
+        report.files_deleted = self.delete_files(pubkey).await?;
+        // Delete the events last. Deleting the files creates the DEL events that are kept.
+        report.events_deleted = self.db().delete_user_events(pubkey)?;
+        report.user_deleted = self.db().delete_user(pubkey)?;
+
+        if !report.is_empty() {
+            tracing::info!("Deleted user {}. {}", pubkey, report);
+        }
+        Ok(report)
+    }
+
+    /// Delete all files of the user. Returns the number of deleted files.
+    async fn delete_files(&self, pubkey: &PublicKey) -> Result<usize, UserDeletionError> {
+        let entry_service = EntryService::new(self.db().clone());
+        let mut deleted = 0;
+        loop {
+            let keys = self.next_keys(pubkey)?;
+            if keys.is_empty() {
+                break;
+            }
+            for key in keys {
+                let path = EntryPath::from_str(&key).map_err(anyhow::Error::from)?;
+                match self.file_service.delete(&path).await {
+                    Ok(()) => deleted += 1,
+                    // The file is missing in the storage. Delete the dangling entry.
+                    Err(FileIoError::NotFound) => match entry_service.delete_entry(&path) {
+                        Ok(()) | Err(FileIoError::NotFound) => {}
+                        Err(e) => return Err(e.into()),
+                    },
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        Ok(deleted)
+    }
+
+    fn next_keys(&self, pubkey: &PublicKey) -> Result<Vec<String>, UserDeletionError> {
+        let rtxn = self.db().env.read_txn()?;
+        let prefix = format!("{pubkey}/");
+        let keys = self
+            .db()
+            .tables
+            .entries
+            .prefix_iter(&rtxn, &prefix)?
+            .take(BATCH_SIZE)
+            .map(|result| result.map(|(key, _)| key.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opendal::Buffer;
+    use pkarr::Keypair;
+    use pubky_common::{session::Session, timestamp::Timestamp};
+
+    use crate::{persistence::files::FileMetadataBuilder, shared::webdav::WebDavPath};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_delete_user() {
+        let context = AppContext::test();
+        let deleter = UserDeleter::from_context(&context);
+        let db = context.db.clone();
+        let pubkey = Keypair::random().public_key();
+        let other = Keypair::random().public_key();
+        db.create_user(&pubkey).unwrap();
+        db.create_user(&other).unwrap();
+
+        for (owner, path) in [
+            (&pubkey, "/pub/a.txt"),
+            (&pubkey, "/pub/dir/b.txt"),
+            (&other, "/pub/c.txt"),
+        ] {
+            let path = EntryPath::new(owner.clone(), WebDavPath::new(path).unwrap());
+            context
+                .file_service
+                .write(&path, Buffer::from(vec![1u8; 8]))
+                .await
+                .unwrap();
+        }
+        {
+            let mut wtxn = db.env.write_txn().unwrap();
+            let session = Session::new(&pubkey, &[], None);
+            db.put_session(&mut wtxn, "secret", &session).unwrap();
+            wtxn.commit().unwrap();
+        }
+
+        // Dangling entry left behind by an interrupted deletion: The entry exists but the file doesn't.
+        let dangling = EntryPath::new(
+            pubkey.clone(),
+            WebDavPath::new("/pub/dangling.txt").unwrap(),
+        );
+        let mut metadata = FileMetadataBuilder::default();
+        metadata.update(&[0u8; 30]);
+        let mut metadata = metadata.finalize();
+        metadata.modified_at = Timestamp::now();
+        EntryService::new(db.clone())
+            .write_entry(&dangling, &metadata)
+            .unwrap();
+
+        let report = deleter.delete(&pubkey).await.unwrap();
+        assert!(report.user_deleted);
+        assert_eq!(report.sessions_deleted, 1);
+        assert_eq!(report.files_deleted, 2);
+        // The three PUT events. The DEL events of the purge are kept as tombstones.
+        assert_eq!(report.events_deleted, 3);
+
+        let rtxn = db.env.read_txn().unwrap();
+        assert!(db.get_user(&pubkey, &rtxn).unwrap().is_none());
+        assert!(db.get_user(&other, &rtxn).unwrap().is_some());
+        assert_eq!(db.tables.sessions.len(&rtxn).unwrap(), 0);
+        assert_eq!(
+            db.tables
+                .entries
+                .prefix_iter(&rtxn, &format!("{pubkey}/"))
+                .unwrap()
+                .count(),
+            0
+        );
+        drop(rtxn);
+        let events = db.list_events(None, None).unwrap();
+        let user_events = events
+            .iter()
+            .filter(|event| event.contains(&pubkey.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(user_events.len(), 3);
+        assert!(user_events.iter().all(|event| event.starts_with("DEL ")));
+        assert!(events
+            .iter()
+            .any(|event| event.contains(&other.to_string())));
+
+        // Idempotent
+        let report = deleter.delete(&pubkey).await.unwrap();
+        assert!(report.is_empty());
+    }
+}
@@ -1,8 +1,10 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use crate::persistence::lmdb::LmDB;
 use crate::shared::webdav::EntryPath;
+use crate::ConfigToml;
 use opendal::raw::*;
 use opendal::Result;
 
@@ -11,6 +13,48 @@ use opendal::Result;
 /// This prevents the user from writing zero byte files that don't count against the quota.
 pub(crate) const FILE_METADATA_SIZE: u64 = 256;
 
+/// The maximum amount of bytes that a user can store in the homeserver.
+///
+/// Shared by all components so a config reload applies everywhere at once.
+#[derive(Debug, Clone)]
+pub struct UserQuota(Arc<AtomicU64>);
+
+impl UserQuota {
+    /// `u64::MAX` means unlimited.
+    pub fn new(bytes: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(bytes)))
+    }
+
+    pub fn from_config(config: &ConfigToml) -> Self {
+        Self::new(Self::mb_to_bytes(config.general.user_storage_quota_mb))
+    }
+
+    /// Set the quota in megabytes. `0` means unlimited.
+    pub fn set_mb(&self, mb: u64) {
+        self.0.store(Self::mb_to_bytes(mb), Ordering::Relaxed);
+    }
+
+    /// The quota in bytes. `u64::MAX` if unlimited.
+    pub fn bytes(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// The quota in bytes or `None` if unlimited.
+    pub fn limit(&self) -> Option<u64> {
+        match self.bytes() {
+            u64::MAX => None,
+            bytes => Some(bytes),
+        }
+    }
+
+    fn mb_to_bytes(mb: u64) -> u64 {
+        match mb {
+            0 => u64::MAX,
+            mb => mb.saturating_mul(1024 * 1024),
+        }
+    }
+}
+
 /// The user quota layer is a layer that wraps the operator and updates the user quota when a file is written or deleted.
 /// It is used to limit the amount of data that a user can store in the homeserver.
 /// It will also enforce that only paths in the form of {pubkey}/{path} are allowed.
@@ -18,15 +62,12 @@ pub(crate) const FILE_METADATA_SIZE: u64 = 256;
 pub struct UserQuotaLayer {
     pub(crate) db: LmDB,
     /// The maximum amount of bytes that a user can store in the homeserver.
-    pub(crate) user_quota_bytes: u64,
+    pub(crate) user_quota: UserQuota,
 }
 
 impl UserQuotaLayer {
-    pub fn new(db: LmDB, user_quota_bytes: u64) -> Self {
-        Self {
-            db,
-            user_quota_bytes,
-        }
+    pub fn new(db: LmDB, user_quota: UserQuota) -> Self {
+        Self { db, user_quota }
     }
 }
 
@@ -37,7 +78,7 @@ impl<A: Access> Layer<A> for UserQuotaLayer {
         UserQuotaAccessor {
             inner: Arc::new(inner),
             db: self.db.clone(),
-            user_quota_bytes: self.user_quota_bytes,
+            user_quota: self.user_quota.clone(),
         }
     }
 }
@@ -62,7 +103,7 @@ fn ensure_valid_path(path: &str) -> Result<EntryPath, opendal::Error> {
 pub struct UserQuotaAccessor<A: Access> {
     inner: Arc<A>,
     db: LmDB,
-    user_quota_bytes: u64,
+    user_quota: UserQuota,
 }
 
 impl<A: Access> LayeredAccess for UserQuotaAccessor<A> {
@@ -96,7 +137,7 @@ impl<A: Access> LayeredAccess for UserQuotaAccessor<A> {
                 bytes_count: 0,
                 entry_path,
                 inner_accessor: self.inner.clone(),
-                user_quota_bytes: self.user_quota_bytes,
+                user_quota_bytes: self.user_quota.bytes(),
             },
         ))
     }
@@ -389,7 +430,7 @@ mod tests {
     async fn test_ensure_valid_path() {
         for (_scheme, operator) in OpendalTestOperators::new().operators() {
             let db = LmDB::test();
-            let layer = UserQuotaLayer::new(db.clone(), 1024 * 1024);
+            let layer = UserQuotaLayer::new(db.clone(), UserQuota::new(1024 * 1024));
             let operator = operator.layer(layer);
 
             operator
@@ -412,7 +453,7 @@ mod tests {
     #[tokio::test]
     async fn test_quota_updated_write_delete() {
         let db = LmDB::test();
-        let layer = UserQuotaLayer::new(db.clone(), 1024 * 1024);
+        let layer = UserQuotaLayer::new(db.clone(), UserQuota::new(1024 * 1024));
         let operator = get_memory_operator().layer(layer);
 
         let user_pubkey1 = pkarr::Keypair::random().public_key();
@@ -462,7 +503,7 @@ mod tests {
     #[tokio::test]
     async fn test_quota_rechead() {
         let db = LmDB::test();
-        let layer = UserQuotaLayer::new(db.clone(), 20 + FILE_METADATA_SIZE);
+        let layer = UserQuotaLayer::new(db.clone(), UserQuota::new(20 + FILE_METADATA_SIZE));
         let operator = get_memory_operator().layer(layer);
 
         let user_pubkey1 = pkarr::Keypair::random().public_key();
@@ -0,0 +1,9 @@
+use heed::{Env, RwTxn};
+
+use crate::persistence::lmdb::tables::uploads;
+
+/// Adds the `uploads` table for resumable uploads.
+pub fn run(env: &Env, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    let _: uploads::UploadsTable = env.create_database(wtxn, Some(uploads::UPLOADS_TABLE))?;
+    Ok(())
+}
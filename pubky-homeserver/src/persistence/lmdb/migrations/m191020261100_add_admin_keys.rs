@@ -0,0 +1,10 @@
+use heed::{Env, RwTxn};
+
+use crate::persistence::lmdb::tables::admin_keys;
+
+/// Adds the `admin_keys` table for scoped admin API keys.
+pub fn run(env: &Env, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    let _: admin_keys::AdminKeysTable =
+        env.create_database(wtxn, Some(admin_keys::ADMIN_KEYS_TABLE))?;
+    Ok(())
+}
@@ -0,0 +1,10 @@
+use heed::{Env, RwTxn};
+
+use crate::persistence::lmdb::tables::audit_log;
+
+/// Adds the `audit_log` table for privileged admin operations.
+pub fn run(env: &Env, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    let _: audit_log::AuditLogTable =
+        env.create_database(wtxn, Some(audit_log::AUDIT_LOG_TABLE))?;
+    Ok(())
+}
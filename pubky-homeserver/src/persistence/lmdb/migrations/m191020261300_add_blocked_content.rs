@@ -0,0 +1,10 @@
+use heed::{Env, RwTxn};
+
+use crate::persistence::lmdb::tables::blocked_content;
+
+/// Adds the `blocked_content` table for the content blocklist.
+pub fn run(env: &Env, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    let _: blocked_content::BlockedContentTable =
+        env.create_database(wtxn, Some(blocked_content::BLOCKED_CONTENT_TABLE))?;
+    Ok(())
+}
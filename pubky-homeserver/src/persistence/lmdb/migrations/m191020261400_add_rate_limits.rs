@@ -0,0 +1,10 @@
+use heed::{Env, RwTxn};
+
+use crate::persistence::lmdb::tables::rate_limits;
+
+/// Adds the `rate_limits` table for the persistent rate limiter state.
+pub fn run(env: &Env, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    let _: rate_limits::RateLimitsTable =
+        env.create_database(wtxn, Some(rate_limits::RATE_LIMITS_TABLE))?;
+    Ok(())
+}
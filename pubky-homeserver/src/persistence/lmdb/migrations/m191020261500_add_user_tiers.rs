@@ -0,0 +1,10 @@
+use heed::{Env, RwTxn};
+
+use crate::persistence::lmdb::tables::user_tiers;
+
+/// Adds the `user_tiers` table for the rate limit tiers of users.
+pub fn run(env: &Env, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    let _: user_tiers::UserTiersTable =
+        env.create_database(wtxn, Some(user_tiers::USER_TIERS_TABLE))?;
+    Ok(())
+}
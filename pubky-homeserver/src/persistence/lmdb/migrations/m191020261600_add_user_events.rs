@@ -0,0 +1,87 @@
+use heed::{Env, RwTxn};
+
+use crate::persistence::lmdb::tables::events::{self, Event};
+
+/// Adds the `user_events` index and fills it with the existing events.
+pub fn run(env: &Env, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    let existing: Option<events::UserEventsTable> =
+        env.open_database(wtxn, Some(events::USER_EVENTS_TABLE))?;
+    if existing.is_some() {
+        return Ok(());
+    }
+    let user_events: events::UserEventsTable =
+        env.create_database(wtxn, Some(events::USER_EVENTS_TABLE))?;
+    let events_table: events::EventsTable = env
+        .open_database(wtxn, Some(events::EVENTS_TABLE))?
+        .expect("Events table already created");
+
+    let mut keys = vec![];
+    for item in events_table.iter(wtxn)? {
+        let (key, bytes) = item?;
+        if let Some(user_key) = events::user_event_key(key, &Event::deserialize(bytes)?) {
+            keys.push(user_key);
+        }
+    }
+    for key in keys {
+        user_events.put(wtxn, &key, &())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use heed::EnvOpenOptions;
+    use pkarr::Keypair;
+
+    use crate::persistence::lmdb::{db::DEFAULT_MAP_SIZE, migrations::m0};
+
+    use super::*;
+
+    #[test]
+    fn test_index_existing_events() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .max_dbs(20)
+                .map_size(DEFAULT_MAP_SIZE)
+                .open(tmp_dir.path())
+        }
+        .unwrap();
+        let mut wtxn = env.write_txn().unwrap();
+        m0::run(&env, &mut wtxn).unwrap();
+
+        let pubkey = Keypair::random().public_key();
+        let events_table: events::EventsTable = env
+            .open_database(&wtxn, Some(events::EVENTS_TABLE))
+            .unwrap()
+            .unwrap();
+        let url = format!("pubky://{pubkey}/pub/a.txt");
+        events_table
+            .put(&mut wtxn, "0000000000001", &Event::put(&url).serialize())
+            .unwrap();
+        events_table
+            .put(&mut wtxn, "0000000000002", &Event::delete(&url).serialize())
+            .unwrap();
+
+        run(&env, &mut wtxn).unwrap();
+        // Running it again is a no-op.
+        run(&env, &mut wtxn).unwrap();
+
+        let user_events: events::UserEventsTable = env
+            .open_database(&wtxn, Some(events::USER_EVENTS_TABLE))
+            .unwrap()
+            .unwrap();
+        let keys = user_events
+            .iter(&wtxn)
+            .unwrap()
+            .map(|item| item.unwrap().0.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                format!("{pubkey}/0000000000001"),
+                format!("{pubkey}/0000000000002")
+            ]
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use heed::{Env, RwTxn};
+use pubky_common::session::Session;
+
+use crate::persistence::lmdb::tables::sessions;
+
+/// Adds the `user_sessions` index and fills it with the existing sessions.
+pub fn run(env: &Env, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    let existing: Option<sessions::UserSessionsTable> =
+        env.open_database(wtxn, Some(sessions::USER_SESSIONS_TABLE))?;
+    if existing.is_some() {
+        return Ok(());
+    }
+    let user_sessions: sessions::UserSessionsTable =
+        env.create_database(wtxn, Some(sessions::USER_SESSIONS_TABLE))?;
+    let sessions_table: sessions::SessionsTable = env
+        .open_database(wtxn, Some(sessions::SESSIONS_TABLE))?
+        .expect("Sessions table already created");
+
+    let mut keys = vec![];
+    for item in sessions_table.iter(wtxn)? {
+        let (secret, bytes) = item?;
+        let session = Session::deserialize(bytes)?;
+        keys.push(sessions::user_session_key(session.pubky(), secret));
+    }
+    for key in keys {
+        user_sessions.put(wtxn, &key, &())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use heed::EnvOpenOptions;
+    use pkarr::Keypair;
+
+    use crate::persistence::lmdb::{db::DEFAULT_MAP_SIZE, migrations::m0};
+
+    use super::*;
+
+    #[test]
+    fn test_index_existing_sessions() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .max_dbs(20)
+                .map_size(DEFAULT_MAP_SIZE)
+                .open(tmp_dir.path())
+        }
+        .unwrap();
+        let mut wtxn = env.write_txn().unwrap();
+        m0::run(&env, &mut wtxn).unwrap();
+
+        let pubkey = Keypair::random().public_key();
+        let sessions_table: sessions::SessionsTable = env
+            .open_database(&wtxn, Some(sessions::SESSIONS_TABLE))
+            .unwrap()
+            .unwrap();
+        let session = Session::new(&pubkey, &[], None).serialize();
+        sessions_table.put(&mut wtxn, "secret1", &session).unwrap();
+        sessions_table.put(&mut wtxn, "secret2", &session).unwrap();
+
+        run(&env, &mut wtxn).unwrap();
+        // Running it again is a no-op.
+        run(&env, &mut wtxn).unwrap();
+
+        let user_sessions: sessions::UserSessionsTable = env
+            .open_database(&wtxn, Some(sessions::USER_SESSIONS_TABLE))
+            .unwrap()
+            .unwrap();
+        let keys = user_sessions
+            .iter(&wtxn)
+            .unwrap()
+            .map(|item| item.unwrap().0.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![format!("{pubkey}/secret1"), format!("{pubkey}/secret2")]
+        );
+    }
+}
@@ -2,6 +2,14 @@ use heed::Env;
 
 mod m0;
 
+mod m191020261000_add_uploads;
+mod m191020261100_add_admin_keys;
+mod m191020261200_add_audit_log;
+mod m191020261300_add_blocked_content;
+mod m191020261400_add_rate_limits;
+mod m191020261500_add_user_tiers;
+mod m191020261600_add_user_events;
+mod m191020261700_add_user_sessions;
 mod m220420251247_add_user_disabled_used_bytes;
 
 /// Run the migrations.
@@ -10,6 +18,14 @@ pub fn run(env: &Env) -> anyhow::Result<()> {
 
     m0::run(env, &mut wtxn)?;
     m220420251247_add_user_disabled_used_bytes::run(env, &mut wtxn)?;
+    m191020261000_add_uploads::run(env, &mut wtxn)?;
+    m191020261100_add_admin_keys::run(env, &mut wtxn)?;
+    m191020261200_add_audit_log::run(env, &mut wtxn)?;
+    m191020261300_add_blocked_content::run(env, &mut wtxn)?;
+    m191020261400_add_rate_limits::run(env, &mut wtxn)?;
+    m191020261500_add_user_tiers::run(env, &mut wtxn)?;
+    m191020261600_add_user_events::run(env, &mut wtxn)?;
+    m191020261700_add_user_sessions::run(env, &mut wtxn)?;
     wtxn.commit()?;
 
     Ok(())
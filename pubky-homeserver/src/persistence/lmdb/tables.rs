@@ -1,27 +1,47 @@
+pub mod admin_keys;
+pub mod audit_log;
+pub mod blocked_content;
 pub mod entries;
 pub mod events;
+pub mod rate_limits;
 pub mod sessions;
 pub mod signup_tokens;
+pub mod uploads;
+pub mod user_tiers;
 pub mod users;
 use heed::{Env, RwTxn};
 
 use self::{
+    admin_keys::{AdminKeysTable, ADMIN_KEYS_TABLE},
+    audit_log::{AuditLogTable, AUDIT_LOG_TABLE},
+    blocked_content::{BlockedContentTable, BLOCKED_CONTENT_TABLE},
     entries::{EntriesTable, ENTRIES_TABLE},
-    events::{EventsTable, EVENTS_TABLE},
-    sessions::{SessionsTable, SESSIONS_TABLE},
+    events::{EventsTable, UserEventsTable, EVENTS_TABLE, USER_EVENTS_TABLE},
+    rate_limits::{RateLimitsTable, RATE_LIMITS_TABLE},
+    sessions::{SessionsTable, UserSessionsTable, SESSIONS_TABLE, USER_SESSIONS_TABLE},
     signup_tokens::{SignupTokensTable, SIGNUP_TOKENS_TABLE},
+    uploads::{UploadsTable, UPLOADS_TABLE},
+    user_tiers::{UserTiersTable, USER_TIERS_TABLE},
     users::{UsersTable, USERS_TABLE},
 };
 
-pub const TABLES_COUNT: u32 = 6;
+pub const TABLES_COUNT: u32 = 14;
 
 #[derive(Debug, Clone)]
 pub struct Tables {
     pub users: UsersTable,
     pub sessions: SessionsTable,
+    pub user_sessions: UserSessionsTable,
     pub entries: EntriesTable,
     pub events: EventsTable,
+    pub user_events: UserEventsTable,
     pub signup_tokens: SignupTokensTable,
+    pub uploads: UploadsTable,
+    pub admin_keys: AdminKeysTable,
+    pub audit_log: AuditLogTable,
+    pub blocked_content: BlockedContentTable,
+    pub rate_limits: RateLimitsTable,
+    pub user_tiers: UserTiersTable,
 }
 
 impl Tables {
@@ -33,15 +53,39 @@ impl Tables {
             sessions: env
                 .open_database(wtxn, Some(SESSIONS_TABLE))?
                 .expect("Sessions table already created"),
+            user_sessions: env
+                .open_database(wtxn, Some(USER_SESSIONS_TABLE))?
+                .expect("User sessions table already created"),
             entries: env
                 .open_database(wtxn, Some(ENTRIES_TABLE))?
                 .expect("Entries table already created"),
             events: env
                 .open_database(wtxn, Some(EVENTS_TABLE))?
                 .expect("Events table already created"),
+            user_events: env
+                .open_database(wtxn, Some(USER_EVENTS_TABLE))?
+                .expect("User events table already created"),
             signup_tokens: env
                 .open_database(wtxn, Some(SIGNUP_TOKENS_TABLE))?
                 .expect("Signup tokens table already created"),
+            uploads: env
+                .open_database(wtxn, Some(UPLOADS_TABLE))?
+                .expect("Uploads table already created"),
+            admin_keys: env
+                .open_database(wtxn, Some(ADMIN_KEYS_TABLE))?
+                .expect("Admin keys table already created"),
+            audit_log: env
+                .open_database(wtxn, Some(AUDIT_LOG_TABLE))?
+                .expect("Audit log table already created"),
+            blocked_content: env
+                .open_database(wtxn, Some(BLOCKED_CONTENT_TABLE))?
+                .expect("Blocked content table already created"),
+            rate_limits: env
+                .open_database(wtxn, Some(RATE_LIMITS_TABLE))?
+                .expect("Rate limits table already created"),
+            user_tiers: env
+                .open_database(wtxn, Some(USER_TIERS_TABLE))?
+                .expect("User tiers table already created"),
         })
     }
 }
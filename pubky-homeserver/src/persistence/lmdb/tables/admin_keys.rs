@@ -0,0 +1,201 @@
+use super::super::LmDB;
+use base32::{encode, Alphabet};
+use heed::{
+    types::{Bytes, Str},
+    Database,
+};
+use postcard::{from_bytes, to_allocvec};
+use pubky_common::{
+    crypto::{hash, random_bytes, Hash},
+    timestamp::Timestamp,
+};
+use serde::{Deserialize, Serialize};
+
+/// Admin key id => AdminKey.
+pub type AdminKeysTable = Database<Str, Bytes>;
+
+pub const ADMIN_KEYS_TABLE: &str = "admin_keys";
+
+/// Avoid a write transaction on every request by updating `last_used_at` only this often.
+const LAST_USED_RESOLUTION_S: u64 = 60;
+
+/// What an admin key is allowed to do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminScope {
+    /// Read-only statistics about the homeserver and its users.
+    Stats,
+    /// Disable, enable, delete, export and import users.
+    Users,
+    /// Generate signup tokens.
+    SignupTokens,
+    /// Access all files via WebDAV.
+    Webdav,
+    /// Reconcile, scrub and migrate the storage.
+    Storage,
+    /// Manage the content blocklist.
+    Moderation,
+}
+
+/// A named API key for the admin server.
+///
+/// Only the hash of the secret is stored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AdminKey {
+    pub id: String,
+    pub name: String,
+    pub secret_hash: [u8; 32],
+    pub scopes: Vec<AdminScope>,
+    /// Timestamp in microseconds.
+    pub created_at: u64,
+    /// Timestamp in microseconds after which the key is rejected.
+    pub expires_at: Option<u64>,
+    /// Timestamp in microseconds of the last successful authentication.
+    pub last_used_at: Option<u64>,
+}
+
+impl AdminKey {
+    pub fn serialize(&self) -> Vec<u8> {
+        to_allocvec(self).expect("serialize admin key")
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> core::result::Result<Self, postcard::Error> {
+        from_bytes(bytes)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| Timestamp::now().as_u64() >= expires_at)
+    }
+
+    pub fn has_scope(&self, scope: AdminScope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+/// Split a `{id}.{secret}` token.
+fn split_token(token: &str) -> Option<(&str, &str)> {
+    token.split_once('.')
+}
+
+impl LmDB {
+    /// Create a new admin key.
+    ///
+    /// Returns the key and the token to authenticate with. The token can't be recovered later.
+    pub fn create_admin_key(
+        &self,
+        name: &str,
+        scopes: Vec<AdminScope>,
+        expires_at: Option<u64>,
+    ) -> anyhow::Result<(AdminKey, String)> {
+        let id = encode(Alphabet::Crockford, &random_bytes::<5>()).to_lowercase();
+        let secret = encode(Alphabet::Crockford, &random_bytes::<32>()).to_lowercase();
+        let key = AdminKey {
+            id: id.clone(),
+            name: name.to_string(),
+            secret_hash: *hash(secret.as_bytes()).as_bytes(),
+            scopes,
+            created_at: Timestamp::now().as_u64(),
+            expires_at,
+            last_used_at: None,
+        };
+        let mut wtxn = self.env.write_txn()?;
+        self.tables
+            .admin_keys
+            .put(&mut wtxn, &key.id, &key.serialize())?;
+        wtxn.commit()?;
+        Ok((key, format!("{id}.{secret}")))
+    }
+
+    /// All admin keys ordered by id.
+    pub fn list_admin_keys(&self) -> anyhow::Result<Vec<AdminKey>> {
+        let rtxn = self.env.read_txn()?;
+        let mut keys = vec![];
+        for result in self.tables.admin_keys.iter(&rtxn)? {
+            let (_, bytes) = result?;
+            keys.push(AdminKey::deserialize(bytes)?);
+        }
+        Ok(keys)
+    }
+
+    /// Delete an admin key. Returns `false` if the key does not exist.
+    pub fn delete_admin_key(&self, id: &str) -> anyhow::Result<bool> {
+        let mut wtxn = self.env.write_txn()?;
+        let deleted = self.tables.admin_keys.delete(&mut wtxn, id)?;
+        wtxn.commit()?;
+        Ok(deleted)
+    }
+
+    /// Return the admin key of `token` if the secret matches and the key is not expired.
+    /// Updates the last use of the key at most once per [LAST_USED_RESOLUTION_S].
+    pub fn verify_admin_key(&self, token: &str) -> anyhow::Result<Option<AdminKey>> {
+        let Some((id, secret)) = split_token(token) else {
+            return Ok(None);
+        };
+        let mut key = {
+            let rtxn = self.env.read_txn()?;
+            match self.tables.admin_keys.get(&rtxn, id)? {
+                Some(bytes) => AdminKey::deserialize(bytes)?,
+                None => return Ok(None),
+            }
+        };
+        // blake3::Hash comparison is constant time.
+        if hash(secret.as_bytes()) != Hash::from_bytes(key.secret_hash) || key.is_expired() {
+            return Ok(None);
+        }
+
+        let now = Timestamp::now().as_u64();
+        let is_stale = key.last_used_at.is_none_or(|last_used_at| {
+            now.saturating_sub(last_used_at) >= LAST_USED_RESOLUTION_S * 1_000_000
+        });
+        if is_stale {
+            key.last_used_at = Some(now);
+            let mut wtxn = self.env.write_txn()?;
+            // Don't resurrect a key that got deleted in the meantime.
+            if self.tables.admin_keys.get(&wtxn, id)?.is_some() {
+                self.tables
+                    .admin_keys
+                    .put(&mut wtxn, &key.id, &key.serialize())?;
+            }
+            wtxn.commit()?;
+        }
+        Ok(Some(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_admin_key() {
+        let db = LmDB::test();
+        let (key, token) = db
+            .create_admin_key("ops", vec![AdminScope::Stats], None)
+            .unwrap();
+
+        let verified = db.verify_admin_key(&token).unwrap().unwrap();
+        assert_eq!(verified.id, key.id);
+        assert!(verified.has_scope(AdminScope::Stats));
+        assert!(!verified.has_scope(AdminScope::Users));
+        assert!(verified.last_used_at.is_some());
+
+        // Wrong secret
+        assert!(db
+            .verify_admin_key(&format!("{}.wrong", key.id))
+            .unwrap()
+            .is_none());
+        assert!(db.verify_admin_key("garbage").unwrap().is_none());
+
+        // Expired
+        let (_, expired) = db
+            .create_admin_key("old", vec![AdminScope::Stats], Some(0))
+            .unwrap();
+        assert!(db.verify_admin_key(&expired).unwrap().is_none());
+
+        // Deleted
+        assert!(db.delete_admin_key(&key.id).unwrap());
+        assert!(db.verify_admin_key(&token).unwrap().is_none());
+        assert_eq!(db.list_admin_keys().unwrap().len(), 1);
+    }
+}
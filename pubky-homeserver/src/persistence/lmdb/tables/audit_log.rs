@@ -0,0 +1,143 @@
+//! Append-only log of privileged operations on the admin server.
+
+use heed::{
+    types::{Bytes, Str},
+    Database, PutFlags,
+};
+use pkarr::Timestamp;
+use postcard::{from_bytes, to_allocvec};
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{DEFAULT_LIST_LIMIT, DEFAULT_MAX_LIST_LIMIT};
+
+use super::super::LmDB;
+
+/// Audit entry [pkarr::Timestamp] base32 => Encoded audit entry.
+pub type AuditLogTable = Database<Str, Bytes>;
+
+pub const AUDIT_LOG_TABLE: &str = "audit_log";
+
+/// A privileged operation performed on the admin server.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct AuditEntry {
+    /// Timestamp in microseconds.
+    pub timestamp: u64,
+    /// `password` or `key:{id} ({name})`.
+    pub actor: String,
+    /// HTTP method and route, for example `POST /users/{pubkey}/disable`.
+    pub action: String,
+    /// The concrete path the action was performed on.
+    pub target: String,
+    /// HTTP status code of the response.
+    pub outcome: u16,
+}
+
+impl AuditEntry {
+    pub fn serialize(&self) -> Vec<u8> {
+        to_allocvec(self).expect("AuditEntry::serialize")
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> core::result::Result<Self, postcard::Error> {
+        from_bytes(bytes)
+    }
+}
+
+impl LmDB {
+    /// Append an entry to the audit log.
+    ///
+    /// The entry is stored under a fresh timestamp. Existing entries are never overwritten.
+    pub fn append_audit_entry(
+        &self,
+        actor: &str,
+        action: &str,
+        target: &str,
+        outcome: u16,
+    ) -> anyhow::Result<AuditEntry> {
+        let timestamp = Timestamp::now();
+        let entry = AuditEntry {
+            timestamp: timestamp.as_u64(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+            outcome,
+        };
+        let mut wtxn = self.env.write_txn()?;
+        self.tables.audit_log.put_with_flags(
+            &mut wtxn,
+            PutFlags::NO_OVERWRITE,
+            &timestamp.to_string(),
+            &entry.serialize(),
+        )?;
+        wtxn.commit()?;
+        Ok(entry)
+    }
+
+    /// Returns a page of audit entries as `(cursor, entry)` pairs, oldest first.
+    ///
+    /// - limit defaults to [crate::constants::DEFAULT_LIST_LIMIT] and capped by [crate::constants::DEFAULT_MAX_LIST_LIMIT]
+    /// - cursor is the cursor of the last entry of the previous page
+    /// - reverse returns the newest entries first
+    pub fn list_audit_entries(
+        &self,
+        limit: Option<u16>,
+        cursor: Option<&str>,
+        reverse: bool,
+    ) -> anyhow::Result<Vec<(String, AuditEntry)>> {
+        let txn = self.env.read_txn()?;
+        let limit = limit
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+            .min(DEFAULT_MAX_LIST_LIMIT) as usize;
+
+        let mut result = Vec::with_capacity(limit);
+        let mut next_cursor = cursor.map(|cursor| cursor.to_string());
+        while result.len() < limit {
+            let item = match (&next_cursor, reverse) {
+                (Some(cursor), false) => self.tables.audit_log.get_greater_than(&txn, cursor)?,
+                (Some(cursor), true) => self.tables.audit_log.get_lower_than(&txn, cursor)?,
+                (None, false) => self.tables.audit_log.first(&txn)?,
+                (None, true) => self.tables.audit_log.last(&txn)?,
+            };
+            let Some((key, bytes)) = item else {
+                break;
+            };
+            let entry = AuditEntry::deserialize(bytes)?;
+            next_cursor = Some(key.to_string());
+            result.push((key.to_string(), entry));
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_audit_entries() {
+        let db = LmDB::test();
+        for i in 0..5 {
+            db.append_audit_entry(
+                "password",
+                "POST /users/{pubkey}/disable",
+                &i.to_string(),
+                200,
+            )
+            .unwrap();
+        }
+
+        let page = db.list_audit_entries(Some(3), None, false).unwrap();
+        assert_eq!(page.len(), 3);
+        assert_eq!(page[0].1.target, "0");
+
+        let cursor = page.last().unwrap().0.clone();
+        let page = db
+            .list_audit_entries(Some(3), Some(&cursor), false)
+            .unwrap();
+        let targets: Vec<&str> = page.iter().map(|(_, e)| e.target.as_str()).collect();
+        assert_eq!(targets, vec!["3", "4"]);
+
+        let page = db.list_audit_entries(Some(2), None, true).unwrap();
+        let targets: Vec<&str> = page.iter().map(|(_, e)| e.target.as_str()).collect();
+        assert_eq!(targets, vec!["4", "3"]);
+    }
+}
@@ -0,0 +1,81 @@
+//! Content that must not be stored on the homeserver, identified by its blake3 hash.
+
+use super::super::LmDB;
+use heed::{
+    types::{Bytes, Str},
+    Database,
+};
+use postcard::{from_bytes, to_allocvec};
+use pubky_common::{crypto::Hash, timestamp::Timestamp};
+use serde::{Deserialize, Serialize};
+
+/// Content hash hex => BlockedContent.
+pub type BlockedContentTable = Database<Str, Bytes>;
+
+pub const BLOCKED_CONTENT_TABLE: &str = "blocked_content";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockedContent {
+    /// Why the content is blocked, for example a reference to a takedown request.
+    pub reason: String,
+    /// Timestamp in microseconds.
+    pub created_at: u64,
+}
+
+impl BlockedContent {
+    pub fn serialize(&self) -> Vec<u8> {
+        to_allocvec(self).expect("serialize blocked content")
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> core::result::Result<Self, postcard::Error> {
+        from_bytes(bytes)
+    }
+}
+
+impl LmDB {
+    /// Block content by its hash. Overwrites the reason if the hash is already blocked.
+    pub fn block_content(&self, hash: &Hash, reason: &str) -> anyhow::Result<BlockedContent> {
+        let blocked = BlockedContent {
+            reason: reason.to_string(),
+            created_at: Timestamp::now().as_u64(),
+        };
+        let mut wtxn = self.env.write_txn()?;
+        self.tables
+            .blocked_content
+            .put(&mut wtxn, hash.to_hex().as_str(), &blocked.serialize())?;
+        wtxn.commit()?;
+        Ok(blocked)
+    }
+
+    /// Remove a hash from the blocklist. Returns `false` if it wasn't blocked.
+    pub fn unblock_content(&self, hash: &Hash) -> anyhow::Result<bool> {
+        let mut wtxn = self.env.write_txn()?;
+        let deleted = self
+            .tables
+            .blocked_content
+            .delete(&mut wtxn, hash.to_hex().as_str())?;
+        wtxn.commit()?;
+        Ok(deleted)
+    }
+
+    /// Whether content with this hash must not be stored.
+    pub fn is_content_blocked(&self, hash: &Hash) -> Result<bool, heed::Error> {
+        let rtxn = self.env.read_txn()?;
+        Ok(self
+            .tables
+            .blocked_content
+            .get(&rtxn, hash.to_hex().as_str())?
+            .is_some())
+    }
+
+    /// All blocked hashes ordered by hash.
+    pub fn list_blocked_content(&self) -> anyhow::Result<Vec<(Hash, BlockedContent)>> {
+        let rtxn = self.env.read_txn()?;
+        let mut result = vec![];
+        for item in self.tables.blocked_content.iter(&rtxn)? {
+            let (hex, bytes) = item?;
+            result.push((Hash::from_hex(hex)?, BlockedContent::deserialize(bytes)?));
+        }
+        Ok(result)
+    }
+}
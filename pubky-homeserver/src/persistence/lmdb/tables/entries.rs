@@ -55,6 +55,21 @@ impl LmDB {
             .is_some())
     }
 
+    /// Paths of all entries with the given content hash, across all users.
+    ///
+    /// Scans the whole entries table.
+    pub fn find_entries_by_content_hash(&self, hash: &Hash) -> anyhow::Result<Vec<EntryPath>> {
+        let txn = self.env.read_txn()?;
+        let mut paths = vec![];
+        for result in self.tables.entries.iter(&txn)? {
+            let (key, bytes) = result?;
+            if Entry::deserialize(bytes)?.content_hash() == hash {
+                paths.push(key.parse()?);
+            }
+        }
+        Ok(paths)
+    }
+
     /// Return a list of pubky urls.
     ///
     /// - limit defaults to [crate::config::DEFAULT_LIST_LIMIT] and capped by [crate::config::DEFAULT_MAX_LIST_LIMIT]
@@ -4,10 +4,12 @@
 //! we implement more self-authenticated merkle data.
 
 use heed::{
-    types::{Bytes, Str},
-    Database,
+    types::{Bytes, Str, Unit},
+    Database, RoTxn, RwTxn,
 };
+use pkarr::PublicKey;
 use postcard::{from_bytes, to_allocvec};
+use pubky_common::timestamp::Timestamp;
 use serde::{Deserialize, Serialize};
 
 use crate::constants::{DEFAULT_LIST_LIMIT, DEFAULT_MAX_LIST_LIMIT};
@@ -19,6 +21,14 @@ pub type EventsTable = Database<Str, Bytes>;
 
 pub const EVENTS_TABLE: &str = "events";
 
+/// Index of the events per user. `{pubkey}/{event key}` => ().
+pub type UserEventsTable = Database<Str, Unit>;
+
+pub const USER_EVENTS_TABLE: &str = "user_events";
+
+/// Number of events deleted per write transaction.
+const DELETE_BATCH_SIZE: usize = 1000;
+
 #[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
 pub enum Event {
     Put(String),
@@ -61,7 +71,22 @@ impl Event {
     }
 }
 
+/// Key of the event in the [UserEventsTable] or `None` if the url is not a pubky url.
+pub(crate) fn user_event_key(event_key: &str, event: &Event) -> Option<String> {
+    let (pubkey, _) = event.url().strip_prefix("pubky://")?.split_once('/')?;
+    Some(format!("{pubkey}/{event_key}"))
+}
+
 impl LmDB {
+    /// Write an event and index it by its user.
+    pub fn put_event(&self, wtxn: &mut RwTxn, key: &str, event: &Event) -> heed::Result<()> {
+        self.tables.events.put(wtxn, key, &event.serialize())?;
+        if let Some(user_key) = user_event_key(key, event) {
+            self.tables.user_events.put(wtxn, &user_key, &())?;
+        }
+        Ok(())
+    }
+
     /// Returns a list of events formatted as `<OP> <url>`.
     ///
     /// - limit defaults to [crate::config::DEFAULT_LIST_LIMIT] and capped by [crate::config::DEFAULT_MAX_LIST_LIMIT]
@@ -104,4 +129,85 @@ impl LmDB {
 
         Ok(result)
     }
+
+    /// Returns the latest events of `pubkey` as `(cursor, event)` pairs, newest first.
+    pub fn list_recent_user_events(
+        &self,
+        txn: &RoTxn,
+        pubkey: &PublicKey,
+        limit: usize,
+    ) -> anyhow::Result<Vec<(String, Event)>> {
+        let prefix = format!("{pubkey}/");
+        let mut result = vec![];
+        for item in self.tables.user_events.rev_prefix_iter(txn, &prefix)? {
+            if result.len() >= limit {
+                break;
+            }
+            let (user_key, _) = item?;
+            let event_key = &user_key[prefix.len()..];
+            if let Some(bytes) = self.tables.events.get(txn, event_key)? {
+                result.push((event_key.to_string(), Event::deserialize(bytes)?));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Timestamp of the latest event of `pubkey`, if any.
+    pub fn get_last_user_event_timestamp(
+        &self,
+        txn: &RoTxn,
+        pubkey: &PublicKey,
+    ) -> anyhow::Result<Option<Timestamp>> {
+        let prefix = format!("{pubkey}/");
+        match self
+            .tables
+            .user_events
+            .rev_prefix_iter(txn, &prefix)?
+            .next()
+        {
+            Some(item) => {
+                let (user_key, _) = item?;
+                Ok(Some(Timestamp::try_from(
+                    user_key[prefix.len()..].to_string(),
+                )?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Delete the `PUT` events of `pubkey`.
+    /// Returns the number of deleted events.
+    ///
+    /// The `DEL` events are kept as tombstones so indexers learn that the files are gone.
+    /// The events are deleted in batches to not block other writers for too long.
+    pub fn delete_user_events(&self, pubkey: &PublicKey) -> anyhow::Result<usize> {
+        let prefix = format!("{pubkey}/");
+        let mut keys = vec![];
+        {
+            let rtxn = self.env.read_txn()?;
+            for item in self.tables.user_events.prefix_iter(&rtxn, &prefix)? {
+                let (user_key, _) = item?;
+                let event_key = &user_key[prefix.len()..];
+                let is_put = match self.tables.events.get(&rtxn, event_key)? {
+                    Some(bytes) => matches!(Event::deserialize(bytes)?, Event::Put(_)),
+                    // The event is gone already. Clean up the index.
+                    None => true,
+                };
+                if is_put {
+                    keys.push(user_key.to_string());
+                }
+            }
+        }
+        for batch in keys.chunks(DELETE_BATCH_SIZE) {
+            let mut wtxn = self.env.write_txn()?;
+            for user_key in batch {
+                self.tables
+                    .events
+                    .delete(&mut wtxn, &user_key[prefix.len()..])?;
+                self.tables.user_events.delete(&mut wtxn, user_key)?;
+            }
+            wtxn.commit()?;
+        }
+        Ok(keys.len())
+    }
 }
@@ -0,0 +1,237 @@
+//! Persistent state of the rate limiters. See [crate::RateLimitStore].
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::super::LmDB;
+use crate::RateLimitStore;
+use heed::{
+    types::{Bytes, Str},
+    Database,
+};
+
+/// `{limit} {key}` => Theoretical arrival time in nanoseconds since the UNIX epoch, big endian.
+pub type RateLimitsTable = Database<Str, Bytes>;
+
+pub const RATE_LIMITS_TABLE: &str = "rate_limits";
+
+/// How often the changed state is written to LMDB.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+fn decode(bytes: &[u8]) -> anyhow::Result<u64> {
+    let bytes: [u8; 8] = bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid rate limit state"))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+impl LmDB {
+    /// Read the state of all rate limiters.
+    fn load_rate_limits(&self) -> anyhow::Result<HashMap<String, u64>> {
+        let rtxn = self.env.read_txn()?;
+        let mut values = HashMap::new();
+        for entry in self.tables.rate_limits.iter(&rtxn)? {
+            let (key, value) = entry?;
+            match decode(value) {
+                Ok(tat) => {
+                    values.insert(key.to_string(), tat);
+                }
+                Err(e) => tracing::warn!("Ignoring rate limit state of {key}: {e}"),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Write a batch of rate limiter state in one transaction. `None` deletes the key.
+    fn write_rate_limits(&self, batch: &HashMap<String, Option<u64>>) -> anyhow::Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+        for (key, value) in batch {
+            match value {
+                Some(tat) => self
+                    .tables
+                    .rate_limits
+                    .put(&mut wtxn, key, &tat.to_be_bytes())?,
+                None => {
+                    self.tables.rate_limits.delete(&mut wtxn, key)?;
+                }
+            }
+        }
+        wtxn.commit()?;
+        Ok(())
+    }
+}
+
+/// A [RateLimitStore] that persists the state in LMDB.
+///
+/// The state is kept in memory so requests never wait for the database.
+/// Changes are written in batches from a blocking task every [FLUSH_INTERVAL],
+/// on [RateLimitStore::flush] and on drop.
+/// Changes since the last flush are lost on a crash.
+#[derive(Debug)]
+pub struct LmdbRateLimitStore {
+    db: LmDB,
+    state: Arc<Mutex<StoreState>>,
+    flushing: Arc<AtomicBool>,
+}
+
+#[derive(Debug)]
+struct StoreState {
+    values: HashMap<String, u64>,
+    /// Changes that are not written to LMDB yet.
+    pending: HashMap<String, Option<u64>>,
+    last_flush: Instant,
+}
+
+impl LmdbRateLimitStore {
+    /// Load the state of the rate limiters from `db`.
+    pub fn new(db: LmDB) -> anyhow::Result<Self> {
+        let values = db.load_rate_limits()?;
+        Ok(Self {
+            db,
+            state: Arc::new(Mutex::new(StoreState {
+                values,
+                pending: HashMap::new(),
+                last_flush: Instant::now(),
+            })),
+            flushing: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StoreState> {
+        self.state.lock().expect("rate limit store lock poisoned")
+    }
+
+    /// Take the pending changes.
+    fn take_pending(state: &Mutex<StoreState>) -> HashMap<String, Option<u64>> {
+        let mut state = state.lock().expect("rate limit store lock poisoned");
+        state.last_flush = Instant::now();
+        std::mem::take(&mut state.pending)
+    }
+
+    /// Put a failed batch back unless the keys changed again in the meantime.
+    fn restore_pending(state: &Mutex<StoreState>, batch: HashMap<String, Option<u64>>) {
+        let mut state = state.lock().expect("rate limit store lock poisoned");
+        for (key, value) in batch {
+            state.pending.entry(key).or_insert(value);
+        }
+    }
+
+    /// Write the pending changes in the background if the flush interval elapsed.
+    fn maybe_flush(&self, state: &StoreState) {
+        if state.pending.is_empty() || state.last_flush.elapsed() < FLUSH_INTERVAL {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            // No runtime to write in the background. The changes are written on flush or drop.
+            return;
+        };
+        if self.flushing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let db = self.db.clone();
+        let state = self.state.clone();
+        let flushing = self.flushing.clone();
+        runtime.spawn_blocking(move || {
+            let batch = Self::take_pending(&state);
+            if let Err(e) = db.write_rate_limits(&batch) {
+                tracing::warn!("Failed to write rate limit state: {e}");
+                Self::restore_pending(&state, batch);
+            }
+            flushing.store(false, Ordering::Release);
+        });
+    }
+}
+
+impl RateLimitStore for LmdbRateLimitStore {
+    fn get(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        Ok(self.lock().values.get(key).copied())
+    }
+
+    fn compare_and_swap(&self, key: &str, current: Option<u64>, new: u64) -> anyhow::Result<bool> {
+        let mut state = self.lock();
+        if state.values.get(key).copied() != current {
+            return Ok(false);
+        }
+        state.values.insert(key.to_string(), new);
+        state.pending.insert(key.to_string(), Some(new));
+        self.maybe_flush(&state);
+        Ok(true)
+    }
+
+    fn remove_expired(&self, now: u64) -> anyhow::Result<()> {
+        let mut state = self.lock();
+        let expired: Vec<String> = state
+            .values
+            .iter()
+            .filter(|(_, tat)| **tat < now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            state.values.remove(&key);
+            state.pending.insert(key, None);
+        }
+        self.maybe_flush(&state);
+        Ok(())
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        let batch = Self::take_pending(&self.state);
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.db.write_rate_limits(&batch).inspect_err(|_| {
+            Self::restore_pending(&self.state, batch.clone());
+        })
+    }
+}
+
+impl Drop for LmdbRateLimitStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            tracing::warn!("Failed to write rate limit state: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_and_swap() {
+        let db = LmDB::test();
+        let store = LmdbRateLimitStore::new(db.clone()).unwrap();
+        assert_eq!(store.get("a").unwrap(), None);
+        assert!(store.compare_and_swap("a", None, 10).unwrap());
+        assert!(!store.compare_and_swap("a", None, 20).unwrap());
+        assert!(store.compare_and_swap("a", Some(10), 20).unwrap());
+        assert!(store.compare_and_swap("b", None, 5).unwrap());
+
+        store.remove_expired(15).unwrap();
+        assert_eq!(store.get("a").unwrap(), Some(20));
+        assert_eq!(store.get("b").unwrap(), None);
+    }
+
+    #[test]
+    fn test_flush_in_batches() {
+        let db = LmDB::test();
+        let store = LmdbRateLimitStore::new(db.clone()).unwrap();
+        assert!(store.compare_and_swap("a", None, 10).unwrap());
+        assert!(store.compare_and_swap("b", None, 5).unwrap());
+        // Nothing is written before the flush.
+        assert!(db.load_rate_limits().unwrap().is_empty());
+
+        store.flush().unwrap();
+        let stored = db.load_rate_limits().unwrap();
+        assert_eq!(stored.get("a"), Some(&10));
+        assert_eq!(stored.get("b"), Some(&5));
+
+        store.remove_expired(8).unwrap();
+        drop(store);
+        let store = LmdbRateLimitStore::new(db).unwrap();
+        assert_eq!(store.get("a").unwrap(), Some(10));
+        assert_eq!(store.get("b").unwrap(), None);
+    }
+}
@@ -1,7 +1,8 @@
 use heed::{
-    types::{Bytes, Str},
-    Database,
+    types::{Bytes, Str, Unit},
+    Database, RoTxn, RwTxn,
 };
+use pkarr::PublicKey;
 use pubky_common::session::Session;
 
 use super::super::LmDB;
@@ -11,6 +12,16 @@ pub type SessionsTable = Database<Str, Bytes>;
 
 pub const SESSIONS_TABLE: &str = "sessions";
 
+/// Index of the sessions per user. `{pubkey}/{session secret}` => ().
+pub type UserSessionsTable = Database<Str, Unit>;
+
+pub const USER_SESSIONS_TABLE: &str = "user_sessions";
+
+/// Key of the session in the [UserSessionsTable].
+pub(crate) fn user_session_key(pubkey: &PublicKey, secret: &str) -> String {
+    format!("{pubkey}/{secret}")
+}
+
 impl LmDB {
     pub fn get_session(&self, session_secret: &str) -> anyhow::Result<Option<Session>> {
         let rtxn = self.env.read_txn()?;
@@ -30,13 +41,78 @@ impl LmDB {
         Ok(None)
     }
 
+    /// Write a session and index it by its user.
+    pub fn put_session(
+        &self,
+        wtxn: &mut RwTxn,
+        secret: &str,
+        session: &Session,
+    ) -> anyhow::Result<()> {
+        self.tables
+            .sessions
+            .put(wtxn, secret, &session.serialize())?;
+        self.tables
+            .user_sessions
+            .put(wtxn, &user_session_key(session.pubky(), secret), &())?;
+        Ok(())
+    }
+
     pub fn delete_session(&mut self, secret: &str) -> anyhow::Result<bool> {
         let mut wtxn = self.env.write_txn()?;
 
+        if let Some(bytes) = self.tables.sessions.get(&wtxn, secret)? {
+            let pubkey = Session::deserialize(bytes)?.pubky().clone();
+            self.tables
+                .user_sessions
+                .delete(&mut wtxn, &user_session_key(&pubkey, secret))?;
+        }
         let deleted = self.tables.sessions.delete(&mut wtxn, secret)?;
 
         wtxn.commit()?;
 
         Ok(deleted)
     }
+
+    /// All sessions of `pubkey`.
+    pub fn list_user_sessions(
+        &self,
+        txn: &RoTxn,
+        pubkey: &PublicKey,
+    ) -> anyhow::Result<Vec<Session>> {
+        let prefix = user_session_key(pubkey, "");
+        let mut sessions = vec![];
+        for result in self.tables.user_sessions.prefix_iter(txn, &prefix)? {
+            let (user_key, _) = result?;
+            if let Some(bytes) = self.tables.sessions.get(txn, &user_key[prefix.len()..])? {
+                sessions.push(Session::deserialize(bytes)?);
+            }
+        }
+        Ok(sessions)
+    }
+
+    /// Delete all sessions of `pubkey`.
+    /// Returns the number of deleted sessions.
+    pub fn delete_user_sessions(&self, pubkey: &PublicKey) -> anyhow::Result<usize> {
+        let mut wtxn = self.env.write_txn()?;
+        let prefix = user_session_key(pubkey, "");
+        let user_keys = self
+            .tables
+            .user_sessions
+            .prefix_iter(&wtxn, &prefix)?
+            .map(|result| result.map(|(user_key, _)| user_key.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut deleted = 0;
+        for user_key in &user_keys {
+            if self
+                .tables
+                .sessions
+                .delete(&mut wtxn, &user_key[prefix.len()..])?
+            {
+                deleted += 1;
+            }
+            self.tables.user_sessions.delete(&mut wtxn, user_key)?;
+        }
+        wtxn.commit()?;
+        Ok(deleted)
+    }
 }
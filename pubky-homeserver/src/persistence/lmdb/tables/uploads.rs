@@ -0,0 +1,128 @@
+use heed::{
+    types::{Bytes, Str},
+    Database,
+};
+use pkarr::PublicKey;
+use postcard::{from_bytes, to_allocvec};
+use pubky_common::{crypto::Hash, timestamp::Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    persistence::files::FileIoError,
+    shared::webdav::{EntryPath, WebDavPath},
+};
+
+use super::super::LmDB;
+
+/// upload id => Upload.
+pub type UploadsTable = Database<Str, Bytes>;
+
+pub const UPLOADS_TABLE: &str = "uploads";
+
+/// A resumable upload in progress.
+///
+/// The received chunks are staged in the storage backend
+/// until the upload is complete.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Upload {
+    pub id: String,
+    pub pubkey: PublicKey,
+    /// The path of the file the upload is committed to.
+    pub path: String,
+    /// Total size of the file in bytes.
+    pub length: u64,
+    /// Size of the staged chunks in order.
+    pub chunks: Vec<u64>,
+    /// Optional expected Blake3 hash of the complete file.
+    pub content_hash: Option<[u8; 32]>,
+    pub created_at: u64,
+    /// Last time a chunk was received.
+    pub updated_at: u64,
+}
+
+impl Upload {
+    pub fn new(id: String, path: &EntryPath, length: u64, content_hash: Option<Hash>) -> Self {
+        let now = Timestamp::now().as_u64();
+        Self {
+            id,
+            pubkey: path.pubkey().clone(),
+            path: path.path().as_str().to_string(),
+            length,
+            chunks: vec![],
+            content_hash: content_hash.map(|hash| *hash.as_bytes()),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        to_allocvec(self).expect("serialize upload")
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> core::result::Result<Self, postcard::Error> {
+        from_bytes(bytes)
+    }
+
+    /// The number of bytes received so far.
+    pub fn offset(&self) -> u64 {
+        self.chunks.iter().sum()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.offset() == self.length
+    }
+
+    pub fn entry_path(&self) -> Result<EntryPath, FileIoError> {
+        let path = WebDavPath::new(&self.path).map_err(|_| FileIoError::NotFound)?;
+        Ok(EntryPath::new(self.pubkey.clone(), path))
+    }
+
+    pub fn content_hash(&self) -> Option<Hash> {
+        self.content_hash.map(Hash::from_bytes)
+    }
+}
+
+impl LmDB {
+    pub fn get_upload(&self, id: &str) -> Result<Upload, FileIoError> {
+        let rtxn = self.env.read_txn()?;
+        match self.tables.uploads.get(&rtxn, id)? {
+            Some(bytes) => Ok(Upload::deserialize(bytes)?),
+            None => Err(FileIoError::NotFound),
+        }
+    }
+
+    /// Store the upload and change the storage usage of its user by `bytes_delta`
+    /// in one transaction.
+    pub fn put_upload(&self, upload: &Upload, bytes_delta: i64) -> Result<(), FileIoError> {
+        let mut wtxn = self.env.write_txn()?;
+        self.tables
+            .uploads
+            .put(&mut wtxn, &upload.id, &upload.serialize())?;
+        self.update_user_usage(&mut wtxn, &upload.pubkey, bytes_delta)?;
+        wtxn.commit()?;
+        Ok(())
+    }
+
+    /// Delete the upload and change the storage usage of its user by `bytes_delta`
+    /// in one transaction.
+    pub fn delete_upload(&self, upload: &Upload, bytes_delta: i64) -> Result<bool, FileIoError> {
+        let mut wtxn = self.env.write_txn()?;
+        let deleted = self.tables.uploads.delete(&mut wtxn, &upload.id)?;
+        if deleted {
+            self.update_user_usage(&mut wtxn, &upload.pubkey, bytes_delta)?;
+        }
+        wtxn.commit()?;
+        Ok(deleted)
+    }
+
+    /// All uploads in progress.
+    pub fn list_uploads(&self) -> Result<Vec<Upload>, FileIoError> {
+        let rtxn = self.env.read_txn()?;
+        let mut uploads = vec![];
+        for result in self.tables.uploads.iter(&rtxn)? {
+            let (_, bytes) = result?;
+            uploads.push(Upload::deserialize(bytes)?);
+        }
+        Ok(uploads)
+    }
+}
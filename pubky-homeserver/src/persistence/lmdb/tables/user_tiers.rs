@@ -0,0 +1,52 @@
+//! Rate limit tiers of users. See [crate::quota_config::PathLimit::tiers].
+
+use super::super::LmDB;
+use super::users::PublicKeyCodec;
+use heed::{types::Str, Database};
+use pkarr::PublicKey;
+
+/// User pubkey => Tier name.
+pub type UserTiersTable = Database<PublicKeyCodec, Str>;
+
+pub const USER_TIERS_TABLE: &str = "user_tiers";
+
+impl LmDB {
+    /// The tier of a user or `None` if the user has no tier.
+    pub fn get_user_tier(&self, pubkey: &PublicKey) -> Result<Option<String>, heed::Error> {
+        let rtxn = self.env.read_txn()?;
+        let tier = self.tables.user_tiers.get(&rtxn, pubkey)?;
+        Ok(tier.map(|tier| tier.to_string()))
+    }
+
+    /// Assign a tier to a user. `None` removes the tier.
+    pub fn set_user_tier(&self, pubkey: &PublicKey, tier: Option<&str>) -> Result<(), heed::Error> {
+        let mut wtxn = self.env.write_txn()?;
+        match tier {
+            Some(tier) => self.tables.user_tiers.put(&mut wtxn, pubkey, tier)?,
+            None => {
+                self.tables.user_tiers.delete(&mut wtxn, pubkey)?;
+            }
+        };
+        wtxn.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pkarr::Keypair;
+
+    #[test]
+    fn test_set_user_tier() {
+        let db = LmDB::test();
+        let pubkey = Keypair::random().public_key();
+        assert_eq!(db.get_user_tier(&pubkey).unwrap(), None);
+
+        db.set_user_tier(&pubkey, Some("paid")).unwrap();
+        assert_eq!(db.get_user_tier(&pubkey).unwrap(), Some("paid".to_string()));
+
+        db.set_user_tier(&pubkey, None).unwrap();
+        assert_eq!(db.get_user_tier(&pubkey).unwrap(), None);
+    }
+}
@@ -36,7 +36,7 @@ impl Default for User {
 impl BytesEncode<'_> for User {
     type EItem = Self;
 
-    fn bytes_encode(user: &Self::EItem) -> Result<Cow<[u8]>, BoxedError> {
+    fn bytes_encode(user: &Self::EItem) -> Result<Cow<'_, [u8]>, BoxedError> {
         let vec = to_allocvec(user).unwrap();
 
         Ok(Cow::Owned(vec))
@@ -58,7 +58,7 @@ pub struct PublicKeyCodec {}
 impl BytesEncode<'_> for PublicKeyCodec {
     type EItem = PublicKey;
 
-    fn bytes_encode(pubky: &Self::EItem) -> Result<Cow<[u8]>, BoxedError> {
+    fn bytes_encode(pubky: &Self::EItem) -> Result<Cow<'_, [u8]>, BoxedError> {
         Ok(Cow::Borrowed(pubky.as_bytes()))
     }
 }
@@ -128,6 +128,34 @@ impl LmDB {
         Ok(())
     }
 
+    /// Change the storage usage of a user by `bytes_delta`.
+    pub fn update_user_usage(
+        &self,
+        wtxn: &mut RwTxn,
+        pubkey: &PublicKey,
+        bytes_delta: i64,
+    ) -> Result<(), heed::Error> {
+        if bytes_delta == 0 {
+            return Ok(());
+        }
+        // The user may have been deleted in the meantime.
+        if let Some(mut user) = self.tables.users.get(wtxn, pubkey)? {
+            user.used_bytes = user.used_bytes.saturating_add_signed(bytes_delta);
+            self.tables.users.put(wtxn, pubkey, &user)?;
+        }
+        Ok(())
+    }
+
+    /// Delete a user record.
+    /// Returns `false` if the user does not exist.
+    pub fn delete_user(&self, pubkey: &PublicKey) -> Result<bool, heed::Error> {
+        let mut wtxn = self.env.write_txn()?;
+        let deleted = self.tables.users.delete(&mut wtxn, pubkey)?;
+        self.tables.user_tiers.delete(&mut wtxn, pubkey)?;
+        wtxn.commit()?;
+        Ok(deleted)
+    }
+
     /// Create a user.
     ///
     /// # Errors
@@ -0,0 +1,226 @@
+//! Readiness checks of the homeserver's dependencies.
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use opendal::Operator;
+use pkarr::Timestamp;
+use serde::Serialize;
+
+use crate::persistence::lmdb::LmDB;
+
+/// Time after which a check is considered failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Below this, writes are about to fail.
+const MIN_FREE_DISK_BYTES: u64 = 100 * 1024 * 1024;
+/// Below this, the disk should be extended soon.
+const LOW_FREE_DISK_BYTES: u64 = 1024 * 1024 * 1024;
+/// Key written in an aborted transaction to check that LMDB accepts writes.
+const LMDB_PROBE_KEY: &str = "__health_check__";
+
+/// Outcome of the last publish of the homeserver's pkarr packet.
+#[derive(Debug, Clone, Serialize)]
+pub struct LastPublish {
+    /// Timestamp in microseconds.
+    pub at: u64,
+    /// `None` if the publish succeeded.
+    pub error: Option<String>,
+}
+
+/// Shared between the [crate::core::HomeserverKeyRepublisher] and the health checks.
+#[derive(Debug, Clone, Default)]
+pub struct PublishStatus(Arc<RwLock<Option<LastPublish>>>);
+
+impl PublishStatus {
+    /// Record the result of a publish.
+    pub fn record<E: ToString>(&self, result: &Result<(), E>) {
+        let last = LastPublish {
+            at: Timestamp::now().as_u64(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        *self.0.write().expect("publish status lock poisoned") = Some(last);
+    }
+
+    /// The last publish or `None` if nothing was published yet.
+    pub fn last(&self) -> Option<LastPublish> {
+        self.0.read().expect("publish status lock poisoned").clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    /// Everything works.
+    Ok,
+    /// Works, but needs attention.
+    Degraded,
+    /// Doesn't work. The homeserver should not receive traffic.
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl CheckResult {
+    fn ok(message: impl Into<Option<String>>) -> Self {
+        Self {
+            status: HealthStatus::Ok,
+            message: message.into(),
+        }
+    }
+
+    fn degraded(message: impl ToString) -> Self {
+        Self {
+            status: HealthStatus::Degraded,
+            message: Some(message.to_string()),
+        }
+    }
+
+    fn failed(message: impl ToString) -> Self {
+        Self {
+            status: HealthStatus::Failed,
+            message: Some(message.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReadinessReport {
+    /// The worst status of all checks.
+    pub status: HealthStatus,
+    pub checks: BTreeMap<&'static str, CheckResult>,
+}
+
+/// Check all dependencies of the homeserver.
+pub async fn check_readiness(
+    db: &LmDB,
+    operator: Operator,
+    publish_status: &PublishStatus,
+    data_dir: &Path,
+) -> ReadinessReport {
+    let mut checks = BTreeMap::new();
+    checks.insert("lmdb", check_lmdb(db).await);
+    checks.insert("storage", check_storage(operator).await);
+    checks.insert("pkarr", check_pkarr(publish_status));
+    checks.insert("disk", check_disk(data_dir));
+    let status = checks
+        .values()
+        .map(|check| check.status)
+        .max()
+        .unwrap_or(HealthStatus::Ok);
+    ReadinessReport { status, checks }
+}
+
+/// Read from LMDB and write in a transaction that is aborted.
+async fn check_lmdb(db: &LmDB) -> CheckResult {
+    let db = db.clone();
+    let check = tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        {
+            let rtxn = db.env.read_txn()?;
+            db.tables.users.len(&rtxn)?;
+        }
+        let mut wtxn = db.env.write_txn()?;
+        db.tables.sessions.put(&mut wtxn, LMDB_PROBE_KEY, &[])?;
+        wtxn.abort();
+        Ok(())
+    });
+    match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(Ok(()))) => CheckResult::ok(None),
+        Ok(Ok(Err(e))) => CheckResult::failed(e),
+        Ok(Err(e)) => CheckResult::failed(e),
+        Err(_) => CheckResult::failed("Timed out waiting for the write lock"),
+    }
+}
+
+/// List the root of the storage backend.
+async fn check_storage(operator: Operator) -> CheckResult {
+    match tokio::time::timeout(CHECK_TIMEOUT, operator.check()).await {
+        Ok(Ok(())) => CheckResult::ok(None),
+        Ok(Err(e)) => CheckResult::failed(e),
+        Err(_) => CheckResult::failed("Timed out"),
+    }
+}
+
+/// A failed publish is only degraded because the last successful
+/// publish stays on the DHT for hours.
+fn check_pkarr(publish_status: &PublishStatus) -> CheckResult {
+    match publish_status.last() {
+        None => CheckResult::degraded("Not published yet"),
+        Some(LastPublish { at, error: None }) => {
+            CheckResult::ok(format!("Last published at {}", Timestamp::from(at)))
+        }
+        Some(LastPublish {
+            at,
+            error: Some(error),
+        }) => CheckResult::degraded(format!(
+            "Last publish at {} failed: {error}",
+            Timestamp::from(at)
+        )),
+    }
+}
+
+fn check_disk(data_dir: &Path) -> CheckResult {
+    let stats = match fs4::statvfs(data_dir) {
+        Ok(stats) => stats,
+        Err(e) => return CheckResult::failed(e),
+    };
+    let available = stats.available_space();
+    let message = format!(
+        "{}MB of {}MB available",
+        available / 1024 / 1024,
+        stats.total_space() / 1024 / 1024
+    );
+    if available < MIN_FREE_DISK_BYTES {
+        CheckResult::failed(message)
+    } else if available < LOW_FREE_DISK_BYTES {
+        CheckResult::degraded(message)
+    } else {
+        CheckResult::ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppContext;
+
+    #[tokio::test]
+    async fn test_check_readiness() {
+        let context = AppContext::test();
+        let publish_status = PublishStatus::default();
+        let report = check_readiness(
+            &context.db,
+            context.file_service.opendal.operator(),
+            &publish_status,
+            context.data_dir.path(),
+        )
+        .await;
+        assert_eq!(report.checks["lmdb"].status, HealthStatus::Ok);
+        assert_eq!(report.checks["storage"].status, HealthStatus::Ok);
+        assert_eq!(report.checks["pkarr"].status, HealthStatus::Degraded);
+        assert!(report.status >= HealthStatus::Degraded);
+
+        publish_status.record::<String>(&Ok(()));
+        assert_eq!(check_pkarr(&publish_status).status, HealthStatus::Ok);
+        publish_status.record(&Err("no nodes"));
+        assert_eq!(check_pkarr(&publish_status).status, HealthStatus::Degraded);
+
+        // The probe write is never committed.
+        let rtxn = context.db.env.read_txn().unwrap();
+        assert!(context
+            .db
+            .tables
+            .sessions
+            .get(&rtxn, LMDB_PROBE_KEY)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,7 +1,9 @@
 //! Server error
 use axum::{http::StatusCode, response::IntoResponse};
 
-use crate::persistence::files::FileIoError;
+use crate::persistence::files::{
+    ArchiveError, FileIoError, UploadError, UserDeletionError, WriteStreamError,
+};
 
 pub(crate) type HttpResult<T, E = HttpError> = core::result::Result<T, E>;
 
@@ -124,12 +126,48 @@ impl From<FileIoError> for HttpError {
         match error {
             FileIoError::NotFound => Self::not_found(),
             FileIoError::DiskSpaceQuotaExceeded => Self::insufficient_storage(),
+            FileIoError::ContentBlocked => Self::forbidden_with_message(error),
+            FileIoError::StreamBroken(e @ WriteStreamError::ContentHashMismatch { .. }) => {
+                Self::bad_request(e)
+            }
             FileIoError::StreamBroken(_) => Self::bad_request("Stream broken"),
             e => Self::internal_server_and_log(format!("FileIoError: {}", e)),
         }
     }
 }
 
+impl From<UploadError> for HttpError {
+    fn from(error: UploadError) -> Self {
+        match error {
+            UploadError::NotFound => Self::new_with_message(StatusCode::NOT_FOUND, error),
+            UploadError::OffsetMismatch(_) | UploadError::Busy => {
+                Self::new_with_message(StatusCode::CONFLICT, error)
+            }
+            UploadError::LengthExceeded(_) => Self::bad_request(error),
+            UploadError::FileIo(e) => e.into(),
+        }
+    }
+}
+
+impl From<UserDeletionError> for HttpError {
+    fn from(error: UserDeletionError) -> Self {
+        match error {
+            UserDeletionError::FileIo(e) => e.into(),
+            UserDeletionError::Upload(e) => e.into(),
+            UserDeletionError::Db(e) => e.into(),
+        }
+    }
+}
+
+impl From<ArchiveError> for HttpError {
+    fn from(error: ArchiveError) -> Self {
+        match error {
+            ArchiveError::FileIo(e) => e.into(),
+            e => Self::bad_request(e),
+        }
+    }
+}
+
 impl From<pubky_common::auth::Error> for HttpError {
     fn from(error: pubky_common::auth::Error) -> Self {
         Self::bad_request(error)
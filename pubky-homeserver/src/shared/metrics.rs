@@ -0,0 +1,367 @@
+//! Prometheus metrics of the homeserver.
+//!
+//! One [Metrics] instance is shared by all components through the [crate::AppContext]
+//! and rendered in the Prometheus text format by the admin server on `/metrics`.
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use prometheus_client::{
+    encoding::{text::encode, EncodeLabelSet, EncodeLabelValue},
+    metrics::{counter::Counter, family::Family, gauge::Gauge, histogram::Histogram},
+    registry::Registry,
+};
+
+use crate::persistence::lmdb::LmDB;
+
+/// Request latency buckets in seconds.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0, 60.0,
+];
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct HttpLabels {
+    server: &'static str,
+    method: String,
+    route: String,
+    status: u16,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RateLimitLabels {
+    method: String,
+    path: String,
+}
+
+/// Why a request was shed by the global limits.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum LoadShedReason {
+    /// The client has too many open connections.
+    Connections,
+    /// Too many uploads are in progress.
+    Uploads,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct LoadShedLabels {
+    reason: LoadShedReason,
+}
+
+/// Which republisher published a key.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum Republisher {
+    /// The homeserver's own pkarr packet.
+    Homeserver,
+    /// The pkarr packets of the users.
+    UserKeys,
+}
+
+/// Outcome of publishing a key.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum RepublishOutcome {
+    /// The key was published.
+    Success,
+    /// The user key was not found on the DHT and could not be republished.
+    Missing,
+    /// Publishing failed.
+    Failure,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RepublishLabels {
+    republisher: Republisher,
+    outcome: RepublishOutcome,
+}
+
+#[derive(Debug)]
+struct MetricsInner {
+    registry: Registry,
+    http_requests: Family<HttpLabels, Counter>,
+    http_request_duration: Family<HttpLabels, Histogram>,
+    rate_limit_rejections: Family<RateLimitLabels, Counter>,
+    load_shed_requests: Family<LoadShedLabels, Counter>,
+    uploaded_bytes: Counter,
+    downloaded_bytes: Counter,
+    republished_keys: Family<RepublishLabels, Counter>,
+    sessions: Gauge,
+    lmdb_map_size_bytes: Gauge,
+    lmdb_used_bytes: Gauge,
+}
+
+/// Prometheus metrics of the homeserver. Cheap to clone.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    inner: Arc<MetricsInner>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let mut registry = Registry::with_prefix("homeserver");
+
+        let http_requests = Family::<HttpLabels, Counter>::default();
+        registry.register(
+            "http_requests",
+            "Number of HTTP requests by server, route and status",
+            http_requests.clone(),
+        );
+        let http_request_duration = Family::<HttpLabels, Histogram>::new_with_constructor(|| {
+            Histogram::new(LATENCY_BUCKETS)
+        });
+        registry.register(
+            "http_request_duration_seconds",
+            "Latency of HTTP requests until the response headers are sent",
+            http_request_duration.clone(),
+        );
+        let rate_limit_rejections = Family::<RateLimitLabels, Counter>::default();
+        registry.register(
+            "rate_limit_rejections",
+            "Number of requests rejected by a rate limit",
+            rate_limit_rejections.clone(),
+        );
+        let load_shed_requests = Family::<LoadShedLabels, Counter>::default();
+        registry.register(
+            "load_shed_requests",
+            "Number of requests rejected with 503 by the global limits",
+            load_shed_requests.clone(),
+        );
+        let uploaded_bytes = Counter::default();
+        registry.register(
+            "uploaded_bytes",
+            "Bytes received in request bodies",
+            uploaded_bytes.clone(),
+        );
+        let downloaded_bytes = Counter::default();
+        registry.register(
+            "downloaded_bytes",
+            "Bytes sent in response bodies",
+            downloaded_bytes.clone(),
+        );
+        let republished_keys = Family::<RepublishLabels, Counter>::default();
+        registry.register(
+            "republished_keys",
+            "Number of pkarr packets published to the DHT by outcome",
+            republished_keys.clone(),
+        );
+        let sessions = Gauge::default();
+        registry.register("sessions", "Number of active sessions", sessions.clone());
+        let lmdb_map_size_bytes = Gauge::default();
+        registry.register(
+            "lmdb_map_size_bytes",
+            "Maximum size of the LMDB database",
+            lmdb_map_size_bytes.clone(),
+        );
+        let lmdb_used_bytes = Gauge::default();
+        registry.register(
+            "lmdb_used_bytes",
+            "Bytes used by the LMDB database",
+            lmdb_used_bytes.clone(),
+        );
+
+        Self {
+            inner: Arc::new(MetricsInner {
+                registry,
+                http_requests,
+                http_request_duration,
+                rate_limit_rejections,
+                load_shed_requests,
+                uploaded_bytes,
+                downloaded_bytes,
+                republished_keys,
+                sessions,
+                lmdb_map_size_bytes,
+                lmdb_used_bytes,
+            }),
+        }
+    }
+}
+
+impl Metrics {
+    /// Count a request rejected by the rate limiter.
+    pub fn inc_rate_limit_rejection(&self, method: &str, path: &str) {
+        self.inner
+            .rate_limit_rejections
+            .get_or_create(&RateLimitLabels {
+                method: method.to_string(),
+                path: path.to_string(),
+            })
+            .inc();
+    }
+
+    /// Count a request rejected by the global limits.
+    pub fn inc_load_shed(&self, reason: LoadShedReason) {
+        self.inner
+            .load_shed_requests
+            .get_or_create(&LoadShedLabels { reason })
+            .inc();
+    }
+
+    /// Count `count` published keys.
+    pub fn inc_republished_keys(
+        &self,
+        republisher: Republisher,
+        outcome: RepublishOutcome,
+        count: u64,
+    ) {
+        self.inner
+            .republished_keys
+            .get_or_create(&RepublishLabels {
+                republisher,
+                outcome,
+            })
+            .inc_by(count);
+    }
+
+    /// Render all metrics in the Prometheus text format.
+    ///
+    /// Reads the gauges that are backed by the database first.
+    pub fn render(&self, db: &LmDB) -> anyhow::Result<String> {
+        let sessions = {
+            let rtxn = db.env.read_txn()?;
+            db.tables.sessions.len(&rtxn)?
+        };
+        self.inner.sessions.set(sessions as i64);
+        self.inner
+            .lmdb_map_size_bytes
+            .set(db.env.info().map_size as i64);
+        self.inner
+            .lmdb_used_bytes
+            .set(db.env.non_free_pages_size()? as i64);
+
+        let mut output = String::new();
+        encode(&mut output, &self.inner.registry)?;
+        Ok(output)
+    }
+}
+
+/// Middleware that records the request count and latency per route and status,
+/// and the transferred body bytes.
+///
+/// Apply with `middleware::from_fn_with_state((metrics, "server name"), track_http_metrics)`
+/// via `Router::layer` so the matched route is known.
+pub(crate) async fn track_http_metrics(
+    State((metrics, server)): State<(Metrics, &'static str)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    // Use the route instead of the path to keep the number of labels bounded.
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let request = request.map(|body| {
+        Body::new(CountingBody::new(
+            body,
+            metrics.inner.uploaded_bytes.clone(),
+        ))
+    });
+    let response = next.run(request).await;
+
+    let labels = HttpLabels {
+        server,
+        method,
+        route,
+        status: response.status().as_u16(),
+    };
+    metrics.inner.http_requests.get_or_create(&labels).inc();
+    metrics
+        .inner
+        .http_request_duration
+        .get_or_create(&labels)
+        .observe(start.elapsed().as_secs_f64());
+
+    response.map(|body| {
+        Body::new(CountingBody::new(
+            body,
+            metrics.inner.downloaded_bytes.clone(),
+        ))
+    })
+}
+
+/// Body that counts the bytes passing through.
+/// Keeps the size hint so the `Content-Length` of the response doesn't change.
+struct CountingBody {
+    inner: Body,
+    counter: Counter,
+}
+
+impl CountingBody {
+    fn new(inner: Body, counter: Counter) -> Self {
+        Self { inner, counter }
+    }
+}
+
+impl HttpBody for CountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.counter.inc_by(data.len() as u64);
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::post, Router};
+
+    #[tokio::test]
+    async fn test_track_http_metrics() {
+        let metrics = Metrics::default();
+        let router = Router::new()
+            .route("/echo/{name}", post(|body: Bytes| async move { body }))
+            .layer(middleware::from_fn_with_state(
+                (metrics.clone(), "test"),
+                track_http_metrics,
+            ));
+        let server = axum_test::TestServer::new(router).unwrap();
+        server
+            .post("/echo/alice")
+            .bytes(Bytes::from_static(b"hello"))
+            .await
+            .assert_status_ok();
+        metrics.inc_rate_limit_rejection("GET", "/*");
+
+        let output = metrics.render(&LmDB::test()).unwrap();
+        assert!(output.contains(
+            "homeserver_http_requests_total{server=\"test\",method=\"POST\",route=\"/echo/{name}\",status=\"200\"} 1"
+        ));
+        assert!(output.contains("homeserver_uploaded_bytes_total 5"));
+        assert!(output.contains("homeserver_downloaded_bytes_total 5"));
+        assert!(
+            output.contains("homeserver_rate_limit_rejections_total{method=\"GET\",path=\"/*\"} 1")
+        );
+        assert!(output.contains("homeserver_sessions 0"));
+    }
+}
@@ -1,4 +1,6 @@
+pub(crate) mod health;
 mod http_error;
+pub(crate) mod metrics;
 mod pubkey_path_validator;
 pub(crate) mod toml_merge;
 pub(crate) mod webdav;
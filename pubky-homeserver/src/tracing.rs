@@ -8,9 +8,15 @@
 //! This way, we don't miss any logs, for example config file loading errors.
 //!
 
-use crate::{ConfigToml, PersistentDataDir};
-use std::path::Path;
-use tracing_subscriber::EnvFilter;
+use crate::{ConfigToml, LoggingToml, PersistentDataDir};
+use std::{path::Path, sync::OnceLock};
+use tracing_subscriber::{
+    layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+/// Handle to swap the log filter when the config is reloaded.
+/// Only set if the subscriber was initialized by [init_tracing_logs_with_config_if_set].
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
 
 fn read_config_from_file(data_dir: &Path) -> anyhow::Result<ConfigToml> {
     let data_dir = PersistentDataDir::new(data_dir.to_path_buf());
@@ -26,23 +32,46 @@ pub fn init_tracing_logs_with_config_if_set(config: &ConfigToml) -> anyhow::Resu
         None => return Ok(()),
     };
 
-    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-        let mut filter = EnvFilter::new("");
-        filter = filter.add_directive(config.level.to_owned().into());
-        // Add any specific filters
-        for filter_str in &config.module_levels {
-            filter = filter.add_directive(filter_str.to_owned().into());
-        }
-        filter
-    });
-    tracing_subscriber::fmt()
-        .with_env_filter(env_filter)
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| config_filter(config));
+    let (filter_layer, handle) = reload::Layer::new(env_filter);
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(tracing_subscriber::fmt::layer())
         .try_init()
         .map_err(|e| anyhow::anyhow!("Failed to initialize tracing: {}", e))?;
+    let _ = FILTER_HANDLE.set(handle);
 
     Ok(())
 }
 
+/// Apply the log levels of a reloaded config.
+///
+/// Returns `false` if the levels can't be changed at runtime because the subscriber
+/// was not initialized from the config or the `RUST_LOG` environment variable overrides it.
+pub(crate) fn reload_tracing_log_levels(config: &LoggingToml) -> anyhow::Result<bool> {
+    let handle = match FILTER_HANDLE.get() {
+        Some(handle) => handle,
+        None => return Ok(false),
+    };
+    if std::env::var(EnvFilter::DEFAULT_ENV).is_ok() {
+        return Ok(false);
+    }
+    handle
+        .reload(config_filter(config))
+        .map_err(|e| anyhow::anyhow!("Failed to reload log levels: {}", e))?;
+    Ok(true)
+}
+
+fn config_filter(config: &LoggingToml) -> EnvFilter {
+    let mut filter = EnvFilter::new("");
+    filter = filter.add_directive(config.level.to_owned().into());
+    // Add any specific filters
+    for filter_str in &config.module_levels {
+        filter = filter.add_directive(filter_str.to_owned().into());
+    }
+    filter
+}
+
 /// Initialize tracing logger based on the values defined in the config file.
 /// If the config file is not found, use default values.
 pub fn init_tracing_logs_if_set(data_dir: &Path) -> anyhow::Result<()> {
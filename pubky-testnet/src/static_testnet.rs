@@ -183,10 +183,11 @@ impl StaticTestnet {
         );
         config.pkdns.dht_relay_nodes = None;
         config.drive.icann_listen_socket =
-            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 6286);
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 6286).into();
         config.drive.pubky_listen_socket =
             SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 6287);
-        config.admin.listen_socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 6288);
+        config.admin.listen_socket =
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 6288).into();
         let mock = MockDataDir::new(config, Some(keypair))?;
 
         let homeserver = HomeserverSuite::start_with_mock_data_dir(mock).await?;